@@ -0,0 +1,107 @@
+//! Times each phase of the compiler on programs that stress one dimension
+//! each. Run with `cargo bench`, or `cargo bench -- <name>` for the
+//! programs whose name contains `<name>`.
+//!
+//! The compiler has no optimizer or register allocator yet. Making the
+//! program canonical is the one pass over the IR, so it stands in for them.
+
+use language::execute::VirtualMachine;
+use language::session::DEFAULT_ENTRY;
+use language::{compiler, hir, parser};
+use std::env;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+/// Each phase runs for at least this long.
+const TARGET: Duration = Duration::from_millis(500);
+
+fn main() {
+    let filter = env::args().skip(1).find(|arg| !arg.starts_with('-'));
+    let programs = [
+        ("deep expression", deep_expression(2000)),
+        ("nested brackets", nested_brackets(200)),
+        ("many functions", many_functions(500)),
+        ("wide tuple", wide_tuple(256)),
+        ("long block chain", long_block_chain(500)),
+    ];
+    for (name, source) in &programs {
+        if filter.as_ref().is_some_and(|filter| !name.contains(filter.as_str())) {
+            continue
+        }
+        println!("{} ({} bytes)", name, source.len());
+        bench("parse", || parser::parse_source(source).unwrap());
+        let ast = parser::parse_source(source).unwrap();
+        bench("lower", || hir::lower(&ast, &[]).unwrap());
+        let hir = hir::lower(&ast, &[]).unwrap();
+        bench("compile", || compiler::compile_program(&hir, true, DEFAULT_ENTRY).map(|(program, entry, _)| (program, entry)).unwrap());
+        let (program, entry, _) = compiler::compile_program(&hir, true, DEFAULT_ENTRY).unwrap();
+        bench("canonical", || program.canonical(entry));
+        let (program, entry) = program.canonical(entry);
+        bench("execute", || {
+            let mut vm = VirtualMachine::new(&program);
+            vm.execute(program.get_function(entry)).unwrap();
+            vm.into_registers()
+        });
+    }
+    // inputs the parser must take without overflowing the stack or slowing
+    // down more than linearly, which the later phases cannot yet
+    let large = [
+        ("10k brackets", format!("{}1{}", "(".repeat(10_000), ")".repeat(10_000))),
+        ("1M character sum", deep_expression(250_000)),
+        ("1M characters of lines", (0..100_000).map(|index| format!("x = {}\n", index)).collect()),
+        ("1M characters of spacing", (0..10_000).map(|index| format!("x = y{}\n\n    // {}\n\n", " ".repeat(80), index)).collect()),
+    ];
+    for (name, source) in &large {
+        if filter.as_ref().is_some_and(|filter| !name.contains(filter.as_str())) {
+            continue
+        }
+        println!("{} ({} bytes)", name, source.len());
+        bench("parse", || parser::parse_source(source).unwrap());
+    }
+}
+
+/// Runs `f` until `TARGET` has passed and prints the mean time of a run.
+fn bench<T>(phase: &str, mut f: impl FnMut() -> T) {
+    let start = Instant::now();
+    let mut runs = 0u32;
+    while start.elapsed() < TARGET {
+        black_box(f());
+        runs += 1;
+    }
+    println!("  {:<10} {:>12.3?} ({} runs)", phase, start.elapsed() / runs, runs);
+}
+
+/// `1 + 1 + ...`, one long chain of one operator.
+fn deep_expression(terms: usize) -> String {
+    vec!["1"; terms].join(" + ")
+}
+
+/// `(1 + (1 + (...)))`, which the parser recurses into.
+fn nested_brackets(depth: usize) -> String {
+    format!("{}1{}", "(1 + ".repeat(depth), ")".repeat(depth))
+}
+
+/// Functions that each call the one before.
+fn many_functions(count: usize) -> String {
+    let mut source = "f0 = fn(x) x + 1\n".to_string();
+    for index in 1..count {
+        source += &format!("f{} = fn(x) f{}(x) + {}\n", index, index - 1, index);
+    }
+    source + &format!("f{}(0)\n", count - 1)
+}
+
+/// A tuple passed through a function and taken apart again.
+fn wide_tuple(width: usize) -> String {
+    let names: Vec<String> = (0..width).map(|index| format!("a{}", index)).collect();
+    let values: Vec<String> = (0..width).map(|index| index.to_string()).collect();
+    format!("id = fn(t) t\n({}) = id({})\na0\n", names.join(", "), values.join(", "))
+}
+
+/// `if`s one after another, each ending the block before it.
+fn long_block_chain(length: usize) -> String {
+    let mut source = "chain = fn(c) {\n  x = 0\n".to_string();
+    for _ in 0..length {
+        source += "  x = (if (c) x + 1) else x + 2\n";
+    }
+    source + "  x\n}\nchain(true)\n"
+}
@@ -0,0 +1,57 @@
+/* C interface to the compiler, built with the `capi` feature.
+ * Keep in sync with src/capi.rs. */
+
+#ifndef LANGUAGE_H
+#define LANGUAGE_H
+
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* The result of compiling, and possibly running, a program. Owned by the
+ * caller and freed with lang_result_free. */
+typedef struct LangResult LangResult;
+
+/* Compile a NUL terminated UTF-8 source without running it. Returns NULL if
+ * source is NULL or not UTF-8, or if the compiler panicked. */
+LangResult *lang_compile(const char *source);
+
+/* Compile a source and run its main within the playground's limits.
+ * Returns NULL as lang_compile does. */
+LangResult *lang_run(const char *source);
+
+/* Compile a source and run its main for at most fuel instructions and
+ * timeout_millis milliseconds, where 0 is no limit. */
+LangResult *lang_run_limited(const char *source, uint64_t fuel, uint64_t timeout_millis);
+
+/* 1 if the program compiled without errors, otherwise 0. */
+int lang_result_compiled(const LangResult *result);
+
+/* Strings owned by the result, valid until it is freed. */
+const char *lang_result_diagnostics(const LangResult *result);
+const char *lang_result_ir(const LangResult *result);
+const char *lang_result_printed(const LangResult *result);
+const char *lang_result_output(const LangResult *result);
+
+/* 1 if the program ran and gave an exit code, and the code, or 0. */
+int lang_result_has_exit_code(const LangResult *result);
+int lang_result_exit_code(const LangResult *result);
+
+/* The limit that stopped the program, or LANG_EXHAUSTED_NONE. */
+#define LANG_EXHAUSTED_NONE 0
+#define LANG_EXHAUSTED_FUEL 1
+#define LANG_EXHAUSTED_MEMORY 2
+#define LANG_EXHAUSTED_TIME 3
+#define LANG_EXHAUSTED_STACK 4
+int lang_result_exhausted(const LangResult *result);
+
+/* Free a result. NULL is ignored. */
+void lang_result_free(LangResult *result);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -1,16 +1,34 @@
 use crate::position::Position;
-use std::fmt;
+use crate::diagnostics::{json_string, Span};
+use std::fmt::Write;
+use std::{fmt, mem};
 
 #[derive(Debug)]
 pub struct Parsed<'a, T> {
     start: Position<'a>,
     end: Position<'a>,
     pub node: T,
+    pub trivia: Trivia<'a>,
+}
+
+/// Comments kept with an expression, so that printing the tree does not
+/// lose them. Only the items of blocks, and the whole program, have any.
+#[derive(Debug, Default)]
+pub struct Trivia<'a> {
+    /// Comments on the lines before the expression, and any inside it that
+    /// no block inside it kept.
+    pub leading: Vec<&'a str>,
+    /// The comment after the expression on the same line, and for the last
+    /// item of a block, the comments after that too.
+    pub trailing: Vec<&'a str>,
+    /// The `///` comments directly before a declaration.
+    pub doc: Vec<&'a str>,
 }
 
 #[derive(Debug)]
 pub enum Expr<'a> {
     IntLiteral(&'a str),
+    FloatLiteral(&'a str),
     BoolLiteral(&'a str),
     Ident(&'a str),
     Tuple {
@@ -36,20 +54,123 @@ pub enum Expr<'a> {
     },
     Struct {
         body: Box<Parsed<'a, Expr<'a>>>,
-    }
+    },
+    /// `match value { pattern => expr, ... }`, which gives the expression of
+    /// the first arm whose pattern matches the value.
+    Match {
+        value: Box<Parsed<'a, Expr<'a>>>,
+        arms: Vec<(Parsed<'a, Expr<'a>>, Parsed<'a, Expr<'a>>)>,
+    },
+    /// `for pattern in iterable body`, which runs `body` once for each value
+    /// the iterable gives, written `label: for ...` to name the loop.
+    For {
+        label: Option<&'a str>,
+        pattern: Box<Parsed<'a, Expr<'a>>>,
+        iterable: Box<Parsed<'a, Expr<'a>>>,
+        body: Box<Parsed<'a, Expr<'a>>>,
+    },
+    /// `while (cond) body`, which runs `body` for as long as `cond` is true,
+    /// written `label: while ...` to name the loop.
+    While {
+        label: Option<&'a str>,
+        cond: Box<Parsed<'a, Expr<'a>>>,
+        body: Box<Parsed<'a, Expr<'a>>>,
+    },
+    Cast {
+        expr: Box<Parsed<'a, Expr<'a>>>,
+        ty: Parsed<'a, &'a str>,
+    },
+    /// `expr.name`, reading a field of a struct. It binds as a call does.
+    Field {
+        expr: Box<Parsed<'a, Expr<'a>>>,
+        name: Parsed<'a, &'a str>,
+    },
+    /// `!expr`, which binds tighter than every operator but a call.
+    Not {
+        expr: Box<Parsed<'a, Expr<'a>>>,
+    },
+    /// `break` or `break label`, which leaves the innermost loop or the one
+    /// with that label.
+    Break {
+        label: Option<&'a str>,
+    },
+    /// `defer body`, which runs `body` when the enclosing block exits.
+    Defer {
+        body: Box<Parsed<'a, Expr<'a>>>,
+    },
+    /// `cfg(name) body`, which is only compiled when `name` is enabled.
+    Cfg {
+        name: Parsed<'a, &'a str>,
+        body: Box<Parsed<'a, Expr<'a>>>,
+    },
+    /// `allow(name) body`, which drops the warnings called `name` in `body`.
+    Allow {
+        name: Parsed<'a, &'a str>,
+        body: Box<Parsed<'a, Expr<'a>>>,
+    },
+    /// `ir { ... }`, holding the text between the braces as written.
+    InlineIr(&'a str),
+}
+
+/// How tightly an expression binds, from loosest to tightest. An operator
+/// continues the expression being parsed only if it binds tighter than the
+/// precedence being parsed at.
+#[derive(PartialEq, PartialOrd, Copy, Clone)]
+pub(crate) enum Prec {
+    Block,
+    Tuple,
+    Expr,
+    Or,
+    And,
+    Compare,
+    BitOr,
+    BitXor,
+    BitAnd,
+    Shift,
+    Sum,
+    Product,
+    Cast,
+    Call,
 }
 
 #[derive(Debug)]
 pub enum BinaryOp {
     Plus,
+    Minus,
+    Times,
+    Divide,
+    Remainder,
+    DoubleEquals,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+    And,
+    Or,
+    BitAnd,
+    BitOr,
+    BitXor,
+    ShiftLeft,
+    ShiftRight,
     Bracket,
     SingleEquals,
+    PlusEquals,
+    MinusEquals,
+    TimesEquals,
+    DivideEquals,
+    RemainderEquals,
+    BitAndEquals,
+    BitOrEquals,
+    BitXorEquals,
+    ShiftLeftEquals,
+    ShiftRightEquals,
     Else,
 }
 
 impl<'a, T> Parsed<'a, T> {
     pub fn new(start: Position<'a>, end: Position<'a>, node: T) -> Parsed<'a, T> {
-        Parsed { start, end, node }
+        Parsed { start, end, node, trivia: Trivia::default() }
     }
     pub fn start(&self) -> Position<'a> {
         self.start
@@ -65,89 +186,427 @@ impl<'a, T> Parsed<'a, T> {
     }
 }
 
+impl<'a> Parsed<'a, Expr<'a>> {
+    /// The text of the doc comments of a declaration, without the slashes.
+    pub fn doc(&self) -> Option<String> {
+        if self.trivia.doc.is_empty() {
+            return None
+        }
+        let lines: Vec<&str> = self.trivia.doc.iter()
+            .map(|line| line.trim_start_matches("///"))
+            .map(|line| line.strip_prefix(' ').unwrap_or(line))
+            .collect();
+        Some(lines.join("\n"))
+    }
+    /// Writes the expression on lines of its own, with its comments.
+    fn write_item(&self, f: &mut fmt::Formatter, align: i32) -> fmt::Result {
+        for comment in self.trivia.leading.iter().chain(&self.trivia.doc) {
+            writeln!(f, "{}{}", Indent(align), comment)?;
+        }
+        write!(f, "{}", Indent(align))?;
+        self.node.write_at(f, align, Prec::Expr, None)?;
+        let mut trailing = self.trivia.trailing.iter();
+        if let Some(comment) = trailing.next() {
+            write!(f, "  {}", comment)?;
+        }
+        for comment in trailing {
+            write!(f, "\n{}{}", Indent(align), comment)?;
+        }
+        Ok(())
+    }
+    /// Moves the expression out, leaving `()` in its place.
+    fn take_node(&mut self) -> Expr<'a> {
+        mem::replace(&mut self.node, Expr::Tuple { exprs: Vec::new() })
+    }
+}
+
+/// A long chain of operators nests deeper than dropping it recursively
+/// would have stack for, so children are moved onto a list and dropped
+/// from there instead.
+impl<'a> Drop for Expr<'a> {
+    fn drop(&mut self) {
+        let mut children = Vec::new();
+        self.take_children(&mut children);
+        while let Some(mut child) = children.pop() {
+            child.take_children(&mut children);
+        }
+    }
+}
+
 impl<'a> Expr<'a> {
+    /// Whether doc comments can be written for the expression: a named
+    /// function, a struct, or one of those assigned to a name.
+    pub fn is_declaration(&self) -> bool {
+        match self {
+            Expr::Func { name: Some(_), .. } | Expr::Struct { .. } => true,
+            Expr::Binary { right, op: BinaryOp::SingleEquals, .. } => {
+                matches!(right.node, Expr::Func { .. } | Expr::Struct { .. })
+            }
+            _ => false,
+        }
+    }
+    pub fn children(&self) -> Vec<&Parsed<'a, Expr<'a>>> {
+        match self {
+            Expr::IntLiteral(_) | Expr::FloatLiteral(_) | Expr::BoolLiteral(_) | Expr::Ident(_) | Expr::InlineIr(_) | Expr::Break { .. } => vec![],
+            Expr::Tuple { exprs } => exprs.iter().collect(),
+            Expr::Block { exprs, last } => exprs.iter().chain(Some(&**last)).collect(),
+            Expr::Func { pattern, expr, .. } => vec![pattern, expr],
+            Expr::Binary { left, right, .. } => vec![left, right],
+            Expr::If { cond, conc } => vec![cond, conc],
+            Expr::Match { value, arms } => Some(&**value).into_iter().chain(arms.iter().flat_map(|(pattern, expr)| [pattern, expr])).collect(),
+            Expr::While { cond, body, .. } => vec![cond, body],
+            Expr::For { pattern, iterable, body, .. } => vec![pattern, iterable, body],
+            Expr::Struct { body } | Expr::Cfg { body, .. } | Expr::Allow { body, .. } | Expr::Defer { body } => vec![body],
+            Expr::Cast { expr, .. } | Expr::Not { expr } | Expr::Field { expr, .. } => vec![expr],
+        }
+    }
+    fn take_children(&mut self, children: &mut Vec<Expr<'a>>) {
+        let mut take = |expr: &mut Parsed<'a, Expr<'a>>| match &expr.node {
+            Expr::Tuple { exprs } if exprs.is_empty() => (),
+            _ => children.push(expr.take_node()),
+        };
+        match self {
+            Expr::IntLiteral(_) | Expr::FloatLiteral(_) | Expr::BoolLiteral(_) | Expr::Ident(_) | Expr::InlineIr(_) | Expr::Break { .. } => (),
+            Expr::Tuple { exprs } => exprs.iter_mut().for_each(take),
+            Expr::Block { exprs, last } => {
+                exprs.iter_mut().for_each(&mut take);
+                take(last)
+            }
+            Expr::Func { pattern, expr, .. } => {
+                take(pattern);
+                take(expr)
+            }
+            Expr::Binary { left, right, .. } => {
+                take(left);
+                take(right)
+            }
+            Expr::If { cond, conc } => {
+                take(cond);
+                take(conc)
+            }
+            Expr::Match { value, arms } => {
+                take(value);
+                for (pattern, expr) in arms {
+                    take(pattern);
+                    take(expr)
+                }
+            }
+            Expr::While { cond, body, .. } => {
+                take(cond);
+                take(body)
+            }
+            Expr::For { pattern, iterable, body, .. } => {
+                take(pattern);
+                take(iterable);
+                take(body)
+            }
+            Expr::Struct { body } | Expr::Cfg { body, .. } | Expr::Allow { body, .. } | Expr::Defer { body } => take(body),
+            Expr::Cast { expr, .. } | Expr::Not { expr } | Expr::Field { expr, .. } => take(expr),
+        }
+    }
     pub fn new_binary(left: Parsed<'a, Expr<'a>>, right: Parsed<'a, Expr<'a>>, op: BinaryOp) -> Parsed<'a, Expr<'a>> {
         Parsed::new(left.start(), right.end(), Expr::Binary { left: Box::new(left), right: Box::new(right), op })
     }
-    pub fn new_tuple(left: Parsed<'a, Expr<'a>>, right: Parsed<'a, Expr<'a>>) -> Parsed<'a, Expr<'a>> {
-        Parsed::new(left.start(), right.end(), match left.node {
-            Expr::Tuple { mut exprs } => {
+    pub fn new_tuple(mut left: Parsed<'a, Expr<'a>>, right: Parsed<'a, Expr<'a>>) -> Parsed<'a, Expr<'a>> {
+        let start = left.start();
+        let end = right.end();
+        Parsed::new(start, end, match &mut left.node {
+            Expr::Tuple { exprs } => {
+                let mut exprs = mem::take(exprs);
                 exprs.push(right);
                 Expr::Tuple { exprs }
             }
             _ => Expr::Tuple { exprs: vec![left, right] }
         })
     }
-    pub fn new_block(left: Parsed<'a, Expr<'a>>, right: Parsed<'a, Expr<'a>>) -> Parsed<'a, Expr<'a>> {
-        Parsed::new(left.start(), right.end(), match left.node {
-            Expr::Block { mut exprs, last } => {
-                exprs.push(*last);
+    pub fn new_block(mut left: Parsed<'a, Expr<'a>>, right: Parsed<'a, Expr<'a>>) -> Parsed<'a, Expr<'a>> {
+        let start = left.start();
+        let end = right.end();
+        Parsed::new(start, end, match &mut left.node {
+            Expr::Block { exprs, last } => {
+                let mut exprs = mem::take(exprs);
+                let trivia = mem::take(&mut last.trivia);
+                exprs.push(Parsed { trivia, ..Parsed::new(last.start(), last.end(), last.take_node()) });
                 Expr::Block { exprs, last: Box::new(right) }
             }
             _ => Expr::Block { exprs: vec![left], last: Box::new(right) }
         })
     }
+    /// Writes the expression so that parsing it gives the same tree back,
+    /// with brackets only where precedence needs them.
     pub fn write(&self, f: &mut fmt::Formatter, align: i32) -> fmt::Result {
+        self.write_at(f, align, Prec::Block, None)
+    }
+    /// Writes the expression where the parser would be parsing at `prec`,
+    /// with `next` joining whatever follows it, if anything does.
+    fn write_at(&self, f: &mut fmt::Formatter, align: i32, prec: Prec, next: Option<Binding>) -> fmt::Result {
+        let bracketed = !self.binding().is_none_or(|binding| binding.continues(prec))
+            || matches!((self.open_prec(), next), (Some(open), Some(next)) if next.continues(open));
+        let (prec, next) = if bracketed {
+            write!(f, "(")?;
+            (Prec::Tuple, None)
+        } else {
+            (prec, next)
+        };
         match self {
             Expr::IntLiteral(src) => write!(f, "{}", src)?,
+            Expr::FloatLiteral(src) => write!(f, "{}", src)?,
             Expr::BoolLiteral(src) => write!(f, "{}", src)?,
             Expr::Ident(src) => write!(f, "{}", src)?,
-            Expr::Tuple { exprs } => {
-                let mut iter = exprs.iter();
-                write!(f, "(")?;
-                if let Some(expr) = iter.next() {
-                    expr.node.write(f, align)?;
-                    for expr in iter {
-                        write!(f, ", ")?;
-                        expr.node.write(f, align)?;
-                    }
-                }
-                write!(f, ")")?;
-            }
+            Expr::InlineIr(text) => write!(f, "ir {{{}}}", text)?,
+            Expr::Tuple { exprs } => write_tuple(f, align, exprs, next)?,
             Expr::Block { exprs, last } => {
                 writeln!(f, "{{")?;
-                for expr in exprs {
-                    write!(f, "{}", Indent(align+4))?;
-                    expr.node.write(f, align+4)?;
-                    writeln!(f, "")?;
+                for expr in exprs.iter().chain(Some(&**last)) {
+                    expr.write_item(f, align+4)?;
+                    writeln!(f)?;
                 }
-                write!(f, "{}", Indent(align+4))?;
-                last.node.write(f, align+4)?;
-                writeln!(f, "\n}}")?;
+                write!(f, "{}}}", Indent(align))?;
             }
             Expr::Func { name, pattern, expr } => {
                 write!(f, "fn")?;
-                match name {
-                    Some(name) => write!(f, " {}", name)?,
-                    None => {},
+                if let Some(name) = name {
+                    write!(f, " {}", name)?;
                 }
-                write!(f, "(")?;
-                pattern.node.write(f, align)?;
-                write!(f, ") ")?;
-                expr.node.write(f, align)?;
-                writeln!(f, "")?;
+                pattern.node.write_bracketed(f, align)?;
+                write!(f, " ")?;
+                expr.node.write_at(f, align, Prec::Expr, next)?;
+            }
+            Expr::Binary { left, right, op: BinaryOp::Bracket } => {
+                left.node.write_at(f, align, prec, Some(Binding::left(Prec::Call)))?;
+                right.node.write_bracketed(f, align)?;
             }
             Expr::Binary { left, right, op } => {
-                left.node.write(f, align)?;
-                write!(f, "{}", match op {
-                    BinaryOp::Plus => " + ",
-                    BinaryOp::Bracket => " (",
-                    BinaryOp::SingleEquals => " = ",
-                    BinaryOp::Else => " else ",
-                })?;
-                right.node.write(f, align)?;
-                write!(f, "{}", if let BinaryOp::Bracket = op { ")" } else { "" })?;
+                let binding = op.binding();
+                left.node.write_at(f, align, prec, Some(binding))?;
+                write!(f, " {} ", op)?;
+                right.node.write_at(f, align, binding.prec, next)?;
             }
             Expr::If { cond, conc } => {
                 write!(f, "if ")?;
-                cond.node.write(f, align)?;
+                cond.node.write_bracketed(f, align)?;
+                write!(f, " ")?;
+                conc.node.write_at(f, align, Prec::Expr, next)?;
+            }
+            Expr::Match { value, arms } => {
+                write!(f, "match ")?;
+                value.node.write_at(f, align, Prec::Expr, None)?;
+                writeln!(f, " {{")?;
+                for (pattern, expr) in arms {
+                    write!(f, "{}", Indent(align+4))?;
+                    pattern.node.write_at(f, align+4, Prec::Call, None)?;
+                    write!(f, " => ")?;
+                    expr.node.write_at(f, align+4, Prec::Expr, None)?;
+                    writeln!(f)?;
+                }
+                write!(f, "{}}}", Indent(align))?;
+            }
+            Expr::For { label, pattern, iterable, body } => {
+                if let Some(label) = label {
+                    write!(f, "{}: ", label)?;
+                }
+                write!(f, "for ")?;
+                pattern.node.write_at(f, align, Prec::Call, None)?;
+                write!(f, " in ")?;
+                iterable.node.write_at(f, align, Prec::Expr, None)?;
                 write!(f, " ")?;
-                conc.node.write(f, align)?;
+                body.node.write_at(f, align, Prec::Expr, next)?;
+            }
+            Expr::While { label, cond, body } => {
+                if let Some(label) = label {
+                    write!(f, "{}: ", label)?;
+                }
+                write!(f, "while ")?;
+                cond.node.write_bracketed(f, align)?;
+                write!(f, " ")?;
+                body.node.write_at(f, align, Prec::Expr, next)?;
+            }
+            Expr::Struct { body } => {
+                write!(f, "struct ")?;
+                match &body.node {
+                    Expr::Block { .. } => body.node.write_at(f, align, Prec::Expr, next)?,
+                    // the body has to start with a brace
+                    body => {
+                        writeln!(f, "{{")?;
+                        write!(f, "{}", Indent(align+4))?;
+                        body.write_at(f, align+4, Prec::Expr, None)?;
+                        write!(f, "\n{}}}", Indent(align))?;
+                    }
+                }
+            }
+            Expr::Cfg { name, body } => {
+                write!(f, "cfg({}) ", name.node)?;
+                body.node.write_at(f, align, Prec::Expr, next)?;
+            }
+            Expr::Allow { name, body } => {
+                write!(f, "allow({}) ", name.node)?;
+                body.node.write_at(f, align, Prec::Expr, next)?;
+            }
+            Expr::Break { label: None } => write!(f, "break")?,
+            Expr::Break { label: Some(label) } => write!(f, "break {}", label)?,
+            Expr::Defer { body } => {
+                write!(f, "defer ")?;
+                body.node.write_at(f, align, Prec::Expr, next)?;
+            }
+            Expr::Cast { expr, ty } => {
+                expr.node.write_at(f, align, prec, Some(Binding::left(Prec::Cast)))?;
+                write!(f, " as {}", ty.node)?;
+            }
+            Expr::Field { expr, name } => {
+                expr.node.write_at(f, align, prec, Some(Binding::left(Prec::Call)))?;
+                write!(f, ".{}", name.node)?;
+            }
+            Expr::Not { expr } => {
+                write!(f, "!")?;
+                expr.node.write_at(f, align, Prec::Cast, next)?;
             }
-            Expr::Struct { body } => body.node.write(f, align)?,
         };
+        if bracketed {
+            write!(f, ")")?;
+        }
         Ok(())
     }
+    /// Writes the expression inside brackets, as arguments and conditions
+    /// are.
+    fn write_bracketed(&self, f: &mut fmt::Formatter, align: i32) -> fmt::Result {
+        match self {
+            Expr::Tuple { exprs } if exprs.is_empty() => write!(f, "()"),
+            _ => {
+                write!(f, "(")?;
+                self.write_at(f, align, Prec::Tuple, None)?;
+                write!(f, ")")
+            }
+        }
+    }
+    /// How the expression joins its first operand to the rest of it, or
+    /// `None` if it is a value on its own.
+    fn binding(&self) -> Option<Binding> {
+        match self {
+            Expr::Tuple { exprs } if !exprs.is_empty() => Some(Binding::right(Prec::Tuple)),
+            Expr::Binary { op, .. } => Some(op.binding()),
+            Expr::Cast { .. } => Some(Binding::left(Prec::Cast)),
+            Expr::Field { .. } => Some(Binding::left(Prec::Call)),
+            _ => None,
+        }
+    }
+    /// The precedence its last operand was parsed at, which an operator
+    /// written after the expression would be taken into, or `None` if the
+    /// expression ends in a closing bracket or a name.
+    fn open_prec(&self) -> Option<Prec> {
+        match self {
+            Expr::Tuple { exprs } if !exprs.is_empty() => Some(Prec::Tuple),
+            Expr::Binary { op: BinaryOp::Bracket, .. } => None,
+            Expr::Binary { op, .. } => Some(op.binding().prec),
+            Expr::Func { .. } | Expr::If { .. } | Expr::For { .. } | Expr::While { .. } | Expr::Struct { .. } | Expr::Cfg { .. } | Expr::Allow { .. } | Expr::Defer { .. } => Some(Prec::Expr),
+            Expr::Not { .. } => Some(Prec::Cast),
+            _ => None,
+        }
+    }
+}
+
+/// Writes the elements of a tuple so that they parse back into the same
+/// tuple. A tuple on the left of `,` gains the element on the right, so
+/// `a, b, c` is `(a, (b, c))`, and `(a, b, c)` must be written `(a, b), c`.
+fn write_tuple(f: &mut fmt::Formatter, align: i32, exprs: &[Parsed<Expr>], next: Option<Binding>) -> fmt::Result {
+    let (last, init) = match exprs.split_last() {
+        Some(split) => split,
+        None => return write!(f, "()"),
+    };
+    match init {
+        [] => write!(f, "(), ")?,
+        [first] => {
+            first.node.write_at(f, align, Prec::Tuple, Some(Binding::right(Prec::Tuple)))?;
+            write!(f, ", ")?;
+        }
+        _ => {
+            write!(f, "(")?;
+            write_tuple(f, align, init, None)?;
+            write!(f, "), ")?;
+        }
+    }
+    last.node.write_at(f, align, Prec::Tuple, next)
+}
+
+/// How an operator joins its left operand: it does so while parsing at any
+/// precedence below `prec`, or up to and including `prec` if it groups to
+/// the right.
+#[derive(Copy, Clone)]
+struct Binding {
+    prec: Prec,
+    right: bool,
+}
+
+impl Binding {
+    fn left(prec: Prec) -> Binding {
+        Binding { prec, right: false }
+    }
+    fn right(prec: Prec) -> Binding {
+        Binding { prec, right: true }
+    }
+    /// Whether the operator continues an expression being parsed at `prec`.
+    fn continues(&self, prec: Prec) -> bool {
+        if self.right { prec <= self.prec } else { prec < self.prec }
+    }
+}
+
+impl BinaryOp {
+    fn binding(&self) -> Binding {
+        match self {
+            BinaryOp::Plus | BinaryOp::Minus => Binding::left(Prec::Sum),
+            BinaryOp::Times | BinaryOp::Divide | BinaryOp::Remainder => Binding::left(Prec::Product),
+            BinaryOp::DoubleEquals | BinaryOp::NotEqual | BinaryOp::Less | BinaryOp::LessOrEqual
+                | BinaryOp::Greater | BinaryOp::GreaterOrEqual => Binding::left(Prec::Compare),
+            BinaryOp::And => Binding::left(Prec::And),
+            BinaryOp::Or => Binding::left(Prec::Or),
+            BinaryOp::BitAnd => Binding::left(Prec::BitAnd),
+            BinaryOp::BitOr => Binding::left(Prec::BitOr),
+            BinaryOp::BitXor => Binding::left(Prec::BitXor),
+            BinaryOp::ShiftLeft | BinaryOp::ShiftRight => Binding::left(Prec::Shift),
+            BinaryOp::Bracket => Binding::left(Prec::Call),
+            BinaryOp::SingleEquals | BinaryOp::PlusEquals | BinaryOp::MinusEquals | BinaryOp::TimesEquals
+                | BinaryOp::DivideEquals | BinaryOp::RemainderEquals | BinaryOp::BitAndEquals | BinaryOp::BitOrEquals
+                | BinaryOp::BitXorEquals | BinaryOp::ShiftLeftEquals | BinaryOp::ShiftRightEquals
+                | BinaryOp::Else => Binding::right(Prec::Expr),
+        }
+    }
+}
+
+impl fmt::Display for BinaryOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", match self {
+            BinaryOp::Plus => "+",
+            BinaryOp::Minus => "-",
+            BinaryOp::Times => "*",
+            BinaryOp::Divide => "/",
+            BinaryOp::Remainder => "%",
+            BinaryOp::DoubleEquals => "==",
+            BinaryOp::NotEqual => "!=",
+            BinaryOp::Less => "<",
+            BinaryOp::LessOrEqual => "<=",
+            BinaryOp::Greater => ">",
+            BinaryOp::GreaterOrEqual => ">=",
+            BinaryOp::And => "&&",
+            BinaryOp::Or => "||",
+            BinaryOp::BitAnd => "&",
+            BinaryOp::BitOr => "|",
+            BinaryOp::BitXor => "^",
+            BinaryOp::ShiftLeft => "<<",
+            BinaryOp::ShiftRight => ">>",
+            BinaryOp::Bracket => "()",
+            BinaryOp::SingleEquals => "=",
+            BinaryOp::PlusEquals => "+=",
+            BinaryOp::MinusEquals => "-=",
+            BinaryOp::TimesEquals => "*=",
+            BinaryOp::DivideEquals => "/=",
+            BinaryOp::RemainderEquals => "%=",
+            BinaryOp::BitAndEquals => "&=",
+            BinaryOp::BitOrEquals => "|=",
+            BinaryOp::BitXorEquals => "^=",
+            BinaryOp::ShiftLeftEquals => "<<=",
+            BinaryOp::ShiftRightEquals => ">>=",
+            BinaryOp::Else => "else",
+        })
+    }
 }
 
 struct Indent(i32);
@@ -165,4 +624,73 @@ impl<'a> fmt::Display for Expr<'a> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         self.write(f, 0)
     }
-}
\ No newline at end of file
+}
+
+/// The whole program, with the comments that are outside of any block.
+impl<'a> fmt::Display for Parsed<'a, Expr<'a>> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for comment in self.trivia.leading.iter().chain(&self.trivia.doc) {
+            writeln!(f, "{}", comment)?;
+        }
+        self.node.write(f, 0)?;
+        for comment in &self.trivia.trailing {
+            write!(f, "\n{}", comment)?;
+        }
+        Ok(())
+    }
+}
+
+/// Renders the tree as parsed as a single JSON object, for tools. Each
+/// declaration carries its doc comments.
+pub fn to_json(expr: &Parsed<Expr>, source: &str) -> String {
+    let mut out = String::new();
+    write_json(&mut out, expr, source);
+    out.push('\n');
+    out
+}
+
+fn write_json(out: &mut String, expr: &Parsed<Expr>, source: &str) {
+    let span = Span::of(source, expr.get_source());
+    let (line, column) = span.line_column(source);
+    let (kind, detail) = match &expr.node {
+        Expr::IntLiteral(src) => ("int", Some(src.to_string())),
+        Expr::FloatLiteral(src) => ("float", Some(src.to_string())),
+        Expr::BoolLiteral(src) => ("bool", Some(src.to_string())),
+        Expr::Ident(src) => ("ident", Some(src.to_string())),
+        Expr::Tuple { .. } => ("tuple", None),
+        Expr::Block { .. } => ("block", None),
+        Expr::Func { name, .. } => ("fn", name.map(str::to_string)),
+        Expr::Binary { op, .. } => ("binary", Some(op.to_string())),
+        Expr::If { .. } => ("if", None),
+        Expr::Match { .. } => ("match", None),
+        Expr::For { label, .. } => ("for", label.map(str::to_string)),
+        Expr::While { label, .. } => ("while", label.map(str::to_string)),
+        Expr::Break { label } => ("break", label.map(str::to_string)),
+        Expr::Struct { .. } => ("struct", None),
+        Expr::Cast { ty, .. } => ("cast", Some(ty.node.to_string())),
+        Expr::Not { .. } => ("not", None),
+        Expr::Field { name, .. } => ("field", Some(name.node.to_string())),
+        Expr::Cfg { name, .. } => ("cfg", Some(name.node.to_string())),
+        Expr::Allow { name, .. } => ("allow", Some(name.node.to_string())),
+        Expr::Defer { .. } => ("defer", None),
+        Expr::InlineIr(text) => ("ir", Some(text.to_string())),
+    };
+    let _ = write!(out, "{{\"kind\":{},\"detail\":", json_string(kind));
+    match detail {
+        Some(detail) => out.push_str(&json_string(&detail)),
+        None => out.push_str("null"),
+    }
+    let _ = write!(out, ",\"start\":{},\"end\":{},\"line\":{},\"column\":{},\"doc\":", span.start, span.end, line, column);
+    match expr.doc() {
+        Some(doc) => out.push_str(&json_string(&doc)),
+        None => out.push_str("null"),
+    }
+    out.push_str(",\"children\":[");
+    for (index, child) in expr.node.children().into_iter().enumerate() {
+        if index > 0 {
+            out.push(',');
+        }
+        write_json(out, child, source);
+    }
+    out.push_str("]}");
+}
@@ -0,0 +1,500 @@
+use crate::hir::CastType;
+use crate::ir::{Block, Effects, Program, Var};
+use crate::scope::Scope;
+use crate::types::Type;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Builtin {
+    Abs,
+    Min,
+    Max,
+    Pow,
+    Sqrt,
+    Floor,
+    Rand,
+    RandRange,
+    Exit,
+    ArgCount,
+    Arg,
+    CheckedAdd,
+    WrappingAdd,
+    SaturatingAdd,
+    Map,
+    Insert,
+    Get,
+    Remove,
+    Len,
+    NowMillis,
+    Sleep,
+    Range,
+    RangeNext,
+    MapNext,
+    Spawn,
+    Yield,
+    Chan,
+    Send,
+    Recv,
+    Print,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Intrinsic {
+    AbsInt,
+    AbsFloat,
+    MinInt,
+    MinUInt,
+    MinFloat,
+    MaxInt,
+    MaxUInt,
+    MaxFloat,
+    PowInt,
+    PowUInt,
+    PowFloat,
+    Sqrt,
+    Floor,
+    Rand,
+    RandRange,
+    ArgCount,
+    ArgInt,
+    CheckedAddInt,
+    CheckedAddUInt,
+    SaturatingAddInt,
+    SaturatingAddUInt,
+    MapNew,
+    MapInsert,
+    MapGet,
+    MapRemove,
+    MapLen,
+    MapNext,
+    RangeNextInt,
+    RangeNextUInt,
+    NowMillis,
+    Sleep,
+    Yield,
+    ChanNew,
+    ChanSend,
+    ChanRecv,
+    PrintInt,
+    PrintUInt,
+    PrintFloat,
+    PrintBool,
+}
+
+/// The shape of the value an intrinsic returns, before it has been given
+/// variables to live in.
+#[derive(Debug, Copy, Clone)]
+enum Returns {
+    Int,
+    UInt,
+    Float,
+    MaybeInt,
+    MaybeUInt,
+    Unit,
+}
+
+const BUILTINS: [Builtin; 30] = [
+    Builtin::Abs,
+    Builtin::Min,
+    Builtin::Max,
+    Builtin::Pow,
+    Builtin::Sqrt,
+    Builtin::Floor,
+    Builtin::Rand,
+    Builtin::RandRange,
+    Builtin::Exit,
+    Builtin::ArgCount,
+    Builtin::Arg,
+    Builtin::CheckedAdd,
+    Builtin::WrappingAdd,
+    Builtin::SaturatingAdd,
+    Builtin::Map,
+    Builtin::Insert,
+    Builtin::Get,
+    Builtin::Remove,
+    Builtin::Len,
+    Builtin::NowMillis,
+    Builtin::Sleep,
+    Builtin::Range,
+    Builtin::RangeNext,
+    Builtin::MapNext,
+    Builtin::Spawn,
+    Builtin::Yield,
+    Builtin::Chan,
+    Builtin::Send,
+    Builtin::Recv,
+    Builtin::Print,
+];
+
+pub fn prelude<'a, 'b>() -> Scope<'a, 'b> {
+    let mut scope = Scope::new();
+    for builtin in BUILTINS.iter() {
+        scope.assign(builtin.name(), Type::Builtin(*builtin));
+    }
+    scope
+}
+
+impl Builtin {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Builtin::Abs => "abs",
+            Builtin::Min => "min",
+            Builtin::Max => "max",
+            Builtin::Pow => "pow",
+            Builtin::Sqrt => "sqrt",
+            Builtin::Floor => "floor",
+            Builtin::Rand => "rand",
+            Builtin::RandRange => "rand_range",
+            Builtin::Exit => "exit",
+            Builtin::ArgCount => "arg_count",
+            Builtin::Arg => "arg",
+            Builtin::CheckedAdd => "checked_add",
+            Builtin::WrappingAdd => "wrapping_add",
+            Builtin::SaturatingAdd => "saturating_add",
+            Builtin::Map => "map",
+            Builtin::Insert => "insert",
+            Builtin::Get => "get",
+            Builtin::Remove => "remove",
+            Builtin::Len => "len",
+            Builtin::NowMillis => "now_millis",
+            Builtin::Sleep => "sleep",
+            Builtin::Range => "range",
+            Builtin::RangeNext => "range_next",
+            Builtin::MapNext => "map_next",
+            Builtin::Spawn => "spawn",
+            Builtin::Yield => "yield",
+            Builtin::Chan => "chan",
+            Builtin::Send => "send",
+            Builtin::Recv => "recv",
+            Builtin::Print => "print",
+        }
+    }
+    fn select(&self, argument_ty: &Type) -> Option<(Intrinsic, Returns)> {
+        Some(match (self, argument_ty) {
+            (Builtin::Abs, Type::Int(_)) => (Intrinsic::AbsInt, Returns::Int),
+            (Builtin::Abs, Type::Float(_)) => (Intrinsic::AbsFloat, Returns::Float),
+            (Builtin::Sqrt, Type::Float(_)) => (Intrinsic::Sqrt, Returns::Float),
+            (Builtin::Floor, Type::Float(_)) => (Intrinsic::Floor, Returns::Float),
+            (Builtin::Rand, Type::Tuple(types)) if types.is_empty() => (Intrinsic::Rand, Returns::Int),
+            (Builtin::ArgCount, Type::Tuple(types)) if types.is_empty() => (Intrinsic::ArgCount, Returns::Int),
+            (Builtin::Arg, Type::Int(_)) => (Intrinsic::ArgInt, Returns::MaybeInt),
+            (Builtin::NowMillis, Type::Tuple(types)) if types.is_empty() => (Intrinsic::NowMillis, Returns::Int),
+            (Builtin::Sleep, Type::Int(_)) => (Intrinsic::Sleep, Returns::Unit),
+            (Builtin::Yield, Type::Tuple(types)) if types.is_empty() => (Intrinsic::Yield, Returns::Unit),
+            (Builtin::Print, Type::Int(_)) => (Intrinsic::PrintInt, Returns::Unit),
+            (Builtin::Print, Type::UInt(_)) => (Intrinsic::PrintUInt, Returns::Unit),
+            (Builtin::Print, Type::Float(_)) => (Intrinsic::PrintFloat, Returns::Unit),
+            (Builtin::Print, Type::Bool(_)) => (Intrinsic::PrintBool, Returns::Unit),
+            (_, Type::Tuple(types)) if types.len() == 2 => {
+                match (self, &types[0], &types[1]) {
+                    (Builtin::Min, Type::Int(_), Type::Int(_)) => (Intrinsic::MinInt, Returns::Int),
+                    (Builtin::Min, Type::UInt(_), Type::UInt(_)) => (Intrinsic::MinUInt, Returns::UInt),
+                    (Builtin::Min, Type::Float(_), Type::Float(_)) => (Intrinsic::MinFloat, Returns::Float),
+                    (Builtin::Max, Type::Int(_), Type::Int(_)) => (Intrinsic::MaxInt, Returns::Int),
+                    (Builtin::Max, Type::UInt(_), Type::UInt(_)) => (Intrinsic::MaxUInt, Returns::UInt),
+                    (Builtin::Max, Type::Float(_), Type::Float(_)) => (Intrinsic::MaxFloat, Returns::Float),
+                    (Builtin::Pow, Type::Int(_), Type::Int(_)) => (Intrinsic::PowInt, Returns::Int),
+                    (Builtin::Pow, Type::UInt(_), Type::UInt(_)) => (Intrinsic::PowUInt, Returns::UInt),
+                    (Builtin::Pow, Type::Float(_), Type::Float(_)) => (Intrinsic::PowFloat, Returns::Float),
+                    (Builtin::RandRange, Type::Int(_), Type::Int(_)) => (Intrinsic::RandRange, Returns::Int),
+                    (Builtin::CheckedAdd, Type::Int(_), Type::Int(_)) => (Intrinsic::CheckedAddInt, Returns::MaybeInt),
+                    (Builtin::CheckedAdd, Type::UInt(_), Type::UInt(_)) => (Intrinsic::CheckedAddUInt, Returns::MaybeUInt),
+                    (Builtin::SaturatingAdd, Type::Int(_), Type::Int(_)) => (Intrinsic::SaturatingAddInt, Returns::Int),
+                    (Builtin::SaturatingAdd, Type::UInt(_), Type::UInt(_)) => (Intrinsic::SaturatingAddUInt, Returns::UInt),
+                    _ => return None,
+                }
+            }
+            _ => return None,
+        })
+    }
+}
+
+impl Returns {
+    fn size(&self) -> usize {
+        match self {
+            Returns::MaybeInt | Returns::MaybeUInt => 2,
+            Returns::Unit => 0,
+            _ => 1,
+        }
+    }
+    fn with_vars<'a, 'b>(&self, vars: &[Var]) -> Type<'a, 'b> {
+        match self {
+            Returns::Int => Type::Int(vars[0]),
+            Returns::UInt => Type::UInt(vars[0]),
+            Returns::Float => Type::Float(vars[0]),
+            Returns::MaybeInt => Type::Maybe(vars[0], Box::new(Type::Int(vars[1]))),
+            Returns::MaybeUInt => Type::Maybe(vars[0], Box::new(Type::UInt(vars[1]))),
+            Returns::Unit => Type::Tuple(vec![]),
+        }
+    }
+}
+
+const INTRINSICS: [Intrinsic; 39] = [
+    Intrinsic::AbsInt,
+    Intrinsic::AbsFloat,
+    Intrinsic::MinInt,
+    Intrinsic::MinUInt,
+    Intrinsic::MinFloat,
+    Intrinsic::MaxInt,
+    Intrinsic::MaxUInt,
+    Intrinsic::MaxFloat,
+    Intrinsic::PowInt,
+    Intrinsic::PowUInt,
+    Intrinsic::PowFloat,
+    Intrinsic::Sqrt,
+    Intrinsic::Floor,
+    Intrinsic::Rand,
+    Intrinsic::RandRange,
+    Intrinsic::ArgCount,
+    Intrinsic::ArgInt,
+    Intrinsic::CheckedAddInt,
+    Intrinsic::CheckedAddUInt,
+    Intrinsic::SaturatingAddInt,
+    Intrinsic::SaturatingAddUInt,
+    Intrinsic::MapNew,
+    Intrinsic::MapInsert,
+    Intrinsic::MapGet,
+    Intrinsic::MapRemove,
+    Intrinsic::MapLen,
+    Intrinsic::MapNext,
+    Intrinsic::RangeNextInt,
+    Intrinsic::RangeNextUInt,
+    Intrinsic::NowMillis,
+    Intrinsic::Sleep,
+    Intrinsic::Yield,
+    Intrinsic::ChanNew,
+    Intrinsic::ChanSend,
+    Intrinsic::ChanRecv,
+    Intrinsic::PrintInt,
+    Intrinsic::PrintUInt,
+    Intrinsic::PrintFloat,
+    Intrinsic::PrintBool,
+];
+
+impl Intrinsic {
+    pub fn from_name(name: &str) -> Option<Intrinsic> {
+        INTRINSICS.iter().copied().find(|intrinsic| intrinsic.name() == name)
+    }
+    pub fn effects(&self) -> Effects {
+        match self {
+            // yielding runs other tasks, which can change the heap
+            Intrinsic::Rand | Intrinsic::MapNew | Intrinsic::MapRemove | Intrinsic::Yield
+            | Intrinsic::ChanNew | Intrinsic::ChanRecv => Effects { writes_state: true, ..Effects::default() },
+            Intrinsic::ArgCount | Intrinsic::ArgInt | Intrinsic::NowMillis | Intrinsic::PrintInt | Intrinsic::PrintUInt
+            | Intrinsic::PrintFloat | Intrinsic::PrintBool => Effects { io: true, ..Effects::default() },
+            // sleeping past a sandbox's deadline stops the program
+            Intrinsic::Sleep => Effects { traps: true, io: true, ..Effects::default() },
+            // an `int` `pow` traps on a negative exponent
+            Intrinsic::PowInt => Effects { traps: true, ..Effects::default() },
+            // an empty range has no value to pick
+            Intrinsic::RandRange => Effects { traps: true, writes_state: true, ..Effects::default() },
+            // inserting can run out of memory in a sandbox
+            Intrinsic::MapInsert => Effects { traps: true, writes_state: true, ..Effects::default() },
+            // sending can wait forever, which stops the program
+            Intrinsic::ChanSend => Effects { traps: true, writes_state: true, ..Effects::default() },
+            _ => Effects::default(),
+        }
+    }
+    pub fn name(&self) -> &'static str {
+        match self {
+            Intrinsic::AbsInt => "abs_int",
+            Intrinsic::AbsFloat => "abs_float",
+            Intrinsic::MinInt => "min_int",
+            Intrinsic::MinUInt => "min_uint",
+            Intrinsic::MinFloat => "min_float",
+            Intrinsic::MaxInt => "max_int",
+            Intrinsic::MaxUInt => "max_uint",
+            Intrinsic::MaxFloat => "max_float",
+            Intrinsic::PowInt => "pow_int",
+            Intrinsic::PowUInt => "pow_uint",
+            Intrinsic::PowFloat => "pow_float",
+            Intrinsic::Sqrt => "sqrt",
+            Intrinsic::Floor => "floor",
+            Intrinsic::Rand => "rand",
+            Intrinsic::RandRange => "rand_range",
+            Intrinsic::ArgCount => "arg_count",
+            Intrinsic::ArgInt => "arg_int",
+            Intrinsic::CheckedAddInt => "checked_add_int",
+            Intrinsic::CheckedAddUInt => "checked_add_uint",
+            Intrinsic::SaturatingAddInt => "saturating_add_int",
+            Intrinsic::SaturatingAddUInt => "saturating_add_uint",
+            Intrinsic::MapNew => "map_new",
+            Intrinsic::MapInsert => "map_insert",
+            Intrinsic::MapGet => "map_get",
+            Intrinsic::MapRemove => "map_remove",
+            Intrinsic::MapLen => "map_len",
+            Intrinsic::MapNext => "map_next",
+            Intrinsic::RangeNextInt => "range_next_int",
+            Intrinsic::RangeNextUInt => "range_next_uint",
+            Intrinsic::NowMillis => "now_millis",
+            Intrinsic::Sleep => "sleep",
+            Intrinsic::Yield => "yield",
+            Intrinsic::ChanNew => "chan_new",
+            Intrinsic::ChanSend => "chan_send",
+            Intrinsic::ChanRecv => "chan_recv",
+            Intrinsic::PrintInt => "print_int",
+            Intrinsic::PrintUInt => "print_uint",
+            Intrinsic::PrintFloat => "print_float",
+            Intrinsic::PrintBool => "print_bool",
+        }
+    }
+}
+
+pub fn call_builtin<'a, 'b>(builtin: Builtin, argument_ty: &Type<'a, 'b>, program: &mut Program, block: &mut Block) -> Option<Type<'a, 'b>> {
+    if let (Builtin::Exit, Type::Int(code)) = (builtin, argument_ty) {
+        block.exit(*code, program);
+        return Some(Type::Tuple(vec![]))
+    }
+    if let Some(ty) = call_map_builtin(builtin, argument_ty, program, block) {
+        return ty
+    }
+    if let Some(ty) = call_iterator_builtin(builtin, argument_ty, program, block) {
+        return ty
+    }
+    if let Some(ty) = call_channel_builtin(builtin, argument_ty, program, block) {
+        return ty
+    }
+    // `+` already wraps, so this is the add instruction itself
+    if let (Builtin::WrappingAdd, Type::Tuple(types)) = (builtin, argument_ty) {
+        return match types.as_slice() {
+            [Type::Int(a), Type::Int(b)] => Some(Type::Int(block.add_int(*a, *b, program))),
+            [Type::UInt(a), Type::UInt(b)] => Some(Type::UInt(block.add_int(*a, *b, program))),
+            _ => None,
+        }
+    }
+    let (intrinsic, return_ty) = builtin.select(argument_ty)?;
+    let returns = block.call_builtin(intrinsic, argument_ty.get_used_vars(), return_ty.size(), program);
+    Some(return_ty.with_vars(&returns))
+}
+
+/// Calls one of the map builtins, or returns `None` if `builtin` is not
+/// one. A map's key and value types are set by the first `insert`, which
+/// returns the map with them, and every other use of the map must match.
+fn call_map_builtin<'a, 'b>(builtin: Builtin, argument_ty: &Type<'a, 'b>, program: &mut Program, block: &mut Block) -> Option<Option<Type<'a, 'b>>> {
+    let args = arguments(argument_ty);
+    Some(match (builtin, args.as_slice()) {
+        (Builtin::Map, []) => Some(Type::Map(block.call_builtin(Intrinsic::MapNew, vec![], 1, program)[0], None)),
+        (Builtin::Insert, [Type::Map(map, types), key, value]) => match (scalar(key), scalar(value)) {
+            (Some((key, key_ty)), Some((value, value_ty))) if types.is_none_or(|types| types == (key_ty, value_ty)) => {
+                block.call_builtin(Intrinsic::MapInsert, vec![*map, key, value], 0, program);
+                Some(Type::Map(*map, Some((key_ty, value_ty))))
+            }
+            _ => None,
+        }
+        (Builtin::Get | Builtin::Remove, [Type::Map(map, Some((key_ty, value_ty))), key]) => {
+            match scalar(key) {
+                Some((key, ty)) if ty == *key_ty => {
+                    let intrinsic = if builtin == Builtin::Get { Intrinsic::MapGet } else { Intrinsic::MapRemove };
+                    let returns = block.call_builtin(intrinsic, vec![*map, key], 2, program);
+                    Some(Type::Maybe(returns[0], Box::new(typed(returns[1], *value_ty))))
+                }
+                _ => None,
+            }
+        }
+        (Builtin::Len, [Type::Map(map, _)]) => Some(Type::Int(block.call_builtin(Intrinsic::MapLen, vec![*map], 1, program)[0])),
+        (Builtin::Map | Builtin::Insert | Builtin::Get | Builtin::Remove | Builtin::Len, _) => None,
+        _ => return None,
+    })
+}
+
+/// Calls one of the channel builtins, or returns `None` if `builtin` is not
+/// one. As with maps, the type of a channel's values is set by the first
+/// `send`, which returns the channel with it.
+fn call_channel_builtin<'a, 'b>(builtin: Builtin, argument_ty: &Type<'a, 'b>, program: &mut Program, block: &mut Block) -> Option<Option<Type<'a, 'b>>> {
+    let args = arguments(argument_ty);
+    Some(match (builtin, args.as_slice()) {
+        (Builtin::Chan, [Type::Int(capacity)]) => Some(Type::Chan(block.call_builtin(Intrinsic::ChanNew, vec![*capacity], 1, program)[0], None)),
+        (Builtin::Send, [Type::Chan(channel, ty), value]) => match scalar(value) {
+            Some((value, value_ty)) if ty.is_none_or(|ty| ty == value_ty) => {
+                block.call_builtin(Intrinsic::ChanSend, vec![*channel, value], 0, program);
+                Some(Type::Chan(*channel, Some(value_ty)))
+            }
+            _ => None,
+        }
+        (Builtin::Recv, [Type::Chan(channel, Some(ty))]) => {
+            let returns = block.call_builtin(Intrinsic::ChanRecv, vec![*channel], 2, program);
+            Some(Type::Maybe(returns[0], Box::new(typed(returns[1], *ty))))
+        }
+        (Builtin::Chan | Builtin::Send | Builtin::Recv, _) => None,
+        _ => return None,
+    })
+}
+
+/// Calls one of the builtins that make up the iterator protocol, or returns
+/// `None` if `builtin` is not one. An iterator is a `(next, state)` pair,
+/// where `next(state)` gives `maybe (value, state)`.
+fn call_iterator_builtin<'a, 'b>(builtin: Builtin, argument_ty: &Type<'a, 'b>, program: &mut Program, block: &mut Block) -> Option<Option<Type<'a, 'b>>> {
+    let args = arguments(argument_ty);
+    Some(match (builtin, args.as_slice()) {
+        (Builtin::Range, [Type::Int(_), Type::Int(_)] | [Type::UInt(_), Type::UInt(_)]) => {
+            Some(Type::Tuple(vec![Type::Builtin(Builtin::RangeNext), argument_ty.clone()]))
+        }
+        (Builtin::RangeNext, [start @ (Type::Int(_) | Type::UInt(_)), end]) if start == end => {
+            let (start, end) = (start.get_used_vars()[0], end.get_used_vars()[0]);
+            let intrinsic = if let Type::Int(_) = args[0] { Intrinsic::RangeNextInt } else { Intrinsic::RangeNextUInt };
+            let more = block.call_builtin(intrinsic, vec![start, end], 1, program)[0];
+            let one = block.constant_int(1, program);
+            let next = block.add_int(start, one, program);
+            let state = Type::Tuple(vec![args[0].map_to(&[next]), args[1].clone()]);
+            Some(Type::Maybe(more, Box::new(Type::Tuple(vec![args[0].clone(), state]))))
+        }
+        (Builtin::MapNext, [map @ Type::Map(handle, Some((key_ty, value_ty))), Type::Bool(started), last]) if scalar(last).map(|(_, ty)| ty) == Some(*key_ty) => {
+            let last = last.get_used_vars()[0];
+            let returns = block.call_builtin(Intrinsic::MapNext, vec![*handle, *started, last], 3, program);
+            let entry = Type::Tuple(vec![typed(returns[1], *key_ty), typed(returns[2], *value_ty)]);
+            let state = Type::Tuple(vec![(*map).clone(), Type::Bool(block.constant_int(1, program)), typed(returns[1], *key_ty)]);
+            Some(Type::Maybe(returns[0], Box::new(Type::Tuple(vec![entry, state]))))
+        }
+        (Builtin::Range | Builtin::RangeNext | Builtin::MapNext, _) => None,
+        _ => return None,
+    })
+}
+
+/// The `(next, state)` pair that a `for` loop over a value of `ty` steps
+/// through, or `None` if it cannot be looped over. A map starts before its
+/// first key, and its entries come in the order of the bits of their keys.
+pub fn iterator<'a, 'b>(ty: &Type<'a, 'b>, program: &mut Program, block: &mut Block) -> Option<(Type<'a, 'b>, Type<'a, 'b>)> {
+    match ty {
+        Type::Map(_, Some((key_ty, _))) => {
+            let started = Type::Bool(block.constant_int(0, program));
+            let last = typed(block.constant_int(0, program), *key_ty);
+            Some((Type::Builtin(Builtin::MapNext), Type::Tuple(vec![ty.clone(), started, last])))
+        }
+        Type::Tuple(types) => match types.as_slice() {
+            [next @ (Type::Func { .. } | Type::Builtin(_)), state] => Some((next.clone(), state.clone())),
+            _ => None,
+        }
+        _ => None,
+    }
+}
+
+/// The arguments of a call, which are nested to the right: `f(a, b, c)`
+/// passes `(a, (b, c))`.
+fn arguments<'c, 'a, 'b>(ty: &'c Type<'a, 'b>) -> Vec<&'c Type<'a, 'b>> {
+    match ty {
+        Type::Tuple(types) => match types.split_last() {
+            Some((last @ Type::Tuple(rest), init)) if !rest.is_empty() => init.iter().chain(arguments(last)).collect(),
+            _ => types.iter().collect(),
+        }
+        ty => vec![ty],
+    }
+}
+
+/// The register and type of a value that fits in one register.
+fn scalar(ty: &Type) -> Option<(Var, CastType)> {
+    match ty {
+        Type::Int(var) => Some((*var, CastType::Int)),
+        Type::UInt(var) => Some((*var, CastType::UInt)),
+        Type::Float(var) => Some((*var, CastType::Float)),
+        Type::Bool(var) => Some((*var, CastType::Bool)),
+        _ => None,
+    }
+}
+
+fn typed<'a, 'b>(var: Var, ty: CastType) -> Type<'a, 'b> {
+    match ty {
+        CastType::Int => Type::Int(var),
+        CastType::UInt => Type::UInt(var),
+        CastType::Float => Type::Float(var),
+        CastType::Bool => Type::Bool(var),
+    }
+}
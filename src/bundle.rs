@@ -0,0 +1,93 @@
+//! Executables that run one program, made by appending the program's
+//! bytecode to a copy of this executable. At startup the executable looks
+//! at its own end, and runs the program it finds there instead of reading
+//! its arguments as a command.
+//!
+//! The end of a bundle is the bytecode, a byte that is 1 if the program
+//! was compiled as a script, the length of the bytecode as a little endian
+//! u64, and `MAGIC`.
+
+use std::convert::TryInto;
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+use std::env;
+
+const MAGIC: &[u8; 8] = b"LANGBNDL";
+const TRAILER_LEN: u64 = 1 + 8 + MAGIC.len() as u64;
+
+/// A program found at the end of an executable.
+pub struct Bundled {
+    pub bytecode: Vec<u8>,
+    pub script: bool,
+}
+
+/// The program bundled into the running executable, if it is a bundle.
+pub fn load() -> Option<Bundled> {
+    read(&env::current_exe().ok()?).ok()?
+}
+
+/// The program bundled into the executable at `path`, or `None` if it is
+/// not a bundle.
+pub fn read(path: &Path) -> io::Result<Option<Bundled>> {
+    let mut file = File::open(path)?;
+    let (start, script) = match payload(&mut file)? {
+        Some(payload) => payload,
+        None => return Ok(None),
+    };
+    let len = file.seek(SeekFrom::End(-(TRAILER_LEN as i64)))? - start;
+    let mut bytecode = vec![0; len as usize];
+    file.seek(SeekFrom::Start(start))?;
+    file.read_exact(&mut bytecode)?;
+    Ok(Some(Bundled { bytecode, script }))
+}
+
+/// Writes an executable to `path` that runs `bytecode`. The executable is
+/// this one without any program already bundled into it.
+pub fn write(path: &Path, bytecode: &[u8], script: bool) -> io::Result<()> {
+    let mut exe = File::open(env::current_exe()?)?;
+    let len = match payload(&mut exe)? {
+        Some((start, _)) => start,
+        None => exe.seek(SeekFrom::End(0))?,
+    };
+    let mut bytes = Vec::with_capacity(len as usize + bytecode.len() + TRAILER_LEN as usize);
+    exe.seek(SeekFrom::Start(0))?;
+    exe.take(len).read_to_end(&mut bytes)?;
+    bytes.extend_from_slice(bytecode);
+    bytes.push(script as u8);
+    bytes.extend_from_slice(&(bytecode.len() as u64).to_le_bytes());
+    bytes.extend_from_slice(MAGIC);
+    fs::write(path, bytes)?;
+    make_executable(path)
+}
+
+/// Where the bundled bytecode of `file` starts and whether it is a script,
+/// or `None` if `file` does not end with a bundle.
+fn payload(file: &mut File) -> io::Result<Option<(u64, bool)>> {
+    let size = file.seek(SeekFrom::End(0))?;
+    if size < TRAILER_LEN {
+        return Ok(None)
+    }
+    let mut trailer = [0; TRAILER_LEN as usize];
+    file.seek(SeekFrom::End(-(TRAILER_LEN as i64)))?;
+    file.read_exact(&mut trailer)?;
+    if &trailer[9..] != MAGIC {
+        return Ok(None)
+    }
+    let len = u64::from_le_bytes(trailer[1..9].try_into().unwrap());
+    match (size - TRAILER_LEN).checked_sub(len) {
+        Some(start) => Ok(Some((start, trailer[0] == 1))),
+        None => Ok(None),
+    }
+}
+
+#[cfg(unix)]
+fn make_executable(path: &Path) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o755))
+}
+
+#[cfg(not(unix))]
+fn make_executable(_: &Path) -> io::Result<()> {
+    Ok(())
+}
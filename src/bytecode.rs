@@ -0,0 +1,544 @@
+//! A binary encoding of compiled programs, so that a program can be run
+//! again without compiling its source.
+//!
+//! Everything is little endian. A file starts with `MAGIC`, `VERSION` and
+//! a hash of the rest of the file, then the entry function, the variable
+//! count and the functions, and ends with the shape of the value the entry
+//! function returns. Encoding the same program always gives the same
+//! bytes, so the hash identifies the program.
+
+use crate::builtins::Intrinsic;
+use crate::cache::Fnv;
+use crate::hir::CastType;
+use crate::ir::{Block, BlockId, Conversion, Effects, ExitInstruction, Function, FunctionId, Instruction, PrintOptions, Program, Var};
+use crate::types::Type;
+use std::convert::TryInto;
+
+const MAGIC: &[u8; 4] = b"LANG";
+/// Bumped whenever the encoding changes, so that older files are rejected
+/// rather than misread.
+const VERSION: u32 = 10;
+const HEADER_LEN: usize = MAGIC.len() + 4 + 8;
+
+struct Writer {
+    bytes: Vec<u8>,
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+/// Encodes `program`, leaving out the functions `entry` never reaches, or
+/// returns `None` if the entry function returns a function, which only
+/// exists while compiling.
+pub fn encode(program: &Program, entry: FunctionId, ty: &Type) -> Option<Vec<u8>> {
+    let (program, entry) = program.reachable(entry);
+    let mut out = Writer { bytes: Vec::new() };
+    out.bytes.extend_from_slice(MAGIC);
+    out.u32(VERSION);
+    out.bytes.extend_from_slice(&[0; 8]);
+    out.usize(entry.get_id());
+    out.usize(program.get_variable_count());
+    out.usize(program.get_function_count());
+    for id in program.get_function_ids() {
+        write_function(&mut out, program.get_function(id));
+    }
+    write_shape(&mut out, ty)?;
+    let hash = hash(&out.bytes[HEADER_LEN..]);
+    out.bytes[HEADER_LEN - 8..HEADER_LEN].copy_from_slice(&hash.to_le_bytes());
+    Some(out.bytes)
+}
+
+/// The hash in the header of `bytes`, or `None` if they are not a program
+/// or do not match their hash.
+pub fn content_hash(bytes: &[u8]) -> Option<u64> {
+    let mut input = Reader { bytes };
+    if input.take(4)? != MAGIC || input.u32()? != VERSION {
+        return None
+    }
+    let stamped = u64::from_le_bytes(input.take(8)?.try_into().unwrap());
+    match hash(input.bytes) == stamped {
+        true => Some(stamped),
+        false => None,
+    }
+}
+
+fn hash(bytes: &[u8]) -> u64 {
+    let mut hash = Fnv::new();
+    hash.write(bytes);
+    hash.finish()
+}
+
+/// Decodes a program written by `encode`, returning `None` if the bytes are
+/// not one, were written by a different version of the compiler, or have
+/// changed since they were written.
+pub fn decode<'a, 'b>(bytes: &[u8]) -> Option<(Program, FunctionId, Type<'a, 'b>)> {
+    content_hash(bytes)?;
+    let mut input = Reader { bytes: &bytes[HEADER_LEN..] };
+    let entry = input.usize()?;
+    let variable_count = input.usize()?;
+    let function_count = input.usize()?;
+    let mut functions = Vec::new();
+    for _ in 0..function_count {
+        functions.push(read_function(&mut input)?);
+    }
+    let entry_function = functions.get(entry)?;
+    let mut returns = entry_function.get_returns().iter().copied();
+    let ty = read_shape(&mut input, &mut returns)?;
+    if !input.bytes.is_empty() || returns.next().is_some() {
+        return None
+    }
+    // a register out of range would only be found when running the program
+    let program = Program::from_parts(functions, variable_count);
+    program.verify().ok()?;
+    Some((program, FunctionId::new(entry), ty))
+}
+
+/// The program in `bytes`, written by `encode`, as text: a header with the
+/// version, the hash, the entry function and what it returns, a line for
+/// each function, then the functions as `--emit ir` prints them, without
+/// the source. Constants are operands of instructions, so there is no
+/// table of them. Returns `None` if `decode` would.
+pub fn disassemble(bytes: &[u8]) -> Option<String> {
+    let hash = content_hash(bytes)?;
+    let (program, entry, ty) = decode(bytes)?;
+    let plural = |count: usize, noun: &str| format!("{} {}{}", count, noun, if count == 1 { "" } else { "s" });
+    let mut out = format!("; version {}, hash {:016x}\n; entry f{}, returning {}\n", VERSION, hash, entry.get_id(), ty);
+    for id in program.get_function_ids() {
+        let function = program.get_function(id);
+        let insts = function.get_blocks().iter().map(|block| block.get_instructions().len()).sum();
+        let effects = function.get_effects();
+        let effects = if effects.is_pure() { ", pure" } else if effects.io { ", io" } else { "" };
+        out.push_str(&format!("; f{}: {}, {}, {}, {}{}\n", id.get_id(), plural(function.get_params().len(), "parameter"),
+            plural(function.get_returns().len(), "result"), plural(function.get_blocks().len(), "block"), plural(insts, "instruction"), effects));
+    }
+    out.push('\n');
+    out.push_str(&program.display(PrintOptions { types: true, align: true, source: None }).to_string());
+    Some(out)
+}
+
+fn write_function(out: &mut Writer, function: &Function) {
+    out.vars(function.get_params());
+    out.vars(function.get_returns());
+    out.effects(function.get_effects());
+    out.usize(function.get_blocks().len());
+    for block in function.get_blocks() {
+        out.effects(block.get_effects());
+        out.usize(block.get_instructions().len());
+        for (index, inst) in block.get_instructions().iter().enumerate() {
+            out.u32(block.get_line(index));
+            write_instruction(out, inst);
+        }
+        match block.get_exit_instruction() {
+            ExitInstruction::Branch { block } => {
+                out.u8(0);
+                out.usize(block.get_id());
+            }
+            ExitInstruction::ConditionalBranch { cond, block1, block2 } => {
+                out.u8(1);
+                out.var(*cond);
+                out.usize(block1.get_id());
+                out.usize(block2.get_id());
+            }
+            ExitInstruction::Return => out.u8(2),
+            ExitInstruction::Switch { scrutinee, targets, default } => {
+                out.u8(3);
+                out.var(*scrutinee);
+                out.usize(targets.len());
+                for target in targets {
+                    out.usize(target.get_id());
+                }
+                out.usize(default.get_id());
+            }
+        }
+    }
+}
+
+fn read_function(input: &mut Reader) -> Option<Function> {
+    let params = input.vars()?;
+    let returns = input.vars()?;
+    let effects = input.effects()?;
+    let block_count = input.usize()?;
+    let mut blocks = Vec::new();
+    for id in 0..block_count {
+        let block_effects = input.effects()?;
+        let inst_count = input.usize()?;
+        let mut insts = Vec::new();
+        let mut lines = Vec::new();
+        for _ in 0..inst_count {
+            lines.push(input.u32()?);
+            insts.push(read_instruction(input)?);
+        }
+        let exit = match input.u8()? {
+            0 => ExitInstruction::Branch { block: BlockId::new(input.usize()?) },
+            1 => ExitInstruction::ConditionalBranch {
+                cond: input.var()?,
+                block1: BlockId::new(input.usize()?),
+                block2: BlockId::new(input.usize()?),
+            },
+            2 => ExitInstruction::Return,
+            3 => {
+                let scrutinee = input.var()?;
+                let len = input.usize()?;
+                // as with variables, every target takes four bytes
+                if len > input.bytes.len() / 4 {
+                    return None
+                }
+                let targets = (0..len).map(|_| Some(BlockId::new(input.usize()?))).collect::<Option<_>>()?;
+                ExitInstruction::Switch { scrutinee, targets, default: BlockId::new(input.usize()?) }
+            }
+            _ => return None,
+        };
+        blocks.push(Block::from_parts(id, insts, lines, exit, block_effects));
+    }
+    Some(Function::from_parts(params, returns, blocks, effects))
+}
+
+fn write_instruction(out: &mut Writer, inst: &Instruction) {
+    match inst {
+        Instruction::AddInt { dest, a, b } => out.binary(0, *dest, *a, *b),
+        Instruction::AddFloat { dest, a, b } => out.binary(1, *dest, *a, *b),
+        Instruction::AndInt { dest, a, b } => out.binary(2, *dest, *a, *b),
+        Instruction::OrInt { dest, a, b } => out.binary(3, *dest, *a, *b),
+        Instruction::XorInt { dest, a, b } => out.binary(4, *dest, *a, *b),
+        Instruction::ShiftLeft { dest, a, b } => out.binary(5, *dest, *a, *b),
+        Instruction::ShiftRightArithmetic { dest, a, b } => out.binary(6, *dest, *a, *b),
+        Instruction::ShiftRightLogical { dest, a, b } => out.binary(7, *dest, *a, *b),
+        Instruction::ConstantInt { dest, constant } => {
+            out.u8(8);
+            out.var(*dest);
+            out.u32(*constant as u32);
+        }
+        Instruction::ConstantFloat { dest, constant } => {
+            out.u8(9);
+            out.var(*dest);
+            out.u32(constant.to_bits());
+        }
+        Instruction::Convert { dest, src, conversion } => {
+            out.u8(10);
+            out.var(*dest);
+            out.var(*src);
+            out.u8(match conversion {
+                Conversion::IntToFloat => 0,
+                Conversion::UIntToFloat => 1,
+                Conversion::FloatToInt => 2,
+                Conversion::FloatToUInt => 3,
+            });
+        }
+        Instruction::Select { cond, a, b, dest } => {
+            out.u8(11);
+            out.var(*cond);
+            out.var(*a);
+            out.var(*b);
+            out.var(*dest);
+        }
+        Instruction::Call { function, args, returns } => {
+            out.u8(12);
+            out.usize(function.get_id());
+            out.vars(args);
+            out.vars(returns);
+        }
+        Instruction::CallBuiltin { intrinsic, args, returns } => {
+            out.u8(13);
+            out.str(intrinsic.name());
+            out.vars(args);
+            out.vars(returns);
+        }
+        Instruction::Exit { code } => {
+            out.u8(14);
+            out.var(*code);
+        }
+        Instruction::Abort => out.u8(15),
+        Instruction::Copy { dest, src } => {
+            out.u8(16);
+            out.var(*dest);
+            out.var(*src);
+        }
+        Instruction::Spawn { function } => {
+            out.u8(17);
+            out.usize(function.get_id());
+        }
+        Instruction::SubInt { dest, a, b } => out.binary(18, *dest, *a, *b),
+        Instruction::SubFloat { dest, a, b } => out.binary(19, *dest, *a, *b),
+        Instruction::MulInt { dest, a, b } => out.binary(20, *dest, *a, *b),
+        Instruction::MulFloat { dest, a, b } => out.binary(21, *dest, *a, *b),
+        Instruction::DivInt { dest, a, b } => out.binary(22, *dest, *a, *b),
+        Instruction::DivUInt { dest, a, b } => out.binary(23, *dest, *a, *b),
+        Instruction::DivFloat { dest, a, b } => out.binary(24, *dest, *a, *b),
+        Instruction::RemInt { dest, a, b } => out.binary(25, *dest, *a, *b),
+        Instruction::RemUInt { dest, a, b } => out.binary(26, *dest, *a, *b),
+        Instruction::RemFloat { dest, a, b } => out.binary(27, *dest, *a, *b),
+        Instruction::EqInt { dest, a, b } => out.binary(28, *dest, *a, *b),
+        Instruction::EqFloat { dest, a, b } => out.binary(29, *dest, *a, *b),
+        Instruction::LessInt { dest, a, b } => out.binary(30, *dest, *a, *b),
+        Instruction::LessUInt { dest, a, b } => out.binary(31, *dest, *a, *b),
+        Instruction::LessFloat { dest, a, b } => out.binary(32, *dest, *a, *b),
+        Instruction::LessEqInt { dest, a, b } => out.binary(33, *dest, *a, *b),
+        Instruction::LessEqUInt { dest, a, b } => out.binary(34, *dest, *a, *b),
+        Instruction::LessEqFloat { dest, a, b } => out.binary(35, *dest, *a, *b),
+        Instruction::Phi { dest, sources } => {
+            out.u8(36);
+            out.var(*dest);
+            out.usize(sources.len());
+            for (block, var) in sources {
+                out.usize(block.get_id());
+                out.var(*var);
+            }
+        }
+    }
+}
+
+fn read_instruction(input: &mut Reader) -> Option<Instruction> {
+    let tag = input.u8()?;
+    Some(match tag {
+        0..=7 | 18..=35 => {
+            let (dest, a, b) = (input.var()?, input.var()?, input.var()?);
+            match tag {
+                0 => Instruction::AddInt { dest, a, b },
+                1 => Instruction::AddFloat { dest, a, b },
+                2 => Instruction::AndInt { dest, a, b },
+                3 => Instruction::OrInt { dest, a, b },
+                4 => Instruction::XorInt { dest, a, b },
+                5 => Instruction::ShiftLeft { dest, a, b },
+                6 => Instruction::ShiftRightArithmetic { dest, a, b },
+                7 => Instruction::ShiftRightLogical { dest, a, b },
+                18 => Instruction::SubInt { dest, a, b },
+                19 => Instruction::SubFloat { dest, a, b },
+                20 => Instruction::MulInt { dest, a, b },
+                21 => Instruction::MulFloat { dest, a, b },
+                22 => Instruction::DivInt { dest, a, b },
+                23 => Instruction::DivUInt { dest, a, b },
+                24 => Instruction::DivFloat { dest, a, b },
+                25 => Instruction::RemInt { dest, a, b },
+                26 => Instruction::RemUInt { dest, a, b },
+                27 => Instruction::RemFloat { dest, a, b },
+                28 => Instruction::EqInt { dest, a, b },
+                29 => Instruction::EqFloat { dest, a, b },
+                30 => Instruction::LessInt { dest, a, b },
+                31 => Instruction::LessUInt { dest, a, b },
+                32 => Instruction::LessFloat { dest, a, b },
+                33 => Instruction::LessEqInt { dest, a, b },
+                34 => Instruction::LessEqUInt { dest, a, b },
+                _ => Instruction::LessEqFloat { dest, a, b },
+            }
+        }
+        8 => Instruction::ConstantInt { dest: input.var()?, constant: input.u32()? as i32 },
+        9 => Instruction::ConstantFloat { dest: input.var()?, constant: f32::from_bits(input.u32()?) },
+        10 => Instruction::Convert {
+            dest: input.var()?,
+            src: input.var()?,
+            conversion: match input.u8()? {
+                0 => Conversion::IntToFloat,
+                1 => Conversion::UIntToFloat,
+                2 => Conversion::FloatToInt,
+                3 => Conversion::FloatToUInt,
+                _ => return None,
+            },
+        },
+        11 => Instruction::Select { cond: input.var()?, a: input.var()?, b: input.var()?, dest: input.var()? },
+        12 => Instruction::Call { function: FunctionId::new(input.usize()?), args: input.vars()?, returns: input.vars()? },
+        13 => Instruction::CallBuiltin { intrinsic: Intrinsic::from_name(&input.str()?)?, args: input.vars()?, returns: input.vars()? },
+        14 => Instruction::Exit { code: input.var()? },
+        15 => Instruction::Abort,
+        16 => Instruction::Copy { dest: input.var()?, src: input.var()? },
+        17 => Instruction::Spawn { function: FunctionId::new(input.usize()?) },
+        36 => {
+            let dest = input.var()?;
+            let len = input.usize()?;
+            // as for `vars`, each source takes eight bytes
+            if len > input.bytes.len() / 8 {
+                return None
+            }
+            let sources = (0..len).map(|_| Some((BlockId::new(input.usize()?), input.var()?))).collect::<Option<_>>()?;
+            Instruction::Phi { dest, sources }
+        }
+        _ => return None,
+    })
+}
+
+/// The structure of a type without its variables, which are the returns of
+/// the entry function in order.
+fn write_shape(out: &mut Writer, ty: &Type) -> Option<()> {
+    match ty {
+        Type::Int(_) => out.u8(0),
+        Type::UInt(_) => out.u8(1),
+        Type::Float(_) => out.u8(2),
+        Type::Bool(_) => out.u8(3),
+        Type::Maybe(_, ty) => {
+            out.u8(4);
+            write_shape(out, ty)?;
+        }
+        Type::Tuple(types) => {
+            out.u8(5);
+            out.usize(types.len());
+            for ty in types {
+                write_shape(out, ty)?;
+            }
+        }
+        Type::Struct(fields) => {
+            out.u8(8);
+            out.usize(fields.len());
+            for (name, ty) in fields {
+                out.str(name);
+                write_shape(out, ty)?;
+            }
+        }
+        Type::Map(_, types) => {
+            out.u8(6);
+            match types {
+                Some((key, value)) => {
+                    out.u8(cast_type_tag(*key));
+                    out.u8(cast_type_tag(*value));
+                }
+                None => out.u8(4),
+            }
+        }
+        Type::Chan(_, ty) => {
+            out.u8(7);
+            out.u8(ty.map_or(4, cast_type_tag));
+        }
+        Type::Func { .. } | Type::Builtin(_) => return None,
+    }
+    Some(())
+}
+
+fn read_shape<'a, 'b>(input: &mut Reader, vars: &mut impl Iterator<Item = Var>) -> Option<Type<'a, 'b>> {
+    Some(match input.u8()? {
+        0 => Type::Int(vars.next()?),
+        1 => Type::UInt(vars.next()?),
+        2 => Type::Float(vars.next()?),
+        3 => Type::Bool(vars.next()?),
+        4 => {
+            let tag = vars.next()?;
+            Type::Maybe(tag, Box::new(read_shape(input, vars)?))
+        }
+        5 => {
+            let len = input.usize()?;
+            let mut types = Vec::new();
+            for _ in 0..len {
+                types.push(read_shape(input, vars)?);
+            }
+            Type::Tuple(types)
+        }
+        6 => {
+            let handle = vars.next()?;
+            let types = match input.u8()? {
+                4 => None,
+                key => Some((read_cast_type(key)?, read_cast_type(input.u8()?)?)),
+            };
+            Type::Map(handle, types)
+        }
+        7 => {
+            let handle = vars.next()?;
+            let ty = match input.u8()? {
+                4 => None,
+                ty => Some(read_cast_type(ty)?),
+            };
+            Type::Chan(handle, ty)
+        }
+        8 => {
+            let len = input.usize()?;
+            let mut fields = Vec::new();
+            for _ in 0..len {
+                let name = input.str()?;
+                fields.push((name.into(), read_shape(input, vars)?));
+            }
+            Type::Struct(fields)
+        }
+        _ => return None,
+    })
+}
+
+/// The types a map can hold are tagged as their shapes are.
+fn cast_type_tag(ty: CastType) -> u8 {
+    match ty {
+        CastType::Int => 0,
+        CastType::UInt => 1,
+        CastType::Float => 2,
+        CastType::Bool => 3,
+    }
+}
+
+fn read_cast_type(tag: u8) -> Option<CastType> {
+    match tag {
+        0 => Some(CastType::Int),
+        1 => Some(CastType::UInt),
+        2 => Some(CastType::Float),
+        3 => Some(CastType::Bool),
+        _ => None,
+    }
+}
+
+impl Writer {
+    fn u8(&mut self, value: u8) {
+        self.bytes.push(value)
+    }
+    fn u32(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes())
+    }
+    fn usize(&mut self, value: usize) {
+        self.u32(value as u32)
+    }
+    fn var(&mut self, var: Var) {
+        self.usize(var.get_id())
+    }
+    fn vars(&mut self, vars: &[Var]) {
+        self.usize(vars.len());
+        for var in vars {
+            self.var(*var);
+        }
+    }
+    fn str(&mut self, value: &str) {
+        self.usize(value.len());
+        self.bytes.extend_from_slice(value.as_bytes())
+    }
+    fn effects(&mut self, effects: Effects) {
+        self.u8(effects.traps as u8 | (effects.writes_state as u8) << 1 | (effects.io as u8) << 2)
+    }
+    fn binary(&mut self, tag: u8, dest: Var, a: Var, b: Var) {
+        self.u8(tag);
+        self.var(dest);
+        self.var(a);
+        self.var(b);
+    }
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        if self.bytes.len() < len {
+            return None
+        }
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Some(taken)
+    }
+    fn u8(&mut self) -> Option<u8> {
+        Some(self.take(1)?[0])
+    }
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.take(4)?.try_into().ok()?))
+    }
+    fn usize(&mut self) -> Option<usize> {
+        Some(self.u32()? as usize)
+    }
+    fn var(&mut self) -> Option<Var> {
+        Some(Var::new(self.usize()?))
+    }
+    fn vars(&mut self) -> Option<Vec<Var>> {
+        let len = self.usize()?;
+        // every variable takes four bytes, so a length longer than the rest
+        // of the input is never allocated for
+        if len > self.bytes.len() / 4 {
+            return None
+        }
+        (0..len).map(|_| self.var()).collect()
+    }
+    fn str(&mut self) -> Option<String> {
+        let len = self.usize()?;
+        String::from_utf8(self.take(len)?.to_vec()).ok()
+    }
+    fn effects(&mut self) -> Option<Effects> {
+        let bits = self.u8()?;
+        Some(Effects { traps: bits & 1 != 0, writes_state: bits & 2 != 0, io: bits & 4 != 0 })
+    }
+}
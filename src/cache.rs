@@ -0,0 +1,86 @@
+//! Compiled programs kept on disk between runs, keyed by a hash of
+//! everything that decides what compiling produces, so that running an
+//! unchanged file skips the compiler.
+
+use crate::bytecode;
+use crate::ir::{FunctionId, Program};
+use crate::session::CompileOptions;
+use crate::types::Type;
+use std::path::{Path, PathBuf};
+use std::{fs, io};
+
+/// Where build output goes, relative to the project root.
+pub const BUILD_DIR: &str = "build";
+
+pub struct Cache {
+    dir: PathBuf,
+}
+
+impl Cache {
+    pub fn new(root: &Path) -> Cache {
+        Cache { dir: root.join(BUILD_DIR).join("cache") }
+    }
+    /// The key of a compilation of `source`. The compiler's version is part
+    /// of it, so a new compiler never runs what an older one wrote.
+    pub fn key(source: &str, options: &CompileOptions) -> u64 {
+        let mut hash = Fnv::new();
+        hash.write(env!("CARGO_PKG_VERSION").as_bytes());
+        hash.write(&[0]);
+        hash.write(format!("{:?}", options).as_bytes());
+        hash.write(&[0]);
+        hash.write(source.as_bytes());
+        hash.finish()
+    }
+    fn path(&self, key: u64) -> PathBuf {
+        self.dir.join(format!("{:016x}.bc", key))
+    }
+    /// The program stored under `key`. A file that cannot be read or decoded
+    /// is treated as missing, and is replaced when the program is stored.
+    pub fn load<'a, 'b>(&self, key: u64) -> Option<(Program, FunctionId, Type<'a, 'b>)> {
+        bytecode::decode(&fs::read(self.path(key)).ok()?)
+    }
+    /// Stores a program under `key`, returning whether it could be encoded.
+    /// The file is written under another name and then renamed, so a run
+    /// that is interrupted never leaves half a program behind.
+    pub fn store(&self, key: u64, program: &Program, entry: FunctionId, ty: &Type) -> io::Result<bool> {
+        let bytes = match bytecode::encode(program, entry, ty) {
+            Some(bytes) => bytes,
+            None => return Ok(false),
+        };
+        fs::create_dir_all(&self.dir)?;
+        let path = self.path(key);
+        let partial = path.with_extension("partial");
+        fs::write(&partial, bytes)?;
+        fs::rename(&partial, &path)?;
+        Ok(true)
+    }
+}
+
+/// Removes the build directory of the project at `root`, returning whether
+/// there was one.
+pub fn clean(root: &Path) -> io::Result<bool> {
+    match fs::remove_dir_all(root.join(BUILD_DIR)) {
+        Ok(()) => Ok(true),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(false),
+        Err(error) => Err(error),
+    }
+}
+
+/// 64-bit FNV-1a. The standard library's hasher is not guaranteed to give
+/// the same hash in different releases, which a hash on disk needs.
+pub(crate) struct Fnv(u64);
+
+impl Fnv {
+    pub(crate) fn new() -> Fnv {
+        Fnv(0xcbf2_9ce4_8422_2325)
+    }
+    pub(crate) fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 ^= byte as u64;
+            self.0 = self.0.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+    pub(crate) fn finish(&self) -> u64 {
+        self.0
+    }
+}
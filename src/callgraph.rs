@@ -0,0 +1,114 @@
+//! Which functions call which, for ordering work over a whole program so
+//! that every function is handled after the functions it calls.
+
+use crate::ir::{FunctionId, Instruction, Program};
+use std::fmt;
+
+pub struct CallGraph {
+    functions: Vec<FunctionId>,
+    callees: Vec<Vec<FunctionId>>,
+}
+
+/// The working state of Tarjan's algorithm.
+struct Search {
+    index: Vec<Option<usize>>,
+    low_link: Vec<usize>,
+    stack: Vec<usize>,
+    on_stack: Vec<bool>,
+    next_index: usize,
+    components: Vec<Vec<FunctionId>>,
+}
+
+impl CallGraph {
+    pub fn new(program: &Program) -> CallGraph {
+        let functions = program.get_function_ids();
+        let callees = functions.iter().map(|&function_id| {
+            let mut callees: Vec<FunctionId> = Vec::new();
+            for block in program.get_function(function_id).get_blocks() {
+                for inst in block.get_instructions() {
+                    if let Instruction::Call { function, .. } | Instruction::Spawn { function } = inst {
+                        if !callees.iter().any(|callee| callee.get_id() == function.get_id()) {
+                            callees.push(*function);
+                        }
+                    }
+                }
+            }
+            callees
+        }).collect();
+        CallGraph { functions, callees }
+    }
+    pub fn callees(&self, function: FunctionId) -> &[FunctionId] {
+        &self.callees[function.get_id()]
+    }
+    /// The strongly connected components of the graph, each one a set of
+    /// functions that can reach each other through calls. Components come
+    /// after every component they call into.
+    pub fn components(&self) -> Vec<Vec<FunctionId>> {
+        let count = self.callees.len();
+        let mut search = Search {
+            index: vec![None; count],
+            low_link: vec![0; count],
+            stack: Vec::new(),
+            on_stack: vec![false; count],
+            next_index: 0,
+            components: Vec::new(),
+        };
+        for id in 0..count {
+            if search.index[id].is_none() {
+                self.connect(id, &mut search);
+            }
+        }
+        search.components
+    }
+    /// Every function, callees before their callers.
+    pub fn bottom_up(&self) -> Vec<FunctionId> {
+        self.components().into_iter().flatten().collect()
+    }
+    fn connect(&self, id: usize, search: &mut Search) {
+        search.index[id] = Some(search.next_index);
+        search.low_link[id] = search.next_index;
+        search.next_index += 1;
+        search.stack.push(id);
+        search.on_stack[id] = true;
+        for callee in &self.callees[id] {
+            let callee = callee.get_id();
+            match search.index[callee] {
+                None => {
+                    self.connect(callee, search);
+                    search.low_link[id] = search.low_link[id].min(search.low_link[callee]);
+                }
+                Some(index) if search.on_stack[callee] => {
+                    search.low_link[id] = search.low_link[id].min(index);
+                }
+                Some(_) => (),
+            }
+        }
+        if Some(search.low_link[id]) == search.index[id] {
+            let mut component = Vec::new();
+            while let Some(member) = search.stack.pop() {
+                search.on_stack[member] = false;
+                component.push(member);
+                if member == id {
+                    break
+                }
+            }
+            component.sort_unstable();
+            search.components.push(component.into_iter().map(|member| self.functions[member]).collect());
+        }
+    }
+}
+
+/// Lists each function after the functions it calls, with its callees.
+impl fmt::Display for CallGraph {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for function in self.bottom_up() {
+            write!(f, "f{}", function.get_id())?;
+            let callees: Vec<String> = self.callees(function).iter().map(|callee| format!("f{}", callee.get_id())).collect();
+            if !callees.is_empty() {
+                write!(f, " -> {}", callees.join(", "))?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,186 @@
+//! C functions for embedding the compiler in programs written in other
+//! languages, declared in `include/language.h`. Build the library as a C
+//! library with, for example,
+//! `cargo rustc --lib --release --features capi --crate-type cdylib`.
+//!
+//! Every result is a `LangResult` owned by the caller, which must free it
+//! with `lang_result_free`. Strings read from a result are owned by it and
+//! stay valid until it is freed. No function unwinds into C: a panic in the
+//! compiler is returned as a null result.
+
+use crate::execute::{Resource, SandboxConfig};
+use crate::playground::{self, PlaygroundOutput};
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::time::Duration;
+
+pub struct LangResult {
+    compiled: bool,
+    diagnostics: CString,
+    ir: CString,
+    printed: CString,
+    output: CString,
+    exit_code: Option<i32>,
+    exhausted: Option<Resource>,
+}
+
+impl LangResult {
+    fn new(out: PlaygroundOutput) -> LangResult {
+        LangResult {
+            compiled: out.compiled,
+            diagnostics: c_string(out.diagnostics),
+            ir: c_string(out.ir),
+            printed: c_string(out.printed),
+            output: c_string(out.output),
+            exit_code: out.exit_code,
+            exhausted: out.exhausted,
+        }
+    }
+}
+
+/// Rendered diagnostics quote the source, which may contain a NUL.
+fn c_string(string: String) -> CString {
+    CString::new(string.replace('\0', "\\0")).unwrap_or_default()
+}
+
+unsafe fn build(source: *const c_char, limits: Option<SandboxConfig>) -> *mut LangResult {
+    if source.is_null() {
+        return ptr::null_mut()
+    }
+    let source = match CStr::from_ptr(source).to_str() {
+        Ok(source) => source,
+        Err(_) => return ptr::null_mut(),
+    };
+    let out = panic::catch_unwind(AssertUnwindSafe(|| {
+        match limits {
+            Some(limits) => playground::compile_and_run_with(source, limits),
+            None => playground::compile(source),
+        }
+    }));
+    match out {
+        Ok(out) => Box::into_raw(Box::new(LangResult::new(out))),
+        Err(_) => ptr::null_mut(),
+    }
+}
+
+/// Compiles `source` without running it.
+///
+/// # Safety
+/// `source` must be null or a NUL terminated string. Returns null if it is
+/// null or not UTF-8.
+#[no_mangle]
+pub unsafe extern "C" fn lang_compile(source: *const c_char) -> *mut LangResult {
+    build(source, None)
+}
+
+/// Compiles `source` and runs its `main` within the playground's limits.
+///
+/// # Safety
+/// As for `lang_compile`.
+#[no_mangle]
+pub unsafe extern "C" fn lang_run(source: *const c_char) -> *mut LangResult {
+    build(source, Some(playground::LIMITS))
+}
+
+/// Compiles `source` and runs its `main` for at most `fuel` instructions
+/// and `timeout_millis` milliseconds, where 0 is no limit. Memory is
+/// limited as for `lang_run`.
+///
+/// # Safety
+/// As for `lang_compile`.
+#[no_mangle]
+pub unsafe extern "C" fn lang_run_limited(source: *const c_char, fuel: u64, timeout_millis: u64) -> *mut LangResult {
+    let limits = SandboxConfig {
+        fuel: Some(fuel).filter(|&fuel| fuel > 0),
+        timeout: Some(timeout_millis).filter(|&millis| millis > 0).map(Duration::from_millis),
+        ..playground::LIMITS
+    };
+    build(source, Some(limits))
+}
+
+/// 1 if the program compiled without errors, otherwise 0.
+///
+/// # Safety
+/// `result` must be a live result from `lang_compile`, `lang_run` or
+/// `lang_run_limited`.
+#[no_mangle]
+pub unsafe extern "C" fn lang_result_compiled(result: *const LangResult) -> c_int {
+    (*result).compiled as c_int
+}
+
+/// # Safety
+/// As for `lang_result_compiled`.
+#[no_mangle]
+pub unsafe extern "C" fn lang_result_diagnostics(result: *const LangResult) -> *const c_char {
+    (*result).diagnostics.as_ptr()
+}
+
+/// # Safety
+/// As for `lang_result_compiled`.
+#[no_mangle]
+pub unsafe extern "C" fn lang_result_ir(result: *const LangResult) -> *const c_char {
+    (*result).ir.as_ptr()
+}
+
+/// What the program wrote with `print`.
+///
+/// # Safety
+/// As for `lang_result_compiled`.
+#[no_mangle]
+pub unsafe extern "C" fn lang_result_printed(result: *const LangResult) -> *const c_char {
+    (*result).printed.as_ptr()
+}
+
+/// # Safety
+/// As for `lang_result_compiled`.
+#[no_mangle]
+pub unsafe extern "C" fn lang_result_output(result: *const LangResult) -> *const c_char {
+    (*result).output.as_ptr()
+}
+
+/// 1 if the program ran and gave an exit code, otherwise 0.
+///
+/// # Safety
+/// As for `lang_result_compiled`.
+#[no_mangle]
+pub unsafe extern "C" fn lang_result_has_exit_code(result: *const LangResult) -> c_int {
+    (*result).exit_code.is_some() as c_int
+}
+
+/// The exit code, or 0 if there is none.
+///
+/// # Safety
+/// As for `lang_result_compiled`.
+#[no_mangle]
+pub unsafe extern "C" fn lang_result_exit_code(result: *const LangResult) -> c_int {
+    (*result).exit_code.unwrap_or(0)
+}
+
+/// The limit that stopped the program: 1 for fuel, 2 for memory, 3 for
+/// time and 4 for nested calls, or 0 if it did not run into one.
+///
+/// # Safety
+/// As for `lang_result_compiled`.
+#[no_mangle]
+pub unsafe extern "C" fn lang_result_exhausted(result: *const LangResult) -> c_int {
+    match (*result).exhausted {
+        None => 0,
+        Some(Resource::Fuel) => 1,
+        Some(Resource::Memory) => 2,
+        Some(Resource::Time) => 3,
+        Some(Resource::Stack) => 4,
+    }
+}
+
+/// Frees a result. Null is ignored.
+///
+/// # Safety
+/// `result` must be null or a result that has not been freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn lang_result_free(result: *mut LangResult) {
+    if !result.is_null() {
+        drop(Box::from_raw(result));
+    }
+}
@@ -0,0 +1,195 @@
+//! Which names from outside a function its body reads. A function keeps the
+//! scope it was created in rather than the values in it, and is only
+//! compiled when it is called, so assigning one of those names again after
+//! the function is created changes what the function reads.
+
+use crate::compiler::CompileError;
+use crate::hir::{Hir, HirKind, Pattern, PatternKind};
+
+/// The first use of each name that `func` reads from outside itself.
+pub fn captures<'h, 'a>(func: &'h Hir<'a>) -> Vec<&'h Hir<'a>> {
+    match &func.kind {
+        HirKind::Func { param, body, .. } => captures_of(param, body),
+        _ => Vec::new(),
+    }
+}
+
+/// `captures`, for the function with `param` and `body`.
+pub fn captures_of<'h, 'a>(param: &Pattern<'a>, body: &'h Hir<'a>) -> Vec<&'h Hir<'a>> {
+    let mut captures = Vec::new();
+    let mut bound = Vec::new();
+    bind(param, &mut bound);
+    collect(body, &mut bound, &mut captures);
+    captures
+}
+
+/// The first use of each name that `expr` reads from the scope it runs in.
+pub fn reads<'h, 'a>(expr: &'h Hir<'a>) -> Vec<&'h Hir<'a>> {
+    let mut captures = Vec::new();
+    collect(expr, &mut Vec::new(), &mut captures);
+    captures
+}
+
+fn collect<'h, 'a>(expr: &'h Hir<'a>, bound: &mut Vec<&'a str>, captures: &mut Vec<&'h Hir<'a>>) {
+    match &expr.kind {
+        HirKind::Var(_) => capture(expr, bound, captures),
+        HirKind::Assign { pattern, value } => {
+            collect(value, bound, captures);
+            bind(pattern, bound);
+        }
+        HirKind::Block(items) => {
+            bound.extend(declared(items));
+            for item in items {
+                collect(item, bound, captures);
+            }
+        }
+        HirKind::InlineIr { ir, inputs } => {
+            for input in inputs {
+                capture(input, bound, captures);
+            }
+            bound.extend(ir.slots.iter().filter(|slot| !slot.input).map(|slot| slot.name));
+        }
+        HirKind::For { pattern, iterable, body, .. } => {
+            collect(iterable, bound, captures);
+            let mut bound = bound.clone();
+            bind(pattern, &mut bound);
+            collect(body, &mut bound, captures);
+        }
+        HirKind::Match { value, arms } => {
+            collect(value, bound, captures);
+            for (pattern, body) in arms {
+                let mut bound = bound.clone();
+                bind(pattern, &mut bound);
+                collect(body, &mut bound, captures);
+            }
+        }
+        // what a function inside reads from outside itself, this one reads
+        // too unless it is bound here
+        HirKind::Func { .. } => for var in self::captures(expr) {
+            capture(var, bound, captures);
+        }
+        _ => for child in expr.children() {
+            collect(child, bound, captures);
+        }
+    }
+}
+
+fn capture<'h, 'a>(var: &'h Hir<'a>, bound: &[&'a str], captures: &mut Vec<&'h Hir<'a>>) {
+    if let HirKind::Var(name) = var.kind {
+        let seen = captures.iter().any(|capture| matches!(capture.kind, HirKind::Var(other) if other == name));
+        if !bound.contains(&name) && !seen {
+            captures.push(var);
+        }
+    }
+}
+
+/// Checks that no function reads a name that the function it is created in
+/// assigns again afterwards. Names bound outside that function are only
+/// hidden by a new assignment, so the function keeps reading the old one.
+pub fn check<'a>(hir: &Hir<'a>) -> Result<(), CompileError<'a>> {
+    check_expr(hir, &mut Vec::new())
+}
+
+fn check_expr<'a>(expr: &Hir<'a>, locals: &mut Vec<&'a str>) -> Result<(), CompileError<'a>> {
+    match &expr.kind {
+        HirKind::Assign { pattern, value } => {
+            check_expr(value, locals)?;
+            bind(pattern, locals);
+        }
+        HirKind::InlineIr { ir, .. } => locals.extend(ir.slots.iter().filter(|slot| !slot.input).map(|slot| slot.name)),
+        HirKind::Block(items) => {
+            locals.extend(declared(items));
+            for (index, item) in items.iter().enumerate() {
+                for func in functions(item) {
+                    for capture in captures(func) {
+                        let name = match capture.kind {
+                            HirKind::Var(name) if locals.contains(&name) => name,
+                            _ => continue,
+                        };
+                        if let Some(assignment) = items[index + 1..].iter().find_map(|later| assignment_to(later, name)) {
+                            return Err(CompileError::captured_reassigned(assignment)
+                                .with_label(capture.source, format!("`{}` is captured here", name))
+                                .with_note(format!("the function reads `{}` when it is first called, which may be after this assignment", name))
+                                .with_help("give the new value a new name"))
+                        }
+                    }
+                }
+                check_expr(item, locals)?;
+            }
+        }
+        // the names the pattern binds are only bound in the body
+        HirKind::For { pattern, iterable, body, .. } => {
+            check_expr(iterable, locals)?;
+            let mut locals = locals.clone();
+            bind(pattern, &mut locals);
+            check_expr(body, &mut locals)?;
+        }
+        HirKind::Match { value, arms } => {
+            check_expr(value, locals)?;
+            for (pattern, body) in arms {
+                let mut locals = locals.clone();
+                bind(pattern, &mut locals);
+                check_expr(body, &mut locals)?;
+            }
+        }
+        HirKind::Func { param, body, .. } => {
+            let mut locals = Vec::new();
+            bind(param, &mut locals);
+            check_expr(body, &mut locals)?;
+        }
+        _ => for child in expr.children() {
+            check_expr(child, locals)?;
+        }
+    }
+    Ok(())
+}
+
+/// The names of the functions declared in a block, which are in scope from
+/// its start.
+fn declared<'a>(items: &[Hir<'a>]) -> Vec<&'a str> {
+    items.iter().filter_map(|item| match item.kind {
+        HirKind::Func { name, .. } => name,
+        _ => None,
+    }).collect()
+}
+
+/// The functions created by `expr` itself, not counting those inside them.
+fn functions<'h, 'a>(expr: &'h Hir<'a>) -> Vec<&'h Hir<'a>> {
+    match expr.kind {
+        HirKind::Func { .. } => vec![expr],
+        _ => expr.children().into_iter().flat_map(functions).collect(),
+    }
+}
+
+/// Where `expr` assigns `name` in the scope it runs in, which leaves out
+/// the bodies of functions.
+fn assignment_to<'a>(expr: &Hir<'a>, name: &str) -> Option<&'a str> {
+    match &expr.kind {
+        HirKind::Func { .. } => None,
+        HirKind::For { pattern, iterable, .. } if binding_of(pattern, name).is_some() => assignment_to(iterable, name),
+        HirKind::Match { value, arms } => assignment_to(value, name).or_else(|| arms.iter()
+            .filter(|(pattern, _)| binding_of(pattern, name).is_none())
+            .find_map(|(_, body)| assignment_to(body, name))),
+        HirKind::Assign { pattern, value } => assignment_to(value, name).or_else(|| binding_of(pattern, name)),
+        HirKind::InlineIr { ir, .. } => ir.slots.iter().find(|slot| !slot.input && slot.name == name).map(|slot| slot.name),
+        _ => expr.children().into_iter().find_map(|child| assignment_to(child, name)),
+    }
+}
+
+fn binding_of<'a>(pattern: &Pattern<'a>, name: &str) -> Option<&'a str> {
+    match &pattern.kind {
+        PatternKind::Bind(bound) if *bound == name => Some(pattern.source),
+        PatternKind::Tuple(patterns) => patterns.iter().find_map(|pattern| binding_of(pattern, name)),
+        _ => None,
+    }
+}
+
+fn bind<'a>(pattern: &Pattern<'a>, bound: &mut Vec<&'a str>) {
+    match &pattern.kind {
+        PatternKind::Bind(name) => bound.push(name),
+        PatternKind::Tuple(patterns) => for pattern in patterns {
+            bind(pattern, bound);
+        }
+        PatternKind::Wildcard | PatternKind::Int(_) | PatternKind::UInt(_) | PatternKind::Bool(_) => (),
+    }
+}
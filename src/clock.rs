@@ -0,0 +1,50 @@
+use std::sync::{Arc, OnceLock};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Where `now_millis` and `sleep` get the time from. The VM uses the
+/// system's clock unless the host gives it another, so that tests and
+/// embedders can run programs against time they control. Nothing reads the
+/// system's clock until the program does, so a VM can be made where there
+/// is none, such as on wasm32-unknown-unknown, if it is given a mock.
+#[derive(Debug, Clone)]
+pub enum Clock {
+    /// The system's monotonic clock, counting from when it is first read.
+    System(OnceLock<Instant>),
+    /// Time that only moves when something sleeps on it. Clones share the
+    /// same time, so the host keeps one to read and advance.
+    Mock(Arc<AtomicU64>),
+}
+
+impl Clock {
+    pub fn system() -> Clock {
+        Clock::System(OnceLock::new())
+    }
+    /// A clock that starts at `millis` and never moves on its own.
+    pub fn mock(millis: u64) -> Clock {
+        Clock::Mock(Arc::new(AtomicU64::new(millis)))
+    }
+    pub fn now_millis(&self) -> u64 {
+        match self {
+            Clock::System(start) => start.get_or_init(Instant::now).elapsed().as_millis() as u64,
+            Clock::Mock(millis) => millis.load(Ordering::Relaxed),
+        }
+    }
+    /// Waits `millis` on the system clock, or moves a mock clock forwards
+    /// without waiting.
+    pub fn sleep(&self, millis: u64) {
+        match self {
+            Clock::System(_) => thread::sleep(Duration::from_millis(millis)),
+            Clock::Mock(now) => {
+                now.fetch_add(millis, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+impl Default for Clock {
+    fn default() -> Clock {
+        Clock::system()
+    }
+}
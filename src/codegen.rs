@@ -0,0 +1,506 @@
+//! x86-64 assembly for a compiled program, in the GNU assembler's AT&T
+//! syntax, which `cc` assembles and links into an executable that prints
+//! what `main` returns, as running the program does.
+//!
+//! Like the VM, the code keeps every variable in one register file, here an
+//! array in memory indexed by variable, and saves a caller's registers
+//! around calls back into its own component of the call graph. Builtins
+//! other than `abs`, `min` and `max` of integers and stepping a `range`,
+//! tasks, and results that print through the heap or as floats are not
+//! compiled.
+
+use crate::builtins::Intrinsic;
+use crate::callgraph::CallGraph;
+use crate::execute::MAX_CALL_DEPTH;
+use crate::ir::{Conversion, ExitInstruction, FunctionId, Instruction, Program, Var};
+use crate::ssa;
+use crate::types::Type;
+
+const DIVIDE_BY_ZERO: &str = "error: division by zero at line %d\n";
+const ABORT: &str = "error: `else trap` or `match` found no value at line %d\n";
+
+/// Assembly for the functions `entry` reaches in `program`, with a `main`
+/// that calls `entry` and prints the `ty` it returns, exiting with it if it
+/// is an `int`. Fails, saying why, if the program uses something that has
+/// no native code.
+pub fn emit(program: &Program, entry: FunctionId, ty: &Type) -> Result<String, String> {
+    let (mut program, entry) = program.reachable(entry);
+    ssa::destruct(&mut program);
+    let program = &program;
+    let mut emitter = Emitter::new(program);
+    emitter.out.push_str("\t.text\n");
+    for function_id in program.get_function_ids() {
+        emitter.function(function_id)?;
+    }
+    emitter.main(entry, ty)?;
+    emitter.runtime();
+    Ok(emitter.out)
+}
+
+struct Emitter<'a> {
+    program: &'a Program,
+    /// The variables of each function that calls itself, directly or not,
+    /// which a call from it into its own component saves.
+    frames: Vec<Option<Vec<Var>>>,
+    components: Vec<usize>,
+    /// Text printed by `main`, emitted as read-only data at the end.
+    strings: Vec<String>,
+    labels: usize,
+    out: String,
+}
+
+impl<'a> Emitter<'a> {
+    fn new(program: &'a Program) -> Emitter<'a> {
+        let call_graph = CallGraph::new(program);
+        let mut frames = vec![None; program.get_function_count()];
+        let mut components = vec![0; program.get_function_count()];
+        for (index, component) in call_graph.components().into_iter().enumerate() {
+            let cyclic = component.len() > 1 || call_graph.callees(component[0]).iter().any(|callee| callee.get_id() == component[0].get_id());
+            for function_id in component {
+                components[function_id.get_id()] = index;
+                if cyclic {
+                    frames[function_id.get_id()] = Some(program.get_function(function_id).vars());
+                }
+            }
+        }
+        Emitter { program, frames, components, strings: Vec::new(), labels: 0, out: String::new() }
+    }
+    fn asm(&mut self, line: &str) {
+        self.out.push('\t');
+        self.out.push_str(line);
+        self.out.push('\n');
+    }
+    fn label(&mut self, label: &str) {
+        self.out.push_str(label);
+        self.out.push_str(":\n");
+    }
+    fn fresh_label(&mut self) -> String {
+        self.labels += 1;
+        format!(".L{}", self.labels)
+    }
+    /// A string in read-only data, printed with `printf`, so `%` is escaped.
+    fn string(&mut self, text: &str) -> String {
+        self.strings.push(text.replace('%', "%%"));
+        format!(".Lstring{}", self.strings.len() - 1)
+    }
+
+    fn function(&mut self, function_id: FunctionId) -> Result<(), String> {
+        let function = self.program.get_function(function_id);
+        let id = function_id.get_id();
+        self.label(&format!("function{}", id));
+        self.asm("pushq %rbp");
+        self.asm("movq %rsp, %rbp");
+        for block in function.get_blocks() {
+            self.label(&block_label(id, block.get_id().get_id()));
+            for (index, inst) in block.get_instructions().iter().enumerate() {
+                self.instruction(function_id, inst, block.get_line(index))?;
+            }
+            let exit = block.get_exit_instruction();
+            match exit {
+                ExitInstruction::Branch { block } => self.asm(&format!("jmp {}", block_label(id, block.get_id()))),
+                ExitInstruction::ConditionalBranch { cond, block1, block2 } => {
+                    self.asm(&format!("movl {}, %eax", reg(*cond)));
+                    self.asm("testl %eax, %eax");
+                    self.asm(&format!("jne {}", block_label(id, block1.get_id())));
+                    self.asm(&format!("jmp {}", block_label(id, block2.get_id())));
+                }
+                ExitInstruction::Switch { scrutinee, targets, default } => {
+                    self.asm(&format!("movl {}, %eax", reg(*scrutinee)));
+                    for (index, target) in targets.iter().enumerate() {
+                        self.asm(&format!("cmpl ${}, %eax", index));
+                        self.asm(&format!("je {}", block_label(id, target.get_id())));
+                    }
+                    self.asm(&format!("jmp {}", block_label(id, default.get_id())));
+                }
+                ExitInstruction::Return => {
+                    self.asm("popq %rbp");
+                    self.asm("ret");
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn instruction(&mut self, caller: FunctionId, inst: &Instruction, line: u32) -> Result<(), String> {
+        match inst {
+            &Instruction::AddInt { dest, a, b } => self.int_op("addl", dest, a, b),
+            &Instruction::SubInt { dest, a, b } => self.int_op("subl", dest, a, b),
+            &Instruction::MulInt { dest, a, b } => self.int_op("imull", dest, a, b),
+            &Instruction::AndInt { dest, a, b } => self.int_op("andl", dest, a, b),
+            &Instruction::OrInt { dest, a, b } => self.int_op("orl", dest, a, b),
+            &Instruction::XorInt { dest, a, b } => self.int_op("xorl", dest, a, b),
+            &Instruction::ShiftLeft { dest, a, b } => self.shift("shll", dest, a, b),
+            &Instruction::ShiftRightArithmetic { dest, a, b } => self.shift("sarl", dest, a, b),
+            &Instruction::ShiftRightLogical { dest, a, b } => self.shift("shrl", dest, a, b),
+            &Instruction::DivInt { dest, a, b } => self.divide(dest, a, b, true, false, line),
+            &Instruction::DivUInt { dest, a, b } => self.divide(dest, a, b, false, false, line),
+            &Instruction::RemInt { dest, a, b } => self.divide(dest, a, b, true, true, line),
+            &Instruction::RemUInt { dest, a, b } => self.divide(dest, a, b, false, true, line),
+            &Instruction::AddFloat { dest, a, b } => self.float_op("addss", dest, a, b),
+            &Instruction::SubFloat { dest, a, b } => self.float_op("subss", dest, a, b),
+            &Instruction::MulFloat { dest, a, b } => self.float_op("mulss", dest, a, b),
+            &Instruction::DivFloat { dest, a, b } => self.float_op("divss", dest, a, b),
+            &Instruction::RemFloat { dest, a, b } => {
+                // `fprem` gives the remainder truncated towards zero, as `%`
+                // does, but may need repeating to finish
+                let again = self.fresh_label();
+                self.asm(&format!("flds {}", reg(b)));
+                self.asm(&format!("flds {}", reg(a)));
+                self.label(&again);
+                self.asm("fprem");
+                self.asm("fnstsw %ax");
+                self.asm("testw $1024, %ax");
+                self.asm(&format!("jne {}", again));
+                self.asm("fstp %st(1)");
+                self.asm(&format!("fstps {}", reg(dest)));
+            }
+            &Instruction::EqInt { dest, a, b } => self.compare_int("sete", dest, a, b),
+            &Instruction::LessInt { dest, a, b } => self.compare_int("setl", dest, a, b),
+            &Instruction::LessUInt { dest, a, b } => self.compare_int("setb", dest, a, b),
+            &Instruction::LessEqInt { dest, a, b } => self.compare_int("setle", dest, a, b),
+            &Instruction::LessEqUInt { dest, a, b } => self.compare_int("setbe", dest, a, b),
+            &Instruction::EqFloat { dest, a, b } => {
+                // unordered, when either is NaN, sets the parity flag
+                self.asm(&format!("movss {}, %xmm0", reg(a)));
+                self.asm(&format!("ucomiss {}, %xmm0", reg(b)));
+                self.asm("sete %al");
+                self.asm("setnp %cl");
+                self.asm("andb %cl, %al");
+                self.asm("movzbl %al, %eax");
+                self.asm(&format!("movl %eax, {}", reg(dest)));
+            }
+            // compared the other way round, so that unordered is false
+            &Instruction::LessFloat { dest, a, b } => self.compare_float("seta", dest, a, b),
+            &Instruction::LessEqFloat { dest, a, b } => self.compare_float("setae", dest, a, b),
+            &Instruction::ConstantInt { dest, constant } => self.asm(&format!("movl ${}, {}", constant, reg(dest))),
+            &Instruction::ConstantFloat { dest, constant } => self.asm(&format!("movl ${}, {}", constant.to_bits() as i32, reg(dest))),
+            &Instruction::Convert { dest, src, conversion } => self.convert(dest, src, conversion),
+            &Instruction::Copy { dest, src } => {
+                self.asm(&format!("movl {}, %eax", reg(src)));
+                self.asm(&format!("movl %eax, {}", reg(dest)));
+            }
+            &Instruction::Select { cond, a, b, dest } => {
+                self.asm(&format!("movl {}, %eax", reg(a)));
+                self.asm(&format!("cmpl $0, {}", reg(cond)));
+                self.asm(&format!("cmovel {}, %eax", reg(b)));
+                self.asm(&format!("movl %eax, {}", reg(dest)));
+            }
+            Instruction::Call { function, args, returns } => self.call(caller, *function, args, returns),
+            Instruction::CallBuiltin { intrinsic, args, returns } => self.builtin(*intrinsic, args, returns)?,
+            &Instruction::Exit { code } => {
+                self.asm(&format!("movl {}, %edi", reg(code)));
+                self.asm("call exit@PLT");
+            }
+            Instruction::Abort => self.trap(ABORT, line),
+            Instruction::Spawn { .. } => return Err("tasks cannot be compiled to native code".to_string()),
+            Instruction::Phi { .. } => unreachable!("phis are turned into copies before emitting"),
+        }
+        Ok(())
+    }
+
+    fn int_op(&mut self, op: &str, dest: Var, a: Var, b: Var) {
+        self.asm(&format!("movl {}, %eax", reg(a)));
+        self.asm(&format!("{} {}, %eax", op, reg(b)));
+        self.asm(&format!("movl %eax, {}", reg(dest)));
+    }
+    /// x86 masks the amount to five bits, as `wrapping_shl` does.
+    fn shift(&mut self, op: &str, dest: Var, a: Var, b: Var) {
+        self.asm(&format!("movl {}, %ecx", reg(b)));
+        self.asm(&format!("movl {}, %eax", reg(a)));
+        self.asm(&format!("{} %cl, %eax", op));
+        self.asm(&format!("movl %eax, {}", reg(dest)));
+    }
+    /// Traps on a zero divisor. `idiv` faults dividing the smallest `int` by
+    /// -1, which wraps in the VM, so that divisor is done by hand.
+    fn divide(&mut self, dest: Var, a: Var, b: Var, signed: bool, remainder: bool, line: u32) {
+        let (nonzero, divide, done) = (self.fresh_label(), self.fresh_label(), self.fresh_label());
+        self.asm(&format!("movl {}, %ecx", reg(b)));
+        self.asm("testl %ecx, %ecx");
+        self.asm(&format!("jne {}", nonzero));
+        self.trap(DIVIDE_BY_ZERO, line);
+        self.label(&nonzero);
+        self.asm(&format!("movl {}, %eax", reg(a)));
+        if signed {
+            self.asm("cmpl $-1, %ecx");
+            self.asm(&format!("jne {}", divide));
+            self.asm(if remainder { "xorl %eax, %eax" } else { "negl %eax" });
+            self.asm(&format!("jmp {}", done));
+            self.label(&divide);
+            self.asm("cltd");
+            self.asm("idivl %ecx");
+        } else {
+            self.asm("xorl %edx, %edx");
+            self.asm("divl %ecx");
+        }
+        if remainder {
+            self.asm("movl %edx, %eax");
+        }
+        self.label(&done);
+        self.asm(&format!("movl %eax, {}", reg(dest)));
+    }
+    fn float_op(&mut self, op: &str, dest: Var, a: Var, b: Var) {
+        self.asm(&format!("movss {}, %xmm0", reg(a)));
+        self.asm(&format!("{} {}, %xmm0", op, reg(b)));
+        self.asm(&format!("movss %xmm0, {}", reg(dest)));
+    }
+    fn compare_int(&mut self, set: &str, dest: Var, a: Var, b: Var) {
+        self.asm(&format!("movl {}, %eax", reg(a)));
+        self.asm(&format!("cmpl {}, %eax", reg(b)));
+        self.asm(&format!("{} %al", set));
+        self.asm("movzbl %al, %eax");
+        self.asm(&format!("movl %eax, {}", reg(dest)));
+    }
+    fn compare_float(&mut self, set: &str, dest: Var, a: Var, b: Var) {
+        self.asm(&format!("movss {}, %xmm0", reg(b)));
+        self.asm(&format!("ucomiss {}, %xmm0", reg(a)));
+        self.asm(&format!("{} %al", set));
+        self.asm("movzbl %al, %eax");
+        self.asm(&format!("movl %eax, {}", reg(dest)));
+    }
+    /// Float to integer conversions saturate, and take NaN to zero, as
+    /// Rust's `as` does, where x86 gives the smallest `int` instead.
+    fn convert(&mut self, dest: Var, src: Var, conversion: Conversion) {
+        match conversion {
+            Conversion::IntToFloat => self.asm(&format!("cvtsi2ssl {}, %xmm0", reg(src))),
+            Conversion::UIntToFloat => {
+                self.asm(&format!("movl {}, %eax", reg(src)));
+                self.asm("cvtsi2ssq %rax, %xmm0");
+            }
+            Conversion::FloatToInt => {
+                let done = self.fresh_label();
+                self.asm(&format!("movss {}, %xmm0", reg(src)));
+                self.asm("cvttss2si %xmm0, %eax");
+                self.asm("cmpl $-2147483648, %eax");
+                self.asm(&format!("jne {}", done));
+                self.asm("xorl %ecx, %ecx");
+                self.asm("movl $2147483647, %edx");
+                self.asm("xorps %xmm1, %xmm1");
+                self.asm("ucomiss %xmm1, %xmm0");
+                self.asm("cmoval %edx, %eax");
+                self.asm("cmovpl %ecx, %eax");
+                self.label(&done);
+            }
+            Conversion::FloatToUInt => {
+                self.asm(&format!("movss {}, %xmm0", reg(src)));
+                self.asm("cvttss2si %xmm0, %rax");
+                self.asm("xorl %ecx, %ecx");
+                self.asm("testq %rax, %rax");
+                self.asm("cmovsq %rcx, %rax");
+                self.asm("movl $4294967295, %ecx");
+                self.asm("cmpq %rcx, %rax");
+                self.asm("cmovaq %rcx, %rax");
+                // 2^63 and above do not fit the conversion at all
+                self.asm(&format!("movl ${}, %edx", 2f32.powi(63).to_bits()));
+                self.asm("movd %edx, %xmm1");
+                self.asm("ucomiss %xmm1, %xmm0");
+                self.asm("cmovaeq %rcx, %rax");
+            }
+        }
+        match conversion {
+            Conversion::IntToFloat | Conversion::UIntToFloat => self.asm(&format!("movss %xmm0, {}", reg(dest))),
+            Conversion::FloatToInt | Conversion::FloatToUInt => self.asm(&format!("movl %eax, {}", reg(dest))),
+        }
+    }
+    /// Passes arguments and results through the stack, since a recursive
+    /// call's arguments can be its own parameters, and restores the saved
+    /// registers between reading the callee's results and writing them.
+    fn call(&mut self, caller: FunctionId, callee_id: FunctionId, args: &[Var], returns: &[Var]) {
+        let callee = self.program.get_function(callee_id);
+        let saved = match &self.frames[caller.get_id()] {
+            Some(vars) if self.components[caller.get_id()] == self.components[callee_id.get_id()] => vars.clone(),
+            _ => Vec::new(),
+        };
+        let slots = saved.len() + args.len().max(returns.len());
+        let size = (slots * 4).div_ceil(16) * 16;
+        let ok = self.fresh_label();
+        self.asm(&format!("cmpl ${}, depth(%rip)", MAX_CALL_DEPTH));
+        self.asm(&format!("jbe {}", ok));
+        self.asm("leaq .Lstack_message(%rip), %rsi");
+        self.asm("jmp trap");
+        self.label(&ok);
+        if size > 0 {
+            self.asm(&format!("subq ${}, %rsp", size));
+        }
+        for (index, &var) in saved.iter().enumerate() {
+            self.asm(&format!("movl {}, %eax", reg(var)));
+            self.asm(&format!("movl %eax, {}(%rsp)", index * 4));
+        }
+        for (index, &arg) in args.iter().enumerate() {
+            self.asm(&format!("movl {}, %eax", reg(arg)));
+            self.asm(&format!("movl %eax, {}(%rsp)", (saved.len() + index) * 4));
+        }
+        for (index, &param) in callee.get_params().iter().enumerate() {
+            self.asm(&format!("movl {}(%rsp), %eax", (saved.len() + index) * 4));
+            self.asm(&format!("movl %eax, {}", reg(param)));
+        }
+        self.asm("incl depth(%rip)");
+        self.asm(&format!("call function{}", callee_id.get_id()));
+        self.asm("decl depth(%rip)");
+        for (index, &ret) in callee.get_returns().iter().enumerate() {
+            self.asm(&format!("movl {}, %eax", reg(ret)));
+            self.asm(&format!("movl %eax, {}(%rsp)", (saved.len() + index) * 4));
+        }
+        for (index, &var) in saved.iter().enumerate() {
+            self.asm(&format!("movl {}(%rsp), %eax", index * 4));
+            self.asm(&format!("movl %eax, {}", reg(var)));
+        }
+        for (index, &ret) in returns.iter().enumerate() {
+            self.asm(&format!("movl {}(%rsp), %eax", (saved.len() + index) * 4));
+            self.asm(&format!("movl %eax, {}", reg(ret)));
+        }
+        if size > 0 {
+            self.asm(&format!("addq ${}, %rsp", size));
+        }
+    }
+    fn builtin(&mut self, intrinsic: Intrinsic, args: &[Var], returns: &[Var]) -> Result<(), String> {
+        let cmov = match intrinsic {
+            Intrinsic::AbsInt => {
+                // negating the smallest `int` wraps to itself
+                self.asm(&format!("movl {}, %eax", reg(args[0])));
+                self.asm("movl %eax, %ecx");
+                self.asm("negl %ecx");
+                self.asm("cmovnsl %ecx, %eax");
+                self.asm(&format!("movl %eax, {}", reg(returns[0])));
+                return Ok(())
+            }
+            Intrinsic::RangeNextInt => {
+                self.compare_int("setl", returns[0], args[0], args[1]);
+                return Ok(())
+            }
+            Intrinsic::RangeNextUInt => {
+                self.compare_int("setb", returns[0], args[0], args[1]);
+                return Ok(())
+            }
+            Intrinsic::MinInt => "cmovgl",
+            Intrinsic::MinUInt => "cmoval",
+            Intrinsic::MaxInt => "cmovll",
+            Intrinsic::MaxUInt => "cmovbl",
+            _ => return Err(format!("the builtin `{}` cannot be compiled to native code", intrinsic.name())),
+        };
+        self.asm(&format!("movl {}, %eax", reg(args[0])));
+        self.asm(&format!("cmpl {}, %eax", reg(args[1])));
+        self.asm(&format!("{} {}, %eax", cmov, reg(args[1])));
+        self.asm(&format!("movl %eax, {}", reg(returns[0])));
+        Ok(())
+    }
+    /// Reports `message`, given the line, and exits with 1, as a trap in
+    /// the VM does.
+    fn trap(&mut self, message: &str, line: u32) {
+        self.strings.push(message.to_string());
+        self.asm(&format!("movl ${}, %edx", line));
+        self.asm(&format!("leaq .Lstring{}(%rip), %rsi", self.strings.len() - 1));
+        self.asm("jmp trap");
+    }
+
+    fn main(&mut self, entry: FunctionId, ty: &Type) -> Result<(), String> {
+        self.out.push_str("\t.globl main\n");
+        self.label("main");
+        self.asm("pushq %rbp");
+        self.asm("movq %rsp, %rbp");
+        self.asm("movl $1, depth(%rip)");
+        self.asm(&format!("call function{}", entry.get_id()));
+        self.print(ty)?;
+        self.print_text("\n");
+        match ty {
+            Type::Int(code) => self.asm(&format!("movl {}, %eax", reg(*code))),
+            _ => self.asm("xorl %eax, %eax"),
+        }
+        self.asm("popq %rbp");
+        self.asm("ret");
+        Ok(())
+    }
+    /// Prints a value of `ty` as `VirtualMachine::format_ty` formats it.
+    fn print(&mut self, ty: &Type) -> Result<(), String> {
+        match ty {
+            Type::Int(var) => self.print_value("%d", *var),
+            Type::UInt(var) => self.print_value("%u", *var),
+            Type::Bool(var) => {
+                let (true_text, false_text) = (self.string("true"), self.string("false"));
+                self.asm(&format!("leaq {}(%rip), %rdi", true_text));
+                self.asm(&format!("leaq {}(%rip), %rsi", false_text));
+                self.asm(&format!("cmpl $0, {}", reg(*var)));
+                self.asm("cmoveq %rsi, %rdi");
+                self.asm("xorl %eax, %eax");
+                self.asm("call printf@PLT");
+            }
+            Type::Maybe(tag, ty) => {
+                let (none, done) = (self.fresh_label(), self.fresh_label());
+                self.asm(&format!("cmpl $0, {}", reg(*tag)));
+                self.asm(&format!("je {}", none));
+                self.print(ty)?;
+                self.asm(&format!("jmp {}", done));
+                self.label(&none);
+                self.print_text("none");
+                self.label(&done);
+            }
+            Type::Tuple(types) => {
+                self.print_text("(");
+                for (index, ty) in types.iter().enumerate() {
+                    if index > 0 {
+                        self.print_text(", ");
+                    }
+                    self.print(ty)?;
+                }
+                self.print_text(")");
+            }
+            Type::Struct(fields) => {
+                self.print_text("{ ");
+                for (index, (name, ty)) in fields.iter().enumerate() {
+                    self.print_text(&format!("{}{}: ", if index > 0 { ", " } else { "" }, name));
+                    self.print(ty)?;
+                }
+                self.print_text(" }");
+            }
+            Type::Chan(..) => self.print_text("chan"),
+            Type::Func { .. } => self.print_text("fn"),
+            Type::Builtin(builtin) => self.print_text(&format!("builtin {}", builtin.name())),
+            Type::Float(_) => return Err("a float result cannot be printed by native code".to_string()),
+            Type::Map(..) => return Err("a map result cannot be printed by native code".to_string()),
+        }
+        Ok(())
+    }
+    fn print_value(&mut self, format: &str, var: Var) {
+        self.strings.push(format.to_string());
+        self.asm(&format!("leaq .Lstring{}(%rip), %rdi", self.strings.len() - 1));
+        self.asm(&format!("movl {}, %esi", reg(var)));
+        self.asm("xorl %eax, %eax");
+        self.asm("call printf@PLT");
+    }
+    fn print_text(&mut self, text: &str) {
+        let label = self.string(text);
+        self.asm(&format!("leaq {}(%rip), %rdi", label));
+        self.asm("xorl %eax, %eax");
+        self.asm("call printf@PLT");
+    }
+
+    /// The trap handler, which is jumped to with the message in `%rsi` and
+    /// the line in `%edx`, the register file and the strings.
+    fn runtime(&mut self) {
+        self.label("trap");
+        self.asm("andq $-16, %rsp");
+        self.asm("movl $2, %edi");
+        self.asm("xorl %eax, %eax");
+        self.asm("call dprintf@PLT");
+        self.asm("movl $1, %edi");
+        self.asm("call exit@PLT");
+        self.asm(&format!(".lcomm registers, {}", self.program.get_variable_count().max(1) * 4));
+        self.asm(".lcomm depth, 4");
+        self.asm(".section .rodata");
+        self.label(".Lstack_message");
+        self.asm(&format!(".string \"error: calls nested more than {} deep\\n\"", MAX_CALL_DEPTH));
+        for (index, text) in std::mem::take(&mut self.strings).iter().enumerate() {
+            self.label(&format!(".Lstring{}", index));
+            self.asm(&format!(".string \"{}\"", text.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")));
+        }
+        self.asm(".section .note.GNU-stack,\"\",@progbits");
+    }
+}
+
+/// The memory holding `var` in the register file.
+fn reg(var: Var) -> String {
+    format!("registers+{}(%rip)", var.get_id() * 4)
+}
+
+fn block_label(function: usize, block: usize) -> String {
+    format!(".Lfunction{}_{}", function, block)
+}
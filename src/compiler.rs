@@ -1,152 +1,1462 @@
-use crate::ast::{Parsed, Expr, BinaryOp};
-use crate::{scope::Scope, ir::{Program, Block, Function}, types::{Implementation, Type}};
-use std::{cell::RefCell, rc::Rc};
+use crate::hir::{Annotation, BinOp, CastType, Hir, HirKind, Pattern, PatternKind, Specialization};
+use crate::builtins::{self, call_builtin, Builtin};
+use crate::consteval::{self, Const};
+use crate::capture;
+use crate::ir_parser::SlotType;
+use crate::{scope::Scope, ir::{Program, Block, BlockId, Function, FunctionId, Conversion, Var}, types::{Implementation, Pending, Type}};
+use crate::diagnostics::{self, Diagnostic, Lint};
+use crate::source_map::SourceMap;
+use std::{cell::RefCell, fmt, rc::Rc};
 
 #[derive(Debug)]
 pub struct CompileError<'a> {
     source: &'a str,
     ty: CompileErrorType,
+    labels: Vec<(&'a str, String)>,
+    notes: Vec<String>,
+    help: Option<String>,
 }
 
 #[derive(Debug)]
 pub enum CompileErrorType {
     TypeError,
     UndefinedVariable,
+    UnknownType,
+    InvalidCast,
+    ExpectedDeclaration,
+    /// No function with the name a program is entered by.
+    MissingEntry(String),
+    LiteralOutOfRange,
+    InvalidStructBody,
+    InvalidPattern,
+    Recursion,
+    CapturedReassigned,
+    InvalidIr,
+    InvalidBreak,
+    UnknownSuffix,
+    EscapingCapture,
 }
 
 impl<'a> CompileError<'a> {
+    fn new(source: &'a str, ty: CompileErrorType) -> CompileError<'a> {
+        CompileError { source, ty, labels: Vec::new(), notes: Vec::new(), help: None }
+    }
     pub fn type_error(source: &'a str) -> CompileError<'a> {
-        CompileError { source, ty: CompileErrorType::TypeError }
+        CompileError::new(source, CompileErrorType::TypeError)
     }
     pub fn undefined_variable(source: &'a str) -> CompileError<'a> {
-        CompileError { source, ty: CompileErrorType::UndefinedVariable }
+        CompileError::new(source, CompileErrorType::UndefinedVariable)
+    }
+    pub fn unknown_type(source: &'a str) -> CompileError<'a> {
+        CompileError::new(source, CompileErrorType::UnknownType)
+    }
+    pub fn invalid_cast(source: &'a str) -> CompileError<'a> {
+        CompileError::new(source, CompileErrorType::InvalidCast)
+    }
+    pub fn expected_declaration(source: &'a str) -> CompileError<'a> {
+        CompileError::new(source, CompileErrorType::ExpectedDeclaration)
+    }
+    pub fn missing_entry(source: &'a str, name: &str) -> CompileError<'a> {
+        CompileError::new(source, CompileErrorType::MissingEntry(name.to_string()))
+    }
+    pub fn literal_out_of_range(source: &'a str) -> CompileError<'a> {
+        CompileError::new(source, CompileErrorType::LiteralOutOfRange)
+    }
+    pub fn invalid_struct_body(source: &'a str) -> CompileError<'a> {
+        CompileError::new(source, CompileErrorType::InvalidStructBody)
+    }
+    pub fn invalid_pattern(source: &'a str) -> CompileError<'a> {
+        CompileError::new(source, CompileErrorType::InvalidPattern)
+    }
+    pub fn recursion(source: &'a str) -> CompileError<'a> {
+        CompileError::new(source, CompileErrorType::Recursion)
+    }
+    pub fn captured_reassigned(source: &'a str) -> CompileError<'a> {
+        CompileError::new(source, CompileErrorType::CapturedReassigned)
+    }
+    pub fn invalid_ir(source: &'a str) -> CompileError<'a> {
+        CompileError::new(source, CompileErrorType::InvalidIr)
+    }
+    pub fn invalid_break(source: &'a str) -> CompileError<'a> {
+        CompileError::new(source, CompileErrorType::InvalidBreak)
+    }
+    pub fn unknown_suffix(source: &'a str) -> CompileError<'a> {
+        CompileError::new(source, CompileErrorType::UnknownSuffix)
+    }
+    pub fn escaping_capture(source: &'a str) -> CompileError<'a> {
+        CompileError::new(source, CompileErrorType::EscapingCapture)
+    }
+    /// Points at `source` with an explanation of how it relates to the error.
+    pub fn with_label(mut self, source: &'a str, message: String) -> CompileError<'a> {
+        self.labels.push((source, message));
+        self
+    }
+    pub fn with_note(mut self, note: String) -> CompileError<'a> {
+        self.notes.push(note);
+        self
+    }
+    pub fn with_help(mut self, help: &str) -> CompileError<'a> {
+        self.help = Some(help.to_string());
+        self
+    }
+    pub fn to_diagnostic(&self, files: &SourceMap) -> Diagnostic {
+        let message = match self.ty {
+            CompileErrorType::UndefinedVariable => format!("undefined variable `{}`", self.source),
+            CompileErrorType::UnknownType => format!("unknown type `{}`", self.source),
+            CompileErrorType::CapturedReassigned => format!("`{}` is assigned again after a function captures it", self.source),
+            CompileErrorType::EscapingCapture => format!("a returned function captures `{}`", self.source),
+            _ => self.ty.to_string(),
+        };
+        let span = match self.ty {
+            CompileErrorType::MissingEntry(_) => None,
+            _ => files.span_of(self.source),
+        };
+        let mut diagnostic = Diagnostic::error(message, span).with_code(self.ty.code());
+        for (label, message) in &self.labels {
+            if let Some(span) = files.span_of(label) {
+                diagnostic = diagnostic.with_label(span, message.clone());
+            }
+        }
+        for note in &self.notes {
+            diagnostic = diagnostic.with_note(note.clone());
+        }
+        if let Some(help) = &self.help {
+            diagnostic = diagnostic.with_help(help.clone());
+        }
+        diagnostic
+    }
+}
+
+impl CompileErrorType {
+    pub fn code(&self) -> &'static str {
+        match self {
+            CompileErrorType::UndefinedVariable => "E0001",
+            CompileErrorType::TypeError => "E0002",
+            CompileErrorType::UnknownType => "E0003",
+            CompileErrorType::InvalidCast => "E0004",
+            CompileErrorType::ExpectedDeclaration => "E0005",
+            CompileErrorType::MissingEntry(_) => "E0006",
+            CompileErrorType::LiteralOutOfRange => "E0007",
+            CompileErrorType::InvalidStructBody => "E0008",
+            CompileErrorType::InvalidPattern => "E0009",
+            CompileErrorType::Recursion => "E0010",
+            CompileErrorType::CapturedReassigned => "E0011",
+            CompileErrorType::InvalidIr => "E0012",
+            CompileErrorType::InvalidBreak => "E0013",
+            CompileErrorType::UnknownSuffix => "E0014",
+            CompileErrorType::EscapingCapture => "E0015",
+        }
+    }
+}
+
+impl fmt::Display for CompileErrorType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CompileErrorType::TypeError => write!(f, "mismatched types"),
+            CompileErrorType::UndefinedVariable => write!(f, "undefined variable"),
+            CompileErrorType::UnknownType => write!(f, "unknown type"),
+            CompileErrorType::InvalidCast => write!(f, "invalid cast"),
+            CompileErrorType::ExpectedDeclaration => write!(f, "expected a declaration"),
+            CompileErrorType::MissingEntry(name) => write!(f, "no `{}` function defined", name),
+            CompileErrorType::LiteralOutOfRange => write!(f, "literal out of range"),
+            CompileErrorType::InvalidStructBody => write!(f, "invalid struct body"),
+            CompileErrorType::InvalidPattern => write!(f, "invalid pattern"),
+            CompileErrorType::Recursion => write!(f, "cannot tell what a recursive call returns"),
+            CompileErrorType::CapturedReassigned => write!(f, "captured variable assigned again"),
+            CompileErrorType::InvalidIr => write!(f, "invalid inline IR"),
+            CompileErrorType::InvalidBreak => write!(f, "`break` outside of a loop"),
+            CompileErrorType::UnknownSuffix => write!(f, "unknown literal suffix"),
+            CompileErrorType::EscapingCapture => write!(f, "returned function captures a local"),
+        }
     }
 }
 
+const MAX_SPECIALIZATION_DEPTH: usize = 32;
+
 pub fn call_function<'a, 'b>(imp: &Implementation<'a, 'b>, argument_ty: Type<'a, 'b>, program: &mut Program, block: &mut Block) -> Type<'a, 'b> {
-    let returns = block.call(imp.function, argument_ty.get_used_vars(), program);
+    let returns = block.call(imp.function, argument_ty.get_used_vars(), imp.return_ty.size(), program);
     imp.return_ty.map_to(&returns)
 }
 
-pub fn compile<'a, 'b>(expr: &'b Parsed<'a, Expr<'a>>, scope: &mut Scope<'a, 'b>, program: &mut Program, function: &mut Function, block: &mut Block) -> Result<Type<'a, 'b>, CompileError<'a>> {
-    match expr.get_node() {
-        Expr::IntLiteral(source) => {
-            let value = source.parse::<i32>().unwrap();
-            Ok(Type::Int(block.constant_int(value, program)))
+/// Calls `callee` with an argument of `argument_ty`. A recursive call, into
+/// a specialization that is still being compiled, takes `expected` as its
+/// return type, which is checked once the body is compiled.
+pub fn call_value<'a, 'b>(callee: Type<'a, 'b>, argument_ty: Type<'a, 'b>, expected: Option<&Type<'a, 'b>>, source: &'a str, program: &mut Program, block: &mut Block) -> Result<Type<'a, 'b>, CompileError<'a>> {
+    match callee {
+        Type::Func { param, body, impls, pending, scope } => {
+            for imp in impls.borrow().iter() {
+                if imp.param_ty == argument_ty {
+                    return Ok(call_function(imp, argument_ty, program, block))
+                }
+            }
+            if let Some(recursive) = pending.borrow_mut().iter_mut().find(|pending| pending.param_ty == argument_ty) {
+                let return_ty = match (&recursive.return_ty, expected) {
+                    (Some(return_ty), _) => return_ty.clone(),
+                    (None, Some(expected)) => recursive.return_ty.insert(expected.clone()).clone(),
+                    (None, None) => return Err(CompileError::recursion(source)
+                        .with_note(format!("this calls a function that is still being compiled for `{}`", argument_ty))
+                        .with_help("put the recursive call in the right side of an `else`, so that the left side gives its type")),
+                };
+                let returns = block.call(recursive.function, argument_ty.get_used_vars(), return_ty.size(), program);
+                return Ok(return_ty.map_to(&returns))
+            }
+            // a chain of ever larger argument types would never finish
+            if pending.borrow().len() >= MAX_SPECIALIZATION_DEPTH {
+                return Err(CompileError::recursion(source)
+                    .with_note(format!("this calls a function that is still being compiled, now for `{}`", argument_ty)))
+            }
+            let mut new_function = Function::new();
+            let mut new_block = new_function.new_block();
+            let param_ty = argument_ty.as_parameter_ty(&mut new_function, program);
+            let boundary = scope.closure();
+            let mut function_scope = boundary.clone();
+            match_pattern(param, param_ty.clone(), &mut function_scope)?;
+            let new_function_id = program.reserve_function();
+            pending.borrow_mut().push(Pending { param_ty: argument_ty.clone(), function: new_function_id, return_ty: None });
+            let return_ty = compile(body, &mut function_scope, program, &mut new_function, &mut new_block);
+            let assumed = pending.borrow_mut().pop().and_then(|pending| pending.return_ty);
+            let return_ty = return_ty?;
+            if let Some(error) = escaping_capture(&return_ty, &boundary, &mut Vec::new()) {
+                return Err(error)
+            }
+            if let Some(assumed) = assumed.filter(|assumed| *assumed != return_ty) {
+                return Err(CompileError::type_error(body.source)
+                    .with_note(format!("recursive calls assumed this returns `{}`, but it returns `{}`", assumed, return_ty)))
+            }
+            return_ty.return_ty(&mut new_function);
+            new_block.ret(&mut new_function);
+            let effects = new_function.get_effects();
+            if effects.io {
+                body.performs_io.set(true);
+            }
+            program.define_function(new_function_id, new_function);
+            body.specializations.borrow_mut().push(Specialization {
+                param_ty: argument_ty.to_string(),
+                return_ty: return_ty.to_string(),
+                function: new_function_id,
+            });
+            let imp = Implementation { param_ty, return_ty: return_ty.clone(), function: new_function_id, effects };
+            let return_ty = call_function(&imp, argument_ty, program, block);
+            impls.borrow_mut().push(imp);
+            Ok(return_ty)
+        }
+        // the task is a function of its own that calls the one given, so
+        // that it can be started without knowing how the call is compiled
+        Type::Builtin(Builtin::Spawn) if matches!(argument_ty, Type::Func { .. }) => {
+            let mut task = Function::new();
+            let mut task_block = task.new_block();
+            call_value(argument_ty, Type::Tuple(vec![]), None, source, program, &mut task_block)?;
+            task_block.ret(&mut task);
+            let task_id = program.add_function(task);
+            block.spawn(task_id, program);
+            Ok(Type::Tuple(vec![]))
+        }
+        Type::Builtin(builtin) => {
+            call_builtin(builtin, &argument_ty, program, block).ok_or_else(|| {
+                let error = CompileError::type_error(source)
+                    .with_note(format!("`{}` cannot be called with `{}`", builtin.name(), argument_ty));
+                match &argument_ty {
+                    Type::Tuple(types) if matches!(types.first(), Some(Type::Map(_, None))) => {
+                        error.with_help("a map's key and value types are set by inserting into it, as in `m = insert(m, key, value)`")
+                    }
+                    Type::Chan(_, None) => {
+                        error.with_help("a channel's value type is set by sending on it, as in `c = send(c, value)`")
+                    }
+                    _ => error,
+                }
+            })
+        }
+        callee => Err(CompileError::type_error(source)
+            .with_note(format!("`{}` is not a function", callee)))
+    }
+}
+
+/// A function in `ty`, which a call is returning, that reads a name bound
+/// since `boundary`, the start of the called function's scope. A function
+/// reads what it captures from registers when it is called, and those of
+/// the call that bound them only hold its values until another call does.
+/// The functions already looked in are in `seen`.
+fn escaping_capture<'a, 'b>(ty: &Type<'a, 'b>, boundary: &Scope<'a, 'b>, seen: &mut Vec<&'b Hir<'a>>) -> Option<CompileError<'a>> {
+    match ty {
+        Type::Maybe(_, ty) => escaping_capture(ty, boundary, seen),
+        Type::Tuple(types) => types.iter().find_map(|ty| escaping_capture(ty, boundary, seen)),
+        Type::Struct(fields) => fields.iter().find_map(|(_, ty)| escaping_capture(ty, boundary, seen)),
+        Type::Func { param, body, scope, .. } if !seen.iter().any(|other| std::ptr::eq(*other, *body)) => {
+            seen.push(body);
+            for capture in capture::captures_of(param, body) {
+                let name = match capture.kind {
+                    HirKind::Var(name) if scope.binds_since(name, boundary) => name,
+                    _ => continue,
+                };
+                let error = match scope.get(name) {
+                    // a function is not held in registers, but what it
+                    // captures may be
+                    Some(captured) if captured.size() == 0 => escaping_capture(&captured, boundary, seen),
+                    Some(_) => Some(CompileError::escaping_capture(capture.source)
+                        .with_label(body.source, format!("this function outlives the call that bound `{}`", name))
+                        .with_note(format!("a function reads what it captures when it is called, but `{}` only holds its value until the function that bound it is called again", name))
+                        .with_help(&format!("pass `{}` to the returned function as an argument instead", name))),
+                    None => None,
+                };
+                if error.is_some() {
+                    return error
+                }
+            }
+            None
+        }
+        _ => None,
+    }
+}
+
+/// Compiles a whole source file into a new program, returning the entry
+/// function and the type of the value it returns, or every error found.
+/// Unless the file is a script, the entry function calls the function
+/// named `entry`.
+pub fn compile_program<'a, 'b>(hir: &'b Hir<'a>, script: bool, entry: &str) -> Result<(Program, FunctionId, Type<'a, 'b>), Vec<CompileError<'a>>> {
+    capture::check(hir).map_err(|error| vec![error])?;
+    let mut program = Program::new();
+    let mut function = Function::new();
+    let mut block = function.new_block();
+    let mut scope = builtins::prelude();
+    let ty = if script {
+        compile(hir, &mut scope, &mut program, &mut function, &mut block).map_err(|error| vec![error])?
+    } else {
+        compile_main(hir, entry, &mut scope, &mut program, &mut function, &mut block)?
+    };
+    block.ret(&mut function);
+    ty.return_ty(&mut function);
+    let entry = program.add_function(function);
+    Ok((program, entry, ty))
+}
+
+/// Compiles a program written as a list of declarations, then calls the
+/// function it declares named `entry`, usually `main`, with no arguments. A declaration that fails to compile
+/// is forgotten and the rest are still compiled, to find more errors, but
+/// those that read a name it binds are skipped, since they would only
+/// report that the name is undefined.
+pub fn compile_main<'a, 'b>(expr: &'b Hir<'a>, entry: &str, scope: &mut Scope<'a, 'b>, program: &mut Program, function: &mut Function, block: &mut Block) -> Result<Type<'a, 'b>, Vec<CompileError<'a>>> {
+    let declarations = match &expr.kind {
+        HirKind::Block(exprs) => exprs.iter().collect(),
+        _ => vec![expr],
+    };
+    declare_functions(declarations.iter().copied(), scope);
+    let mut errors = Vec::new();
+    let mut failed = Vec::new();
+    // the names declared, which the entry function is looked up among
+    let mut declared = Vec::new();
+    for declaration in declarations {
+        let bound = match &declaration.kind {
+            HirKind::Func { name: Some(name), .. } => vec![*name],
+            HirKind::Assign { pattern, .. } => {
+                let mut names = Vec::new();
+                bound_names(pattern, &mut names);
+                names
+            }
+            // what a `cfg` that is turned off lowers to
+            HirKind::Tuple(exprs) if exprs.is_empty() => continue,
+            _ => {
+                errors.push(CompileError::expected_declaration(declaration.source)
+                    .with_help("move this expression into `main`, or pass `--script` to run the file as one expression"));
+                continue
+            }
+        };
+        declared.extend(bound.iter().copied());
+        if reads_any(declaration, &failed) {
+            failed.extend(bound);
+            continue
+        }
+        let snapshot = scope.snapshot();
+        if let Err(error) = compile(declaration, scope, program, function, block) {
+            scope.rollback(snapshot);
+            errors.push(error);
+            failed.extend(bound);
+        }
+    }
+    if failed.contains(&entry) {
+        return Err(errors)
+    }
+    let result = match declared.into_iter().find(|&name| name == entry).and_then(|name| scope.get(name)) {
+        Some(value) => call_value(value, Type::Tuple(vec![]), None, expr.source, program, block),
+        None => Err(CompileError::missing_entry(expr.source, entry)
+            .with_help(&format!("add a function such as `fn {}() 0`, or pass `--script` to run the file as one expression", entry))),
+    };
+    match result {
+        Ok(ty) if errors.is_empty() => Ok(ty),
+        Ok(_) => Err(errors),
+        Err(error) => {
+            errors.push(error);
+            Err(errors)
         }
-        Expr::Binary { left, right, op } => match op {
-            BinaryOp::Plus => {
-                let left = compile(left, scope, program, function, block)?;
-                let right = compile(right, scope, program, function, block)?;
-                match (left, right) {
+    }
+}
+
+/// Whether `expr` reads any of `names` from the scope it runs in.
+fn reads_any(expr: &Hir, names: &[&str]) -> bool {
+    capture::reads(expr).iter().any(|var| matches!(var.kind, HirKind::Var(name) if names.contains(&name)))
+}
+
+pub fn compile<'a, 'b>(expr: &'b Hir<'a>, scope: &mut Scope<'a, 'b>, program: &mut Program, function: &mut Function, block: &mut Block) -> Result<Type<'a, 'b>, CompileError<'a>> {
+    compile_expecting(expr, None, scope, program, function, block)
+}
+
+/// `compile`, with the type the value is expected to have if it is known,
+/// which a recursive call needs as its return type.
+fn compile_expecting<'a, 'b>(expr: &'b Hir<'a>, expected: Option<&Type<'a, 'b>>, scope: &mut Scope<'a, 'b>, program: &mut Program, function: &mut Function, block: &mut Block) -> Result<Type<'a, 'b>, CompileError<'a>> {
+    let line = program.set_line(expr.line);
+    let ty = compile_node(expr, expected, scope, program, function, block);
+    program.set_line(line);
+    let ty = ty?;
+    let definition = match expr.kind {
+        HirKind::Var(name) => scope.definition(name),
+        _ => None,
+    };
+    expr.annotate(Annotation { ty: ty.to_string(), definition });
+    Ok(ty)
+}
+
+fn compile_node<'a, 'b>(expr: &'b Hir<'a>, expected: Option<&Type<'a, 'b>>, scope: &mut Scope<'a, 'b>, program: &mut Program, function: &mut Function, block: &mut Block) -> Result<Type<'a, 'b>, CompileError<'a>> {
+    if let HirKind::Binary { .. } | HirKind::Cast { .. } | HirKind::Not { .. } = expr.kind {
+        if let Some(value) = consteval::eval(expr) {
+            annotate_folded(expr);
+            return Ok(constant(&value, program, block))
+        }
+    }
+    match &expr.kind {
+        HirKind::Int(value) => Ok(Type::Int(block.constant_int(*value, program))),
+        HirKind::UInt(value) => Ok(Type::UInt(block.constant_int(*value as i32, program))),
+        HirKind::Float(value) => Ok(Type::Float(block.constant_float(*value, program))),
+        HirKind::Bool(value) => Ok(Type::Bool(block.constant_int(if *value { 1 } else { 0 }, program))),
+        // the operands of most operators have the type of the result or of
+        // each other, which is what a recursive call among them returns
+        HirKind::Binary { op, left, right } => match op {
+            BinOp::Add => {
+                let left_ty = compile_expecting(left, expected, scope, program, function, block)?;
+                let right_ty = compile_expecting(right, Some(&left_ty), scope, program, function, block)?;
+                match (left_ty, right_ty) {
                     (Type::Int(a), Type::Int(b)) => Ok(Type::Int(block.add_int(a, b, program))),
-                    _ => Err(CompileError::type_error(expr.get_source()))
+                    (Type::UInt(a), Type::UInt(b)) => Ok(Type::UInt(block.add_int(a, b, program))),
+                    (Type::Float(a), Type::Float(b)) => Ok(Type::Float(block.add_float(a, b, program))),
+                    (left_ty, right_ty) => Err(operand_mismatch(expr, (left, &left_ty), (right, &right_ty))),
                 }
             }
-            BinaryOp::Bracket => {
-                match compile(left, scope, program, function, block)? {
-                    Type::Func { pattern, expr, impls } => {
-                        let argument_ty = compile(right, scope, program, function, block)?;
-                        for imp in impls.borrow().iter() {
-                            if imp.param_ty == argument_ty {
-                                return Ok(call_function(imp, argument_ty, program, block))
-                            }
-                        }
-                        let mut new_function = Function::new();
-                        let mut new_block = new_function.new_block();
-                        let param_ty = argument_ty.as_parameter_ty(&mut new_function, program);
-                        let mut function_scope = Scope::new();
-                        match_pattern(pattern, param_ty.clone(), &mut function_scope)?;
-                        let return_ty = compile(expr, &mut function_scope, program, &mut new_function, &mut new_block)?;
-                        return_ty.return_ty(&mut new_function);
-                        new_block.ret(&mut new_function);
-                        let new_function_id = program.add_function(new_function);
-                        let imp = Implementation { param_ty, return_ty: return_ty.clone(), function: new_function_id };
-                        let return_ty = call_function(&imp, argument_ty, program, block);
-                        impls.borrow_mut().push(imp);
-                        Ok(return_ty)
-                    }
-                    _ => Err(CompileError::type_error(expr.get_source()))
+            BinOp::Sub | BinOp::Mul | BinOp::Div | BinOp::Rem => {
+                let left_ty = compile_expecting(left, expected, scope, program, function, block)?;
+                let right_ty = compile_expecting(right, Some(&left_ty), scope, program, function, block)?;
+                let (int, uint, float): (Emit, Emit, Emit) = match op {
+                    BinOp::Sub => (Block::sub_int, Block::sub_int, Block::sub_float),
+                    BinOp::Mul => (Block::mul_int, Block::mul_int, Block::mul_float),
+                    BinOp::Div => (Block::div_int, Block::div_uint, Block::div_float),
+                    _ => (Block::rem_int, Block::rem_uint, Block::rem_float),
+                };
+                match (left_ty, right_ty) {
+                    (Type::Int(a), Type::Int(b)) => Ok(Type::Int(int(block, a, b, program))),
+                    (Type::UInt(a), Type::UInt(b)) => Ok(Type::UInt(uint(block, a, b, program))),
+                    (Type::Float(a), Type::Float(b)) => Ok(Type::Float(float(block, a, b, program))),
+                    (left_ty, right_ty) => Err(operand_mismatch(expr, (left, &left_ty), (right, &right_ty))),
+                }
+            }
+            // `a != b` is `(a == b) ^ true`, which also holds for NaN
+            BinOp::Eq | BinOp::Ne => {
+                let left_ty = compile(left, scope, program, function, block)?;
+                let right_ty = compile_expecting(right, Some(&left_ty), scope, program, function, block)?;
+                let result = emit_eq(&left_ty, &right_ty, program, function, block)
+                    .ok_or_else(|| operand_mismatch(expr, (left, &left_ty), (right, &right_ty)))?;
+                if let BinOp::Ne = op {
+                    let one = block.constant_int(1, program);
+                    return Ok(Type::Bool(block.xor_int(result, one, program)))
+                }
+                Ok(Type::Bool(result))
+            }
+            // `a > b` is `b < a`
+            BinOp::Lt | BinOp::Le | BinOp::Gt | BinOp::Ge => {
+                let left_ty = compile(left, scope, program, function, block)?;
+                let right_ty = compile_expecting(right, Some(&left_ty), scope, program, function, block)?;
+                let (int, uint, float): (Emit, Emit, Emit) = match op {
+                    BinOp::Lt | BinOp::Gt => (Block::less_int, Block::less_uint, Block::less_float),
+                    _ => (Block::less_eq_int, Block::less_eq_uint, Block::less_eq_float),
+                };
+                let (emit, a, b) = match (left_ty, right_ty) {
+                    (Type::Int(a), Type::Int(b)) => (int, a, b),
+                    (Type::UInt(a), Type::UInt(b)) => (uint, a, b),
+                    (Type::Float(a), Type::Float(b)) => (float, a, b),
+                    (left_ty, right_ty) => return Err(operand_mismatch(expr, (left, &left_ty), (right, &right_ty))),
+                };
+                Ok(Type::Bool(match op {
+                    BinOp::Gt | BinOp::Ge => emit(block, b, a, program),
+                    _ => emit(block, a, b, program),
+                }))
+            }
+            // the right side gets a block of its own, and the result is
+            // picked from whichever side decided it
+            BinOp::And | BinOp::Or => {
+                let a = match compile(left, scope, program, function, block)? {
+                    Type::Bool(a) => a,
+                    ty => return Err(CompileError::type_error(expr.source)
+                        .with_label(left.source, format!("expected `bool`, found `{}`", ty))),
+                };
+                let mut right_block = function.new_block();
+                let exit_block = function.new_block();
+                let (when_true, when_false) = match op {
+                    BinOp::And => (right_block.get_id(), exit_block.get_id()),
+                    _ => (exit_block.get_id(), right_block.get_id()),
+                };
+                let from_left = block.get_id();
+                block.clone().conditional_branch(a, when_true, when_false, function);
+                let mut right_scope = scope.arm();
+                let b = match compile_expecting(right, Some(&Type::Bool(a)), &mut right_scope, program, function, &mut right_block)? {
+                    Type::Bool(b) => b,
+                    ty => return Err(CompileError::type_error(expr.source)
+                        .with_label(right.source, format!("expected `bool`, found `{}`", ty))),
+                };
+                let from_right = right_block.get_id();
+                right_block.branch(exit_block.get_id(), function);
+                *block = exit_block;
+                join_assigned(right, &right_scope, from_right, scope, from_left, program, block)?;
+                Ok(Type::Bool(match op {
+                    BinOp::And => block.select(a, b, a, program),
+                    _ => block.select(a, a, b, program),
+                }))
+            }
+            BinOp::BitAnd | BinOp::BitOr | BinOp::BitXor => {
+                let left_ty = compile_expecting(left, expected, scope, program, function, block)?;
+                let right_ty = compile_expecting(right, Some(&left_ty), scope, program, function, block)?;
+                let emit = match op {
+                    BinOp::BitAnd => Block::and_int,
+                    BinOp::BitOr => Block::or_int,
+                    _ => Block::xor_int,
+                };
+                match (left_ty, right_ty) {
+                    (Type::Int(a), Type::Int(b)) => Ok(Type::Int(emit(block, a, b, program))),
+                    (Type::UInt(a), Type::UInt(b)) => Ok(Type::UInt(emit(block, a, b, program))),
+                    (Type::Bool(a), Type::Bool(b)) => Ok(Type::Bool(emit(block, a, b, program))),
+                    (left_ty, right_ty) => Err(operand_mismatch(expr, (left, &left_ty), (right, &right_ty))),
+                }
+            }
+            BinOp::ShiftLeft | BinOp::ShiftRight => {
+                let left_ty = compile_expecting(left, expected, scope, program, function, block)?;
+                let amount = match compile(right, scope, program, function, block)? {
+                    Type::Int(amount) | Type::UInt(amount) => amount,
+                    ty => return Err(CompileError::type_error(expr.source)
+                        .with_label(right.source, format!("expected `int` or `uint`, found `{}`", ty))),
+                };
+                match (left_ty, op) {
+                    (Type::Int(a), BinOp::ShiftLeft) => Ok(Type::Int(block.shift_left(a, amount, program))),
+                    (Type::UInt(a), BinOp::ShiftLeft) => Ok(Type::UInt(block.shift_left(a, amount, program))),
+                    (Type::Int(a), _) => Ok(Type::Int(block.shift_right_arithmetic(a, amount, program))),
+                    (Type::UInt(a), _) => Ok(Type::UInt(block.shift_right_logical(a, amount, program))),
+                    (ty, _) => Err(CompileError::type_error(expr.source)
+                        .with_label(left.source, format!("expected `int` or `uint`, found `{}`", ty))),
                 }
-            },
-            BinaryOp::SingleEquals => {
-                let ty = compile(right, scope, program, function, block)?;
-                match_pattern(left, ty.clone(), scope)?;
-                Ok(ty)
             }
-            BinaryOp::Else => {
-                if let Type::Maybe(tag, ty) = compile(left, scope, program, function, block)? {
+        }
+        HirKind::Call { callee, argument } => {
+            let callee = compile(callee, scope, program, function, block)?;
+            let argument_ty = compile(argument, scope, program, function, block)?;
+            call_value(callee, argument_ty, expected, expr.source, program, block)
+        }
+        // every value is held in registers of its own, so binding a name
+        // never changes a value computed before it, and `(a, b) = (b, a)`
+        // swaps without copying
+        HirKind::Assign { pattern, value } => {
+            let ty = compile(value, scope, program, function, block)?;
+            match_pattern(pattern, ty.clone(), scope)?;
+            Ok(ty)
+        }
+        HirKind::Else { value, default } => {
+            let value_ty = compile(value, scope, program, function, block)?;
+            if let Type::Maybe(tag, ty) = value_ty {
+                let always = match &value.kind {
+                    HirKind::If { cond, .. } => constant_condition(cond),
+                    _ => None,
+                };
+                // the two sides meet in a block of their own, with phis
+                // taking each value from the side that ran
+                let (conc, merged) = if always == Some(true) {
+                    let conc = compile_unreachable(default, Some(&ty), &mut scope.arm(), program)?;
+                    let merged = Type::merge(tag, &ty, &conc, program, block);
+                    (conc, merged)
+                } else if always == Some(false) || is_trivial(default) {
+                    let conc = compile_expecting(default, Some(&ty), scope, program, function, block)?;
+                    let merged = Type::merge(tag, &ty, &conc, program, block);
+                    (conc, merged)
+                } else {
                     let mut cond_block = function.new_block();
                     let exit_block = function.new_block();
+                    let from_value = block.get_id();
                     block.clone().conditional_branch(tag, exit_block.get_id(), cond_block.get_id(), function);
-                    let conc = compile(right, scope, program, function, &mut cond_block)?;
+                    let mut default_scope = scope.arm();
+                    let conc = compile_expecting(default, Some(&ty), &mut default_scope, program, function, &mut cond_block)?;
+                    let from_default = cond_block.get_id();
                     cond_block.branch(exit_block.get_id(), function);
                     *block = exit_block;
-                    Ok(Type::merge(tag, &*ty, &conc, program, block))
-                } else {
-                    Err(CompileError::type_error(expr.get_source()))
+                    join_assigned(default, &default_scope, from_default, scope, from_value, program, block)?;
+                    let merged = Type::join(&ty, from_value, &conc, from_default, program, block);
+                    (conc, merged)
+                };
+                merged.ok_or_else(|| CompileError::type_error(expr.source)
+                    .with_label(value.source, format!("this is `maybe {}`", ty))
+                    .with_label(default.source, format!("this is `{}`", conc))
+                    .with_note("both sides of `else` must have the same type".to_string()))
+            } else {
+                Err(CompileError::type_error(expr.source)
+                    .with_label(value.source, format!("expected a `maybe` value, found `{}`", value_ty))
+                    .with_note("`else` supplies a value for when an `if` without an `else` did not run".to_string()))
+            }
+        }
+        HirKind::Unwrap { value } => match compile(value, scope, program, function, block)? {
+            Type::Maybe(tag, ty) => {
+                let always = match &value.kind {
+                    HirKind::If { cond, .. } => constant_condition(cond),
+                    _ => None,
+                };
+                if always != Some(true) {
+                    let mut trap_block = function.new_block();
+                    let exit_block = function.new_block();
+                    block.clone().conditional_branch(tag, exit_block.get_id(), trap_block.get_id(), function);
+                    trap_block.abort(program);
+                    trap_block.ret(function);
+                    *block = exit_block;
                 }
-            },
+                Ok(*ty)
+            }
+            value_ty => Err(CompileError::type_error(expr.source)
+                .with_label(value.source, format!("expected a `maybe` value, found `{}`", value_ty))
+                .with_note("`else trap` stops the program when an `if` without an `else` did not run".to_string())),
         }
-        Expr::If { cond, conc } => {
-            if let Type::Bool(cond) = compile(cond, scope, program, function, block)? {
+        HirKind::If { cond, conc } => {
+            let cond_ty = compile(cond, scope, program, function, block)?;
+            if let Type::Bool(cond_var) = cond_ty {
+                let expected = match expected {
+                    Some(Type::Maybe(_, ty)) => Some(&**ty),
+                    _ => None,
+                };
+                let always = constant_condition(cond);
+                if always == Some(false) {
+                    let conc = compile_unreachable(conc, expected, &mut scope.arm(), program)?;
+                    return Ok(Type::Maybe(cond_var, Box::new(conc)))
+                }
+                if always == Some(true) || is_trivial(conc) {
+                    let conc = compile_expecting(conc, expected, scope, program, function, block)?;
+                    return Ok(Type::Maybe(cond_var, Box::new(conc)))
+                }
                 let mut cond_block = function.new_block();
                 let exit_block = function.new_block();
-                block.clone().conditional_branch(cond, cond_block.get_id(), exit_block.get_id(), function);
-                let conc = compile(conc, scope, program, function, &mut cond_block)?;
+                let from_cond = block.get_id();
+                block.clone().conditional_branch(cond_var, cond_block.get_id(), exit_block.get_id(), function);
+                let mut conc_scope = scope.arm();
+                let conc_ty = compile_expecting(conc, expected, &mut conc_scope, program, function, &mut cond_block)?;
+                let from_conc = cond_block.get_id();
                 cond_block.branch(exit_block.get_id(), function);
                 *block = exit_block;
-                Ok(Type::Maybe(cond, Box::new(conc)))
+                join_assigned(conc, &conc_scope, from_conc, scope, from_cond, program, block)?;
+                Ok(Type::Maybe(cond_var, Box::new(conc_ty)))
             } else {
-                Err(CompileError::type_error(expr.get_source()))
+                Err(CompileError::type_error(expr.source)
+                    .with_label(cond.source, format!("expected `bool`, found `{}`", cond_ty)))
             }
-        },
-        Expr::Tuple { exprs } => {
+        }
+        HirKind::Match { value, arms } => {
+            let value_ty = compile(value, scope, program, function, block)?;
+            let (ty, end) = compile_arms(arms, vec![block.clone()], &value_ty, expected, scope, program, function)?;
+            *block = end;
+            Ok(ty)
+        }
+        // the state is copied into registers of its own, which each pass
+        // overwrites with the state `next` gives back, and names from
+        // outside the loop that the body assigns are carried as in `while`
+        HirKind::For { label, pattern, iterable, body } => {
+            let iterable_ty = compile(iterable, scope, program, function, block)?;
+            let (next, state) = builtins::iterator(&iterable_ty, program, block).ok_or_else(|| {
+                let error = CompileError::type_error(expr.source)
+                    .with_label(iterable.source, format!("`{}` cannot be looped over", iterable_ty))
+                    .with_note("a loop goes over a map, a `range(start, end)`, or a `(next, state)` pair whose `next(state)` gives `maybe (value, state)`".to_string());
+                match iterable_ty {
+                    Type::Map(_, None) => error.with_help("a map's key and value types come from an `insert` whose result is in scope, as in `m = insert(m, key, value)`"),
+                    _ => error,
+                }
+            })?;
+            let carried: Vec<Var> = (0..state.size()).map(|_| program.new_variable()).collect();
+            copy_state(&carried, state.get_used_vars(), program, block);
+            let state = state.map_to(&carried);
+            let mut names = Vec::new();
+            body_assigned_names(pattern, body, &mut names);
+            let assigned: Rc<[_]> = carried_names(names, scope, program, block).into();
+            let mut header = function.new_block();
+            let header_id = header.get_id();
+            let mut body_block = function.new_block();
+            let exit_block = function.new_block();
+            block.clone().branch(header_id, function);
+            let step = call_value(next, state.clone(), None, iterable.source, program, &mut header)?;
+            let (more, value, next_state) = match step {
+                Type::Maybe(more, step) => match *step {
+                    Type::Tuple(mut types) if types.len() == 2 && types[1] == state => {
+                        let next_state = types.pop().unwrap();
+                        (more, types.pop().unwrap(), next_state)
+                    }
+                    step => return Err(CompileError::type_error(expr.source)
+                        .with_label(iterable.source, format!("`next` gives `maybe {}`", step))
+                        .with_note(format!("`next` must give `maybe (value, state)`, with a state of `{}`", state))),
+                },
+                step => return Err(CompileError::type_error(expr.source)
+                    .with_label(iterable.source, format!("`next` gives `{}`", step))
+                    .with_note("`next` must give `maybe (value, state)`, which is empty once there are no more values".to_string())),
+            };
+            header.conditional_branch(more, body_block.get_id(), exit_block.get_id(), function);
+            let mut body_scope = scope.loop_body(*label, exit_block.get_id(), Rc::clone(&assigned));
+            match_pattern(pattern, value, &mut body_scope)?;
+            compile(body, &mut body_scope, program, function, &mut body_block)?;
+            carry(&assigned, &body_scope, body.source, program, &mut body_block)?;
+            copy_state(&carried, next_state.get_used_vars(), program, &mut body_block);
+            body_block.branch(header_id, function);
+            *block = exit_block;
+            Ok(Type::Tuple(vec![]))
+        }
+        // names from outside the loop that it assigns are carried in
+        // registers of their own, which the condition, the end of the body
+        // and each `break` copy their values into
+        HirKind::While { label, cond, body } => {
+            let mut names = Vec::new();
+            assigned_names(cond, &mut names);
+            assigned_names(body, &mut names);
+            let carried = carried_names(names, scope, program, block);
+            let mut header = function.new_block();
+            let header_id = header.get_id();
+            let mut body_block = function.new_block();
+            let exit_block = function.new_block();
+            block.clone().branch(header_id, function);
+            let carried: Rc<[_]> = carried.into();
+            let mut body_scope = scope.loop_body(*label, exit_block.get_id(), Rc::clone(&carried));
+            let cond_var = match compile(cond, &mut body_scope, program, function, &mut header)? {
+                Type::Bool(var) => var,
+                cond_ty => return Err(CompileError::type_error(expr.source)
+                    .with_label(cond.source, format!("expected `bool`, found `{}`", cond_ty))),
+            };
+            carry(&carried, &body_scope, cond.source, program, &mut header)?;
+            header.conditional_branch(cond_var, body_block.get_id(), exit_block.get_id(), function);
+            compile(body, &mut body_scope, program, function, &mut body_block)?;
+            carry(&carried, &body_scope, body.source, program, &mut body_block)?;
+            body_block.branch(header_id, function);
+            *block = exit_block;
+            Ok(Type::Tuple(vec![]))
+        }
+        // the code after a `break` goes in a block nothing branches to
+        HirKind::Break { label } => {
+            let (exit, carried) = scope.loop_exit(*label).ok_or_else(|| match label {
+                Some(label) => CompileError::invalid_break(expr.source)
+                    .with_note(format!("no loop around this `break` is labelled `{}`", label)),
+                None => CompileError::invalid_break(expr.source)
+                    .with_note("`break` can only leave a loop in the same function".to_string()),
+            })?;
+            // the `defer`s of the blocks it leaves run first, and are not
+            // pending while they do, so a `break` in one does not run it again
+            let defers = scope.pending_defers(*label);
+            for (deferring, _) in &defers {
+                deferring.set_pending(false);
+            }
+            for (_, deferred) in &defers {
+                for expr in *deferred {
+                    compile(expr, scope, program, function, block)?;
+                }
+            }
+            for (deferring, _) in &defers {
+                deferring.set_pending(true);
+            }
+            carry(&carried, scope, expr.source, program, block)?;
+            let after = function.new_block();
+            std::mem::replace(block, after).branch(exit, function);
+            Ok(Type::Tuple(vec![]))
+        }
+        HirKind::Deferred { items, deferred } => {
+            let deferring = scope.deferring(deferred);
+            *scope = deferring.clone();
+            let (last, items) = items.split_last().unwrap();
+            for item in items {
+                compile(item, scope, program, function, block)?;
+            }
+            let ty = compile_expecting(last, expected, scope, program, function, block)?;
+            deferring.set_pending(false);
+            for expr in deferred {
+                compile(expr, scope, program, function, block)?;
+            }
+            Ok(ty)
+        }
+        HirKind::Tuple(exprs) => {
+            let expected = match expected {
+                Some(Type::Tuple(types)) if types.len() == exprs.len() => types.iter().map(Some).collect(),
+                _ => vec![None; exprs.len()],
+            };
             let mut types = Vec::new();
-            for expr in exprs {
-                types.push(compile(expr, scope, program, function, block)?)
+            for (expr, expected) in exprs.iter().zip(expected) {
+                types.push(compile_expecting(expr, expected, scope, program, function, block)?)
             }
             Ok(Type::Tuple(types))
         }
-        Expr::Block { exprs, last } => {
+        HirKind::Struct(fields) => {
+            let mut types = Vec::new();
+            for (name, value) in fields {
+                types.push((Rc::from(*name), compile(value, scope, program, function, block)?))
+            }
+            Ok(Type::Struct(types))
+        }
+        HirKind::Field { value, name } => match compile(value, scope, program, function, block)? {
+            Type::Struct(fields) => match fields.iter().find(|(field, _)| &**field == *name) {
+                Some((_, ty)) => Ok(ty.clone()),
+                None => {
+                    let names: Vec<String> = fields.iter().map(|(field, _)| format!("`{}`", field)).collect();
+                    let note = if names.is_empty() {
+                        "the struct has no fields".to_string()
+                    } else {
+                        format!("the struct has the fields {}", names.join(", "))
+                    };
+                    Err(CompileError::type_error(expr.source)
+                        .with_label(value.source, format!("this struct has no field `{}`", name))
+                        .with_note(note))
+                }
+            }
+            value_ty => Err(CompileError::type_error(expr.source)
+                .with_label(value.source, format!("expected a struct with a field `{}`, found `{}`", name, value_ty))),
+        }
+        HirKind::Block(exprs) => {
+            declare_functions(exprs.iter(), scope);
+            let (last, exprs) = exprs.split_last().unwrap();
             for expr in exprs {
                 compile(expr, scope, program, function, block)?;
             }
-            compile(last, scope, program, function, block)
-        },
-        Expr::Ident(source) => match scope.get(source) {
+            compile_expecting(last, expected, scope, program, function, block)
+        }
+        HirKind::Var(name) => match scope.get(name) {
             Some(ty) => Ok(ty),
-            None => Err(CompileError::undefined_variable(expr.get_source())),
+            None => Err(CompileError::undefined_variable(expr.source)),
         }
-        Expr::Func { name, pattern, expr } => {
-            let func = Type::Func { pattern, expr, impls: Rc::new(RefCell::new(Vec::new())) };
-            match name {
-                Some(name) => scope.assign(name, func.clone()),
-                None => (),
+        HirKind::Func { name: Some(name), param, body } => {
+            let func = match scope.get(name) {
+                // declared at the start of the block, but it should see the
+                // names bound before it as well, so only its specializations
+                // are kept
+                Some(Type::Func { body: declared, impls, pending, .. }) if std::ptr::eq(declared, &**body) => {
+                    Type::Func { param, body, impls, pending, scope: scope.clone() }
+                }
+                _ => function_type(param, body, scope),
             };
+            scope.assign(name, func.clone());
             Ok(func)
+        }
+        HirKind::Func { name: None, param, body } => Ok(function_type(param, body, scope)),
+        HirKind::Not { value } => match compile(value, scope, program, function, block)? {
+            Type::Bool(value) => {
+                let one = block.constant_int(1, program);
+                Ok(Type::Bool(block.xor_int(value, one, program)))
+            }
+            ty => Err(CompileError::type_error(expr.source)
+                .with_label(value.source, format!("expected `bool`, found `{}`", ty))),
+        }
+        HirKind::Cast { value, ty } => {
+            let value_ty = compile(value, scope, program, function, block)?;
+            match (value_ty, ty) {
+                (Type::Int(var), CastType::Int) | (Type::UInt(var), CastType::Int) | (Type::Bool(var), CastType::Int) => Ok(Type::Int(var)),
+                (Type::Int(var), CastType::UInt) | (Type::UInt(var), CastType::UInt) | (Type::Bool(var), CastType::UInt) => Ok(Type::UInt(var)),
+                (Type::Float(var), CastType::Float) => Ok(Type::Float(var)),
+                (Type::Bool(var), CastType::Bool) => Ok(Type::Bool(var)),
+                (Type::Int(var), CastType::Float) => Ok(Type::Float(block.convert(var, Conversion::IntToFloat, program))),
+                (Type::UInt(var), CastType::Float) => Ok(Type::Float(block.convert(var, Conversion::UIntToFloat, program))),
+                (Type::Float(var), CastType::Int) => Ok(Type::Int(block.convert(var, Conversion::FloatToInt, program))),
+                (Type::Float(var), CastType::UInt) => Ok(Type::UInt(block.convert(var, Conversion::FloatToUInt, program))),
+                (Type::Bool(_), CastType::Float) => Err(CompileError::invalid_cast(expr.source)
+                    .with_label(value.source, "this is `bool`".to_string())
+                    .with_help("cast to `int` first, as in `x as int as float`")),
+                (value_ty, ty) => Err(CompileError::invalid_cast(expr.source)
+                    .with_label(value.source, format!("this is `{}`", value_ty))
+                    .with_note(format!("`{}` cannot be cast to `{}`", value_ty, ty))),
+            }
+        }
+        // each slot of the IR gets a register: the one its input is already
+        // in, or a new one for a definition
+        HirKind::InlineIr { ir, inputs } => {
+            let mut inputs = inputs.iter();
+            let mut types = Vec::new();
+            for slot in &ir.slots {
+                types.push(match slot.input {
+                    true => {
+                        let input = inputs.next().unwrap();
+                        match compile(input, scope, program, function, block)? {
+                            ty @ (Type::Int(_) | Type::UInt(_) | Type::Float(_) | Type::Bool(_)) => Some(ty),
+                            ty => return Err(CompileError::type_error(input.source)
+                                .with_label(input.source, format!("expected a number or a `bool`, found `{}`", ty))
+                                .with_note("inline IR can only read values held in a single register".to_string())),
+                        }
+                    }
+                    false => None,
+                });
+            }
+            let vars: Vec<Var> = types.iter().map(|ty| match ty {
+                Some(ty) => ty.get_used_vars()[0],
+                None => program.new_variable(),
+            }).collect();
+            for line in &ir.instructions {
+                if let Some((slot, ty)) = line.defines {
+                    let var = vars[slot.get_id()];
+                    types[slot.get_id()] = Some(match ty {
+                        SlotType::Is(CastType::Int) => Type::Int(var),
+                        SlotType::Is(CastType::UInt) => Type::UInt(var),
+                        SlotType::Is(CastType::Float) => Type::Float(var),
+                        SlotType::Is(CastType::Bool) => Type::Bool(var),
+                        SlotType::SameAs(other) => types[other.get_id()].as_ref().unwrap().map_to(&[var]),
+                    });
+                }
+                let outer = program.set_line(expr.line + line.offset);
+                block.splice(&line.instruction, &vars, program);
+                program.set_line(outer);
+            }
+            for (name, slot) in ir.bindings() {
+                if !ir.slots[slot.get_id()].input {
+                    scope.assign(name, types[slot.get_id()].clone().unwrap());
+                }
+            }
+            // the value of the block is the last value it defines
+            Ok(match ir.instructions.iter().rev().find_map(|line| line.defines) {
+                Some((slot, _)) => types[slot.get_id()].clone().unwrap(),
+                None => Type::Tuple(vec![]),
+            })
+        }
+    }
+}
+
+fn function_type<'a, 'b>(param: &'b Pattern<'a>, body: &'b Hir<'a>, scope: &Scope<'a, 'b>) -> Type<'a, 'b> {
+    Type::Func {
+        param,
+        body,
+        impls: Rc::new(RefCell::new(Vec::new())),
+        pending: Rc::new(RefCell::new(Vec::new())),
+        scope: scope.clone(),
+    }
+}
+
+/// Binds every named function among `items` before any of them is compiled,
+/// so that an item can call a function declared after it. The names are
+/// bound first and then filled in place, so each function's scope sees all
+/// of them.
+fn declare_functions<'a, 'b>(items: impl Iterator<Item = &'b Hir<'a>> + Clone, scope: &mut Scope<'a, 'b>) {
+    let functions = items.flat_map(block_items).filter_map(|item| match &item.kind {
+        HirKind::Func { name: Some(name), param, body } => Some((*name, param, &**body)),
+        _ => None,
+    });
+    for (name, _, _) in functions.clone() {
+        scope.assign(name, Type::Tuple(vec![]));
+    }
+    for (name, param, body) in functions {
+        let func = function_type(param, body, scope);
+        scope.assign(name, func);
+    }
+}
+
+/// The item, or the items of the block it is the rest of after a `defer`.
+fn block_items<'a, 'b>(item: &'b Hir<'a>) -> Vec<&'b Hir<'a>> {
+    let mut items = vec![item];
+    while let Some(HirKind::Deferred { items: rest, .. }) = items.last().map(|item| &item.kind) {
+        items.pop();
+        items.extend(rest);
+    }
+    items
+}
+
+/// The value of a condition that is known at compile time.
+fn constant_condition(cond: &Hir) -> Option<bool> {
+    match consteval::eval(cond) {
+        Some(Const::Bool(value)) => Some(value),
+        _ => None,
+    }
+}
+
+/// Gives each name from outside a branch that `branch` assigns the value it
+/// has after the branch, joining its value in `taken`, from the end of the
+/// branch, with its value in `scope`, from where the branch was skipped.
+/// Names the branch binds for the first time are only bound inside it.
+fn join_assigned<'a, 'b>(branch: &'b Hir<'a>, taken: &Scope<'a, 'b>, from_taken: BlockId, scope: &mut Scope<'a, 'b>, from_skipped: BlockId, program: &mut Program, block: &mut Block) -> Result<(), CompileError<'a>> {
+    let mut names = Vec::new();
+    assigned_names(branch, &mut names);
+    for name in names {
+        if let (Some(before), Some(after)) = (scope.get(name), taken.get(name)) {
+            let joined = Type::join(&after, from_taken, &before, from_skipped, program, block).ok_or_else(|| CompileError::type_error(branch.source)
+                .with_note(format!("`{}` is `{}` before this runs, but `{}` after it", name, before, after))
+                .with_note("a name assigned in code that may not run keeps the type it had before".to_string()))?;
+            scope.assign(name, joined);
+        }
+    }
+    Ok(())
+}
+
+/// The fewest arms a `match` on an int must start with, each matching a
+/// different literal, for it to branch to them through a switch, and how
+/// many entries the switch's table can have for each of them.
+const MIN_SWITCH_ARMS: usize = 3;
+const SWITCH_ENTRIES_PER_ARM: usize = 2;
+
+/// Compiles the arms of a `match` as a chain of branches, each testing its
+/// pattern only when the arms before it did not match, and joins the value
+/// of each arm, and of each name from outside that it assigns, with those
+/// of the arms after it. A value that no arm matches stops the program, as
+/// `else trap` does. Arms that start with literals close enough together
+/// are branched to by a switch instead. Gives the value of the `match` and
+/// the block it goes on from.
+///
+/// `entries` holds the block the tests start in, or, once a switch has
+/// branched, the block of each arm it branches to and then the block it
+/// goes to when none matches.
+fn compile_arms<'a, 'b>(arms: &'b [(Pattern<'a>, Hir<'a>)], mut entries: Vec<Block>, ty: &Type<'a, 'b>, expected: Option<&Type<'a, 'b>>, scope: &mut Scope<'a, 'b>, program: &mut Program, function: &mut Function) -> Result<(Type<'a, 'b>, Block), CompileError<'a>> {
+    let ((pattern, body), rest) = arms.split_first().expect("a `match` has at least one arm");
+    let mut arm_scope = scope.arm();
+    let mut arm_block = if entries.len() > 1 {
+        // a switch has already branched here, so the pattern is a literal
+        // that binds nothing
+        entries.remove(0)
+    } else if let Some((scrutinee, min, cases)) = switch_cases(arms, ty) {
+        let mut block = entries.pop().expect("the tests start in a block");
+        let index = match min {
+            0 => scrutinee,
+            _ => {
+                let min = block.constant_int(min, program);
+                block.sub_int(scrutinee, min, program)
+            }
+        };
+        let mut entries: Vec<Block> = cases.iter().map(|_| function.new_block()).collect();
+        let default = function.new_block();
+        let mut targets = vec![default.get_id(); cases.iter().max().map_or(0, |&case| case + 1)];
+        for (entry, &case) in entries.iter().zip(&cases) {
+            targets[case] = entry.get_id();
+        }
+        block.switch(index, targets, default.get_id(), function);
+        entries.push(default);
+        return compile_arms(arms, entries, ty, expected, scope, program, function)
+    } else {
+        let mut block = entries.pop().expect("the tests start in a block");
+        match match_arm(pattern, ty.clone(), &mut arm_scope, program, &mut block)? {
+            Some(matched) => {
+                let arm_block = function.new_block();
+                let rest_block = function.new_block();
+                block.conditional_branch(matched, arm_block.get_id(), rest_block.get_id(), function);
+                entries.push(rest_block);
+                arm_block
+            }
+            // every value matches, so the arms after this one never run
+            None => {
+                for (pattern, body) in rest {
+                    let mut arm_scope = scope.arm();
+                    match_arm(pattern, ty.clone(), &mut arm_scope, program, &mut Function::new().new_block())?;
+                    compile_unreachable(body, expected, &mut arm_scope, program)?;
+                }
+                let arm_ty = compile_expecting(body, expected, &mut arm_scope, program, function, &mut block)?;
+                keep_assigned(pattern, body, &arm_scope, scope);
+                return Ok((arm_ty, block))
+            }
+        }
+    };
+    let arm_ty = compile_expecting(body, expected, &mut arm_scope, program, function, &mut arm_block)?;
+    if rest.is_empty() {
+        let mut rest_block = entries.pop().expect("no arm matching goes somewhere");
+        rest_block.abort(program);
+        rest_block.ret(function);
+        keep_assigned(pattern, body, &arm_scope, scope);
+        return Ok((arm_ty, arm_block))
+    }
+    let mut block = function.new_block();
+    let from_arm = arm_block.get_id();
+    arm_block.branch(block.get_id(), function);
+    let mut rest_scope = scope.arm();
+    let (rest_ty, rest_block) = compile_arms(rest, entries, ty, Some(expected.unwrap_or(&arm_ty)), &mut rest_scope, program, function)?;
+    let from_rest = rest_block.get_id();
+    rest_block.branch(block.get_id(), function);
+    let mut arm_names = Vec::new();
+    body_assigned_names(pattern, body, &mut arm_names);
+    let mut names = arm_names.clone();
+    for (pattern, body) in rest {
+        body_assigned_names(pattern, body, &mut names);
+    }
+    for name in names {
+        let Some(before) = scope.get(name) else { continue };
+        let after_arm = match arm_names.contains(&name) {
+            true => arm_scope.get(name).unwrap_or(before),
+            false => before,
+        };
+        let after_rest = rest_scope.get(name).expect("the arms after it are in the same scope");
+        let joined = Type::join(&after_arm, from_arm, &after_rest, from_rest, program, &mut block).ok_or_else(|| CompileError::type_error(body.source)
+            .with_note(format!("`{}` is `{}` after this arm, but `{}` after the arms that follow it", name, after_arm, after_rest))
+            .with_note("a name assigned in a `match` must have the same type after every arm".to_string()))?;
+        scope.assign(name, joined);
+    }
+    let joined = Type::join(&arm_ty, from_arm, &rest_ty, from_rest, program, &mut block).ok_or_else(|| CompileError::type_error(rest[0].1.source)
+        .with_label(body.source, format!("this arm is `{}`", arm_ty))
+        .with_note(format!("this arm is `{}`, but every arm of a `match` must have the same type", rest_ty)))?;
+    Ok((joined, block))
+}
+
+/// The register of an int that `arms` start by matching literals of, the
+/// smallest of the literals, and how far past it each one is, when there
+/// are enough of them, all different and close enough together, for a
+/// switch.
+fn switch_cases(arms: &[(Pattern, Hir)], ty: &Type) -> Option<(Var, i32, Vec<usize>)> {
+    let (scrutinee, values): (Var, Vec<i64>) = match *ty {
+        Type::Int(var) => (var, arms.iter().map_while(|(pattern, _)| match pattern.kind {
+            PatternKind::Int(value) => Some(value as i64),
+            _ => None,
+        }).collect()),
+        Type::UInt(var) => (var, arms.iter().map_while(|(pattern, _)| match pattern.kind {
+            PatternKind::UInt(value) => Some(value as i64),
+            _ => None,
+        }).collect()),
+        _ => return None,
+    };
+    let min = *values.iter().min()?;
+    let cases: Vec<usize> = values.iter().map(|&value| (value - min) as usize).collect();
+    let distinct = cases.iter().enumerate().all(|(index, case)| !cases[..index].contains(case));
+    let entries = cases.iter().max()? + 1;
+    match values.len() >= MIN_SWITCH_ARMS && distinct && entries <= values.len() * SWITCH_ENTRIES_PER_ARM {
+        true => Some((scrutinee, min as i32, cases)),
+        false => None,
+    }
+}
+
+/// Gives each name from outside the last arm that could run that its body
+/// assigns the value it has at the end of the arm, where the `match` goes on
+/// from.
+fn keep_assigned<'a, 'b>(pattern: &Pattern<'a>, body: &Hir<'a>, arm_scope: &Scope<'a, 'b>, scope: &mut Scope<'a, 'b>) {
+    let mut names = Vec::new();
+    body_assigned_names(pattern, body, &mut names);
+    for name in names {
+        if let (Some(_), Some(after)) = (scope.get(name), arm_scope.get(name)) {
+            scope.assign(name, after);
+        }
+    }
+}
+
+/// Binds the names in `pattern` to the parts of `ty` in `scope`, and gives
+/// whether the value matches the pattern, or `None` if every value does.
+fn match_arm<'a, 'b>(pattern: &'b Pattern<'a>, ty: Type<'a, 'b>, scope: &mut Scope<'a, 'b>, program: &mut Program, block: &mut Block) -> Result<Option<Var>, CompileError<'a>> {
+    let (var, value) = match (&pattern.kind, ty) {
+        (PatternKind::Int(value), Type::Int(var)) => (var, *value),
+        (PatternKind::UInt(value), Type::UInt(var)) => (var, *value as i32),
+        (PatternKind::Bool(value), Type::Bool(var)) => (var, *value as i32),
+        (PatternKind::Int(_) | PatternKind::UInt(_) | PatternKind::Bool(_), ty) => return Err(CompileError::type_error(pattern.source)
+            .with_note(format!("the pattern `{}` cannot match `{}`", pattern.source, ty))),
+        (PatternKind::Tuple(patterns), Type::Tuple(types)) if types.len() == patterns.len() => {
+            let mut matched = None;
+            for (pattern, ty) in patterns.iter().zip(types) {
+                if let Some(part) = match_arm(pattern, ty, scope, program, block)? {
+                    matched = Some(match matched {
+                        Some(all) => block.and_int(all, part, program),
+                        None => part,
+                    });
+                }
+            }
+            return Ok(matched)
+        }
+        (_, ty) => {
+            match_pattern(pattern, ty, scope)?;
+            return Ok(None)
+        }
+    };
+    let constant = block.constant_int(value, program);
+    Ok(Some(block.eq_int(var, constant, program)))
+}
+
+/// Type checks an arm that can never run, without emitting any code for it.
+fn compile_unreachable<'a, 'b>(expr: &'b Hir<'a>, expected: Option<&Type<'a, 'b>>, scope: &mut Scope<'a, 'b>, program: &mut Program) -> Result<Type<'a, 'b>, CompileError<'a>> {
+    expr.unreachable.set(true);
+    let mut function = Function::new();
+    let mut block = function.new_block();
+    compile_expecting(expr, expected, scope, program, &mut function, &mut block)
+}
+
+/// Warnings for the arms that compiling found can never run, and for the
+/// named functions it never called.
+pub fn warnings(hir: &Hir, files: &SourceMap) -> Vec<Diagnostic> {
+    let mut warnings = Vec::new();
+    collect_warnings(hir, files, &mut Vec::new(), &mut warnings);
+    warnings
+}
+
+/// `allowed` holds the names allowed around `expr`.
+fn collect_warnings<'a>(expr: &Hir<'a>, files: &SourceMap, allowed: &mut Vec<&'a str>, warnings: &mut Vec<Diagnostic>) {
+    let outer = allowed.len();
+    for &name in &expr.allowed {
+        if !Lint::is_name(name) {
+            warnings.extend(files.span_of(name).map(|span| Diagnostic::warning(format!("unknown warning `{}`", name), Some(span))
+                .with_note(format!("the warnings that can be allowed are {}", Lint::names()))));
+        }
+        allowed.push(name);
+    }
+    let is_allowed = |lint: Lint| allowed.iter().any(|&name| name == lint.name() || name == diagnostics::ALL_LINTS);
+    let dead = match &expr.kind {
+        HirKind::If { cond, conc } => Some((cond, conc, false)),
+        HirKind::Else { value, default } => match &value.kind {
+            HirKind::If { cond, .. } => Some((cond, default, true)),
+            _ => None,
         },
-        Expr::BoolLiteral(source) => {
-            Ok(Type::Bool(block.constant_int(if *source == "true" { 1 } else { 0 }, program)))
+        _ => None,
+    };
+    if let Some((cond, arm, always)) = dead {
+        if let (true, Some(arm_span), Some(cond_span)) = (arm.unreachable.get(), files.span_of(arm.source), files.span_of(cond.source)) {
+            if !is_allowed(Lint::Unreachable) {
+                warnings.push(Diagnostic::warning("unreachable code".to_string(), Some(arm_span))
+                    .with_label(cond_span, format!("this condition is always `{}`", always))
+                    .with_lint(Lint::Unreachable));
+            }
+        }
+    }
+    // the arms after one whose pattern matches every value never run
+    if let HirKind::Match { arms, .. } = &expr.kind {
+        let first_dead = arms.iter().position(|(_, body)| body.unreachable.get()).filter(|&index| index > 0);
+        if let (Some(index), false) = (first_dead, is_allowed(Lint::Unreachable)) {
+            let catch_all = arms[index - 1].0.source;
+            for (pattern, _) in &arms[index..] {
+                if let (Some(span), Some(catch_all_span)) = (files.span_of(pattern.source), files.span_of(catch_all)) {
+                    warnings.push(Diagnostic::warning("unreachable pattern".to_string(), Some(span))
+                        .with_label(catch_all_span, "this pattern matches every value".to_string())
+                        .with_lint(Lint::Unreachable));
+                }
+            }
+        }
+    }
+    // a function is only compiled when it is called, so one that never was
+    // is left out of the program, along with the functions inside it
+    let never_called = match &expr.kind {
+        HirKind::Func { name: Some(name), body, .. } if body.specializations.borrow().is_empty() => Some(name),
+        _ => None,
+    };
+    if let (Some(name), Some(span)) = (never_called, never_called.and_then(|name| files.span_of(name))) {
+        if !is_allowed(Lint::DeadCode) {
+            warnings.push(Diagnostic::warning(format!("function `{}` is never called", name), Some(span))
+                .with_lint(Lint::DeadCode));
+        }
+    }
+    for child in expr.children() {
+        if !child.unreachable.get() && never_called.is_none() {
+            collect_warnings(child, files, allowed, warnings);
+        }
+    }
+    allowed.truncate(outer);
+}
+
+/// Emits a value computed at compile time.
+fn constant<'a, 'b>(value: &Const, program: &mut Program, block: &mut Block) -> Type<'a, 'b> {
+    match value {
+        Const::Int(value) => Type::Int(block.constant_int(*value, program)),
+        Const::UInt(value) => Type::UInt(block.constant_int(*value as i32, program)),
+        Const::Float(value) => Type::Float(block.constant_float(*value, program)),
+        Const::Bool(value) => Type::Bool(block.constant_int(*value as i32, program)),
+        Const::Tuple(values) => Type::Tuple(values.iter().map(|value| constant(value, program, block)).collect()),
+    }
+}
+
+/// The operands of a folded expression are never compiled, so they are
+/// annotated here instead.
+fn annotate_folded(expr: &Hir) {
+    for child in expr.children() {
+        if let Some(value) = consteval::eval(child) {
+            child.annotate(Annotation { ty: value.type_name(), definition: None });
+            annotate_folded(child);
         }
     }
 }
 
-fn match_pattern<'a, 'b>(pattern: &'b Parsed<'a, Expr<'a>>, ty: Type<'a, 'b>, scope: &mut Scope<'a, 'b>) -> Result<(), CompileError<'a>> {
-    match pattern.get_node() {
-        Expr::Ident(source) => {
-            scope.assign(source, ty);
+/// Arms this cheap are computed whether or not they are needed, so the
+/// result can be picked with a select instead of branching around them.
+fn is_trivial(expr: &Hir) -> bool {
+    match &expr.kind {
+        HirKind::Int(_) | HirKind::UInt(_) | HirKind::Float(_) | HirKind::Bool(_) | HirKind::Var(_) => true,
+        HirKind::Tuple(exprs) => exprs.iter().all(is_trivial),
+        _ => false,
+    }
+}
+
+/// Whether two values are equal, or `None` if their types cannot be
+/// compared. Tuples and structs are equal when each of their parts is. Two
+/// maybes are equal when both are empty or both hold equal values, which
+/// are only compared when both are there, since the registers of an empty
+/// maybe's value may never have been written.
+fn emit_eq<'a, 'b>(a: &Type<'a, 'b>, b: &Type<'a, 'b>, program: &mut Program, function: &mut Function, block: &mut Block) -> Option<Var> {
+    Some(match (a, b) {
+        (Type::Int(a), Type::Int(b)) | (Type::UInt(a), Type::UInt(b)) | (Type::Bool(a), Type::Bool(b)) => block.eq_int(*a, *b, program),
+        (Type::Float(a), Type::Float(b)) => block.eq_float(*a, *b, program),
+        (Type::Tuple(a), Type::Tuple(b)) if a.len() == b.len() => emit_all_eq(a.iter().zip(b), program, function, block)?,
+        (Type::Struct(a), Type::Struct(b)) if a.len() == b.len() && a.iter().zip(b).all(|((a, _), (b, _))| a == b) => {
+            emit_all_eq(a.iter().map(|(_, ty)| ty).zip(b.iter().map(|(_, ty)| ty)), program, function, block)?
+        }
+        (Type::Maybe(a_tag, a), Type::Maybe(b_tag, b)) => {
+            let same = block.eq_int(*a_tag, *b_tag, program);
+            let both = block.and_int(*a_tag, *b_tag, program);
+            let mut values_block = function.new_block();
+            let exit_block = function.new_block();
+            block.clone().conditional_branch(both, values_block.get_id(), exit_block.get_id(), function);
+            let values = emit_eq(a, b, program, function, &mut values_block)?;
+            values_block.branch(exit_block.get_id(), function);
+            *block = exit_block;
+            block.select(both, values, same, program)
+        }
+        _ => return None,
+    })
+}
+
+fn emit_all_eq<'t, 'a: 'b, 'b: 't>(pairs: impl Iterator<Item = (&'t Type<'a, 'b>, &'t Type<'a, 'b>)>, program: &mut Program, function: &mut Function, block: &mut Block) -> Option<Var> {
+    let mut all = block.constant_int(1, program);
+    for (a, b) in pairs {
+        let eq = emit_eq(a, b, program, function, block)?;
+        all = block.and_int(all, eq, program);
+    }
+    Some(all)
+}
+
+/// Emits a binary instruction, for operators that pick one by type.
+type Emit = fn(&mut Block, Var, Var, &mut Program) -> Var;
+
+/// A binary operator applied to operands it does not accept, with each
+/// operand labelled by its type.
+fn operand_mismatch<'a, 'b>(expr: &Hir<'a>, left: (&Hir<'a>, &Type<'a, 'b>), right: (&Hir<'a>, &Type<'a, 'b>)) -> CompileError<'a> {
+    let error = CompileError::type_error(expr.source)
+        .with_label(left.0.source, format!("this is `{}`", left.1))
+        .with_label(right.0.source, format!("this is `{}`", right.1));
+    let is_number = |ty: &Type| matches!(ty, Type::Int(_) | Type::UInt(_) | Type::Float(_));
+    if is_number(left.1) && is_number(right.1) {
+        error.with_help("use `as` to convert one side, as in `x as float`")
+    } else {
+        error
+    }
+}
+
+/// Copies `sources` into the registers a loop carries its state in. When a
+/// source is another of those registers, as when `next` swaps two parts of
+/// the state, every source is read before any register is written.
+fn copy_state(carried: &[Var], sources: Vec<Var>, program: &mut Program, block: &mut Block) {
+    let crossed = sources.iter().enumerate().any(|(index, source)| {
+        carried.iter().position(|dest| dest == source).is_some_and(|other| other != index)
+    });
+    let sources = match crossed {
+        true => sources.into_iter().map(|source| {
+            let temporary = program.new_variable();
+            block.copy(temporary, source, program);
+            temporary
+        }).collect(),
+        false => sources,
+    };
+    for (&dest, source) in carried.iter().zip(sources).filter(|(&dest, source)| dest != *source) {
+        block.copy(dest, source, program);
+    }
+}
+
+/// The names `expr` assigns in the scope it runs in, leaving out the
+/// bodies of functions, which have scopes of their own, and the names the
+/// patterns of `for` loops and `match` arms bind.
+fn assigned_names<'a>(expr: &Hir<'a>, names: &mut Vec<&'a str>) {
+    match &expr.kind {
+        HirKind::Func { .. } => (),
+        HirKind::For { pattern, iterable, body, .. } => {
+            assigned_names(iterable, names);
+            body_assigned_names(pattern, body, names);
+        }
+        HirKind::Match { value, arms } => {
+            assigned_names(value, names);
+            for (pattern, body) in arms {
+                body_assigned_names(pattern, body, names);
+            }
+        }
+        HirKind::Assign { pattern, value } => {
+            assigned_names(value, names);
+            bound_names(pattern, names);
+        }
+        HirKind::InlineIr { ir, inputs } => {
+            for input in inputs {
+                assigned_names(input, names);
+            }
+            for slot in ir.slots.iter().filter(|slot| !slot.input) {
+                add_name(slot.name, names);
+            }
+        }
+        _ => for child in expr.children() {
+            assigned_names(child, names);
+        }
+    }
+}
+
+/// The names the body of a `for` loop or a `match` arm assigns, other than
+/// those its `pattern` binds.
+fn body_assigned_names<'a>(pattern: &Pattern<'a>, body: &Hir<'a>, names: &mut Vec<&'a str>) {
+    let mut bound = Vec::new();
+    bound_names(pattern, &mut bound);
+    let mut assigned = Vec::new();
+    assigned_names(body, &mut assigned);
+    for name in assigned.into_iter().filter(|name| !bound.contains(name)) {
+        add_name(name, names);
+    }
+}
+
+fn bound_names<'a>(pattern: &Pattern<'a>, names: &mut Vec<&'a str>) {
+    match &pattern.kind {
+        PatternKind::Bind(name) => add_name(name, names),
+        PatternKind::Tuple(patterns) => for pattern in patterns {
+            bound_names(pattern, names);
+        },
+        PatternKind::Wildcard | PatternKind::Int(_) | PatternKind::UInt(_) | PatternKind::Bool(_) => (),
+    }
+}
+
+fn add_name<'a>(name: &'a str, names: &mut Vec<&'a str>) {
+    if !names.contains(&name) {
+        names.push(name)
+    }
+}
+
+/// Binds each of `names` that is bound in `scope` to a copy of its value
+/// in registers of its own, which a loop carries it in, and gives the names
+/// with their types.
+fn carried_names<'a, 'b>(names: Vec<&'a str>, scope: &mut Scope<'a, 'b>, program: &mut Program, block: &mut Block) -> Vec<(&'a str, Type<'a, 'b>)> {
+    let mut carried = Vec::new();
+    for name in names {
+        if let Some(ty) = scope.get(name) {
+            let vars: Vec<Var> = (0..ty.size()).map(|_| program.new_variable()).collect();
+            copy_state(&vars, ty.get_used_vars(), program, block);
+            let ty = ty.map_to(&vars);
+            scope.assign(name, ty.clone());
+            carried.push((name, ty));
+        }
+    }
+    carried
+}
+
+/// Copies the values the `carried` names have in `scope` into the registers
+/// they are carried in, which only works when the types are the same.
+fn carry<'a, 'b>(carried: &[(&'a str, Type<'a, 'b>)], scope: &Scope<'a, 'b>, source: &'a str, program: &mut Program, block: &mut Block) -> Result<(), CompileError<'a>> {
+    let mut vars = Vec::new();
+    let mut sources = Vec::new();
+    for (name, ty) in carried {
+        let value = scope.get(name).expect("a carried name is bound outside the loop");
+        if value != *ty {
+            return Err(CompileError::type_error(source)
+                .with_note(format!("`{}` is `{}` before the loop, but `{}` here", name, ty, value))
+                .with_note("a name assigned in a loop keeps one type through every pass".to_string()))
+        }
+        ty.add_vars_to_vec(&mut vars);
+        value.add_vars_to_vec(&mut sources);
+    }
+    copy_state(&vars, sources, program, block);
+    Ok(())
+}
+
+fn match_pattern<'a, 'b>(pattern: &'b Pattern<'a>, ty: Type<'a, 'b>, scope: &mut Scope<'a, 'b>) -> Result<(), CompileError<'a>> {
+    match &pattern.kind {
+        PatternKind::Bind(name) => {
+            scope.assign(name, ty);
             Ok(())
         }
-        Expr::Tuple { exprs } => match ty {
-            Type::Tuple(types) if types.len() == exprs.len() => {
-                for (ty, pattern) in types.iter().zip(exprs) {
+        PatternKind::Wildcard => Ok(()),
+        PatternKind::Int(_) | PatternKind::UInt(_) | PatternKind::Bool(_) => unreachable!("only a `match` arm has a literal pattern, which `match_arm` tests"),
+        PatternKind::Tuple(patterns) => match ty {
+            Type::Tuple(types) if types.len() == patterns.len() => {
+                for (ty, pattern) in types.iter().zip(patterns) {
                     match_pattern(pattern, ty.clone(), scope)?;
                 }
                 Ok(())
             }
-            _ => Err(CompileError::type_error(pattern.get_source()))
+            ty => Err(CompileError::type_error(pattern.source)
+                .with_note(format!("a pattern of {} elements cannot match `{}`", patterns.len(), ty))),
         },
-        _ => unimplemented!(),
     }
-}
\ No newline at end of file
+}
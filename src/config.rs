@@ -0,0 +1,129 @@
+use std::path::{Path, PathBuf};
+use std::{fs, io};
+use language::diagnostics::{ColorChoice, ErrorFormat};
+use language::log;
+
+pub const CONFIG_FILE: &str = "compiler.toml";
+
+/// Defaults for the command line options, read from the `compiler.toml` of
+/// the project. Flags given on the command line take precedence.
+///
+/// The file is a small subset of TOML: one `key = value` per line, where a
+/// value is a boolean, an integer or a double quoted string, and `#` starts
+/// a comment.
+#[derive(Default)]
+pub struct Config {
+    /// The entry file, relative to the project root.
+    pub entry: Option<PathBuf>,
+    pub script: Option<bool>,
+    pub emit: Option<String>,
+    pub color: Option<ColorChoice>,
+    pub error_format: Option<ErrorFormat>,
+    pub verbosity: Option<log::Level>,
+    pub seed: Option<u64>,
+}
+
+enum Value {
+    Bool(bool),
+    Int(u64),
+    String(String),
+}
+
+impl Config {
+    /// Reads the config file in `root`, or returns the empty config if there
+    /// is none.
+    pub fn load(root: &Path) -> Result<Config, String> {
+        let path = root.join(CONFIG_FILE);
+        match fs::read_to_string(&path) {
+            Ok(contents) => Config::parse(&contents).map_err(|(line, message)| format!("{}:{}: {}", path.display(), line, message)),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(Config::default()),
+            Err(error) => Err(format!("could not read {}: {}", path.display(), error)),
+        }
+    }
+    fn parse(contents: &str) -> Result<Config, (usize, String)> {
+        let mut config = Config::default();
+        for (index, line) in contents.lines().enumerate() {
+            let line_number = index + 1;
+            let line = strip_comment(line).trim();
+            if line.is_empty() {
+                continue
+            }
+            let (key, value) = line.split_once('=').ok_or((line_number, "expected `key = value`".to_string()))?;
+            let key = key.trim();
+            let value = parse_value(value.trim()).ok_or((line_number, format!("invalid value for `{}`", key)))?;
+            config.set(key, value).map_err(|message| (line_number, message))?;
+        }
+        Ok(config)
+    }
+    fn set(&mut self, key: &str, value: Value) -> Result<(), String> {
+        match (key, value) {
+            ("entry", Value::String(entry)) => self.entry = Some(PathBuf::from(entry)),
+            ("script", Value::Bool(script)) => self.script = Some(script),
+            ("emit", Value::String(emit)) => self.emit = Some(emit),
+            ("color", Value::String(color)) => {
+                self.color = Some(ColorChoice::parse(&color).ok_or_else(|| format!("invalid color choice '{}'", color))?)
+            }
+            ("error-format", Value::String(format)) => {
+                self.error_format = Some(ErrorFormat::parse(&format).ok_or_else(|| format!("invalid error format '{}'", format))?)
+            }
+            ("verbosity", Value::String(level)) => self.verbosity = Some(match level.as_str() {
+                "quiet" => log::Level::Quiet,
+                "normal" => log::Level::Normal,
+                "verbose" => log::Level::Verbose,
+                "debug" => log::Level::Debug,
+                _ => return Err(format!("invalid verbosity '{}'", level)),
+            }),
+            ("seed", Value::Int(seed)) => self.seed = Some(seed),
+            ("script", _) => return Err("`script` expects true or false".to_string()),
+            ("seed", _) => return Err("`seed` expects an integer".to_string()),
+            ("entry", _) | ("emit", _) | ("color", _) | ("error-format", _) | ("verbosity", _) => return Err(format!("`{}` expects a string", key)),
+            _ => return Err(format!("unknown key `{}`", key)),
+        }
+        Ok(())
+    }
+}
+
+/// Removes a comment, unless the `#` is inside a string.
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    let mut escaped = false;
+    for (index, ch) in line.char_indices() {
+        match ch {
+            '\\' if in_string => {
+                escaped = !escaped;
+                continue
+            }
+            '"' if !escaped => in_string = !in_string,
+            '#' if !in_string => return &line[..index],
+            _ => (),
+        }
+        escaped = false;
+    }
+    line
+}
+
+fn parse_value(value: &str) -> Option<Value> {
+    match value {
+        "true" => return Some(Value::Bool(true)),
+        "false" => return Some(Value::Bool(false)),
+        _ => (),
+    }
+    if let Some(quoted) = value.strip_prefix('"') {
+        let mut string = String::new();
+        let mut chars = quoted.chars();
+        loop {
+            match chars.next()? {
+                '"' => break,
+                '\\' => string.push(match chars.next()? {
+                    'n' => '\n',
+                    't' => '\t',
+                    ch @ ('"' | '\\') => ch,
+                    _ => return None,
+                }),
+                ch => string.push(ch),
+            }
+        }
+        return if chars.as_str().is_empty() { Some(Value::String(string)) } else { None }
+    }
+    value.replace('_', "").parse::<u64>().ok().map(Value::Int)
+}
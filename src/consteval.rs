@@ -0,0 +1,132 @@
+//! Evaluation of HIR at compile time. Only closed expressions over literals
+//! are evaluated; anything that reads a variable, calls a function or does
+//! not type check is left for the compiler, which reports the error.
+
+use crate::hir::{BinOp, CastType, Hir, HirKind};
+use crate::ops;
+use std::cmp::Ordering;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Const {
+    Int(i32),
+    UInt(u32),
+    Float(f32),
+    Bool(bool),
+    Tuple(Vec<Const>),
+}
+
+impl Const {
+    /// The name of the type the compiler gives this value.
+    pub fn type_name(&self) -> String {
+        match self {
+            Const::Int(_) => "int".to_string(),
+            Const::UInt(_) => "uint".to_string(),
+            Const::Float(_) => "float".to_string(),
+            Const::Bool(_) => "bool".to_string(),
+            Const::Tuple(values) => {
+                let names: Vec<String> = values.iter().map(Const::type_name).collect();
+                format!("({})", names.join(", "))
+            }
+        }
+    }
+}
+
+pub fn eval(expr: &Hir) -> Option<Const> {
+    match &expr.kind {
+        HirKind::Int(value) => Some(Const::Int(*value)),
+        HirKind::UInt(value) => Some(Const::UInt(*value)),
+        HirKind::Float(value) => Some(Const::Float(*value)),
+        HirKind::Bool(value) => Some(Const::Bool(*value)),
+        HirKind::Tuple(exprs) => exprs.iter().map(eval).collect::<Option<_>>().map(Const::Tuple),
+        HirKind::Block(exprs) if exprs.len() == 1 => eval(&exprs[0]),
+        HirKind::Binary { op, left, right } => binary(*op, eval(left)?, eval(right)?),
+        HirKind::Cast { value, ty } => cast(eval(value)?, *ty),
+        HirKind::Not { value } => match eval(value)? {
+            Const::Bool(value) => Some(Const::Bool(!value)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn binary(op: BinOp, left: Const, right: Const) -> Option<Const> {
+    match (op, left, right) {
+        (BinOp::Add, Const::Int(a), Const::Int(b)) => Some(Const::Int(ops::add_int(a, b))),
+        (BinOp::Add, Const::UInt(a), Const::UInt(b)) => Some(Const::UInt(ops::add_int(a as i32, b as i32) as u32)),
+        (BinOp::Add, Const::Float(a), Const::Float(b)) => Some(Const::Float(ops::add_float(a, b))),
+        (BinOp::Sub, Const::Int(a), Const::Int(b)) => Some(Const::Int(ops::sub_int(a, b))),
+        (BinOp::Sub, Const::UInt(a), Const::UInt(b)) => Some(Const::UInt(ops::sub_int(a as i32, b as i32) as u32)),
+        (BinOp::Sub, Const::Float(a), Const::Float(b)) => Some(Const::Float(ops::sub_float(a, b))),
+        (BinOp::Mul, Const::Int(a), Const::Int(b)) => Some(Const::Int(ops::mul_int(a, b))),
+        (BinOp::Mul, Const::UInt(a), Const::UInt(b)) => Some(Const::UInt(ops::mul_int(a as i32, b as i32) as u32)),
+        (BinOp::Mul, Const::Float(a), Const::Float(b)) => Some(Const::Float(ops::mul_float(a, b))),
+        // dividing by zero is left to trap when the program runs
+        (BinOp::Div, Const::Int(a), Const::Int(b)) => ops::div_int(a, b).map(Const::Int),
+        (BinOp::Div, Const::UInt(a), Const::UInt(b)) => ops::div_uint(a as i32, b as i32).map(|value| Const::UInt(value as u32)),
+        (BinOp::Div, Const::Float(a), Const::Float(b)) => Some(Const::Float(ops::div_float(a, b))),
+        (BinOp::Rem, Const::Int(a), Const::Int(b)) => ops::rem_int(a, b).map(Const::Int),
+        (BinOp::Rem, Const::UInt(a), Const::UInt(b)) => ops::rem_uint(a as i32, b as i32).map(|value| Const::UInt(value as u32)),
+        (BinOp::Rem, Const::Float(a), Const::Float(b)) => Some(Const::Float(ops::rem_float(a, b))),
+        (BinOp::Eq | BinOp::Ne | BinOp::Lt | BinOp::Le | BinOp::Gt | BinOp::Ge, left, right) => {
+            let ordering = match (left, right) {
+                (Const::Int(a), Const::Int(b)) => a.partial_cmp(&b),
+                (Const::UInt(a), Const::UInt(b)) => a.partial_cmp(&b),
+                (Const::Float(a), Const::Float(b)) => a.partial_cmp(&b),
+                (Const::Bool(a), Const::Bool(b)) if matches!(op, BinOp::Eq | BinOp::Ne) => a.partial_cmp(&b),
+                _ => return None,
+            };
+            Some(Const::Bool(match op {
+                BinOp::Eq => ordering == Some(Ordering::Equal),
+                BinOp::Ne => ordering != Some(Ordering::Equal),
+                BinOp::Lt => ordering == Some(Ordering::Less),
+                BinOp::Le => matches!(ordering, Some(Ordering::Less | Ordering::Equal)),
+                BinOp::Gt => ordering == Some(Ordering::Greater),
+                _ => matches!(ordering, Some(Ordering::Greater | Ordering::Equal)),
+            }))
+        }
+        (BinOp::And, Const::Bool(a), Const::Bool(b)) => Some(Const::Bool(a && b)),
+        (BinOp::Or, Const::Bool(a), Const::Bool(b)) => Some(Const::Bool(a || b)),
+        (BinOp::BitAnd, Const::Int(a), Const::Int(b)) => Some(Const::Int(a & b)),
+        (BinOp::BitAnd, Const::UInt(a), Const::UInt(b)) => Some(Const::UInt(a & b)),
+        (BinOp::BitAnd, Const::Bool(a), Const::Bool(b)) => Some(Const::Bool(a & b)),
+        (BinOp::BitOr, Const::Int(a), Const::Int(b)) => Some(Const::Int(a | b)),
+        (BinOp::BitOr, Const::UInt(a), Const::UInt(b)) => Some(Const::UInt(a | b)),
+        (BinOp::BitOr, Const::Bool(a), Const::Bool(b)) => Some(Const::Bool(a | b)),
+        (BinOp::BitXor, Const::Int(a), Const::Int(b)) => Some(Const::Int(a ^ b)),
+        (BinOp::BitXor, Const::UInt(a), Const::UInt(b)) => Some(Const::UInt(a ^ b)),
+        (BinOp::BitXor, Const::Bool(a), Const::Bool(b)) => Some(Const::Bool(a ^ b)),
+        (BinOp::ShiftLeft, left, right) | (BinOp::ShiftRight, left, right) => {
+            let amount = match right {
+                Const::Int(amount) => amount,
+                Const::UInt(amount) => amount as i32,
+                _ => return None,
+            };
+            match (op, left) {
+                (BinOp::ShiftLeft, Const::Int(a)) => Some(Const::Int(ops::shift_left(a, amount))),
+                (BinOp::ShiftLeft, Const::UInt(a)) => Some(Const::UInt(ops::shift_left(a as i32, amount) as u32)),
+                (_, Const::Int(a)) => Some(Const::Int(ops::shift_right_arithmetic(a, amount))),
+                (_, Const::UInt(a)) => Some(Const::UInt(ops::shift_right_logical(a as i32, amount) as u32)),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+fn cast(value: Const, ty: CastType) -> Option<Const> {
+    match (value, ty) {
+        (Const::Int(value), CastType::Int) => Some(Const::Int(value)),
+        (Const::UInt(value), CastType::Int) => Some(Const::Int(value as i32)),
+        (Const::Bool(value), CastType::Int) => Some(Const::Int(value as i32)),
+        (Const::Int(value), CastType::UInt) => Some(Const::UInt(value as u32)),
+        (Const::UInt(value), CastType::UInt) => Some(Const::UInt(value)),
+        (Const::Bool(value), CastType::UInt) => Some(Const::UInt(value as u32)),
+        (Const::Float(value), CastType::Float) => Some(Const::Float(value)),
+        (Const::Bool(value), CastType::Bool) => Some(Const::Bool(value)),
+        (Const::Int(value), CastType::Float) => Some(Const::Float(ops::int_to_float(value))),
+        (Const::UInt(value), CastType::Float) => Some(Const::Float(ops::uint_to_float(value as i32))),
+        (Const::Float(value), CastType::Int) => Some(Const::Int(ops::float_to_int(value))),
+        (Const::Float(value), CastType::UInt) => Some(Const::UInt(ops::float_to_uint(value) as u32)),
+        _ => None,
+    }
+}
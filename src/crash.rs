@@ -0,0 +1,179 @@
+//! Reports for when the compiler itself panics. The report holds the source
+//! and options that caused the panic, and the smallest program found by
+//! removing top-level items from the source that still panics in the same
+//! place, so that a bug can be filed with a small reproduction.
+
+use language::ast::Expr;
+use language::cache::{self, Cache};
+use language::parser;
+use language::session::{CompileOptions, Session};
+use language::diagnostics::Span;
+use std::any::Any;
+use std::cell::RefCell;
+use std::fmt::Write;
+use std::panic::{self, AssertUnwindSafe, PanicHookInfo};
+use std::path::{Path, PathBuf};
+use std::{fs, process};
+
+/// The stages of compiling that are run under a guard.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Stage {
+    Parse,
+    Lower,
+    Compile,
+}
+
+impl Stage {
+    fn name(self) -> &'static str {
+        match self {
+            Stage::Parse => "parsing",
+            Stage::Lower => "lowering",
+            Stage::Compile => "compiling",
+        }
+    }
+}
+
+/// Where a panic happened, and what it said.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Panic {
+    pub message: String,
+    pub location: String,
+}
+
+thread_local! {
+    static LAST_PANIC: RefCell<Option<Panic>> = const { RefCell::new(None) };
+}
+
+/// What a crash report needs to reproduce a crash.
+pub struct Reporter<'a> {
+    pub file_name: &'a str,
+    pub source: &'a str,
+    pub options: &'a CompileOptions,
+    pub project_root: &'a Path,
+}
+
+impl Reporter<'_> {
+    /// Runs `run`, which is `stage` of compiling the source. If it panics,
+    /// writes a crash report and exits.
+    pub fn guard<T>(&self, stage: Stage, run: impl FnOnce() -> T) -> T {
+        let panicked = match catch(run) {
+            Ok(value) => return value,
+            Err(panicked) => panicked,
+        };
+        eprintln!("error: the compiler crashed while {} {}: {}", stage.name(), self.file_name, panicked.message);
+        eprintln!("note: the panic was at {}", panicked.location);
+        match self.write(stage, &panicked) {
+            Ok(path) => eprintln!("note: a crash report with a reduced program was written to {}", path.display()),
+            Err(error) => eprintln!("note: could not write a crash report: {}", error),
+        }
+        process::exit(101)
+    }
+
+    fn write(&self, stage: Stage, panicked: &Panic) -> std::io::Result<PathBuf> {
+        let reduced = minimize(self.source, |source| crashes(source, self.options) == Some((stage, panicked.location.clone())));
+        let mut report = String::new();
+        let _ = writeln!(report, "the compiler panicked while {} {}", stage.name(), self.file_name);
+        let _ = writeln!(report, "panic: {}", panicked.message);
+        let _ = writeln!(report, "at: {}", panicked.location);
+        let _ = writeln!(report, "version: {}", env!("CARGO_PKG_VERSION"));
+        let _ = writeln!(report, "options: {:?}", self.options);
+        let _ = writeln!(report, "\n--- source ---\n{}", self.source);
+        let _ = writeln!(report, "--- reduced ---\n{}", reduced);
+        let dir = self.project_root.join(cache::BUILD_DIR);
+        let path = dir.join(format!("crash-{:016x}.txt", Cache::key(self.source, self.options)));
+        fs::create_dir_all(&dir)?;
+        fs::write(&path, report)?;
+        Ok(path)
+    }
+}
+
+/// Runs `run`, returning the panic it caused if it panicked. The panic is
+/// not printed.
+pub fn catch<T>(run: impl FnOnce() -> T) -> Result<T, Panic> {
+    let previous = panic::take_hook();
+    panic::set_hook(Box::new(record));
+    let result = panic::catch_unwind(AssertUnwindSafe(run));
+    panic::set_hook(previous);
+    result.map_err(|payload| LAST_PANIC.with(|last| last.borrow_mut().take()).unwrap_or_else(|| Panic {
+        message: message(&*payload),
+        location: "an unknown location".to_string(),
+    }))
+}
+
+fn record(info: &PanicHookInfo) {
+    let panicked = Panic {
+        message: message(info.payload()),
+        location: info.location().map_or_else(|| "an unknown location".to_string(), |location| location.to_string()),
+    };
+    LAST_PANIC.with(|last| *last.borrow_mut() = Some(panicked));
+}
+
+fn message(payload: &(dyn Any + Send)) -> String {
+    match (payload.downcast_ref::<&str>(), payload.downcast_ref::<String>()) {
+        (Some(message), _) => message.to_string(),
+        (_, Some(message)) => message.clone(),
+        _ => "no message".to_string(),
+    }
+}
+
+/// The stage compiling `source` panics in, and where, if it does.
+fn crashes(source: &str, options: &CompileOptions) -> Option<(Stage, String)> {
+    let session = Session::new(String::new(), source.to_string(), options.clone());
+    let ast = match catch(|| session.parse()) {
+        Ok(ast) => ast?,
+        Err(panicked) => return Some((Stage::Parse, panicked.location)),
+    };
+    let hir = match catch(|| session.lower(&ast)) {
+        Ok(hir) => hir?,
+        Err(panicked) => return Some((Stage::Lower, panicked.location)),
+    };
+    catch(|| session.compile(&hir)).err().map(|panicked| (Stage::Compile, panicked.location))
+}
+
+/// The smallest program found by removing top-level items from `source`
+/// for which `keep` still holds. Items are removed in halves, then
+/// quarters, and so on down to one at a time. If the source does not parse,
+/// its lines are removed instead.
+pub fn minimize(source: &str, keep: impl Fn(&str) -> bool) -> String {
+    let starts = catch(|| item_starts(source)).ok().flatten()
+        .unwrap_or_else(|| source.match_indices('\n').map(|(index, _)| index + 1).filter(|&start| start < source.len()).collect());
+    let (prefix, mut items) = split_at(source, &starts);
+    let mut size = items.len() / 2;
+    while size > 0 {
+        let mut start = 0;
+        while start < items.len() {
+            let mut candidate = items.clone();
+            candidate.drain(start..(start + size).min(items.len()));
+            if keep(&format!("{}{}", prefix, candidate.concat())) {
+                items = candidate;
+            } else {
+                start += size;
+            }
+        }
+        size /= 2;
+    }
+    format!("{}{}", prefix, items.concat())
+}
+
+/// The offsets of the lines the top-level items of `source` start on.
+fn item_starts(source: &str) -> Option<Vec<usize>> {
+    let ast = parser::parse_source(source).ok()?;
+    let items = match ast.get_node() {
+        Expr::Block { exprs, last } => exprs.iter().chain(Some(&**last)).collect(),
+        _ => vec![&ast],
+    };
+    Some(items.iter().map(|item| {
+        let start = Span::of(source, item.get_source()).start;
+        source[..start].rfind('\n').map_or(0, |index| index + 1)
+    }).collect())
+}
+
+/// Splits `source` at each of `starts`, returning the text before the first
+/// and the pieces after each.
+fn split_at<'a>(source: &'a str, starts: &[usize]) -> (&'a str, Vec<&'a str>) {
+    let first = starts.first().copied().unwrap_or(source.len());
+    let pieces = starts.iter().enumerate()
+        .map(|(index, &start)| &source[start..starts.get(index + 1).copied().unwrap_or(source.len())])
+        .collect();
+    (&source[..first], pieces)
+}
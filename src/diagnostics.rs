@@ -0,0 +1,391 @@
+use std::fmt::{self, Write};
+use std::io::{self, IsTerminal};
+use crate::source_map::{FileId, SourceMap};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+    Note,
+}
+
+/// A byte range in a `SourceMap`, or into the source text a diagnostic was
+/// produced from when there is only one file.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+/// A span underlined in the rendered source. The primary label marks where
+/// the error is, secondary labels point at whatever explains it.
+#[derive(Debug, Clone)]
+pub struct Label {
+    pub span: Span,
+    pub message: String,
+    pub primary: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub code: Option<&'static str>,
+    pub message: String,
+    pub labels: Vec<Label>,
+    pub notes: Vec<String>,
+    pub help: Option<String>,
+    /// Which warning this is, for those whose level can be set.
+    pub lint: Option<Lint>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ColorChoice {
+    Auto,
+    Always,
+    Never,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ErrorFormat {
+    Human,
+    Json,
+}
+
+pub struct Renderer {
+    color: bool,
+    format: ErrorFormat,
+}
+
+impl Span {
+    /// The span of `slice`, which must be a subslice of `source`.
+    pub fn of(source: &str, slice: &str) -> Span {
+        let start = slice.as_ptr() as usize - source.as_ptr() as usize;
+        Span { start, end: start + slice.len() }
+    }
+    /// The span of `slice` if it is a subslice of `source`, which names
+    /// defined by the compiler itself are not.
+    pub fn within(source: &str, slice: &str) -> Option<Span> {
+        let start = (slice.as_ptr() as usize).checked_sub(source.as_ptr() as usize)?;
+        if start + slice.len() <= source.len() {
+            Some(Span { start, end: start + slice.len() })
+        } else {
+            None
+        }
+    }
+    /// The offset of `line` and `column` in `source`, both counted from
+    /// one, if the line has that many characters or one more.
+    pub fn offset_at(source: &str, line: usize, column: usize) -> Option<usize> {
+        let line_start = match line {
+            0 => return None,
+            1 => 0,
+            _ => source.match_indices('\n').nth(line - 2)?.0 + 1,
+        };
+        let text = source[line_start..].split('\n').next().unwrap_or("");
+        match column.checked_sub(1)? {
+            index if index == text.chars().count() => Some(line_start + text.len()),
+            index => text.char_indices().nth(index).map(|(offset, _)| line_start + offset),
+        }
+    }
+    /// The line and column the span starts at, both counted from one.
+    pub fn line_column(&self, source: &str) -> (usize, usize) {
+        let location = Location::of(source, self.start);
+        (location.line, location.column)
+    }
+}
+
+impl Diagnostic {
+    pub fn error(message: String, span: Option<Span>) -> Diagnostic {
+        let labels = span.map(|span| Label { span, message: String::new(), primary: true }).into_iter().collect();
+        Diagnostic { severity: Severity::Error, code: None, message, labels, notes: Vec::new(), help: None, lint: None }
+    }
+    pub fn warning(message: String, span: Option<Span>) -> Diagnostic {
+        Diagnostic { severity: Severity::Warning, ..Diagnostic::error(message, span) }
+    }
+    pub fn with_code(mut self, code: &'static str) -> Diagnostic {
+        self.code = Some(code);
+        self
+    }
+    pub fn with_label(mut self, span: Span, message: String) -> Diagnostic {
+        self.labels.push(Label { span, message, primary: false });
+        self
+    }
+    pub fn with_note(mut self, note: String) -> Diagnostic {
+        self.notes.push(note);
+        self
+    }
+    pub fn with_help(mut self, help: String) -> Diagnostic {
+        self.help = Some(help);
+        self
+    }
+    pub fn with_lint(mut self, lint: Lint) -> Diagnostic {
+        self.lint = Some(lint);
+        self
+    }
+    fn primary_span(&self) -> Option<Span> {
+        self.labels.iter().find(|label| label.primary).or_else(|| self.labels.first()).map(|label| label.span)
+    }
+}
+
+/// The heading of the diagnostic, as it is rendered without the source.
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.code {
+            Some(code) => write!(f, "{}[{}]: {}", self.severity.name(), code, self.message),
+            None => write!(f, "{}: {}", self.severity.name(), self.message),
+        }
+    }
+}
+
+impl std::error::Error for Diagnostic {}
+
+/// Whether a warning is dropped, shown, or reported as an error.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LintLevel {
+    Allow,
+    Warn,
+    Deny,
+}
+
+/// A warning whose level can be set, by the name `-A`, `-W`, `-D` and
+/// `allow(name)` take.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Lint {
+    Unreachable,
+    /// A named function that nothing calls.
+    DeadCode,
+}
+
+/// The name that stands for every warning at once.
+pub const ALL_LINTS: &str = "warnings";
+
+impl Lint {
+    pub const ALL: &'static [Lint] = &[Lint::Unreachable, Lint::DeadCode];
+    pub fn name(&self) -> &'static str {
+        match self {
+            Lint::Unreachable => "unreachable",
+            Lint::DeadCode => "dead_code",
+        }
+    }
+    /// Whether `name` is a warning, or all of them.
+    pub fn is_name(name: &str) -> bool {
+        name == ALL_LINTS || Lint::ALL.iter().any(|lint| lint.name() == name)
+    }
+    /// The names of every warning, for messages.
+    pub fn names() -> String {
+        Lint::ALL.iter().map(Lint::name).chain([ALL_LINTS]).collect::<Vec<_>>().join(", ")
+    }
+}
+
+impl ErrorFormat {
+    pub fn parse(value: &str) -> Option<ErrorFormat> {
+        match value {
+            "human" => Some(ErrorFormat::Human),
+            "json" => Some(ErrorFormat::Json),
+            _ => None,
+        }
+    }
+}
+
+impl ColorChoice {
+    pub fn parse(value: &str) -> Option<ColorChoice> {
+        match value {
+            "auto" => Some(ColorChoice::Auto),
+            "always" => Some(ColorChoice::Always),
+            "never" => Some(ColorChoice::Never),
+            _ => None,
+        }
+    }
+}
+
+const RESET: &str = "\x1b[0m";
+const BOLD: &str = "\x1b[1m";
+const BLUE: &str = "\x1b[1;34m";
+
+impl Severity {
+    fn name(&self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+            Severity::Note => "note",
+        }
+    }
+    fn color(&self) -> &'static str {
+        match self {
+            Severity::Error => "\x1b[1;31m",
+            Severity::Warning => "\x1b[1;33m",
+            Severity::Note => "\x1b[1;36m",
+        }
+    }
+}
+
+/// Where a span falls in the source, with line and column counted from one.
+struct Location {
+    line: usize,
+    column: usize,
+    line_start: usize,
+    line_end: usize,
+}
+
+impl Location {
+    fn of(source: &str, offset: usize) -> Location {
+        let line_start = source[..offset].rfind('\n').map_or(0, |index| index + 1);
+        let line_end = source[offset..].find('\n').map_or(source.len(), |index| offset + index);
+        let line = source[..offset].matches('\n').count() + 1;
+        let column = source[line_start..offset].chars().count() + 1;
+        Location { line, column, line_start, line_end }
+    }
+}
+
+impl Renderer {
+    /// Diagnostics are written to stderr, so `Auto` colors them only when
+    /// stderr is a terminal. JSON output is never colored.
+    pub fn new(choice: ColorChoice, format: ErrorFormat) -> Renderer {
+        let color = match choice {
+            ColorChoice::Auto => io::stderr().is_terminal(),
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+        };
+        Renderer { color: color && format == ErrorFormat::Human, format }
+    }
+    fn paint(&self, style: &str, text: &str) -> String {
+        if self.color {
+            format!("{}{}{}", style, text, RESET)
+        } else {
+            text.to_string()
+        }
+    }
+    pub fn render(&self, diagnostic: &Diagnostic, files: &SourceMap) -> String {
+        match self.format {
+            ErrorFormat::Human => self.render_human(diagnostic, files),
+            ErrorFormat::Json => render_json(diagnostic, files),
+        }
+    }
+    fn render_human(&self, diagnostic: &Diagnostic, files: &SourceMap) -> String {
+        let severity = diagnostic.severity;
+        let mut out = String::new();
+        let heading = match diagnostic.code {
+            Some(code) => format!("{}[{}]", severity.name(), code),
+            None => severity.name().to_string(),
+        };
+        let _ = writeln!(out, "{}{}", self.paint(severity.color(), &heading), self.paint(BOLD, &format!(": {}", diagnostic.message)));
+
+        let primary_file = diagnostic.primary_span().and_then(|span| files.lookup(span.start));
+        let mut labels: Vec<(FileId, Location, &Label)> = diagnostic.labels.iter()
+            .filter_map(|label| {
+                let id = files.lookup(label.span.start)?;
+                let file = files.get_file(id);
+                Some((id, Location::of(file.source, file.local(label.span).start), label))
+            })
+            .collect();
+        // labels in the file of the primary label come first, then those in
+        // any other files in the order they were added
+        labels.sort_by_key(|(id, location, label)| (Some(*id) != primary_file, *id, location.line, !label.primary, location.column));
+        let gutter_width = labels.iter().map(|(_, location, _)| location.line.to_string().len()).max().unwrap_or(0);
+        let gutter = " ".repeat(gutter_width);
+
+        let mut current = None;
+        for (id, location, label) in &labels {
+            let file = files.get_file(*id);
+            if current.is_none_or(|(current_id, _)| current_id != *id) {
+                if current.is_some() {
+                    let _ = writeln!(out, "{} {}", gutter, self.paint(BLUE, "..."));
+                }
+                // the primary label is where the diagnostic is said to be,
+                // even when a label above it comes first
+                let heading = match diagnostic.primary_span() {
+                    Some(span) if Some(*id) == primary_file => Location::of(file.source, file.local(span).start),
+                    _ => Location::of(file.source, file.local(label.span).start),
+                };
+                let _ = writeln!(out, "{}{} {}:{}:{}", gutter, self.paint(BLUE, "-->"), file.name, heading.line, heading.column);
+                let _ = writeln!(out, "{} {}", gutter, self.paint(BLUE, "|"));
+            }
+            if current != Some((*id, location.line)) {
+                current = Some((*id, location.line));
+                let line_number = format!("{:>width$}", location.line, width = gutter_width);
+                let _ = writeln!(out, "{} {} {}", self.paint(BLUE, &line_number), self.paint(BLUE, "|"), &file.source[location.line_start..location.line_end]);
+            }
+            let span = file.local(label.span);
+            let underline_end = span.end.min(location.line_end);
+            let width = file.source[span.start..underline_end].chars().count().max(1);
+            let (marker, style) = if label.primary { ("^", severity.color()) } else { ("-", BLUE) };
+            let mut underline = marker.repeat(width);
+            if !label.message.is_empty() {
+                underline.push(' ');
+                underline.push_str(&label.message);
+            }
+            let _ = writeln!(out, "{} {} {}{}", gutter, self.paint(BLUE, "|"), " ".repeat(location.column - 1), self.paint(style, &underline));
+        }
+        for note in &diagnostic.notes {
+            let _ = writeln!(out, "{} {} {}: {}", gutter, self.paint(BLUE, "="), self.paint(BOLD, "note"), note);
+        }
+        if let Some(help) = &diagnostic.help {
+            let _ = writeln!(out, "{} {} {}: {}", gutter, self.paint(BLUE, "="), self.paint(BOLD, "help"), help);
+        }
+        out
+    }
+    pub fn emit(&self, diagnostic: &Diagnostic, files: &SourceMap) {
+        eprint!("{}", self.render(diagnostic, files));
+    }
+}
+
+/// Renders a diagnostic as a single line of JSON, for editors and other
+/// tools that consume the compiler's output. Offsets are into the file each
+/// label is in, and `file` is the file of the primary label, or the first
+/// file when there is none.
+fn render_json(diagnostic: &Diagnostic, files: &SourceMap) -> String {
+    let mut out = String::new();
+    let _ = write!(out, "{{\"severity\":{},", json_string(diagnostic.severity.name()));
+    match diagnostic.code {
+        Some(code) => { let _ = write!(out, "\"code\":{},", json_string(code)); }
+        None => out.push_str("\"code\":null,"),
+    }
+    let file_name = diagnostic.primary_span()
+        .and_then(|span| files.lookup(span.start))
+        .or_else(|| files.files().next().map(|(id, _)| id))
+        .map_or("", |id| files.get_file(id).name.as_str());
+    let _ = write!(out, "\"message\":{},\"file\":{},\"labels\":[", json_string(&diagnostic.message), json_string(file_name));
+    let mut first = true;
+    for label in &diagnostic.labels {
+        let file = match files.lookup(label.span.start) {
+            Some(id) => files.get_file(id),
+            None => continue,
+        };
+        if !first {
+            out.push(',');
+        }
+        first = false;
+        let span = file.local(label.span);
+        let location = Location::of(file.source, span.start);
+        let _ = write!(
+            out, "{{\"file\":{},\"start\":{},\"end\":{},\"line\":{},\"column\":{},\"primary\":{},\"message\":{}}}",
+            json_string(&file.name), span.start, span.end, location.line, location.column, label.primary, json_string(&label.message),
+        );
+    }
+    out.push_str("],\"notes\":[");
+    let notes: Vec<String> = diagnostic.notes.iter().map(|note| json_string(note)).collect();
+    out.push_str(&notes.join(","));
+    out.push_str("],\"help\":");
+    match &diagnostic.help {
+        Some(help) => out.push_str(&json_string(help)),
+        None => out.push_str("null"),
+    }
+    out.push_str("}\n");
+    out
+}
+
+pub fn json_string(text: &str) -> String {
+    let mut out = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => { let _ = write!(out, "\\u{:04x}", c as u32); }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
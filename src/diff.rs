@@ -0,0 +1,101 @@
+//! Differences between two texts, line by line, in the unified format that
+//! `diff -u` prints.
+
+/// How many unchanged lines are shown either side of a change.
+const CONTEXT: usize = 3;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Edit {
+    Same,
+    Removed,
+    Added,
+}
+
+/// The unified diff from `old` to `new`, headed with their names, or
+/// nothing when they are the same.
+pub fn unified(old_name: &str, new_name: &str, old: &str, new: &str) -> String {
+    let old: Vec<&str> = old.lines().collect();
+    let new: Vec<&str> = new.lines().collect();
+    let edits = edits(&old, &new);
+    // the line of each text that each edit starts at, and where they end
+    let mut positions = Vec::with_capacity(edits.len() + 1);
+    let (mut i, mut j) = (0, 0);
+    for edit in &edits {
+        positions.push((i, j));
+        match edit {
+            Edit::Same => (i, j) = (i + 1, j + 1),
+            Edit::Removed => i += 1,
+            Edit::Added => j += 1,
+        }
+    }
+    positions.push((i, j));
+    let changes: Vec<usize> = (0..edits.len()).filter(|&index| edits[index] != Edit::Same).collect();
+    if changes.is_empty() {
+        return String::new()
+    }
+    let mut out = format!("--- {}\n+++ {}\n", old_name, new_name);
+    let mut next = 0;
+    while next < changes.len() {
+        let start = changes[next].saturating_sub(CONTEXT);
+        let mut last = changes[next];
+        next += 1;
+        // changes closer than twice the context share a hunk
+        while next < changes.len() && changes[next] - last <= 2 * CONTEXT + 1 {
+            last = changes[next];
+            next += 1;
+        }
+        let end = (last + 1 + CONTEXT).min(edits.len());
+        let (old_start, new_start) = positions[start];
+        let (old_end, new_end) = positions[end];
+        out.push_str(&format!("@@ -{} +{} @@\n", range(old_start, old_end - old_start), range(new_start, new_end - new_start)));
+        for (edit, &(i, j)) in edits[start..end].iter().zip(&positions[start..end]) {
+            match edit {
+                Edit::Same => out.push_str(&format!(" {}\n", old[i])),
+                Edit::Removed => out.push_str(&format!("-{}\n", old[i])),
+                Edit::Added => out.push_str(&format!("+{}\n", new[j])),
+            }
+        }
+    }
+    out
+}
+
+/// A hunk's lines in one text, from the line after `start`, counting from
+/// one, or the line before when there are none.
+fn range(start: usize, count: usize) -> String {
+    match count {
+        0 => format!("{},0", start),
+        1 => format!("{}", start + 1),
+        _ => format!("{},{}", start + 1, count),
+    }
+}
+
+/// The fewest lines to remove from `old` and add to it to give `new`, by
+/// the longest sequence of lines they have in common.
+fn edits(old: &[&str], new: &[&str]) -> Vec<Edit> {
+    // how many lines the ends of the texts from each pair of lines share
+    let mut common = vec![vec![0; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            common[i][j] = if old[i] == new[j] {
+                common[i + 1][j + 1] + 1
+            } else {
+                common[i + 1][j].max(common[i][j + 1])
+            };
+        }
+    }
+    let (mut i, mut j) = (0, 0);
+    let mut edits = Vec::new();
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            edits.push(Edit::Same);
+            (i, j) = (i + 1, j + 1);
+        } else if j == new.len() || (i < old.len() && common[i + 1][j] >= common[i][j + 1]) {
+            edits.push(Edit::Removed);
+            i += 1;
+        } else {
+            edits.push(Edit::Added);
+            j += 1;
+        }
+    }
+    edits
+}
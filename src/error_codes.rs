@@ -0,0 +1,355 @@
+/// Long-form explanations for every error code, shown by `explain`.
+/// Compile errors are numbered from E0001 and parse errors from E0100.
+pub struct ErrorCode {
+    pub code: &'static str,
+    pub explanation: &'static str,
+}
+
+pub const ERROR_CODES: &[ErrorCode] = &[
+    ErrorCode {
+        code: "E0001",
+        explanation: r#"A name was used that has not been defined.
+
+Names must be bound, by an assignment, a function declaration or a
+function parameter, before they are used:
+
+    fn main() {
+        y = x + 1    // error: `x` is not defined
+        y
+    }
+
+Functions can use names from the scope they are defined in, but only
+those defined before the function itself, along with every function
+declared with `fn` in the same block.
+
+A name first bound in code that may not run, such as the body of an `if`
+or an arm of a `match`, is only bound there:
+
+    fn main() {
+        if (1 < 2) y = 1
+        y            // error: `y` is not defined
+    }
+"#,
+    },
+    ErrorCode {
+        code: "E0002",
+        explanation: r#"An operation was applied to values of the wrong types.
+
+Operators only work on matching types, and there are no implicit
+conversions between them:
+
+    fn main() 1 + 2.5    // error: int + float
+
+Use an explicit cast to convert between numeric types:
+
+    fn main() 1 as float + 2.5
+
+This error is also reported when calling something that is not a
+function, when a builtin is given arguments it does not accept, and when
+a pattern does not match the shape of the value assigned to it, and
+when code that may not run, such as the body of an `if`, assigns a name
+from outside a value of a different type.
+"#,
+    },
+    ErrorCode {
+        code: "E0003",
+        explanation: r#"A cast named a type that does not exist.
+
+The types that can appear after `as` are `int`, `uint`, `float` and
+`bool`:
+
+    fn main() 1 as integer    // error: unknown type `integer`
+"#,
+    },
+    ErrorCode {
+        code: "E0004",
+        explanation: r#"A cast between two types that cannot be converted.
+
+Casts are allowed between the numeric types (`int`, `uint` and `float`)
+and from `bool` to `int` or `uint`. Anything else is rejected:
+
+    fn main() true as float    // error: invalid cast
+
+To turn a bool into a float, go through an integer first:
+
+    fn main() true as int as float
+"#,
+    },
+    ErrorCode {
+        code: "E0005",
+        explanation: r#"A program contained an expression at the top level.
+
+Outside of script mode, a source file is a list of declarations: named
+functions and `name = value` bindings. Code that should run belongs in
+`main`:
+
+    x = 1
+    x + 1          // error: expected a declaration
+
+    fn main() x + 1
+
+Pass `--script` to evaluate the whole file as a single expression
+instead.
+"#,
+    },
+    ErrorCode {
+        code: "E0006",
+        explanation: r#"A program did not define a `main` function.
+
+Outside of script mode, running a program calls its `main` function with
+no arguments and prints what it returns:
+
+    fn main() 42
+
+`--entry name` calls the function `name` instead, so one file can hold
+several programs. Pass `--script` to evaluate the whole file as a single
+expression instead.
+"#,
+    },
+    ErrorCode {
+        code: "E0007",
+        explanation: r#"A numeric literal does not fit in its type.
+
+`int` literals must be at most 2147483647 and `uint` literals, written
+with a `u` suffix, at most 4294967295:
+
+    fn main() 3000000000     // error: literal out of range
+    fn main() 3000000000u    // ok
+
+The suffixes `i32` and `u32` name the types by size, so `42i32` is an
+`int` and `42u32` a `uint`, with the same ranges.
+"#,
+    },
+    ErrorCode {
+        code: "E0008",
+        explanation: r#"A `struct` expression held something other than its fields.
+
+A `struct` body can only hold fields, each written `name = value`, or
+`name` for a name in scope, and each field can only be given once:
+
+    fn main() {
+        x = 1
+        p = struct { x, y = 2 }    // ok
+        q = struct { x, x = 2 }    // error: `x` is given twice
+        r = struct { x + 1 }       // error: not a field
+        p.x + p.y
+    }
+"#,
+    },
+    ErrorCode {
+        code: "E0009",
+        explanation: r#"Something other than a pattern appeared where one was expected.
+
+The left side of `=` and the parameter of a function are patterns. A
+pattern is a name, `_`, which binds nothing, or a tuple of patterns:
+
+    fn main() {
+        (a, b) = (1, 2)    // ok
+        (a, b) = (b, a)    // ok, swaps `a` and `b`
+        (a, _) = (3, 4)    // ok, only binds `a`
+        1 = 2              // error: invalid pattern
+        a
+    }
+
+The whole right side is computed before any name in the pattern is
+bound again, so a name on both sides keeps its old value on the right.
+
+The pattern of a `match` arm can also hold `int`, `uint` and `bool`
+literals, which only match an equal value:
+
+    fn describe(n) match n {
+        0 => 10
+        _ => 20
+    }
+"#,
+    },
+    ErrorCode {
+        code: "E0010",
+        explanation: r#"A function called itself where its return type cannot be known.
+
+Functions are compiled separately for each type of argument they are
+called with, and the return type of a call is only known once its body
+has been compiled. A call back into a function that is still being
+compiled takes its type from the left side of the `else` it is in the
+right side of, so recursion has to end in the `if` before it. The type
+reaches a call through arithmetic and comparisons, whose operands have
+the type of the result or of each other, and through tuples:
+
+    fn f(x, done) (if (done) x) else f(x + 1, true)    // ok
+    fn fib(n) (if (n < 2) n) else fib(n - 1) + fib(n - 2)    // ok
+    fn g(x) 1 + g(x)    // error: cannot tell what a recursive call returns
+
+The same error is reported when calls keep producing new argument types,
+which would otherwise never finish compiling.
+"#,
+    },
+    ErrorCode {
+        code: "E0011",
+        explanation: r#"A name was assigned again after a function that reads it was created.
+
+A function keeps the scope it was created in, not the values in it, and
+is compiled when it is first called. An assignment in between changes
+what the function reads:
+
+    fn main() {
+        x = 1
+        f = fn() x
+        x = 2    // error: `x` is assigned again after a function captures it
+        f()
+    }
+
+Give the new value a new name instead. Assigning a name from outside the
+function the closure was created in is fine, since that only hides the
+outer name from the code after it.
+"#,
+    },
+    ErrorCode {
+        code: "E0012",
+        explanation: r#"An `ir { ... }` block contained a line that is not an instruction.
+
+Inline IR is written one instruction per line, as the IR dump prints it
+but with names in place of registers. Names read before the block
+defines them come from the surrounding code, and names it defines are
+bound after it:
+
+    fn main() {
+        a = 1
+        ir {
+            b = a << a    ; ok
+            c = a ** b    ; error: unknown operator `**`
+        }
+        b
+    }
+
+The instructions are arithmetic, comparisons, constants, conversions,
+`select`, `exit` and `abort`. Calls cannot be written in inline IR.
+"#,
+    },
+    ErrorCode {
+        code: "E0013",
+        explanation: r#"A `break` was not inside a loop it can leave.
+
+`break` leaves the innermost `for` or `while` loop around it, and
+`break label` leaves the loop written `label: for ...` or
+`label: while ...`:
+
+    fn main() {
+        outer: for x in range(0, 10) {
+            for y in range(0, 10) {
+                break outer    // ok, leaves both loops
+            }
+        }
+        break                  // error: `break` outside of a loop
+    }
+
+A function's body cannot leave a loop outside the function, even if the
+function is created inside the loop.
+"#,
+    },
+    ErrorCode {
+        code: "E0014",
+        explanation: r#"An integer literal has a suffix that names no type.
+
+`int` and `uint` are 32 bits, so the only suffixes are `u` or `u32` for a
+`uint` and `i32` for an `int`:
+
+    fn main() 42i32    // ok, an `int`
+    fn main() 42u32    // ok, a `uint`
+    fn main() 42i64    // error: unknown literal suffix
+"#,
+    },
+    ErrorCode {
+        code: "E0015",
+        explanation: r#"A function returned from a call reads a parameter or local of that call.
+
+A function reads the names it captures from where they are held when it
+is called, not when it is created. The parameters and locals of a call
+are only held until the function they belong to is called again, so a
+function returned from the call would read another call's values:
+
+    fn add(n) |x| x + n    // error: a returned function captures `n`
+
+    fn main() {
+        a = add(1)
+        b = add(2)
+        (a(10), b(10))
+    }
+
+Pass the value to the returned function as an argument instead, as in
+`fn add(x, n) x + n`. Functions the returned one calls may be captured,
+as long as they do not capture a local themselves.
+"#,
+    },
+    ErrorCode {
+        code: "E0100",
+        explanation: r#"The parser expected a value but found something else.
+
+A value is a literal, a name, a parenthesised expression, a block, a
+`|x| x + 1` function, `!` before a value, `break`, or a `fn`, `if`,
+`for`, `while`, `defer`, `cfg`, `allow`, `struct` or `ir` expression. This is usually caused by an
+operator with a missing operand:
+
+    fn main() 1 +    // error: expected a value
+"#,
+    },
+    ErrorCode {
+        code: "E0101",
+        explanation: r#"The parser found something that cannot appear at that point.
+
+The error lists everything that would have been accepted instead. This
+is most often an unclosed bracket, or a keyword missing the part that
+must follow it:
+
+    fn main() (1, 2        // error: expected `)`, `,`, or an operator
+    fn main() if true 1    // error: expected `(`
+
+A `/* */` comment that is never closed runs to the end of the file, and
+expects its `*/` there. Comments nest, so each `/*` needs its own `*/`.
+"#,
+    },
+    ErrorCode {
+        code: "E0102",
+        explanation: r#"A keyword was written where a name was expected.
+
+Keywords cannot name values, parameters, functions or loops:
+
+    fn main() {
+        else = 1             // error: `else` is a keyword
+        match = 2            // error: `match` is a keyword
+        fn while(x) x        // error: `while` is a keyword
+        fn f(for) 1          // error: `for` is a keyword
+        x = 1
+        x
+    }
+
+The keywords are `allow`, `as`, `break`, `cfg`, `defer`, `else`,
+`false`, `fn`, `for`, `if`, `in`, `match`, `struct`, `true` and
+`while`, along with `return`, which is kept for syntax to come. `ir` is
+only a keyword before a `{` on the same line, so it can still be a name.
+"#,
+    },
+    ErrorCode {
+        code: "E0103",
+        explanation: r#"An expression was nested more than 128 levels deep.
+
+Each operand, argument, block, branch and function body inside another
+expression is one level deeper, and each `defer` in a block counts as a
+level for the items after it:
+
+    fn main() 1 + 1 + 1 + ... + 1   // error: a sum of 200 terms
+
+Give part of the expression a name of its own to split it up:
+
+    fn main() {
+        half = 1 + 1 + ... + 1
+        half + 1 + ... + 1
+    }
+"#,
+    },
+];
+
+pub fn explain(code: &str) -> Option<&'static str> {
+    ERROR_CODES.iter()
+        .find(|error_code| error_code.code.eq_ignore_ascii_case(code))
+        .map(|error_code| error_code.explanation)
+}
@@ -0,0 +1,127 @@
+//! Evaluating a single expression against values supplied by the caller,
+//! for using the language as a formula engine.
+
+use crate::diagnostics::Diagnostic;
+use crate::execute::{Trap, VirtualMachine};
+use crate::ir::{Block, Function, Program};
+use crate::source_map::SourceMap;
+use crate::types::Type;
+use crate::{builtins, compiler, hir, parser};
+use std::fmt;
+
+const FILE_NAME: &str = "<expr>";
+
+/// A value passed into or returned from an evaluated expression.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Int(i32),
+    UInt(u32),
+    Float(f32),
+    Bool(bool),
+    /// A `maybe` value. An empty one can be returned but not passed in,
+    /// since nothing would say what type it holds.
+    Maybe(Option<Box<Value>>),
+    Tuple(Vec<Value>),
+    Struct(Vec<(String, Value)>),
+}
+
+/// Evaluates `source` as one expression, with each name in `env` bound to
+/// its value as if it had been assigned before the expression. The builtins
+/// are in scope too, and a name in `env` hides the builtin of that name.
+pub fn eval_expr<'a>(source: &'a str, env: &[(&'a str, Value)]) -> Result<Value, Vec<Diagnostic>> {
+    let files = SourceMap::single(FILE_NAME, source);
+    let ast = parser::parse_source(source).map_err(|error| vec![error.to_diagnostic(&files)])?;
+    let hir = hir::lower(&ast, &[]).map_err(|error| vec![error.to_diagnostic(&files)])?;
+
+    let mut program = Program::new();
+    let mut function = Function::new();
+    let mut block = function.new_block();
+    let mut scope = builtins::prelude();
+    for (name, value) in env {
+        let ty = constant(value, &mut program, &mut block).ok_or_else(|| vec![Diagnostic::error(
+            format!("the type of `{}` cannot be known from an empty maybe", name), None,
+        )])?;
+        scope.assign(name, ty);
+    }
+    let ty = compiler::compile(&hir, &mut scope, &mut program, &mut function, &mut block)
+        .map_err(|error| vec![error.to_diagnostic(&files)])?;
+    block.ret(&mut function);
+    ty.return_ty(&mut function);
+    let entry = program.add_function(function);
+
+    let mut vm = VirtualMachine::new(&program);
+    match vm.execute(program.get_function(entry)) {
+        Ok(()) => (),
+        Err(Trap::Exit { code, .. }) => return Err(vec![Diagnostic::error(format!("the expression called `exit` with {}", code), None)]),
+        Err(Trap::Abort { .. }) => return Err(vec![Diagnostic::error("`else trap` or `match` found no value".to_string(), None)]),
+        Err(Trap::Cancelled) => return Err(vec![Diagnostic::error("evaluation was cancelled".to_string(), None)]),
+        Err(Trap::Exhausted(resource)) => return Err(vec![Diagnostic::error(format!("evaluation ran out of {:?}", resource), None)]),
+        Err(Trap::Uninitialized { register, instruction, .. }) => return Err(vec![Diagnostic::error(format!("r{} was read before it was written, by `{}`", register, instruction), None)]),
+        Err(Trap::DivideByZero { .. }) => return Err(vec![Diagnostic::error("the expression divided by zero".to_string(), None)]),
+        Err(Trap::NegativeExponent { .. }) => return Err(vec![Diagnostic::error("the expression gave `pow` a negative exponent".to_string(), None)]),
+        Err(Trap::EmptyRange { .. }) => return Err(vec![Diagnostic::error("the expression gave `rand_range` an empty range".to_string(), None)]),
+        Err(Trap::Deadlock { .. }) => return Err(vec![Diagnostic::error("every task of the evaluation was waiting on a channel".to_string(), None)]),
+        Err(Trap::Diverged) => return Err(vec![Diagnostic::error("the replayed evaluation read the clock more than the recording".to_string(), None)]),
+        Err(Trap::InvalidHandle { .. }) => return Err(vec![Diagnostic::error("the expression used a map or channel that was never created".to_string(), None)]),
+    }
+    read(&ty, &vm).ok_or_else(|| vec![Diagnostic::error(format!("the expression is `{}`, which is not a value", ty), None)])
+}
+
+/// Emits `value` as constants, or returns `None` for an empty maybe.
+fn constant<'a, 'b>(value: &Value, program: &mut Program, block: &mut Block) -> Option<Type<'a, 'b>> {
+    Some(match value {
+        Value::Int(value) => Type::Int(block.constant_int(*value, program)),
+        Value::UInt(value) => Type::UInt(block.constant_int(*value as i32, program)),
+        Value::Float(value) => Type::Float(block.constant_float(*value, program)),
+        Value::Bool(value) => Type::Bool(block.constant_int(*value as i32, program)),
+        Value::Maybe(value) => {
+            let value = constant(value.as_ref()?, program, block)?;
+            Type::Maybe(block.constant_int(1, program), Box::new(value))
+        }
+        Value::Tuple(values) => Type::Tuple(values.iter().map(|value| constant(value, program, block)).collect::<Option<_>>()?),
+        Value::Struct(fields) => Type::Struct(fields.iter()
+            .map(|(name, value)| Some((name.as_str().into(), constant(value, program, block)?)))
+            .collect::<Option<_>>()?),
+    })
+}
+
+/// Reads the value of `ty` from the registers, or returns `None` if it
+/// holds a function.
+pub(crate) fn read(ty: &Type, vm: &VirtualMachine) -> Option<Value> {
+    Some(match ty {
+        Type::Int(var) => Value::Int(vm.get_register(*var)),
+        Type::UInt(var) => Value::UInt(vm.get_register(*var) as u32),
+        Type::Float(var) => Value::Float(vm.get_float_register(*var)),
+        Type::Bool(var) => Value::Bool(vm.get_register(*var) != 0),
+        Type::Maybe(tag, ty) => {
+            let value = read(ty, vm)?;
+            Value::Maybe(if vm.get_register(*tag) != 0 { Some(Box::new(value)) } else { None })
+        }
+        Type::Tuple(types) => Value::Tuple(types.iter().map(|ty| read(ty, vm)).collect::<Option<_>>()?),
+        Type::Struct(fields) => Value::Struct(fields.iter().map(|(name, ty)| Some((name.to_string(), read(ty, vm)?))).collect::<Option<_>>()?),
+        Type::Map(..) | Type::Chan(..) | Type::Func { .. } | Type::Builtin(_) => return None,
+    })
+}
+
+/// Values are written as they would be in source, and as the driver prints
+/// results.
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Int(value) => write!(f, "{}", value),
+            Value::UInt(value) => write!(f, "{}", value),
+            Value::Float(value) => write!(f, "{:?}", value),
+            Value::Bool(value) => write!(f, "{}", value),
+            Value::Maybe(Some(value)) => write!(f, "{}", value),
+            Value::Maybe(None) => write!(f, "none"),
+            Value::Tuple(values) => {
+                let values: Vec<String> = values.iter().map(Value::to_string).collect();
+                write!(f, "({})", values.join(", "))
+            }
+            Value::Struct(fields) => {
+                let fields: Vec<String> = fields.iter().map(|(name, value)| format!("{} = {}", name, value)).collect();
+                write!(f, "struct {{ {} }}", fields.join(", "))
+            }
+        }
+    }
+}
@@ -0,0 +1,130 @@
+//! Runs a directory of example programs, each of which gives what it prints
+//! in `// output:` comments, on every backend, so that the examples stay
+//! true as the language changes.
+
+use language::bytecode;
+use language::clock::Clock;
+use language::diagnostics::{Renderer, Severity};
+use language::execute::VirtualMachine;
+use language::ir::{FunctionId, Program};
+use language::optimize;
+use language::session::{CompileOptions, Session};
+use language::types::Type;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// The directory of examples in a project, used when none is given.
+pub const DEFAULT_DIR: &str = "examples";
+
+const OUTPUT: &str = "// output:";
+
+/// The ways a compiled program can be run, which must all print the same.
+#[derive(Copy, Clone)]
+enum Backend {
+    /// The program as the compiler produced it.
+    Direct,
+    /// The program after a round trip through bytecode, as cached and
+    /// bundled programs are run.
+    Bytecode,
+    /// The program after the optimization passes, as `--optimize` runs it.
+    Optimized,
+}
+
+impl Backend {
+    fn name(self) -> &'static str {
+        match self {
+            Backend::Direct => "direct",
+            Backend::Bytecode => "bytecode",
+            Backend::Optimized => "optimized",
+        }
+    }
+}
+
+const BACKENDS: [Backend; 3] = [Backend::Direct, Backend::Bytecode, Backend::Optimized];
+
+/// Runs each `.txt` file in `dir`, printing a line for each, and returns
+/// whether every one printed what it says it does.
+pub fn run_all(dir: &Path, renderer: &Renderer) -> io::Result<bool> {
+    let mut paths: Vec<PathBuf> = fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<io::Result<_>>()?;
+    paths.retain(|path| path.extension().is_some_and(|extension| extension == "txt"));
+    paths.sort();
+    let mut failed = 0;
+    for path in &paths {
+        let file_name = path.display().to_string();
+        let source = fs::read_to_string(path)?;
+        match check(&file_name, source, renderer) {
+            Ok(()) => println!("{} ... ok", file_name),
+            Err(message) => {
+                println!("{} ... FAILED\n    {}", file_name, message.replace('\n', "\n    "));
+                failed += 1;
+            }
+        }
+    }
+    println!("{} examples, {} passed, {} failed", paths.len(), paths.len() - failed, failed);
+    Ok(failed == 0)
+}
+
+/// Compiles `source` and runs it on each backend, describing the first
+/// difference from its `// output:` comments.
+fn check(file_name: &str, source: String, renderer: &Renderer) -> Result<(), String> {
+    let expected: Vec<&str> = source.lines()
+        .filter_map(|line| line.trim().strip_prefix(OUTPUT))
+        .map(str::trim)
+        .collect();
+    if expected.is_empty() {
+        return Err(format!("no `{}` comment says what the example prints", OUTPUT))
+    }
+    let expected = expected.join("\n");
+    let session = Session::new(file_name.to_string(), source, CompileOptions::default());
+    let compiled = session.parse()
+        .and_then(|ast| {
+            let hir = session.lower(&ast)?;
+            let (program, main_id, ty) = session.compile(&hir)?;
+            Some(BACKENDS.iter().map(|&backend| (backend, run(backend, &program, main_id, &ty))).collect::<Vec<_>>())
+        });
+    let diagnostics = session.take_diagnostics();
+    if diagnostics.iter().any(|diagnostic| diagnostic.severity == Severity::Error) {
+        for diagnostic in &diagnostics {
+            renderer.emit(diagnostic, &session.source_map());
+        }
+    }
+    let results = compiled.ok_or("the example did not compile")?;
+    for (backend, result) in results {
+        match result {
+            Ok(output) if output == expected => (),
+            Ok(output) => return Err(format!("on the {} backend, expected\n{}\nbut it printed\n{}", backend.name(), expected, output)),
+            Err(message) => return Err(format!("on the {} backend, {}", backend.name(), message)),
+        }
+    }
+    Ok(())
+}
+
+/// Runs the program with a fixed seed and a mock clock, so that every run
+/// prints the same, and gives what it printed, followed by its result.
+fn run(backend: Backend, program: &Program, main_id: FunctionId, ty: &Type) -> Result<String, String> {
+    let decoded;
+    let mut optimized;
+    let (program, main_id, ty) = match backend {
+        Backend::Direct => (program, main_id, ty),
+        Backend::Bytecode => {
+            decoded = bytecode::encode(program, main_id, ty)
+                .and_then(|bytes| bytecode::decode(&bytes))
+                .ok_or("the program could not be encoded as bytecode")?;
+            (&decoded.0, decoded.1, &decoded.2)
+        }
+        Backend::Optimized => {
+            optimized = program.clone();
+            optimize::optimize(&mut optimized, true);
+            (&optimized, main_id, ty)
+        }
+    };
+    let mut vm = VirtualMachine::new(program);
+    vm.set_seed(0);
+    vm.set_clock(Clock::mock(0));
+    vm.capture_output();
+    vm.execute(program.get_function(main_id)).map_err(|trap| format!("the program stopped: {}", trap))?;
+    Ok(vm.take_output() + &vm.format_ty(ty))
+}
@@ -1,69 +1,1027 @@
-use crate::ir::{Program, BlockId, Instruction, ExitInstruction, Var, Function};
+use std::convert::TryFrom;
+use std::collections::VecDeque;
+use std::ops::Bound;
+use std::{fmt, mem, ptr};
+use std::time::{Duration, Instant};
+use crate::ir::{Program, Block, BlockId, Instruction, ExitInstruction, Var, Function, FunctionId, Conversion};
+use crate::callgraph::CallGraph;
+use crate::types::Type;
+use crate::builtins::Intrinsic;
+use crate::ops;
+use crate::cancel::CancellationToken;
+use crate::clock::Clock;
+use crate::replay::Recording;
+use crate::heap::Heap;
+use crate::hir::CastType;
 
 pub struct VirtualMachine<'a> {
+    /// Grows as registers are written, since a run usually touches only
+    /// some of the program's registers. Registers past its end read as 0.
+    /// There is one file for the whole program rather than a window for
+    /// each call, since a function reads what it captures straight from the
+    /// registers of the function it was made in.
     register_file: Vec<i32>,
+    /// In strict mode, which registers hold a value, so that reading one
+    /// that does not traps. A copy of a register that holds none holds
+    /// none either, since a `maybe` that is empty leaves its value unset.
+    written: Option<Vec<bool>>,
+    heap: Heap,
     program: &'a Program,
+    rng_state: u64,
+    clock: Clock,
+    args: Vec<String>,
+    /// What `print` has written, when output is captured rather than
+    /// written to stdout.
+    output: Option<String>,
+    cancellation: Option<CancellationToken>,
+    /// Blocks entered since the token and the deadline were last checked.
+    steps: u32,
+    /// Instructions left to run, when running in a sandbox with a fuel limit.
+    fuel: Option<u64>,
+    max_memory: Option<usize>,
+    deadline: Option<Instant>,
+    stats: VmStats,
+    /// Every register is shared by all calls of the function that uses it,
+    /// so a call that can come back into its caller saves the caller's
+    /// registers first. These are the registers of each function that is
+    /// part of a cycle of calls.
+    frames: Vec<Option<Vec<Var>>>,
+    /// The cycle of calls each function is part of.
+    components: Vec<usize>,
+    /// Tasks waiting for the running task to yield, in the order they run.
+    tasks: VecDeque<Task<'a>>,
+    /// Tasks in a row that have had to wait on a channel. Once every task
+    /// has, none of them can go on.
+    waiting: usize,
+    replay: Replay,
 }
 
+/// A call of a compiled function that has not returned yet.
+struct ActiveCall<'a> {
+    function: &'a Function,
+    function_id: FunctionId,
+    block: &'a Block,
+    /// The block that branched to `block`, which its phis take their
+    /// values from.
+    previous: Option<BlockId>,
+    /// The next instruction of `block` to run.
+    index: usize,
+    /// The caller's registers to restore when the call returns.
+    saved: Option<Vec<(Var, i32)>>,
+    /// Where the caller takes the returned values from.
+    returns: &'a [Var],
+}
+
+impl<'a> ActiveCall<'a> {
+    fn new(program: &'a Program, function_id: FunctionId, saved: Option<Vec<(Var, i32)>>, returns: &'a [Var]) -> ActiveCall<'a> {
+        let function = program.get_function(function_id);
+        ActiveCall { function, function_id, block: function.get_entry_block(), previous: None, index: 0, saved, returns }
+    }
+}
+
+/// A task started by `spawn`, or the one the program started with. Each
+/// task has registers of its own, starting as a copy of those of the task
+/// that spawned it, and they share the heap.
+struct Task<'a> {
+    calls: Vec<ActiveCall<'a>>,
+    /// The task's registers while it waits. The running task's registers
+    /// are the VM's.
+    register_file: Vec<i32>,
+    written: Option<Vec<bool>>,
+    /// Whether this is the task the program started with.
+    first: bool,
+}
+
+/// Whether the inputs of the run are being recorded or replayed.
+enum Replay {
+    Off,
+    Record(Recording),
+    /// Replaying a recording, with how many clock readings have been used.
+    Play(Recording, usize),
+}
+
+/// What running a program cost, counted across every run of the VM.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct VmStats {
+    /// One more than the highest register written, which is how much of the
+    /// register file the program needed.
+    pub max_registers_touched: usize,
+    /// The most the heap has held at once.
+    pub heap_bytes_allocated: usize,
+    /// Instructions run, counting the exit of each block.
+    pub instructions_executed: u64,
+    /// Calls of compiled functions. Builtins count as instructions only.
+    pub calls: u64,
+}
+
+/// Limits for running programs that are not trusted. Every limit is off by
+/// default.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct SandboxConfig {
+    /// The number of instructions that may run, counting those of callees.
+    pub fuel: Option<u64>,
+    /// The most memory, in bytes, the register file and the heap may use
+    /// together.
+    pub max_memory: Option<usize>,
+    /// How long the program may run, from when the sandbox is set.
+    pub timeout: Option<Duration>,
+}
+
+/// The limit a sandboxed program ran into.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Resource {
+    Fuel,
+    Memory,
+    Time,
+    /// Calls nested deeper than `MAX_CALL_DEPTH`.
+    Stack,
+}
+
+/// Stops execution of the whole program, unwinding every active call.
+#[derive(Debug, PartialEq)]
+pub enum Trap {
+    /// `exit` was called, on the given source line.
+    Exit { code: i32, line: u32 },
+    /// `else trap` found an empty `maybe`, or no arm of a `match` matched,
+    /// on the given source line.
+    Abort { line: u32 },
+    /// The host cancelled the run.
+    Cancelled,
+    /// The program ran past a limit of its sandbox.
+    Exhausted(Resource),
+    /// An `int` or `uint` was divided by zero, on the given source line.
+    DivideByZero { line: u32 },
+    /// `pow` of two `int`s was given a negative exponent, on the given
+    /// source line.
+    NegativeExponent { line: u32 },
+    /// `rand_range` was given a range with no values in it, on the given
+    /// source line.
+    EmptyRange { line: u32 },
+    /// In strict mode, an instruction read a register nothing had written,
+    /// which is a bug in the compiler.
+    Uninitialized { register: usize, instruction: String, line: u32 },
+    /// Every task was waiting, and the `send` on the given source line
+    /// could never finish.
+    Deadlock { line: u32 },
+    /// A replayed program read the clock more often than when it was
+    /// recorded.
+    Diverged,
+    /// A map or channel was used, on the given source line, through a
+    /// handle that no `map` or `chan` gave, which is a bug in the compiler.
+    InvalidHandle { line: u32 },
+}
+
+impl fmt::Display for Trap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Trap::Exit { code, line } => write!(f, "the program exited with code {} at line {}", code, line),
+            Trap::Abort { line } => write!(f, "`else trap` or `match` found no value at line {}", line),
+            Trap::Cancelled => write!(f, "the program was cancelled"),
+            Trap::Exhausted(Resource::Fuel) => write!(f, "the program ran out of fuel"),
+            Trap::Exhausted(Resource::Memory) => write!(f, "the program ran out of memory"),
+            Trap::Exhausted(Resource::Time) => write!(f, "the program ran out of time"),
+            Trap::Exhausted(Resource::Stack) => write!(f, "calls nested more than {} deep", MAX_CALL_DEPTH),
+            Trap::DivideByZero { line } => write!(f, "division by zero at line {}", line),
+            Trap::NegativeExponent { line } => write!(f, "`pow` was given a negative exponent at line {}", line),
+            Trap::EmptyRange { line } => write!(f, "`rand_range` was given an empty range at line {}", line),
+            Trap::Uninitialized { register, instruction, line } => {
+                write!(f, "r{} was read before it was written, by `{}` at line {}", register, instruction, line)
+            }
+            Trap::Deadlock { line } => write!(f, "every task was waiting, so the `send` at line {} could never finish", line),
+            Trap::Diverged => write!(f, "the program read the clock more times than when it was recorded"),
+            Trap::InvalidHandle { line } => write!(f, "a map or channel that was never created was used at line {}", line),
+        }
+    }
+}
+
+impl std::error::Error for Trap {}
+
+const DEFAULT_SEED: u64 = 0x853c_49e6_748f_ea9b;
+
+/// How many blocks run between checks of the cancellation token and the
+/// deadline.
+const CANCELLATION_INTERVAL: u32 = 1024;
+
+/// How deep calls may nest in a task, so that recursion that never ends
+/// stops with a trap rather than using up memory.
+pub const MAX_CALL_DEPTH: usize = 1024;
+
 impl<'a> VirtualMachine<'a> {
     pub fn new(program: &'a Program) -> VirtualMachine<'a> {
+        let call_graph = CallGraph::new(program);
+        let mut frames = vec![None; program.get_function_count()];
+        let mut components = vec![0; program.get_function_count()];
+        for (index, component) in call_graph.components().into_iter().enumerate() {
+            let cyclic = component.len() > 1 || call_graph.callees(component[0]).iter().any(|callee| callee.get_id() == component[0].get_id());
+            for function_id in component {
+                components[function_id.get_id()] = index;
+                if cyclic {
+                    frames[function_id.get_id()] = Some(program.get_function(function_id).vars());
+                }
+            }
+        }
         VirtualMachine {
-            register_file: vec![0; program.get_variable_count()],
+            register_file: Vec::new(),
+            written: None,
+            heap: Heap::default(),
             program,
+            rng_state: DEFAULT_SEED,
+            clock: Clock::default(),
+            args: Vec::new(),
+            output: None,
+            cancellation: None,
+            steps: 0,
+            fuel: None,
+            max_memory: None,
+            deadline: None,
+            stats: VmStats::default(),
+            frames,
+            components,
+            tasks: VecDeque::new(),
+            waiting: 0,
+            replay: Replay::Off,
+        }
+    }
+    pub fn set_sandbox(&mut self, config: SandboxConfig) {
+        self.fuel = config.fuel;
+        self.max_memory = config.max_memory;
+        self.deadline = config.timeout.map(|timeout| Instant::now() + timeout);
+    }
+    /// Traps with `Trap::Uninitialized` when a register is read before it
+    /// is written. Registers the VM was created with count as written.
+    pub fn set_strict(&mut self, strict: bool) {
+        self.written = strict.then(|| vec![true; self.register_file.len()]);
+    }
+    /// Checks `token` as the program runs, stopping it with
+    /// `Trap::Cancelled` once it is cancelled.
+    pub fn set_cancellation(&mut self, token: CancellationToken) {
+        self.cancellation = Some(token);
+    }
+    pub fn get_stats(&self) -> VmStats {
+        self.stats
+    }
+    pub fn set_args(&mut self, args: Vec<String>) {
+        self.args = args;
+    }
+    /// Keeps what `print` writes for `take_output`, rather than writing it
+    /// to stdout.
+    pub fn capture_output(&mut self) {
+        self.output = Some(String::new());
+    }
+    /// What `print` has written since output was captured, or since this
+    /// was last called.
+    pub fn take_output(&mut self) -> String {
+        self.output.as_mut().map(mem::take).unwrap_or_default()
+    }
+    fn print(&mut self, line: String) {
+        match &mut self.output {
+            Some(output) => {
+                output.push_str(&line);
+                output.push('\n');
+            }
+            None => println!("{}", line),
+        }
+    }
+    /// Creates a VM that continues from the registers of a previous run, so
+    /// values computed there stay readable by code added to the program since.
+    pub fn with_registers(program: &'a Program, register_file: Vec<i32>) -> VirtualMachine<'a> {
+        VirtualMachine { register_file, ..VirtualMachine::new(program) }
+    }
+    pub fn into_registers(self) -> Vec<i32> {
+        self.register_file
+    }
+    /// Continues with the heap of a previous run, which the handles in its
+    /// registers refer to.
+    pub fn with_heap(mut self, heap: Heap) -> VirtualMachine<'a> {
+        self.heap = heap;
+        self
+    }
+    pub fn take_heap(&mut self) -> Heap {
+        mem::take(&mut self.heap)
+    }
+    pub fn set_seed(&mut self, seed: u64) {
+        self.rng_state = seed;
+    }
+    /// Reads the time for `now_millis` and `sleep` from `clock`.
+    pub fn set_clock(&mut self, clock: Clock) {
+        self.clock = clock;
+    }
+    /// Records the inputs of the run, starting from the seed and arguments
+    /// already set, for `take_recording` to return once it is over.
+    pub fn record(&mut self, program: Option<u64>) {
+        self.replay = Replay::Record(Recording { program, seed: self.rng_state, args: self.args.clone(), clock: Vec::new() });
+    }
+    pub fn take_recording(&mut self) -> Option<Recording> {
+        match mem::replace(&mut self.replay, Replay::Off) {
+            Replay::Record(recording) => Some(recording),
+            replay => {
+                self.replay = replay;
+                None
+            }
         }
     }
-    pub fn execute(&mut self, function: &Function) {
-        let mut block = function.get_block(BlockId::entry());
+    /// Runs with the seed, arguments and clock readings of `recording`.
+    /// Sleeping returns at once, since the clock readings already include
+    /// the time slept.
+    pub fn replay(&mut self, recording: Recording) {
+        self.rng_state = recording.seed;
+        self.args = recording.args.clone();
+        self.replay = Replay::Play(recording, 0);
+    }
+    fn now_millis(&mut self) -> Result<u64, Trap> {
+        match &mut self.replay {
+            Replay::Off => Ok(self.clock.now_millis()),
+            Replay::Record(recording) => {
+                let now = self.clock.now_millis();
+                recording.clock.push(now);
+                Ok(now)
+            }
+            Replay::Play(recording, read) => {
+                let now = recording.clock.get(*read).copied().ok_or(Trap::Diverged)?;
+                *read += 1;
+                Ok(now)
+            }
+        }
+    }
+    fn next_random(&mut self) -> u64 {
+        // splitmix64, so that every seed (including 0) gives a usable stream
+        self.rng_state = self.rng_state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.rng_state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+    /// Runs `function`, which must be one of the program's.
+    pub fn execute(&mut self, function: &Function) -> Result<(), Trap> {
+        let function_id = self.program.get_function_ids().into_iter()
+            .find(|&function_id| ptr::eq(self.program.get_function(function_id), function))
+            .expect("the function is part of the program");
+        self.run(function_id)
+    }
+    /// The registers of the caller to restore after a call that might come
+    /// back into it.
+    fn save_frame(&self, caller: FunctionId, callee: FunctionId) -> Option<Vec<(Var, i32)>> {
+        let caller = caller.get_id();
+        if self.components[caller] != self.components[callee.get_id()] {
+            return None
+        }
+        let vars = self.frames[caller].as_ref()?;
+        Some(vars.iter().map(|&var| (var, self.get_register(var))).collect())
+    }
+    fn check_memory(&self) -> Result<(), Trap> {
+        let registers = self.register_file.len() + self.tasks.iter().map(|task| task.register_file.len()).sum::<usize>();
+        let used = registers * mem::size_of::<i32>() + self.heap.bytes();
+        if self.max_memory.is_some_and(|max| used > max) {
+            return Err(Trap::Exhausted(Resource::Memory))
+        }
+        Ok(())
+    }
+    /// Runs `entry` as the first task, then every task it spawns, in turn
+    /// each time the running task yields, until all of them have finished.
+    /// The registers left are those of the first task.
+    fn run(&mut self, entry: FunctionId) -> Result<(), Trap> {
+        self.tasks.clear();
+        let mut task = Task { calls: vec![ActiveCall::new(self.program, entry, None, &[])], register_file: Vec::new(), written: None, first: true };
+        let mut first_registers = None;
         loop {
-            for inst in block.get_instructions() {
+            let finished = self.run_task(&mut task.calls)?;
+            if finished {
+                self.waiting = 0;
+            }
+            if finished && task.first {
+                first_registers = Some((mem::take(&mut self.register_file), self.written.as_mut().map(mem::take)));
+            }
+            if !finished && self.tasks.is_empty() {
+                continue
+            }
+            let mut next = match self.tasks.pop_front() {
+                Some(next) => next,
+                None => break,
+            };
+            mem::swap(&mut self.register_file, &mut next.register_file);
+            mem::swap(&mut self.written, &mut next.written);
+            if !finished {
+                self.tasks.push_back(Task { register_file: next.register_file, written: next.written, ..task });
+            }
+            task = Task { register_file: Vec::new(), written: None, ..next };
+        }
+        if let Some((registers, written)) = first_registers {
+            self.register_file = registers;
+            self.written = written;
+        }
+        Ok(())
+    }
+    /// Runs the calls of a task until it yields, returning false, or its
+    /// first call returns, returning true.
+    fn run_task(&mut self, calls: &mut Vec<ActiveCall<'a>>) -> Result<bool, Trap> {
+        self.check_memory()?;
+        'calls: while let Some(call) = calls.last_mut() {
+            let (block, caller) = (call.block, call.function_id);
+            if call.index == 0 {
+                self.steps += 1;
+                if self.steps >= CANCELLATION_INTERVAL {
+                    self.steps = 0;
+                    if self.cancellation.as_ref().is_some_and(CancellationToken::is_cancelled) {
+                        return Err(Trap::Cancelled)
+                    }
+                    if self.deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                        return Err(Trap::Exhausted(Resource::Time))
+                    }
+                }
+                // the block's exit counts as an instruction too
+                let cost = block.get_instructions().len() as u64 + 1;
+                self.stats.instructions_executed += cost;
+                if let Some(fuel) = &mut self.fuel {
+                    if *fuel < cost {
+                        return Err(Trap::Exhausted(Resource::Fuel))
+                    }
+                    *fuel -= cost;
+                }
+            }
+            while let Some(inst) = block.get_instructions().get(call.index) {
+                let index = call.index;
+                call.index += 1;
+                self.check_reads(inst, block.get_line(index))?;
                 match inst {
                     &Instruction::AddInt { dest, a, b } => {
                         let a = self.get_register(a);
                         let b = self.get_register(b);
-                        self.set_register(dest, a + b)
+                        self.set_register(dest, ops::add_int(a, b))
+                    }
+                    &Instruction::AddFloat { dest, a, b } => {
+                        let a = self.get_float_register(a);
+                        let b = self.get_float_register(b);
+                        self.set_float_register(dest, ops::add_float(a, b))
+                    }
+                    &Instruction::AndInt { dest, a, b } => {
+                        let a = self.get_register(a);
+                        let b = self.get_register(b);
+                        self.set_register(dest, a & b)
+                    }
+                    &Instruction::OrInt { dest, a, b } => {
+                        let a = self.get_register(a);
+                        let b = self.get_register(b);
+                        self.set_register(dest, a | b)
+                    }
+                    &Instruction::XorInt { dest, a, b } => {
+                        let a = self.get_register(a);
+                        let b = self.get_register(b);
+                        self.set_register(dest, a ^ b)
+                    }
+                    &Instruction::ShiftLeft { dest, a, b } => {
+                        let a = self.get_register(a);
+                        let b = self.get_register(b);
+                        self.set_register(dest, ops::shift_left(a, b))
+                    }
+                    &Instruction::ShiftRightArithmetic { dest, a, b } => {
+                        let a = self.get_register(a);
+                        let b = self.get_register(b);
+                        self.set_register(dest, ops::shift_right_arithmetic(a, b))
+                    }
+                    &Instruction::ShiftRightLogical { dest, a, b } => {
+                        let a = self.get_register(a);
+                        let b = self.get_register(b);
+                        self.set_register(dest, ops::shift_right_logical(a, b))
+                    }
+                    &Instruction::SubInt { dest, a, b } => {
+                        let a = self.get_register(a);
+                        let b = self.get_register(b);
+                        self.set_register(dest, ops::sub_int(a, b))
+                    }
+                    &Instruction::SubFloat { dest, a, b } => {
+                        let a = self.get_float_register(a);
+                        let b = self.get_float_register(b);
+                        self.set_float_register(dest, ops::sub_float(a, b))
+                    }
+                    &Instruction::MulInt { dest, a, b } => {
+                        let a = self.get_register(a);
+                        let b = self.get_register(b);
+                        self.set_register(dest, ops::mul_int(a, b))
+                    }
+                    &Instruction::MulFloat { dest, a, b } => {
+                        let a = self.get_float_register(a);
+                        let b = self.get_float_register(b);
+                        self.set_float_register(dest, ops::mul_float(a, b))
+                    }
+                    &Instruction::DivInt { dest, a, b } => {
+                        let a = self.get_register(a);
+                        let b = self.get_register(b);
+                        let value = ops::div_int(a, b).ok_or(Trap::DivideByZero { line: block.get_line(index) })?;
+                        self.set_register(dest, value)
+                    }
+                    &Instruction::DivUInt { dest, a, b } => {
+                        let a = self.get_register(a);
+                        let b = self.get_register(b);
+                        let value = ops::div_uint(a, b).ok_or(Trap::DivideByZero { line: block.get_line(index) })?;
+                        self.set_register(dest, value)
+                    }
+                    &Instruction::DivFloat { dest, a, b } => {
+                        let a = self.get_float_register(a);
+                        let b = self.get_float_register(b);
+                        self.set_float_register(dest, ops::div_float(a, b))
+                    }
+                    &Instruction::RemInt { dest, a, b } => {
+                        let a = self.get_register(a);
+                        let b = self.get_register(b);
+                        let value = ops::rem_int(a, b).ok_or(Trap::DivideByZero { line: block.get_line(index) })?;
+                        self.set_register(dest, value)
+                    }
+                    &Instruction::RemUInt { dest, a, b } => {
+                        let a = self.get_register(a);
+                        let b = self.get_register(b);
+                        let value = ops::rem_uint(a, b).ok_or(Trap::DivideByZero { line: block.get_line(index) })?;
+                        self.set_register(dest, value)
+                    }
+                    &Instruction::RemFloat { dest, a, b } => {
+                        let a = self.get_float_register(a);
+                        let b = self.get_float_register(b);
+                        self.set_float_register(dest, ops::rem_float(a, b))
+                    }
+                    &Instruction::EqInt { dest, a, b } => {
+                        let a = self.get_register(a);
+                        let b = self.get_register(b);
+                        self.set_register(dest, (a == b) as i32)
+                    }
+                    &Instruction::EqFloat { dest, a, b } => {
+                        let a = self.get_float_register(a);
+                        let b = self.get_float_register(b);
+                        self.set_register(dest, (a == b) as i32)
+                    }
+                    &Instruction::LessInt { dest, a, b } => {
+                        let a = self.get_register(a);
+                        let b = self.get_register(b);
+                        self.set_register(dest, (a < b) as i32)
+                    }
+                    &Instruction::LessUInt { dest, a, b } => {
+                        let a = self.get_register(a);
+                        let b = self.get_register(b);
+                        self.set_register(dest, ops::less_uint(a, b) as i32)
+                    }
+                    &Instruction::LessFloat { dest, a, b } => {
+                        let a = self.get_float_register(a);
+                        let b = self.get_float_register(b);
+                        self.set_register(dest, (a < b) as i32)
+                    }
+                    &Instruction::LessEqInt { dest, a, b } => {
+                        let a = self.get_register(a);
+                        let b = self.get_register(b);
+                        self.set_register(dest, (a <= b) as i32)
+                    }
+                    &Instruction::LessEqUInt { dest, a, b } => {
+                        let a = self.get_register(a);
+                        let b = self.get_register(b);
+                        self.set_register(dest, ops::less_eq_uint(a, b) as i32)
+                    }
+                    &Instruction::LessEqFloat { dest, a, b } => {
+                        let a = self.get_float_register(a);
+                        let b = self.get_float_register(b);
+                        self.set_register(dest, (a <= b) as i32)
                     }
                     &Instruction::ConstantInt { dest, constant } => {
                         self.set_register(dest, constant);
                     }
-                    &Instruction::Phi { cond, a, b, dest } => {
-                        let value = if self.get_register(cond) != 0 {
-                            self.get_register(a)
-                        } else {
-                            self.get_register(b)
-                        };
-                        self.set_register(dest, value)
+                    &Instruction::ConstantFloat { dest, constant } => {
+                        self.set_float_register(dest, constant);
+                    }
+                    &Instruction::Convert { dest, src, conversion } => match conversion {
+                        Conversion::IntToFloat => {
+                            let value = self.get_register(src);
+                            self.set_float_register(dest, ops::int_to_float(value))
+                        }
+                        Conversion::UIntToFloat => {
+                            let value = self.get_register(src);
+                            self.set_float_register(dest, ops::uint_to_float(value))
+                        }
+                        Conversion::FloatToInt => {
+                            let value = self.get_float_register(src);
+                            self.set_register(dest, ops::float_to_int(value))
+                        }
+                        Conversion::FloatToUInt => {
+                            let value = self.get_float_register(src);
+                            self.set_register(dest, ops::float_to_uint(value))
+                        }
+                    }
+                    &Instruction::Copy { dest, src } => self.copy_register(dest, src),
+                    &Instruction::Select { cond, a, b, dest } => {
+                        let src = if self.get_register(cond) != 0 { a } else { b };
+                        self.copy_register(dest, src)
+                    }
+                    // the phis at the start of a block all read before any
+                    // writes, since one may read another's destination
+                    Instruction::Phi { .. } => {
+                        let previous = call.previous.expect("only a block that is branched to has phis");
+                        let values: Vec<(Var, i32, bool)> = block.get_instructions()[index..].iter()
+                            .map_while(|inst| match inst {
+                                Instruction::Phi { dest, sources } => Some((*dest, sources)),
+                                _ => None,
+                            })
+                            .map(|(dest, sources)| {
+                                let &(_, src) = sources.iter().find(|(block, _)| block.get_id() == previous.get_id())
+                                    .expect("a phi has a source for each block that branches to it");
+                                (dest, self.get_register(src), self.is_written(src))
+                            })
+                            .collect();
+                        call.index = index + values.len();
+                        for (dest, value, written) in values {
+                            self.set_register(dest, value);
+                            self.set_written(dest, written);
+                        }
                     }
-                    Instruction::Call { function, args, returns } => {
-                        let function = self.program.get_function(*function);
-                        for (param, arg) in function.get_params().iter().zip(args) {
-                            let arg = self.get_register(*arg);
+                    Instruction::Call { function: callee_id, args, returns } => {
+                        self.stats.calls += 1;
+                        if calls.len() > MAX_CALL_DEPTH {
+                            return Err(Trap::Exhausted(Resource::Stack))
+                        }
+                        let saved = self.save_frame(caller, *callee_id);
+                        // read every argument before writing any parameter,
+                        // which may be the same register in a recursive call
+                        let args: Vec<(i32, bool)> = args.iter().map(|&arg| (self.get_register(arg), self.is_written(arg))).collect();
+                        for (param, (arg, written)) in self.program.get_function(*callee_id).get_params().iter().zip(args) {
                             self.set_register(*param, arg);
+                            self.set_written(*param, written);
                         }
-                        self.execute(function);
-                        for (ret, var) in function.get_returns().iter().zip(returns) {
-                            let ret = self.get_register(*ret);
-                            self.set_register(*var, ret);
+                        calls.push(ActiveCall::new(self.program, *callee_id, saved, returns));
+                        self.check_memory()?;
+                        continue 'calls
+                    }
+                    Instruction::CallBuiltin { intrinsic: Intrinsic::Yield, .. } => {
+                        self.waiting = 0;
+                        return Ok(false)
+                    }
+                    Instruction::CallBuiltin { intrinsic: intrinsic @ (Intrinsic::ChanSend | Intrinsic::ChanRecv), args, returns } => {
+                        if !self.use_channel(*intrinsic, args, returns, block.get_line(index))? {
+                            // run it again when the task's turn comes back
+                            call.index = index;
+                            return Ok(false)
                         }
                     }
+                    Instruction::CallBuiltin { intrinsic, args, returns } => {
+                        self.call_intrinsic(*intrinsic, args, returns, block.get_line(index))?
+                    }
+                    &Instruction::Spawn { function } => {
+                        let calls = vec![ActiveCall::new(self.program, function, None, &[])];
+                        self.tasks.push_back(Task { calls, register_file: self.register_file.clone(), written: self.written.clone(), first: false });
+                        self.waiting = 0;
+                        self.check_memory()?;
+                    }
+                    &Instruction::Exit { code } => {
+                        return Err(Trap::Exit { code: self.get_register(code), line: block.get_line(index) })
+                    }
+                    Instruction::Abort => {
+                        return Err(Trap::Abort { line: block.get_line(index) })
+                    }
                 }
             }
-            match block.get_exit_instruction() {
-                &ExitInstruction::Branch { block: block_id } => { block = function.get_block(block_id) }
+            let exit = block.get_exit_instruction();
+            if let ExitInstruction::ConditionalBranch { cond: read, .. } | ExitInstruction::Switch { scrutinee: read, .. } = exit {
+                self.check_read(*read, exit, block.get_exit_line())?;
+            }
+            let next = match exit {
+                &ExitInstruction::Branch { block } => block,
                 &ExitInstruction::ConditionalBranch { cond, block1, block2 } => {
-                    if self.get_register(cond) != 0 {
-                        block = function.get_block(block1);
-                    } else {
-                        block = function.get_block(block2);
+                    if self.get_register(cond) != 0 { block1 } else { block2 }
+                }
+                ExitInstruction::Switch { scrutinee, targets, default } => {
+                    *targets.get(self.get_register(*scrutinee) as u32 as usize).unwrap_or(default)
+                }
+                ExitInstruction::Return => {
+                    let call = calls.pop().expect("a call is running");
+                    let values: Vec<(i32, bool)> = call.function.get_returns().iter().map(|&ret| (self.get_register(ret), self.is_written(ret))).collect();
+                    for (var, value) in call.saved.into_iter().flatten() {
+                        self.write_register(var, value);
+                    }
+                    for (var, (value, written)) in call.returns.iter().zip(values) {
+                        self.set_register(*var, value);
+                        self.set_written(*var, written);
                     }
+                    continue
+                }
+            };
+            call.previous = Some(block.get_id());
+            call.block = call.function.get_block(next);
+            call.index = 0;
+        }
+        Ok(true)
+    }
+    /// Sends or receives on a channel, returning false if the task has to
+    /// wait for another to receive or send first. A receive that would wait
+    /// while every other task is waiting too gives `none` instead.
+    fn use_channel(&mut self, intrinsic: Intrinsic, args: &[Var], returns: &[Var], line: u32) -> Result<bool, Trap> {
+        let channel = self.get_register(args[0]);
+        let done = match intrinsic {
+            Intrinsic::ChanSend => {
+                let sent = self.heap.send(channel, self.get_register(args[1])).ok_or(Trap::InvalidHandle { line })?;
+                if sent {
+                    self.stats.heap_bytes_allocated = self.stats.heap_bytes_allocated.max(self.heap.bytes());
+                    self.check_memory()?;
+                } else if self.waiting >= self.tasks.len() {
+                    return Err(Trap::Deadlock { line })
                 }
-                ExitInstruction::Return => { break }
+                sent
+            }
+            _ => {
+                let value = self.heap.receive(channel).ok_or(Trap::InvalidHandle { line })?;
+                let done = value.is_some() || self.waiting >= self.tasks.len();
+                if done {
+                    self.set_register(returns[0], value.is_some() as i32);
+                    self.set_register(returns[1], value.unwrap_or(0));
+                }
+                done
+            }
+        };
+        self.waiting = if done { 0 } else { self.waiting + 1 };
+        Ok(done)
+    }
+    fn call_intrinsic(&mut self, intrinsic: Intrinsic, args: &[Var], returns: &[Var], line: u32) -> Result<(), Trap> {
+        let invalid = Trap::InvalidHandle { line };
+        match intrinsic {
+            Intrinsic::AbsInt => {
+                let a = self.get_register(args[0]);
+                self.set_register(returns[0], a.wrapping_abs())
+            }
+            Intrinsic::AbsFloat => {
+                let a = self.get_float_register(args[0]);
+                self.set_float_register(returns[0], a.abs())
+            }
+            Intrinsic::MinInt => {
+                let a = self.get_register(args[0]);
+                let b = self.get_register(args[1]);
+                self.set_register(returns[0], a.min(b))
+            }
+            Intrinsic::MinUInt => {
+                let a = self.get_register(args[0]) as u32;
+                let b = self.get_register(args[1]) as u32;
+                self.set_register(returns[0], a.min(b) as i32)
+            }
+            Intrinsic::MinFloat => {
+                let a = self.get_float_register(args[0]);
+                let b = self.get_float_register(args[1]);
+                self.set_float_register(returns[0], a.min(b))
+            }
+            Intrinsic::MaxInt => {
+                let a = self.get_register(args[0]);
+                let b = self.get_register(args[1]);
+                self.set_register(returns[0], a.max(b))
+            }
+            Intrinsic::MaxUInt => {
+                let a = self.get_register(args[0]) as u32;
+                let b = self.get_register(args[1]) as u32;
+                self.set_register(returns[0], a.max(b) as i32)
+            }
+            Intrinsic::MaxFloat => {
+                let a = self.get_float_register(args[0]);
+                let b = self.get_float_register(args[1]);
+                self.set_float_register(returns[0], a.max(b))
+            }
+            Intrinsic::PowInt => {
+                let a = self.get_register(args[0]);
+                let b = self.get_register(args[1]);
+                if b < 0 {
+                    return Err(Trap::NegativeExponent { line })
+                }
+                let value = a.wrapping_pow(b as u32);
+                self.set_register(returns[0], value)
+            }
+            Intrinsic::PowUInt => {
+                let a = self.get_register(args[0]) as u32;
+                let b = self.get_register(args[1]) as u32;
+                self.set_register(returns[0], a.wrapping_pow(b) as i32)
+            }
+            Intrinsic::PowFloat => {
+                let a = self.get_float_register(args[0]);
+                let b = self.get_float_register(args[1]);
+                self.set_float_register(returns[0], a.powf(b))
+            }
+            Intrinsic::Sqrt => {
+                let a = self.get_float_register(args[0]);
+                self.set_float_register(returns[0], a.sqrt())
+            }
+            Intrinsic::Floor => {
+                let a = self.get_float_register(args[0]);
+                self.set_float_register(returns[0], a.floor())
+            }
+            Intrinsic::Rand => {
+                let value = (self.next_random() >> 33) as i32;
+                self.set_register(returns[0], value)
+            }
+            Intrinsic::RandRange => {
+                let a = self.get_register(args[0]);
+                let b = self.get_register(args[1]);
+                if b <= a {
+                    return Err(Trap::EmptyRange { line })
+                }
+                let range = (b as i64 - a as i64) as u64;
+                let value = (a as i64 + (self.next_random() % range) as i64) as i32;
+                self.set_register(returns[0], value)
+            }
+            Intrinsic::ArgCount => {
+                self.set_register(returns[0], self.args.len() as i32)
+            }
+            Intrinsic::ArgInt => {
+                let index = self.get_register(args[0]);
+                let value = usize::try_from(index).ok()
+                    .and_then(|index| self.args.get(index))
+                    .and_then(|arg| arg.parse::<i32>().ok());
+                self.set_register(returns[0], value.is_some() as i32);
+                self.set_register(returns[1], value.unwrap_or(0));
+            }
+            Intrinsic::CheckedAddInt => {
+                let a = self.get_register(args[0]);
+                let b = self.get_register(args[1]);
+                let value = a.checked_add(b);
+                self.set_register(returns[0], value.is_some() as i32);
+                self.set_register(returns[1], value.unwrap_or(0));
+            }
+            Intrinsic::CheckedAddUInt => {
+                let a = self.get_register(args[0]) as u32;
+                let b = self.get_register(args[1]) as u32;
+                let value = a.checked_add(b);
+                self.set_register(returns[0], value.is_some() as i32);
+                self.set_register(returns[1], value.unwrap_or(0) as i32);
+            }
+            Intrinsic::SaturatingAddInt => {
+                let a = self.get_register(args[0]);
+                let b = self.get_register(args[1]);
+                self.set_register(returns[0], a.saturating_add(b))
+            }
+            Intrinsic::SaturatingAddUInt => {
+                let a = self.get_register(args[0]) as u32;
+                let b = self.get_register(args[1]) as u32;
+                self.set_register(returns[0], a.saturating_add(b) as i32)
+            }
+            // the low bits of the time, which wrap after about 24 days but
+            // still give the right difference between two readings
+            Intrinsic::NowMillis => {
+                let now = self.now_millis()? as i32;
+                self.set_register(returns[0], now)
+            }
+            Intrinsic::Sleep => {
+                let millis = self.get_register(args[0]).max(0) as u64;
+                // a sleep past the deadline would only be stopped after it
+                let past_deadline = self.deadline.is_some_and(|deadline| Instant::now() + Duration::from_millis(millis) > deadline);
+                if past_deadline && matches!(self.clock, Clock::System(_)) {
+                    return Err(Trap::Exhausted(Resource::Time))
+                }
+                if !matches!(self.replay, Replay::Play(..)) {
+                    self.clock.sleep(millis)
+                }
+            }
+            Intrinsic::PrintInt => self.print(self.get_register(args[0]).to_string()),
+            Intrinsic::PrintUInt => self.print((self.get_register(args[0]) as u32).to_string()),
+            Intrinsic::PrintFloat => self.print(format!("{:?}", self.get_float_register(args[0]))),
+            Intrinsic::PrintBool => self.print((self.get_register(args[0]) != 0).to_string()),
+            Intrinsic::MapNew => {
+                let handle = self.heap.new_map();
+                self.set_register(returns[0], handle)
+            }
+            Intrinsic::MapInsert => {
+                let (map, key, value) = (self.get_register(args[0]), self.get_register(args[1]), self.get_register(args[2]));
+                if self.heap.insert(map, key, value).ok_or(invalid)? {
+                    self.stats.heap_bytes_allocated = self.stats.heap_bytes_allocated.max(self.heap.bytes());
+                    self.check_memory()?;
+                }
+            }
+            Intrinsic::MapGet => {
+                let value = self.heap.map(self.get_register(args[0])).ok_or(invalid)?.get(&self.get_register(args[1])).copied();
+                self.set_register(returns[0], value.is_some() as i32);
+                self.set_register(returns[1], value.unwrap_or(0));
+            }
+            Intrinsic::MapRemove => {
+                let value = self.heap.remove(self.get_register(args[0]), self.get_register(args[1])).ok_or(invalid)?;
+                self.set_register(returns[0], value.is_some() as i32);
+                self.set_register(returns[1], value.unwrap_or(0));
+            }
+            Intrinsic::MapLen => {
+                let len = self.heap.map(self.get_register(args[0])).ok_or(invalid)?.len();
+                self.set_register(returns[0], len as i32)
+            }
+            Intrinsic::MapNext => {
+                let map = self.heap.map(self.get_register(args[0])).ok_or(invalid)?;
+                let entry = match self.get_register(args[1]) != 0 {
+                    true => map.range((Bound::Excluded(self.get_register(args[2])), Bound::Unbounded)).next(),
+                    false => map.iter().next(),
+                };
+                let (key, value) = entry.map_or((0, 0), |(&key, &value)| (key, value));
+                self.set_register(returns[0], entry.is_some() as i32);
+                self.set_register(returns[1], key);
+                self.set_register(returns[2], value);
+            }
+            Intrinsic::RangeNextInt => {
+                let more = self.get_register(args[0]) < self.get_register(args[1]);
+                self.set_register(returns[0], more as i32)
+            }
+            Intrinsic::RangeNextUInt => {
+                let more = (self.get_register(args[0]) as u32) < (self.get_register(args[1]) as u32);
+                self.set_register(returns[0], more as i32)
+            }
+            // these switch tasks, which is up to `run_task`
+            Intrinsic::Yield | Intrinsic::ChanSend | Intrinsic::ChanRecv => (),
+            Intrinsic::ChanNew => {
+                let handle = self.heap.new_channel(self.get_register(args[0]));
+                self.set_register(returns[0], handle)
             }
         }
+        Ok(())
     }
     pub fn set_register(&mut self, reg: Var, value: i32) {
+        self.write_register(reg, value);
+        self.stats.max_registers_touched = self.stats.max_registers_touched.max(reg.get_id() + 1);
+    }
+    fn write_register(&mut self, reg: Var, value: i32) {
+        if reg.get_id() >= self.register_file.len() {
+            self.register_file.resize(reg.get_id() + 1, 0);
+        }
         self.register_file[reg.get_id()] = value;
+        self.set_written(reg, true);
     }
-    pub fn get_register(&mut self, reg: Var) -> i32 {
-        self.register_file[reg.get_id()]
+    fn copy_register(&mut self, dest: Var, src: Var) {
+        let value = self.get_register(src);
+        self.set_register(dest, value);
+        self.set_written(dest, self.is_written(src));
     }
-}
\ No newline at end of file
+    /// Whether `reg` holds a value, which it always does outside strict mode.
+    fn is_written(&self, reg: Var) -> bool {
+        self.written.as_ref().is_none_or(|written| written.get(reg.get_id()).copied().unwrap_or(false))
+    }
+    fn set_written(&mut self, reg: Var, value: bool) {
+        if let Some(written) = &mut self.written {
+            if reg.get_id() >= written.len() {
+                written.resize(reg.get_id() + 1, false);
+            }
+            written[reg.get_id()] = value;
+        }
+    }
+    /// In strict mode, traps if `inst` reads a register that holds no
+    /// value. Copies, calls, phis and `select` pass on that a register
+    /// holds none instead, since they also move the unset value of an empty
+    /// `maybe`.
+    fn check_reads(&self, inst: &Instruction, line: u32) -> Result<(), Trap> {
+        if self.written.is_none() {
+            return Ok(())
+        }
+        let reads = match inst {
+            Instruction::Copy { .. } | Instruction::Call { .. } | Instruction::Phi { .. } => return Ok(()),
+            &Instruction::Select { cond, .. } => vec![cond],
+            inst => inst.uses(),
+        };
+        reads.into_iter().try_for_each(|reg| self.check_read(reg, inst, line))
+    }
+    fn check_read(&self, reg: Var, inst: &dyn fmt::Display, line: u32) -> Result<(), Trap> {
+        if self.is_written(reg) {
+            return Ok(())
+        }
+        Err(Trap::Uninitialized { register: reg.get_id(), instruction: inst.to_string(), line })
+    }
+    pub fn get_register(&self, reg: Var) -> i32 {
+        self.register_file.get(reg.get_id()).copied().unwrap_or(0)
+    }
+    pub fn set_float_register(&mut self, reg: Var, value: f32) {
+        self.set_register(reg, value.to_bits() as i32)
+    }
+    pub fn get_float_register(&self, reg: Var) -> f32 {
+        f32::from_bits(self.get_register(reg) as u32)
+    }
+    pub fn format_ty(&self, ty: &Type) -> String {
+        match ty {
+            Type::Int(var) => format!("{}", self.get_register(*var)),
+            Type::UInt(var) => format!("{}", self.get_register(*var) as u32),
+            Type::Float(var) => format!("{:?}", self.get_float_register(*var)),
+            Type::Bool(var) => format!("{}", self.get_register(*var) != 0),
+            Type::Maybe(tag, ty) => if self.get_register(*tag) != 0 {
+                self.format_ty(ty)
+            } else {
+                "none".to_string()
+            },
+            Type::Tuple(types) => {
+                let values = types.iter().map(|ty| self.format_ty(ty)).collect::<Vec<String>>();
+                format!("({})", values.join(", "))
+            }
+            Type::Struct(fields) => {
+                let values = fields.iter().map(|(name, ty)| format!("{}: {}", name, self.format_ty(ty))).collect::<Vec<String>>();
+                format!("{{ {} }}", values.join(", "))
+            }
+            Type::Chan(..) => "chan".to_string(),
+            Type::Func { .. } => "fn".to_string(),
+            Type::Map(handle, elements) => {
+                let entries = match (self.heap.map(self.get_register(*handle)), elements) {
+                    (Some(map), Some((key_ty, value_ty))) => map.iter()
+                        .map(|(&key, &value)| format!("{}: {}", format_bits(key, *key_ty), format_bits(value, *value_ty)))
+                        .collect(),
+                    _ => Vec::new(),
+                };
+                format!("{{{}}}", entries.join(", "))
+            }
+            Type::Builtin(builtin) => format!("builtin {}", builtin.name()),
+        }
+    }
+}
+
+/// Formats the value of a register holding a `ty`, as map entries are.
+fn format_bits(bits: i32, ty: CastType) -> String {
+    match ty {
+        CastType::Int => format!("{}", bits),
+        CastType::UInt => format!("{}", bits as u32),
+        CastType::Float => format!("{:?}", f32::from_bits(bits as u32)),
+        CastType::Bool => format!("{}", bits != 0),
+    }
+}
@@ -0,0 +1,264 @@
+//! Random programs generated from the grammar, run through every stage with
+//! the register checks of `--strict-registers` on, to find programs that
+//! crash the compiler or that it compiles wrongly. The programs are built
+//! to be well typed and every loop in them ends, so each one should compile
+//! and run, and print the same once optimized; any that does not is
+//! reported along with a reduced version.
+
+use crate::crash;
+use language::diagnostics::Severity;
+use language::execute::{SandboxConfig, Trap, VirtualMachine};
+use language::ir::{FunctionId, Program};
+use language::optimize;
+use language::session::{CompileOptions, Session};
+use language::types::Type;
+
+/// Instructions each program may run before it is stopped, since a bug can
+/// still make a loop run forever.
+const FUEL: u64 = 1_000_000;
+
+/// How deeply expressions nest before only names and literals are chosen.
+const MAX_DEPTH: usize = 5;
+
+/// Generates programs from a seed, so that any one can be made again.
+pub struct Generator {
+    state: u64,
+    /// The `int` names in scope.
+    names: Vec<String>,
+    /// The functions declared so far, which each take and return an `int`.
+    functions: Vec<String>,
+    fresh: usize,
+}
+
+impl Generator {
+    pub fn new(seed: u64) -> Generator {
+        Generator { state: seed, names: Vec::new(), functions: Vec::new(), fresh: 0 }
+    }
+    /// splitmix64, as the VM's `rand` is.
+    fn next(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+    /// Picks an index into `weights` with probability in proportion to it.
+    fn weighted(&mut self, weights: &[usize]) -> usize {
+        let mut pick = self.below(weights.iter().sum());
+        for (index, &weight) in weights.iter().enumerate() {
+            if pick < weight {
+                return index
+            }
+            pick -= weight;
+        }
+        unreachable!("the pick is below the sum of the weights")
+    }
+    fn fresh_name(&mut self) -> String {
+        self.fresh += 1;
+        format!("v{}", self.fresh)
+    }
+
+    /// A few functions, then a `main` that returns an `int`.
+    pub fn program(&mut self) -> String {
+        let mut out = String::new();
+        for index in 0..self.below(4) {
+            let name = format!("f{}", index);
+            self.names = vec!["x".to_string()];
+            let body = self.int(1);
+            out.push_str(&format!("fn {}(x) {}\n\n", name, body));
+            self.functions.push(name);
+        }
+        self.names = Vec::new();
+        let body = self.int(0);
+        out.push_str(&format!("fn main() {}\n", body));
+        out
+    }
+
+    /// Binds `name` while `body` generates, then unbinds it.
+    fn with_name(&mut self, name: &str, body: impl FnOnce(&mut Generator) -> String) -> String {
+        self.names.push(name.to_string());
+        let generated = body(self);
+        self.names.pop();
+        generated
+    }
+
+    fn int(&mut self, depth: usize) -> String {
+        if depth >= MAX_DEPTH {
+            return self.int_leaf()
+        }
+        let call_weight = if self.functions.is_empty() { 0 } else { 2 };
+        match self.weighted(&[4, 6, 2, 3, 3, 3, 2, 2, call_weight, 1, 1, 1, 1]) {
+            0 => self.int_leaf(),
+            1 => {
+                let op = ["+", "-", "*", "/", "%", "&", "|", "^"][self.below(8)];
+                format!("({} {} {})", self.int(depth + 1), op, self.int(depth + 1))
+            }
+            2 => {
+                let op = ["<<", ">>"][self.below(2)];
+                format!("({} {} {})", self.int(depth + 1), op, self.below(32))
+            }
+            3 => format!("((if ({}) {}) else {})", self.bool(depth + 1), self.int(depth + 1), self.int(depth + 1)),
+            4 => self.while_loop(depth),
+            5 => self.block(depth),
+            6 => self.for_loop(depth),
+            7 => self.structure(depth),
+            8 => {
+                let index = self.below(self.functions.len());
+                let function = self.functions[index].clone();
+                format!("{}({})", function, self.int(depth + 1))
+            }
+            // a function that reads a name a loop goes on to assign is an
+            // error, so it only sees its parameter
+            9 => {
+                let param = self.fresh_name();
+                let outer = std::mem::replace(&mut self.names, vec![param.clone()]);
+                let body = self.int(depth + 1);
+                self.names = outer;
+                format!("(|{}| {})({})", param, body, self.int(depth + 1))
+            }
+            10 => {
+                let (a, b) = (self.fresh_name(), self.fresh_name());
+                let (first, second) = (self.int(depth + 1), self.int(depth + 1));
+                let body = self.with_name(&a, |generator| generator.with_name(&b, |generator| generator.int(depth + 1)));
+                format!("{{\n({}, {}) = ({}, {})\n{}\n}}", a, b, first, second, body)
+            }
+            11 => {
+                let builtin = ["min", "max"][self.below(2)];
+                format!("{}({}, {})", builtin, self.int(depth + 1), self.int(depth + 1))
+            }
+            _ => format!("({} as float as int)", self.int(depth + 1)),
+        }
+    }
+
+    fn int_leaf(&mut self) -> String {
+        match self.below(4) {
+            0 | 1 if !self.names.is_empty() => {
+                let index = self.below(self.names.len());
+                self.names[index].clone()
+            }
+            2 => format!("(0 - {})", self.below(1000)),
+            _ => self.below(100).to_string(),
+        }
+    }
+
+    fn bool(&mut self, depth: usize) -> String {
+        if depth >= MAX_DEPTH {
+            return ["true", "false"][self.below(2)].to_string()
+        }
+        match self.weighted(&[5, 2, 2, 1]) {
+            0 => {
+                let op = ["==", "!=", "<", "<=", ">", ">="][self.below(6)];
+                format!("({} {} {})", self.int(depth + 1), op, self.int(depth + 1))
+            }
+            1 => {
+                let op = ["&&", "||"][self.below(2)];
+                format!("({} {} {})", self.bool(depth + 1), op, self.bool(depth + 1))
+            }
+            2 => format!("!{}", self.bool(depth + 1)),
+            _ => ["true", "false"][self.below(2)].to_string(),
+        }
+    }
+
+    /// A block that binds a name and uses it.
+    fn block(&mut self, depth: usize) -> String {
+        let name = self.fresh_name();
+        let value = self.int(depth + 1);
+        let body = self.with_name(&name, |generator| generator.int(depth + 1));
+        format!("{{\n{} = {}\n{}\n}}", name, value, body)
+    }
+
+    /// A `while` loop that counts up to a bound, updating a name that
+    /// outlives it.
+    fn while_loop(&mut self, depth: usize) -> String {
+        let (counter, total) = (self.fresh_name(), self.fresh_name());
+        let start = self.int(depth + 1);
+        let bound = self.below(10);
+        let update = self.with_name(&counter, |generator| generator.with_name(&total, |generator| generator.int(depth + 1)));
+        let escape = match self.below(3) {
+            0 => {
+                let cond = self.with_name(&total, |generator| generator.bool(depth + 1));
+                format!("if ({}) break\n", cond)
+            }
+            _ => String::new(),
+        };
+        format!("{{\n{c} = 0\n{t} = {}\nwhile ({c} < {}) {{\n{c} += 1\n{}{t} = {}\n}}\n{t}\n}}", start, bound, escape, update, c = counter, t = total)
+    }
+
+    /// A `for` loop that may leave early.
+    fn for_loop(&mut self, depth: usize) -> String {
+        let item = self.fresh_name();
+        let bound = self.below(10);
+        let cond = self.with_name(&item, |generator| generator.bool(depth + 1));
+        let after = self.int(depth + 1);
+        format!("{{\nfor {} in range(0, {}) {{\nif ({}) break\n}}\n{}\n}}", item, bound, cond, after)
+    }
+
+    fn structure(&mut self, depth: usize) -> String {
+        let name = self.fresh_name();
+        let (a, b) = (self.int(depth + 1), self.int(depth + 1));
+        let field = ["a", "b"][self.below(2)];
+        format!("{{\n{} = struct {{ a = {}, b = {} }}\n{}.{}\n}}", name, a, b, name, field)
+    }
+}
+
+/// Runs `runs` programs generated from `seed` onwards, printing each one
+/// that fails, and returns whether they all passed.
+pub fn run(seed: u64, runs: u64) -> bool {
+    let mut failed = 0;
+    for run in 0..runs {
+        let source = Generator::new(seed.wrapping_add(run)).program();
+        if let Some(finding) = check(&source) {
+            failed += 1;
+            let reduced = crash::minimize(&source, |source| check(source).as_ref() == Some(&finding));
+            println!("seed {}: {}\n--- reduced ---\n{}", seed.wrapping_add(run), finding, reduced);
+        }
+    }
+    println!("{} programs, {} failed", runs, failed);
+    failed == 0
+}
+
+/// What went wrong with `source`, if anything. Traps other than reading an
+/// unwritten register are the program's own doing, such as dividing by
+/// zero, and are not reported.
+fn check(source: &str) -> Option<String> {
+    let session = Session::new("fuzz".to_string(), source.to_string(), CompileOptions { check_passes: true, ..CompileOptions::default() });
+    let result = crash::catch(|| {
+        let ast = session.parse()?;
+        let hir = session.lower(&ast)?;
+        let (program, main_id, ty) = session.compile(&hir)?;
+        let outcome = run_program(&program, main_id, &ty);
+        let mut optimized = program.clone();
+        optimize::optimize(&mut optimized, true);
+        Some((outcome, run_program(&optimized, main_id, &ty)))
+    });
+    match result {
+        Err(panicked) => Some(format!("the compiler panicked at {}: {}", panicked.location, panicked.message)),
+        Ok(None) => {
+            let diagnostics = session.take_diagnostics();
+            let error = diagnostics.iter().find(|diagnostic| diagnostic.severity == Severity::Error);
+            Some(format!("the program did not compile: {}", error.map_or("no error was reported", |error| &error.message)))
+        }
+        Ok(Some((Err(Trap::Uninitialized { .. }), _))) => Some("a register was read before it was written".to_string()),
+        Ok(Some((_, Err(Trap::Uninitialized { .. })))) => Some("the optimized program read a register before it was written".to_string()),
+        // the optimized program runs fewer instructions, so it can finish
+        // where the original ran out of fuel
+        Ok(Some((Err(Trap::Exhausted(_)), _))) => None,
+        Ok(Some((outcome, optimized))) if outcome != optimized => {
+            Some(format!("the optimized program gave {:?} where the original gave {:?}", optimized, outcome))
+        }
+        Ok(Some(_)) => None,
+    }
+}
+
+/// Runs the program with the register checks on and a limit on how long it
+/// runs, giving what it returned or how it stopped.
+fn run_program(program: &Program, main_id: FunctionId, ty: &Type) -> Result<String, Trap> {
+    let mut vm = VirtualMachine::new(program);
+    vm.set_strict(true);
+    vm.set_sandbox(SandboxConfig { fuel: Some(FUEL), ..SandboxConfig::default() });
+    vm.execute(program.get_function(main_id))?;
+    Ok(vm.format_ty(ty))
+}
@@ -0,0 +1,79 @@
+//! Values that live outside the register file, which registers refer to by
+//! handle. Nothing is freed yet, so everything a program allocates lives
+//! until the VM that ran it is dropped.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::convert::TryFrom;
+use std::mem;
+
+/// The bytes counted for each entry of a map, its key and its value.
+pub const MAP_ENTRY_BYTES: usize = 2 * mem::size_of::<i32>();
+
+#[derive(Debug, Default)]
+pub struct Heap {
+    /// Maps from the bits of a key to the bits of its value, by handle,
+    /// kept in order so that a loop over one can resume after a key.
+    maps: Vec<BTreeMap<i32, i32>>,
+    entries: usize,
+    channels: Vec<Channel>,
+    /// Values sent on every channel and not yet received.
+    queued: usize,
+}
+
+/// A queue of values sent from one task to another.
+#[derive(Debug)]
+struct Channel {
+    capacity: usize,
+    values: VecDeque<i32>,
+}
+
+impl Heap {
+    /// Allocates an empty map, returning its handle.
+    pub fn new_map(&mut self) -> i32 {
+        self.maps.push(BTreeMap::new());
+        (self.maps.len() - 1) as i32
+    }
+    /// The map with `handle`, or `None` if `new_map` never gave it. The
+    /// methods that take a handle give `None` for such a handle too.
+    pub fn map(&self, handle: i32) -> Option<&BTreeMap<i32, i32>> {
+        self.maps.get(usize::try_from(handle).ok()?)
+    }
+    /// Sets `key` to `value`, returning whether the key is new.
+    pub fn insert(&mut self, handle: i32, key: i32, value: i32) -> Option<bool> {
+        let new = self.maps.get_mut(usize::try_from(handle).ok()?)?.insert(key, value).is_none();
+        self.entries += new as usize;
+        Some(new)
+    }
+    pub fn remove(&mut self, handle: i32, key: i32) -> Option<Option<i32>> {
+        let value = self.maps.get_mut(usize::try_from(handle).ok()?)?.remove(&key);
+        self.entries -= value.is_some() as usize;
+        Some(value)
+    }
+    /// Allocates an empty channel that holds up to `capacity` values, or
+    /// one value if `capacity` is less than that, returning its handle.
+    pub fn new_channel(&mut self, capacity: i32) -> i32 {
+        self.channels.push(Channel { capacity: capacity.max(1) as usize, values: VecDeque::new() });
+        (self.channels.len() - 1) as i32
+    }
+    /// Queues `value` on a channel, returning false if the channel is full.
+    pub fn send(&mut self, handle: i32, value: i32) -> Option<bool> {
+        let channel = self.channels.get_mut(usize::try_from(handle).ok()?)?;
+        if channel.values.len() >= channel.capacity {
+            return Some(false)
+        }
+        channel.values.push_back(value);
+        self.queued += 1;
+        Some(true)
+    }
+    /// Takes the value sent first from a channel, if it holds any.
+    pub fn receive(&mut self, handle: i32) -> Option<Option<i32>> {
+        let value = self.channels.get_mut(usize::try_from(handle).ok()?)?.values.pop_front();
+        self.queued -= value.is_some() as usize;
+        Some(value)
+    }
+    /// The bytes held by every entry of every map, and every value queued
+    /// on a channel.
+    pub fn bytes(&self) -> usize {
+        self.entries * MAP_ENTRY_BYTES + self.queued * mem::size_of::<i32>()
+    }
+}
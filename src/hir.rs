@@ -0,0 +1,511 @@
+use crate::ast::{BinaryOp, Expr, Parsed};
+use crate::compiler::CompileError;
+use crate::ir::FunctionId;
+use crate::ir_parser::{self, InlineIr};
+use std::{cell::{Cell, RefCell}, fmt};
+
+/// The core language the compiler works on. Lowering from the AST checks
+/// literals, patterns and type names and removes syntax sugar, so that
+/// surface syntax can grow without touching code generation. Every node
+/// keeps the source it came from for diagnostics.
+#[derive(Debug)]
+pub struct Hir<'a> {
+    pub source: &'a str,
+    /// The line the node starts on, recorded against the code generated for it.
+    pub line: u32,
+    pub kind: HirKind<'a>,
+    /// Filled in as the node is compiled, once per distinct result, since a
+    /// function body is compiled again for every argument type.
+    pub annotations: RefCell<Vec<Annotation<'a>>>,
+    /// Set when compiling finds that the node can never run.
+    pub unreachable: Cell<bool>,
+    /// Set on the body of a function when some specialization of it does
+    /// I/O, directly or through a function it calls.
+    pub performs_io: Cell<bool>,
+    /// Set on the body of a function, with each type it was compiled for.
+    pub specializations: RefCell<Vec<Specialization>>,
+    /// The names from the `allow(name)` expressions around the node, whose
+    /// warnings are not reported in it.
+    pub allowed: Vec<&'a str>,
+}
+
+/// A function compiled for one type of argument.
+#[derive(Debug, Clone)]
+pub struct Specialization {
+    pub param_ty: String,
+    pub return_ty: String,
+    pub function: FunctionId,
+}
+
+/// What compiling a node found out about it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Annotation<'a> {
+    pub ty: String,
+    /// For a variable, the name where it was bound.
+    pub definition: Option<&'a str>,
+}
+
+#[derive(Debug)]
+pub enum HirKind<'a> {
+    Int(i32),
+    UInt(u32),
+    Float(f32),
+    Bool(bool),
+    Var(&'a str),
+    Tuple(Vec<Hir<'a>>),
+    /// A sequence of expressions, evaluating to the last. Never empty.
+    Block(Vec<Hir<'a>>),
+    Func {
+        name: Option<&'a str>,
+        param: Pattern<'a>,
+        body: Box<Hir<'a>>,
+    },
+    Binary {
+        op: BinOp,
+        left: Box<Hir<'a>>,
+        right: Box<Hir<'a>>,
+    },
+    Call {
+        callee: Box<Hir<'a>>,
+        argument: Box<Hir<'a>>,
+    },
+    Assign {
+        pattern: Pattern<'a>,
+        value: Box<Hir<'a>>,
+    },
+    If {
+        cond: Box<Hir<'a>>,
+        conc: Box<Hir<'a>>,
+    },
+    /// Gives the body of the first arm whose pattern matches `value`, with
+    /// the pattern's names bound in a scope of the arm's own.
+    Match {
+        value: Box<Hir<'a>>,
+        arms: Vec<(Pattern<'a>, Hir<'a>)>,
+    },
+    /// Runs `body` with `pattern` bound to each value of `iterable`, which
+    /// is a map or a `(next, state)` pair whose `next(state)` gives
+    /// `maybe (value, state)`.
+    For {
+        label: Option<&'a str>,
+        pattern: Pattern<'a>,
+        iterable: Box<Hir<'a>>,
+        body: Box<Hir<'a>>,
+    },
+    /// Runs `body` for as long as `cond` is true. Names bound outside the
+    /// loop that it assigns keep their last values after it.
+    While {
+        label: Option<&'a str>,
+        cond: Box<Hir<'a>>,
+        body: Box<Hir<'a>>,
+    },
+    Else {
+        value: Box<Hir<'a>>,
+        default: Box<Hir<'a>>,
+    },
+    /// Leaves the innermost loop, or the one with `label`.
+    Break {
+        label: Option<&'a str>,
+    },
+    /// The items of a block after a `defer`, which are run like a block's,
+    /// then the `deferred` expressions in order, giving the last item. A
+    /// `break` out of the items runs the `deferred` expressions first.
+    Deferred {
+        items: Vec<Hir<'a>>,
+        deferred: Vec<Hir<'a>>,
+    },
+    /// `value else trap`, which stops the program if `value` is empty.
+    Unwrap {
+        value: Box<Hir<'a>>,
+    },
+    Cast {
+        value: Box<Hir<'a>>,
+        ty: CastType,
+    },
+    Not {
+        value: Box<Hir<'a>>,
+    },
+    /// A struct, with the value of each field in the order written.
+    Struct(Vec<(&'a str, Hir<'a>)>),
+    Field {
+        value: Box<Hir<'a>>,
+        name: &'a str,
+    },
+    /// Hand-written IR, with a `Var` for each name it reads from outside.
+    InlineIr {
+        ir: InlineIr<'a>,
+        inputs: Vec<Hir<'a>>,
+    },
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    /// `&&` and `||`, which only compute their right side when the left
+    /// does not already decide the result.
+    And,
+    Or,
+    BitAnd,
+    BitOr,
+    BitXor,
+    ShiftLeft,
+    ShiftRight,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CastType {
+    Int,
+    UInt,
+    Float,
+    Bool,
+}
+
+#[derive(Debug)]
+pub struct Pattern<'a> {
+    pub source: &'a str,
+    pub kind: PatternKind<'a>,
+}
+
+#[derive(Debug)]
+pub enum PatternKind<'a> {
+    Bind(&'a str),
+    /// `_`, which matches anything without binding it.
+    Wildcard,
+    Tuple(Vec<Pattern<'a>>),
+    /// A literal, which only the pattern of a `match` arm can be, matching
+    /// the values equal to it.
+    Int(i32),
+    UInt(u32),
+    Bool(bool),
+}
+
+impl<'a> Hir<'a> {
+    fn new(source: &'a str, line: u32, kind: HirKind<'a>) -> Hir<'a> {
+        Hir { source, line, kind, annotations: RefCell::new(Vec::new()), unreachable: Cell::new(false), performs_io: Cell::new(false), specializations: RefCell::new(Vec::new()), allowed: Vec::new() }
+    }
+    pub fn children(&self) -> Vec<&Hir<'a>> {
+        match &self.kind {
+            HirKind::Tuple(exprs) | HirKind::Block(exprs) | HirKind::InlineIr { inputs: exprs, .. } => exprs.iter().collect(),
+            HirKind::Func { body, .. } => vec![body],
+            HirKind::Binary { left, right, .. } => vec![left, right],
+            HirKind::Call { callee, argument } => vec![callee, argument],
+            HirKind::Assign { value, .. } => vec![value],
+            HirKind::If { cond, conc } => vec![cond, conc],
+            HirKind::Match { value, arms } => Some(&**value).into_iter().chain(arms.iter().map(|(_, body)| body)).collect(),
+            HirKind::For { iterable, body, .. } => vec![iterable, body],
+            HirKind::While { cond, body, .. } => vec![cond, body],
+            HirKind::Else { value, default } => vec![value, default],
+            HirKind::Deferred { items, deferred } => items.iter().chain(deferred).collect(),
+            HirKind::Cast { value, .. } | HirKind::Unwrap { value } | HirKind::Not { value } | HirKind::Field { value, .. } => vec![value],
+            HirKind::Struct(fields) => fields.iter().map(|(_, value)| value).collect(),
+            _ => vec![],
+        }
+    }
+    pub fn annotate(&self, annotation: Annotation<'a>) {
+        let mut annotations = self.annotations.borrow_mut();
+        if !annotations.contains(&annotation) {
+            annotations.push(annotation);
+        }
+    }
+}
+
+impl CastType {
+    fn from_name(name: &str) -> Option<CastType> {
+        match name {
+            "int" => Some(CastType::Int),
+            "uint" => Some(CastType::UInt),
+            "float" => Some(CastType::Float),
+            "bool" => Some(CastType::Bool),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for BinOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BinOp::Add => write!(f, "+"),
+            BinOp::Sub => write!(f, "-"),
+            BinOp::Mul => write!(f, "*"),
+            BinOp::Div => write!(f, "/"),
+            BinOp::Rem => write!(f, "%"),
+            BinOp::Eq => write!(f, "=="),
+            BinOp::Ne => write!(f, "!="),
+            BinOp::Lt => write!(f, "<"),
+            BinOp::Le => write!(f, "<="),
+            BinOp::Gt => write!(f, ">"),
+            BinOp::Ge => write!(f, ">="),
+            BinOp::And => write!(f, "&&"),
+            BinOp::Or => write!(f, "||"),
+            BinOp::BitAnd => write!(f, "&"),
+            BinOp::BitOr => write!(f, "|"),
+            BinOp::BitXor => write!(f, "^"),
+            BinOp::ShiftLeft => write!(f, "<<"),
+            BinOp::ShiftRight => write!(f, ">>"),
+        }
+    }
+}
+
+impl fmt::Display for CastType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CastType::Int => write!(f, "int"),
+            CastType::UInt => write!(f, "uint"),
+            CastType::Float => write!(f, "float"),
+            CastType::Bool => write!(f, "bool"),
+        }
+    }
+}
+
+/// Lowers `expr`, keeping the `cfg(name)` expressions whose name is in
+/// `cfg` and replacing the rest with `()`.
+pub fn lower<'a>(expr: &Parsed<'a, Expr<'a>>, cfg: &[String]) -> Result<Hir<'a>, CompileError<'a>> {
+    let source = expr.get_source();
+    let line = expr.start().line() as u32;
+    let kind = match expr.get_node() {
+        Expr::IntLiteral(literal) => {
+            let (digits, suffix) = literal.split_at(literal.find(['i', 'u']).unwrap_or(literal.len()));
+            match suffix {
+                "u" | "u32" => HirKind::UInt(digits.parse::<u32>().map_err(|_| CompileError::literal_out_of_range(source)
+                    .with_note(format!("the largest `uint` is {}{}", u32::MAX, suffix)))?),
+                "" | "i32" => HirKind::Int(digits.parse::<i32>().map_err(|_| CompileError::literal_out_of_range(source)
+                    .with_note(format!("the largest `int` is {}{}", i32::MAX, suffix))
+                    .with_help("use a `u` suffix for a `uint` literal"))?),
+                _ => return Err(CompileError::unknown_suffix(source)
+                    .with_note(format!("`{}` is not an integer type", suffix))
+                    .with_help("use `i32` for an `int` literal or `u32` for a `uint` literal")),
+            }
+        }
+        Expr::FloatLiteral(literal) => HirKind::Float(literal.parse::<f32>().map_err(|_| CompileError::literal_out_of_range(source))?),
+        Expr::BoolLiteral(literal) => HirKind::Bool(*literal == "true"),
+        Expr::Ident(name) => HirKind::Var(name),
+        Expr::Tuple { exprs } => HirKind::Tuple(exprs.iter().map(|expr| lower(expr, cfg)).collect::<Result<_, _>>()?),
+        Expr::Block { exprs, last } => lower_block(exprs.iter().chain(Some(&**last)), cfg)?,
+        Expr::Func { name, pattern, expr } => HirKind::Func {
+            name: *name,
+            param: lower_pattern(pattern)?,
+            body: Box::new(lower(expr, cfg)?),
+        },
+        Expr::Binary { left, right, op } => match op {
+            BinaryOp::Plus => binary(BinOp::Add, left, right, cfg)?,
+            BinaryOp::Minus => binary(BinOp::Sub, left, right, cfg)?,
+            BinaryOp::Times => binary(BinOp::Mul, left, right, cfg)?,
+            BinaryOp::Divide => binary(BinOp::Div, left, right, cfg)?,
+            BinaryOp::Remainder => binary(BinOp::Rem, left, right, cfg)?,
+            BinaryOp::DoubleEquals => binary(BinOp::Eq, left, right, cfg)?,
+            BinaryOp::NotEqual => binary(BinOp::Ne, left, right, cfg)?,
+            BinaryOp::Less => binary(BinOp::Lt, left, right, cfg)?,
+            BinaryOp::LessOrEqual => binary(BinOp::Le, left, right, cfg)?,
+            BinaryOp::Greater => binary(BinOp::Gt, left, right, cfg)?,
+            BinaryOp::GreaterOrEqual => binary(BinOp::Ge, left, right, cfg)?,
+            BinaryOp::And => binary(BinOp::And, left, right, cfg)?,
+            BinaryOp::Or => binary(BinOp::Or, left, right, cfg)?,
+            BinaryOp::BitAnd => binary(BinOp::BitAnd, left, right, cfg)?,
+            BinaryOp::BitOr => binary(BinOp::BitOr, left, right, cfg)?,
+            BinaryOp::BitXor => binary(BinOp::BitXor, left, right, cfg)?,
+            BinaryOp::ShiftLeft => binary(BinOp::ShiftLeft, left, right, cfg)?,
+            BinaryOp::ShiftRight => binary(BinOp::ShiftRight, left, right, cfg)?,
+            BinaryOp::Bracket => HirKind::Call { callee: Box::new(lower(left, cfg)?), argument: Box::new(lower(right, cfg)?) },
+            BinaryOp::SingleEquals => HirKind::Assign { pattern: lower_pattern(left)?, value: Box::new(lower(right, cfg)?) },
+            BinaryOp::Else if matches!(right.get_node(), Expr::Ident("trap")) => HirKind::Unwrap { value: Box::new(lower(left, cfg)?) },
+            BinaryOp::Else => HirKind::Else { value: Box::new(lower(left, cfg)?), default: Box::new(lower(right, cfg)?) },
+            BinaryOp::PlusEquals => compound_assign(BinOp::Add, source, line, left, right, cfg)?,
+            BinaryOp::MinusEquals => compound_assign(BinOp::Sub, source, line, left, right, cfg)?,
+            BinaryOp::TimesEquals => compound_assign(BinOp::Mul, source, line, left, right, cfg)?,
+            BinaryOp::DivideEquals => compound_assign(BinOp::Div, source, line, left, right, cfg)?,
+            BinaryOp::RemainderEquals => compound_assign(BinOp::Rem, source, line, left, right, cfg)?,
+            BinaryOp::BitAndEquals => compound_assign(BinOp::BitAnd, source, line, left, right, cfg)?,
+            BinaryOp::BitOrEquals => compound_assign(BinOp::BitOr, source, line, left, right, cfg)?,
+            BinaryOp::BitXorEquals => compound_assign(BinOp::BitXor, source, line, left, right, cfg)?,
+            BinaryOp::ShiftLeftEquals => compound_assign(BinOp::ShiftLeft, source, line, left, right, cfg)?,
+            BinaryOp::ShiftRightEquals => compound_assign(BinOp::ShiftRight, source, line, left, right, cfg)?,
+        }
+        Expr::If { cond, conc } => HirKind::If { cond: Box::new(lower(cond, cfg)?), conc: Box::new(lower(conc, cfg)?) },
+        Expr::Match { value, arms } => HirKind::Match {
+            value: Box::new(lower(value, cfg)?),
+            arms: arms.iter().map(|(pattern, body)| Ok((lower_arm_pattern(pattern)?, lower(body, cfg)?))).collect::<Result<_, _>>()?,
+        },
+        Expr::For { label, pattern, iterable, body } => HirKind::For {
+            label: *label,
+            pattern: lower_pattern(pattern)?,
+            iterable: Box::new(lower(iterable, cfg)?),
+            body: Box::new(lower(body, cfg)?),
+        },
+        Expr::While { label, cond, body } => HirKind::While {
+            label: *label,
+            cond: Box::new(lower(cond, cfg)?),
+            body: Box::new(lower(body, cfg)?),
+        },
+        Expr::Struct { body } => HirKind::Struct(lower_fields(body, cfg)?),
+        Expr::Field { expr, name } => HirKind::Field { value: Box::new(lower(expr, cfg)?), name: name.node },
+        Expr::Cast { expr, ty } => match CastType::from_name(ty.node) {
+            Some(cast) => HirKind::Cast { value: Box::new(lower(expr, cfg)?), ty: cast },
+            None => return Err(CompileError::unknown_type(ty.get_source())),
+        },
+        Expr::Not { expr } => HirKind::Not { value: Box::new(lower(expr, cfg)?) },
+        Expr::Break { label } => HirKind::Break { label: *label },
+        // outside a block there is nothing to wait for, so it runs at once
+        Expr::Defer { body } => HirKind::Block(vec![lower(body, cfg)?, Hir::new(source, line, HirKind::Tuple(Vec::new()))]),
+        Expr::Cfg { name, body } if cfg.iter().any(|enabled| enabled == name.node) => return lower(body, cfg),
+        Expr::Cfg { .. } => HirKind::Tuple(Vec::new()),
+        Expr::Allow { name, body } => {
+            let mut body = lower(body, cfg)?;
+            body.allowed.push(name.node);
+            return Ok(body)
+        }
+        Expr::InlineIr(text) => {
+            let ir = ir_parser::parse(text).map_err(|error| CompileError::invalid_ir(error.source)
+                .with_label(error.source, error.message)
+                .with_note("inline IR is written as the IR dump prints it, with names for registers".to_string()))?;
+            let inputs = ir.slots.iter()
+                .filter(|slot| slot.input)
+                .map(|slot| Hir::new(slot.name, line, HirKind::Var(slot.name)))
+                .collect();
+            HirKind::InlineIr { ir, inputs }
+        }
+    };
+    Ok(Hir::new(source, line, kind))
+}
+
+/// Lowers the items of a block. The items after a `defer` go in a
+/// `Deferred` with what it defers, and with what the `defer`s right after
+/// it defer, in reverse order. A `defer` item is `()` where it stands, so a
+/// block ending in one has the value `()`.
+fn lower_block<'p, 'a: 'p>(items: impl DoubleEndedIterator<Item = &'p Parsed<'a, Expr<'a>>>, cfg: &[String]) -> Result<HirKind<'a>, CompileError<'a>> {
+    // built from the end, so `exprs` holds the items after `item` in
+    // reverse order, and `deferred` what the `defer`s just after it defer
+    let mut exprs = Vec::new();
+    let mut deferred = Vec::new();
+    for item in items.rev() {
+        match deferred_body(item, cfg) {
+            Some(body) => {
+                // a block that ends in a `defer` gives `()`, which is found
+                // at the `defer` rather than at what it defers
+                if exprs.is_empty() {
+                    exprs.push(Hir::new(item.get_source(), item.start().line() as u32, HirKind::Tuple(Vec::new())));
+                }
+                deferred.push(lower(body, cfg)?);
+            }
+            None => {
+                wrap_deferred(&mut exprs, &mut deferred);
+                exprs.push(lower(item, cfg)?);
+            }
+        }
+    }
+    wrap_deferred(&mut exprs, &mut deferred);
+    exprs.reverse();
+    Ok(HirKind::Block(exprs))
+}
+
+/// Replaces the items in `exprs`, which are in reverse order, with a
+/// `Deferred` running them and then `deferred`, if there is anything to defer.
+fn wrap_deferred<'a>(exprs: &mut Vec<Hir<'a>>, deferred: &mut Vec<Hir<'a>>) {
+    if deferred.is_empty() {
+        return
+    }
+    let mut items = std::mem::take(exprs);
+    items.reverse();
+    let last = items.last().unwrap();
+    let (source, line) = (last.source, last.line);
+    exprs.push(Hir::new(source, line, HirKind::Deferred { items, deferred: std::mem::take(deferred) }));
+}
+
+/// The expression `item` defers, if it is a `defer`, including one behind
+/// an enabled `cfg`.
+fn deferred_body<'p, 'a>(item: &'p Parsed<'a, Expr<'a>>, cfg: &[String]) -> Option<&'p Parsed<'a, Expr<'a>>> {
+    match item.get_node() {
+        Expr::Defer { body } => Some(body),
+        Expr::Cfg { name, body } if cfg.iter().any(|enabled| enabled == name.node) => deferred_body(body, cfg),
+        _ => None,
+    }
+}
+
+fn binary<'a>(op: BinOp, left: &Parsed<'a, Expr<'a>>, right: &Parsed<'a, Expr<'a>>, cfg: &[String]) -> Result<HirKind<'a>, CompileError<'a>> {
+    Ok(HirKind::Binary { op, left: Box::new(lower(left, cfg)?), right: Box::new(lower(right, cfg)?) })
+}
+
+/// `x += y` becomes `x = x + y`, so only a plain name can be updated.
+fn compound_assign<'a>(op: BinOp, source: &'a str, line: u32, target: &Parsed<'a, Expr<'a>>, value: &Parsed<'a, Expr<'a>>, cfg: &[String]) -> Result<HirKind<'a>, CompileError<'a>> {
+    let name = match target.get_node() {
+        Expr::Ident(name) => *name,
+        _ => return Err(CompileError::invalid_pattern(target.get_source())
+            .with_note("only a name can be updated in place".to_string())),
+    };
+    let current = Hir::new(target.get_source(), line, HirKind::Var(name));
+    let updated = Hir::new(source, line, HirKind::Binary { op, left: Box::new(current), right: Box::new(lower(value, cfg)?) });
+    Ok(HirKind::Assign {
+        pattern: Pattern { source: target.get_source(), kind: PatternKind::Bind(name) },
+        value: Box::new(updated),
+    })
+}
+
+/// The fields of a struct body, which are its items, or the elements of a
+/// tuple. A field is written `name = value`, or just `name` for
+/// `name = name`.
+fn lower_fields<'a>(body: &Parsed<'a, Expr<'a>>, cfg: &[String]) -> Result<Vec<(&'a str, Hir<'a>)>, CompileError<'a>> {
+    let items: Vec<_> = match body.get_node() {
+        Expr::Block { exprs, last } => exprs.iter().chain(Some(&**last)).collect(),
+        Expr::Tuple { exprs } => exprs.iter().collect(),
+        _ => vec![body],
+    };
+    let mut fields: Vec<(&str, Hir)> = Vec::new();
+    for item in items {
+        let (name, value) = match item.get_node() {
+            Expr::Ident(name) => (*name, lower(item, cfg)?),
+            Expr::Binary { left, right, op: BinaryOp::SingleEquals } if matches!(left.get_node(), Expr::Ident(_)) => {
+                (left.get_source(), lower(right, cfg)?)
+            }
+            _ => return Err(CompileError::invalid_struct_body(item.get_source())
+                .with_note("a struct body can only hold fields, written `name = value` or `name`".to_string())),
+        };
+        if let Some((first, _)) = fields.iter().find(|(field, _)| *field == name) {
+            return Err(CompileError::invalid_struct_body(item.get_source())
+                .with_label(first, format!("`{}` is first given here", name))
+                .with_note("each field of a struct can only be given once".to_string()))
+        }
+        fields.push((name, value));
+    }
+    Ok(fields)
+}
+
+fn lower_pattern<'a>(pattern: &Parsed<'a, Expr<'a>>) -> Result<Pattern<'a>, CompileError<'a>> {
+    let kind = match pattern.get_node() {
+        Expr::Ident("_") => PatternKind::Wildcard,
+        Expr::Ident(name) => PatternKind::Bind(name),
+        Expr::Tuple { exprs } => PatternKind::Tuple(exprs.iter().map(lower_pattern).collect::<Result<_, _>>()?),
+        Expr::IntLiteral(_) | Expr::BoolLiteral(_) => return Err(CompileError::invalid_pattern(pattern.get_source())
+            .with_note("a literal can only be the pattern of a `match` arm".to_string())),
+        _ => return Err(CompileError::invalid_pattern(pattern.get_source())
+            .with_note("only names, `_` and tuples of patterns can be assigned to".to_string())),
+    };
+    Ok(Pattern { source: pattern.get_source(), kind })
+}
+
+/// The pattern of a `match` arm, which can also hold `int`, `uint` and
+/// `bool` literals.
+fn lower_arm_pattern<'a>(pattern: &Parsed<'a, Expr<'a>>) -> Result<Pattern<'a>, CompileError<'a>> {
+    let kind = match pattern.get_node() {
+        Expr::Tuple { exprs } => PatternKind::Tuple(exprs.iter().map(lower_arm_pattern).collect::<Result<_, _>>()?),
+        Expr::IntLiteral(_) | Expr::BoolLiteral(_) => match lower(pattern, &[])?.kind {
+            HirKind::Int(value) => PatternKind::Int(value),
+            HirKind::UInt(value) => PatternKind::UInt(value),
+            HirKind::Bool(value) => PatternKind::Bool(value),
+            _ => unreachable!("a literal lowers to its value"),
+        },
+        Expr::FloatLiteral(_) => return Err(CompileError::invalid_pattern(pattern.get_source())
+            .with_note("a `float` cannot be matched, since a value can differ from it by rounding".to_string())
+            .with_help("compare with `<` and `>` in an `if` instead")),
+        _ => return lower_pattern(pattern),
+    };
+    Ok(Pattern { source: pattern.get_source(), kind })
+}
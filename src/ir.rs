@@ -1,8 +1,11 @@
 use std::fmt;
+use crate::builtins::Intrinsic;
 
+#[derive(Clone)]
 pub struct Program {
     functions: Vec<Function>,
     variable_count: usize,
+    line: u32,
 }
 
 #[derive(Debug, Clone)]
@@ -10,6 +13,7 @@ pub struct Function {
     params: Vec<Var>,
     returns: Vec<Var>,
     blocks: Vec<Block>,
+    effects: Effects,
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -20,10 +24,28 @@ pub struct FunctionId {
 #[derive(Debug, Clone)]
 pub struct Block {
     insts: Vec<Instruction>,
+    effects: Effects,
+    /// The source line of each instruction, for traps and debug output.
+    lines: Vec<u32>,
     exit: ExitInstruction,
     id: usize,
 }
 
+/// What running code can do besides computing its results. Code with no
+/// effects is pure: a call to it can be removed if its results are unused,
+/// or moved, without changing what the program does.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct Effects {
+    /// It can stop the program.
+    pub traps: bool,
+    /// It changes state outside its own registers, such as the random
+    /// number generator.
+    pub writes_state: bool,
+    /// It talks to the host, by reading the program's arguments or the
+    /// clock, or by sleeping.
+    pub io: bool,
+}
+
 #[derive(Debug, Copy, Clone)]
 pub struct BlockId {
     id: usize,
@@ -36,21 +58,196 @@ pub enum Instruction {
         a: Var,
         b: Var,
     },
+    AddFloat {
+        dest: Var,
+        a: Var,
+        b: Var,
+    },
+    AndInt {
+        dest: Var,
+        a: Var,
+        b: Var,
+    },
+    OrInt {
+        dest: Var,
+        a: Var,
+        b: Var,
+    },
+    XorInt {
+        dest: Var,
+        a: Var,
+        b: Var,
+    },
+    ShiftLeft {
+        dest: Var,
+        a: Var,
+        b: Var,
+    },
+    ShiftRightArithmetic {
+        dest: Var,
+        a: Var,
+        b: Var,
+    },
+    ShiftRightLogical {
+        dest: Var,
+        a: Var,
+        b: Var,
+    },
+    SubInt {
+        dest: Var,
+        a: Var,
+        b: Var,
+    },
+    SubFloat {
+        dest: Var,
+        a: Var,
+        b: Var,
+    },
+    MulInt {
+        dest: Var,
+        a: Var,
+        b: Var,
+    },
+    MulFloat {
+        dest: Var,
+        a: Var,
+        b: Var,
+    },
+    /// Traps when `b` is zero, as do the other integer divisions and
+    /// remainders.
+    DivInt {
+        dest: Var,
+        a: Var,
+        b: Var,
+    },
+    DivUInt {
+        dest: Var,
+        a: Var,
+        b: Var,
+    },
+    DivFloat {
+        dest: Var,
+        a: Var,
+        b: Var,
+    },
+    RemInt {
+        dest: Var,
+        a: Var,
+        b: Var,
+    },
+    RemUInt {
+        dest: Var,
+        a: Var,
+        b: Var,
+    },
+    RemFloat {
+        dest: Var,
+        a: Var,
+        b: Var,
+    },
+    /// `dest = a == b`, comparing the bits, so it also compares `uint`
+    /// and `bool` values.
+    EqInt {
+        dest: Var,
+        a: Var,
+        b: Var,
+    },
+    EqFloat {
+        dest: Var,
+        a: Var,
+        b: Var,
+    },
+    LessInt {
+        dest: Var,
+        a: Var,
+        b: Var,
+    },
+    LessUInt {
+        dest: Var,
+        a: Var,
+        b: Var,
+    },
+    LessFloat {
+        dest: Var,
+        a: Var,
+        b: Var,
+    },
+    LessEqInt {
+        dest: Var,
+        a: Var,
+        b: Var,
+    },
+    LessEqUInt {
+        dest: Var,
+        a: Var,
+        b: Var,
+    },
+    LessEqFloat {
+        dest: Var,
+        a: Var,
+        b: Var,
+    },
     ConstantInt {
         dest: Var,
         constant: i32,
     },
-    Phi {
+    ConstantFloat {
+        dest: Var,
+        constant: f32,
+    },
+    Convert {
+        dest: Var,
+        src: Var,
+        conversion: Conversion,
+    },
+    /// Overwrites `dest`, which unlike other destinations may already
+    /// hold a value, for state carried from one pass of a loop to the next.
+    Copy {
+        dest: Var,
+        src: Var,
+    },
+    /// `dest = cond ? a : b`, without branching.
+    Select {
         cond: Var,
         a: Var,
         b: Var,
         dest: Var,
     },
+    /// Takes the value of the source paired with the block that branched
+    /// here. Phis come before a block's other instructions, and read their
+    /// sources together before any of them is written.
+    Phi {
+        dest: Var,
+        sources: Vec<(BlockId, Var)>,
+    },
     Call {
         function: FunctionId,
         args: Vec<Var>,
         returns: Vec<Var>,
     },
+    CallBuiltin {
+        intrinsic: Intrinsic,
+        args: Vec<Var>,
+        returns: Vec<Var>,
+    },
+    Exit {
+        code: Var,
+    },
+    /// Stops the program because `else trap` found an empty `maybe`.
+    Abort,
+    /// Starts a task that calls `function`, which has no parameters, with
+    /// a copy of the registers. It runs when the running task yields.
+    Spawn {
+        function: FunctionId,
+    },
+}
+
+#[derive(Debug, Copy, Clone, Hash, Eq, PartialEq)]
+pub enum Conversion {
+    IntToFloat,
+    UIntToFloat,
+    FloatToInt,
+    FloatToUInt,
 }
 
 #[derive(Debug, Clone)]
@@ -63,6 +260,13 @@ pub enum ExitInstruction {
         block1: BlockId,
         block2: BlockId,
     },
+    /// Goes to `targets[scrutinee]`, or to `default` when the scrutinee,
+    /// read as unsigned, is past the end of `targets`.
+    Switch {
+        scrutinee: Var,
+        targets: Vec<BlockId>,
+        default: BlockId,
+    },
     Return,
 }
 
@@ -76,14 +280,31 @@ impl Program {
         Program {
             functions: vec![],
             variable_count: 0,
+            line: 0,
         }
     }
+    /// Sets the source line recorded against instructions added from now on,
+    /// returning the previous one so it can be restored.
+    pub fn set_line(&mut self, line: u32) -> u32 {
+        std::mem::replace(&mut self.line, line)
+    }
     pub fn add_function(&mut self, function: Function) -> FunctionId {
         let id = self.functions.len();
         self.functions.push(function);
         FunctionId { id }
     }
-    fn new_variable(&mut self) -> Var {
+    /// Takes an id for a function whose body is not compiled yet, so that
+    /// recursive calls can be made to it before it is defined. Until then a
+    /// call to it counts as trapping and writing state, since it might not
+    /// return, but not as doing I/O, which `performs_io` finds exactly.
+    pub fn reserve_function(&mut self) -> FunctionId {
+        let effects = Effects { traps: true, writes_state: true, io: false };
+        self.add_function(Function { effects, ..Function::new() })
+    }
+    pub fn define_function(&mut self, function_id: FunctionId, function: Function) {
+        self.functions[function_id.id] = function;
+    }
+    pub(crate) fn new_variable(&mut self) -> Var {
         let variable = Var { id: self.variable_count };
         self.variable_count += 1;
         variable
@@ -91,14 +312,54 @@ impl Program {
     pub fn get_variable_count(&self) -> usize {
         self.variable_count
     }
+    pub fn get_function_count(&self) -> usize {
+        self.functions.len()
+    }
+    pub fn get_function_ids(&self) -> Vec<FunctionId> {
+        (0..self.functions.len()).map(|id| FunctionId { id }).collect()
+    }
     pub fn get_function(&self, function_id: FunctionId) -> &Function {
         &self.functions[function_id.id]
     }
+    pub(crate) fn get_function_mut(&mut self, function_id: FunctionId) -> &mut Function {
+        &mut self.functions[function_id.id]
+    }
+    /// Whether running `function_id` can do I/O, through any function it
+    /// can reach. Effects miss the I/O of a function called while it was
+    /// still being compiled, so a host deciding whether to run a program
+    /// should ask this instead.
+    pub fn performs_io(&self, function_id: FunctionId) -> bool {
+        let mut visited = vec![false; self.functions.len()];
+        let mut stack = vec![function_id.id];
+        while let Some(id) = stack.pop() {
+            if std::mem::replace(&mut visited[id], true) {
+                continue
+            }
+            for inst in self.functions[id].blocks.iter().flat_map(|block| &block.insts) {
+                match inst {
+                    Instruction::CallBuiltin { intrinsic, .. } if intrinsic.effects().io => return true,
+                    Instruction::Call { function, .. } | Instruction::Spawn { function } => stack.push(function.id),
+                    _ => (),
+                }
+            }
+        }
+        false
+    }
+    /// Rebuilds a program from its functions, as read back from bytecode.
+    pub(crate) fn from_parts(functions: Vec<Function>, variable_count: usize) -> Program {
+        Program { functions, variable_count, line: 0 }
+    }
+}
+
+impl Default for Program {
+    fn default() -> Program {
+        Program::new()
+    }
 }
 
 impl Function {
     pub fn new() -> Function {
-        Function { params: vec![], blocks: vec![], returns: vec![] }
+        Function { params: vec![], blocks: vec![], returns: vec![], effects: Effects::default() }
     }
     pub fn new_parameter(&mut self, program: &mut Program) -> Var {
         let var = program.new_variable();
@@ -109,58 +370,283 @@ impl Function {
         self.returns.push(var)
     }
     pub fn new_block(&mut self) -> Block {
-        let block = Block { id: self.blocks.len(), insts: vec![], exit: ExitInstruction::Return };
+        let block = Block { id: self.blocks.len(), insts: vec![], effects: Effects::default(), lines: vec![], exit: ExitInstruction::Return };
         self.blocks.push(block.clone());
         block
     }
     fn submit_block(&mut self, block: Block) {
         let id = block.id;
+        self.effects = self.effects.union(block.effects);
         self.blocks[id] = block;
     }
     pub fn get_block(&self, id: BlockId) -> &Block {
         &self.blocks[id.id]
     }
+    pub fn get_entry_block(&self) -> &Block {
+        self.get_block(BlockId::entry())
+    }
+    pub fn get_blocks(&self) -> &Vec<Block> {
+        &self.blocks
+    }
+    pub(crate) fn get_blocks_mut(&mut self) -> &mut Vec<Block> {
+        &mut self.blocks
+    }
     pub fn get_params(&self) -> &Vec<Var> {
         &self.params
     }
     pub fn get_returns(&self) -> &Vec<Var> {
         &self.returns
     }
+    /// The effects of every block submitted so far, including those of the
+    /// functions they call.
+    pub fn get_effects(&self) -> Effects {
+        self.effects
+    }
+    pub(crate) fn from_parts(params: Vec<Var>, returns: Vec<Var>, blocks: Vec<Block>, effects: Effects) -> Function {
+        Function { params, returns, blocks, effects }
+    }
+}
+
+impl Default for Function {
+    fn default() -> Function {
+        Function::new()
+    }
+}
+
+impl Effects {
+    pub fn is_pure(&self) -> bool {
+        *self == Effects::default()
+    }
+    pub fn union(self, other: Effects) -> Effects {
+        Effects {
+            traps: self.traps || other.traps,
+            writes_state: self.writes_state || other.writes_state,
+            io: self.io || other.io,
+        }
+    }
 }
 
 impl BlockId {
+    /// The block every function starts in, which is the first one made
+    /// with `Function::new_block`.
     pub fn entry() -> BlockId {
         BlockId { id: 0 }
     }
+    pub(crate) fn new(id: usize) -> BlockId {
+        BlockId { id }
+    }
+    pub fn get_id(&self) -> usize {
+        self.id
+    }
 }
 
 impl Block {
     pub fn get_id(&self) -> BlockId {
         BlockId { id: self.id }
     }
+    fn push(&mut self, inst: Instruction, program: &Program) {
+        let effects = match &inst {
+            Instruction::Call { function, .. } => program.functions[function.id].effects,
+            Instruction::CallBuiltin { intrinsic, .. } => intrinsic.effects(),
+            Instruction::Exit { .. } | Instruction::Abort
+                | Instruction::DivInt { .. } | Instruction::DivUInt { .. }
+                | Instruction::RemInt { .. } | Instruction::RemUInt { .. } => Effects { traps: true, ..Effects::default() },
+            Instruction::Spawn { function } => program.functions[function.id].effects.union(Effects { writes_state: true, ..Effects::default() }),
+            _ => Effects::default(),
+        };
+        self.effects = self.effects.union(effects);
+        self.insts.push(inst);
+        self.lines.push(program.line);
+    }
     pub fn add_int(&mut self, a: Var, b: Var, program: &mut Program) -> Var {
         let dest = program.new_variable();
-        self.insts.push(Instruction::AddInt { dest, a, b });
+        self.push(Instruction::AddInt { dest, a, b }, program);
+        dest
+    }
+    pub fn add_float(&mut self, a: Var, b: Var, program: &mut Program) -> Var {
+        let dest = program.new_variable();
+        self.push(Instruction::AddFloat { dest, a, b }, program);
+        dest
+    }
+    pub fn and_int(&mut self, a: Var, b: Var, program: &mut Program) -> Var {
+        let dest = program.new_variable();
+        self.push(Instruction::AndInt { dest, a, b }, program);
+        dest
+    }
+    pub fn or_int(&mut self, a: Var, b: Var, program: &mut Program) -> Var {
+        let dest = program.new_variable();
+        self.push(Instruction::OrInt { dest, a, b }, program);
+        dest
+    }
+    pub fn xor_int(&mut self, a: Var, b: Var, program: &mut Program) -> Var {
+        let dest = program.new_variable();
+        self.push(Instruction::XorInt { dest, a, b }, program);
+        dest
+    }
+    pub fn shift_left(&mut self, a: Var, b: Var, program: &mut Program) -> Var {
+        let dest = program.new_variable();
+        self.push(Instruction::ShiftLeft { dest, a, b }, program);
+        dest
+    }
+    pub fn shift_right_arithmetic(&mut self, a: Var, b: Var, program: &mut Program) -> Var {
+        let dest = program.new_variable();
+        self.push(Instruction::ShiftRightArithmetic { dest, a, b }, program);
+        dest
+    }
+    pub fn shift_right_logical(&mut self, a: Var, b: Var, program: &mut Program) -> Var {
+        let dest = program.new_variable();
+        self.push(Instruction::ShiftRightLogical { dest, a, b }, program);
+        dest
+    }
+    pub fn sub_int(&mut self, a: Var, b: Var, program: &mut Program) -> Var {
+        let dest = program.new_variable();
+        self.push(Instruction::SubInt { dest, a, b }, program);
+        dest
+    }
+    pub fn sub_float(&mut self, a: Var, b: Var, program: &mut Program) -> Var {
+        let dest = program.new_variable();
+        self.push(Instruction::SubFloat { dest, a, b }, program);
+        dest
+    }
+    pub fn mul_int(&mut self, a: Var, b: Var, program: &mut Program) -> Var {
+        let dest = program.new_variable();
+        self.push(Instruction::MulInt { dest, a, b }, program);
+        dest
+    }
+    pub fn mul_float(&mut self, a: Var, b: Var, program: &mut Program) -> Var {
+        let dest = program.new_variable();
+        self.push(Instruction::MulFloat { dest, a, b }, program);
+        dest
+    }
+    pub fn div_int(&mut self, a: Var, b: Var, program: &mut Program) -> Var {
+        let dest = program.new_variable();
+        self.push(Instruction::DivInt { dest, a, b }, program);
+        dest
+    }
+    pub fn div_uint(&mut self, a: Var, b: Var, program: &mut Program) -> Var {
+        let dest = program.new_variable();
+        self.push(Instruction::DivUInt { dest, a, b }, program);
+        dest
+    }
+    pub fn div_float(&mut self, a: Var, b: Var, program: &mut Program) -> Var {
+        let dest = program.new_variable();
+        self.push(Instruction::DivFloat { dest, a, b }, program);
+        dest
+    }
+    pub fn rem_int(&mut self, a: Var, b: Var, program: &mut Program) -> Var {
+        let dest = program.new_variable();
+        self.push(Instruction::RemInt { dest, a, b }, program);
+        dest
+    }
+    pub fn rem_uint(&mut self, a: Var, b: Var, program: &mut Program) -> Var {
+        let dest = program.new_variable();
+        self.push(Instruction::RemUInt { dest, a, b }, program);
+        dest
+    }
+    pub fn rem_float(&mut self, a: Var, b: Var, program: &mut Program) -> Var {
+        let dest = program.new_variable();
+        self.push(Instruction::RemFloat { dest, a, b }, program);
+        dest
+    }
+    pub fn eq_int(&mut self, a: Var, b: Var, program: &mut Program) -> Var {
+        let dest = program.new_variable();
+        self.push(Instruction::EqInt { dest, a, b }, program);
+        dest
+    }
+    pub fn eq_float(&mut self, a: Var, b: Var, program: &mut Program) -> Var {
+        let dest = program.new_variable();
+        self.push(Instruction::EqFloat { dest, a, b }, program);
+        dest
+    }
+    pub fn less_int(&mut self, a: Var, b: Var, program: &mut Program) -> Var {
+        let dest = program.new_variable();
+        self.push(Instruction::LessInt { dest, a, b }, program);
+        dest
+    }
+    pub fn less_uint(&mut self, a: Var, b: Var, program: &mut Program) -> Var {
+        let dest = program.new_variable();
+        self.push(Instruction::LessUInt { dest, a, b }, program);
+        dest
+    }
+    pub fn less_float(&mut self, a: Var, b: Var, program: &mut Program) -> Var {
+        let dest = program.new_variable();
+        self.push(Instruction::LessFloat { dest, a, b }, program);
+        dest
+    }
+    pub fn less_eq_int(&mut self, a: Var, b: Var, program: &mut Program) -> Var {
+        let dest = program.new_variable();
+        self.push(Instruction::LessEqInt { dest, a, b }, program);
+        dest
+    }
+    pub fn less_eq_uint(&mut self, a: Var, b: Var, program: &mut Program) -> Var {
+        let dest = program.new_variable();
+        self.push(Instruction::LessEqUInt { dest, a, b }, program);
+        dest
+    }
+    pub fn less_eq_float(&mut self, a: Var, b: Var, program: &mut Program) -> Var {
+        let dest = program.new_variable();
+        self.push(Instruction::LessEqFloat { dest, a, b }, program);
         dest
     }
     pub fn constant_int(&mut self, constant: i32, program: &mut Program) -> Var {
         let dest = program.new_variable();
-        self.insts.push(Instruction::ConstantInt { dest, constant });
+        self.push(Instruction::ConstantInt { dest, constant }, program);
+        dest
+    }
+    pub fn constant_float(&mut self, constant: f32, program: &mut Program) -> Var {
+        let dest = program.new_variable();
+        self.push(Instruction::ConstantFloat { dest, constant }, program);
         dest
     }
-    pub fn phi(&mut self, cond: Var, a: Var, b: Var, program: &mut Program) -> Var {
+    pub fn convert(&mut self, src: Var, conversion: Conversion, program: &mut Program) -> Var {
         let dest = program.new_variable();
-        self.insts.push(Instruction::Phi { dest, cond, a, b });
+        self.push(Instruction::Convert { dest, src, conversion }, program);
         dest
     }
-    pub fn call(&mut self, target_function_id: FunctionId, args: Vec<Var>, program: &mut Program) -> Vec<Var> {
+    pub fn select(&mut self, cond: Var, a: Var, b: Var, program: &mut Program) -> Var {
+        let dest = program.new_variable();
+        self.push(Instruction::Select { dest, cond, a, b }, program);
+        dest
+    }
+    pub fn phi(&mut self, sources: Vec<(BlockId, Var)>, program: &mut Program) -> Var {
+        let dest = program.new_variable();
+        self.push(Instruction::Phi { dest, sources }, program);
+        dest
+    }
+    pub fn copy(&mut self, dest: Var, src: Var, program: &Program) {
+        self.push(Instruction::Copy { dest, src }, program);
+    }
+    pub fn call(&mut self, target_function_id: FunctionId, args: Vec<Var>, return_count: usize, program: &mut Program) -> Vec<Var> {
+        let mut returns = Vec::new();
+        for _ in 0..return_count {
+            returns.push(program.new_variable())
+        }
+        self.push(Instruction::Call { function: target_function_id, args, returns: returns.clone() }, program);
+        returns
+    }
+    pub fn call_builtin(&mut self, intrinsic: Intrinsic, args: Vec<Var>, return_count: usize, program: &mut Program) -> Vec<Var> {
         let mut returns = Vec::new();
-        for _ in 0..program.functions[target_function_id.id].returns.len() {
+        for _ in 0..return_count {
             returns.push(program.new_variable())
         }
-        self.insts.push(Instruction::Call { function: target_function_id, args, returns: returns.clone() });
+        self.push(Instruction::CallBuiltin { intrinsic, args, returns: returns.clone() }, program);
         returns
     }
+    pub fn spawn(&mut self, function: FunctionId, program: &Program) {
+        self.push(Instruction::Spawn { function }, program);
+    }
+    pub fn exit(&mut self, code: Var, program: &Program) {
+        self.push(Instruction::Exit { code }, program);
+    }
+    /// Adds a copy of `inst` whose variables are indices into `vars`, as
+    /// the instructions of inline IR are.
+    pub(crate) fn splice(&mut self, inst: &Instruction, vars: &[Var], program: &Program) {
+        let inst = inst.renamed(&mut |var| vars[var.id], &[]);
+        self.push(inst, program);
+    }
+    pub fn abort(&mut self, program: &Program) {
+        self.push(Instruction::Abort, program);
+    }
     pub fn ret(mut self, function: &mut Function) {
         self.exit = ExitInstruction::Return;
         function.submit_block(self)
@@ -169,6 +655,10 @@ impl Block {
         self.exit = ExitInstruction::ConditionalBranch { cond, block1, block2 };
         function.submit_block(self)
     }
+    pub fn switch(mut self, scrutinee: Var, targets: Vec<BlockId>, default: BlockId, function: &mut Function) {
+        self.exit = ExitInstruction::Switch { scrutinee, targets, default };
+        function.submit_block(self)
+    }
     pub fn branch(mut self, block: BlockId, function: &mut Function) {
         self.exit = ExitInstruction::Branch { block };
         function.submit_block(self)
@@ -176,90 +666,1030 @@ impl Block {
     pub fn get_instructions(&self) -> &Vec<Instruction> {
         &self.insts
     }
+    /// The source line of the instruction at `index`.
+    pub fn get_line(&self, index: usize) -> u32 {
+        self.lines[index]
+    }
+    /// The line of the block's last instruction, which its exit is part of,
+    /// or 0 for a block with none.
+    pub fn get_exit_line(&self) -> u32 {
+        self.lines.last().copied().unwrap_or(0)
+    }
     pub fn get_exit_instruction(&self) -> &ExitInstruction {
         &self.exit
     }
+    pub fn get_effects(&self) -> Effects {
+        self.effects
+    }
+    /// The instructions, to change in place. Each keeps its line, so none
+    /// can be added or removed this way.
+    pub(crate) fn get_instructions_mut(&mut self) -> &mut [Instruction] {
+        &mut self.insts
+    }
+    /// Removes the instructions `keep` rejects, along with their lines.
+    pub(crate) fn retain_instructions(&mut self, mut keep: impl FnMut(&Instruction) -> bool) {
+        let keep: Vec<bool> = self.insts.iter().map(&mut keep).collect();
+        let mut kept = keep.iter();
+        self.insts.retain(|_| *kept.next().expect("one flag per instruction"));
+        let mut kept = keep.iter();
+        self.lines.retain(|_| *kept.next().expect("one flag per line"));
+    }
+    pub(crate) fn set_exit_instruction(&mut self, exit: ExitInstruction) {
+        self.exit = exit;
+    }
+    /// Adds `phis` after the phis the block starts with.
+    pub(crate) fn insert_phis(&mut self, phis: Vec<Instruction>) {
+        let at = self.insts.iter().take_while(|inst| matches!(inst, Instruction::Phi { .. })).count();
+        let line = self.lines.first().copied().unwrap_or(0);
+        self.lines.splice(at..at, phis.iter().map(|_| line));
+        self.insts.splice(at..at, phis);
+    }
+    /// Runs `next` after the block's own instructions, in place of its exit.
+    pub(crate) fn append(&mut self, next: &Block) {
+        self.insts.extend_from_slice(&next.insts);
+        self.lines.extend_from_slice(&next.lines);
+        self.effects = self.effects.union(next.effects);
+        self.exit = next.exit.clone();
+    }
+    pub(crate) fn from_parts(id: usize, insts: Vec<Instruction>, lines: Vec<u32>, exit: ExitInstruction, effects: Effects) -> Block {
+        Block { insts, effects, lines, exit, id }
+    }
 }
 
-impl fmt::Display for Program {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        for (function_id, function) in self.functions.iter().enumerate() {
-            write!(f, "f{} (", function_id)?;
-            let mut iter = function.params.iter();
-            if let Some(param) = iter.next() {
-                write!(f, "r{}", param.id)?;
-                for param in iter {
-                    write!(f, ", r{}", param.id)?;
-                }
+impl Program {
+    /// A copy of the program numbered by its structure alone, so that dumps
+    /// do not change with the order things happened to be compiled in.
+    /// Functions come callees first, in the order `entry` reaches them, then
+    /// any it never reaches. Blocks are in reverse postorder and variables
+    /// are numbered by first use.
+    pub fn canonical(&self, entry: FunctionId) -> (Program, FunctionId) {
+        let mut order = Vec::new();
+        let mut visited = vec![false; self.functions.len()];
+        self.visit_callees(entry.id, &mut visited, &mut order);
+        for id in 0..self.functions.len() {
+            if !visited[id] {
+                self.visit_callees(id, &mut visited, &mut order);
+            }
+        }
+        let mut function_map = vec![0; self.functions.len()];
+        for (new_id, &id) in order.iter().enumerate() {
+            function_map[id] = new_id;
+        }
+        let mut var_map = vec![None; self.variable_count];
+        let mut variable_count = 0;
+        let mut rename = |var: Var| {
+            let id = *var_map[var.id].get_or_insert_with(|| {
+                variable_count += 1;
+                variable_count - 1
+            });
+            Var { id }
+        };
+        let functions = order.iter().map(|&id| {
+            let function = &self.functions[id];
+            let block_order = function.reverse_postorder();
+            let mut block_map = vec![0; function.blocks.len()];
+            for (new_id, &id) in block_order.iter().enumerate() {
+                block_map[id] = new_id;
             }
-            write!(f, ")")?;
-            let mut iter = function.returns.iter();
-            if let Some(ret) = iter.next() {
-                write!(f, " -> r{}", ret.id)?;
-                for ret in iter {
-                    write!(f, ", r{}", ret.id)?;
+            let params = function.params.iter().map(|&var| rename(var)).collect();
+            let blocks = block_order.iter().enumerate().map(|(new_id, &id)| {
+                let block = &function.blocks[id];
+                let insts = block.insts.iter().map(|inst| {
+                    let mut inst = inst.renamed(&mut rename, &function_map);
+                    inst.map_phi_blocks(|block| Some(BlockId { id: block_map[block.id] }));
+                    inst
+                }).collect();
+                let exit = match &block.exit {
+                    ExitInstruction::Branch { block } => ExitInstruction::Branch { block: BlockId { id: block_map[block.id] } },
+                    &ExitInstruction::ConditionalBranch { cond, block1, block2 } => ExitInstruction::ConditionalBranch {
+                        cond: rename(cond),
+                        block1: BlockId { id: block_map[block1.id] },
+                        block2: BlockId { id: block_map[block2.id] },
+                    },
+                    ExitInstruction::Switch { scrutinee, targets, default } => ExitInstruction::Switch {
+                        scrutinee: rename(*scrutinee),
+                        targets: targets.iter().map(|target| BlockId { id: block_map[target.id] }).collect(),
+                        default: BlockId { id: block_map[default.id] },
+                    },
+                    ExitInstruction::Return => ExitInstruction::Return,
+                };
+                Block { insts, effects: block.effects, lines: block.lines.clone(), exit, id: new_id }
+            }).collect();
+            let returns = function.returns.iter().map(|&var| rename(var)).collect();
+            Function { params, returns, blocks, effects: function.effects }
+        }).collect();
+        (Program { functions, variable_count, line: 0 }, FunctionId { id: function_map[entry.id] })
+    }
+    /// A copy of the program without the functions `entry` never reaches,
+    /// such as those compiled for a call that was later found to be dead.
+    /// Functions keep their order and variables their numbers.
+    pub fn reachable(&self, entry: FunctionId) -> (Program, FunctionId) {
+        let mut visited = vec![false; self.functions.len()];
+        self.visit_callees(entry.id, &mut visited, &mut Vec::new());
+        let mut function_map = vec![0; self.functions.len()];
+        let mut functions = Vec::new();
+        for (id, function) in self.functions.iter().enumerate() {
+            if visited[id] {
+                function_map[id] = functions.len();
+                functions.push(function);
+            }
+        }
+        let functions = functions.into_iter().map(|function| {
+            let blocks = function.blocks.iter().map(|block| Block {
+                insts: block.insts.iter().map(|inst| inst.renamed(&mut |var| var, &function_map)).collect(),
+                ..block.clone()
+            }).collect();
+            Function { blocks, ..function.clone() }
+        }).collect();
+        (Program { functions, variable_count: self.variable_count, line: 0 }, FunctionId { id: function_map[entry.id] })
+    }
+    fn visit_callees(&self, id: usize, visited: &mut Vec<bool>, order: &mut Vec<usize>) {
+        visited[id] = true;
+        let function = &self.functions[id];
+        for block_id in function.reverse_postorder() {
+            for inst in &function.blocks[block_id].insts {
+                if let Instruction::Call { function, .. } | Instruction::Spawn { function } = inst {
+                    if !visited[function.id] {
+                        self.visit_callees(function.id, visited, order);
+                    }
                 }
             }
-            writeln!(f, "")?;
+        }
+        order.push(id);
+    }
+    /// Checks what running the program relies on: every function has an
+    /// entry block, every variable, block and function named exists, calls
+    /// pass and receive as many values as the function they call takes and
+    /// returns, and phis start their block and have a source for each block
+    /// that branches to it. Describes the first problem found.
+    pub fn verify(&self) -> Result<(), String> {
+        let var = |var: &Var, at: &dyn Fn() -> String| match var.id < self.variable_count {
+            true => Ok(()),
+            false => Err(format!("{} uses r{}, but the program has {} variables", at(), var.id, self.variable_count)),
+        };
+        for (function_id, function) in self.functions.iter().enumerate() {
+            if function.blocks.is_empty() {
+                return Err(format!("f{} has no blocks", function_id))
+            }
+            for param in function.params.iter().chain(&function.returns) {
+                var(param, &|| format!("f{}", function_id))?;
+            }
             for (block_id, block) in function.blocks.iter().enumerate() {
-                if block_id == 0 {
-                    writeln!(f, "    main:")?;
-                } else {
-                    writeln!(f, "    b{}:", block_id)?;
+                let at = || format!("b{} of f{}", block_id, function_id);
+                if block.id != block_id {
+                    return Err(format!("{} is numbered b{}", at(), block.id))
+                }
+                if block.lines.len() != block.insts.len() {
+                    return Err(format!("{} has {} instructions but {} lines", at(), block.insts.len(), block.lines.len()))
                 }
-                for inst in block.insts.iter() {
-                    write!(f, "        ")?;
-                    match inst {
-                        Instruction::AddInt { dest, a, b } => {
-                            writeln!(f, "r{} = r{} + r{}", dest.id, a.id, b.id)?
+                let phis = block.insts.iter().take_while(|inst| matches!(inst, Instruction::Phi { .. })).count();
+                for (index, inst) in block.insts.iter().enumerate() {
+                    for used in inst.vars() {
+                        var(&used, &at)?;
+                    }
+                    if let Instruction::Phi { sources, .. } = inst {
+                        if index >= phis {
+                            return Err(format!("{} has a phi after other instructions", at()))
                         }
-                        Instruction::ConstantInt { dest, constant } => {
-                            writeln!(f, "r{} = {}", dest.id, constant)?
+                        if block_id == BlockId::entry().id {
+                            return Err(format!("{} is an entry block with a phi", at()))
                         }
-                        Instruction::Phi { dest, cond, a, b } => {
-                            writeln!(f, "r{} = phi(r{}, r{}, r{})", dest.id, cond.id, a.id, b.id)?
+                        if let Some((source, _)) = sources.iter().find(|(source, _)| source.id >= function.blocks.len()) {
+                            return Err(format!("{} has a phi from b{}, which does not exist", at(), source.id))
                         }
+                    }
+                    // a spawned task's results are dropped
+                    let (callee, args, returns) = match inst {
+                        Instruction::Call { function, args, returns } => (function, args.len(), Some(returns.len())),
+                        Instruction::Spawn { function } => (function, 0, None),
+                        _ => continue,
+                    };
+                    let Some(callee_function) = self.functions.get(callee.id) else {
+                        return Err(format!("{} calls f{}, which does not exist", at(), callee.id))
+                    };
+                    if callee_function.params.len() != args {
+                        return Err(format!("{} calls f{} with {} arguments, but it takes {}", at(), callee.id, args, callee_function.params.len()))
+                    }
+                    if let Some(returns) = returns.filter(|&returns| returns != callee_function.returns.len()) {
+                        return Err(format!("{} takes {} results from f{}, but it returns {}", at(), returns, callee.id, callee_function.returns.len()))
+                    }
+                }
+                let targets = match &block.exit {
+                    ExitInstruction::Branch { block } => vec![*block],
+                    ExitInstruction::ConditionalBranch { cond, block1, block2 } => {
+                        var(cond, &at)?;
+                        vec![*block1, *block2]
+                    }
+                    ExitInstruction::Switch { scrutinee, targets, default } => {
+                        var(scrutinee, &at)?;
+                        targets.iter().chain([default]).copied().collect()
+                    }
+                    ExitInstruction::Return => vec![],
+                };
+                if let Some(target) = targets.iter().find(|target| target.id >= function.blocks.len()) {
+                    return Err(format!("{} branches to b{}, which does not exist", at(), target.id))
+                }
+                for target in targets {
+                    let sourceless = function.blocks[target.id].insts.iter().any(|inst| matches!(inst,
+                        Instruction::Phi { sources, .. } if !sources.iter().any(|(source, _)| source.id == block_id)
+                    ));
+                    if sourceless {
+                        return Err(format!("{} branches to b{}, which has a phi with no source for it", at(), target.id))
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Function {
+    /// Every variable the function uses or defines, each once.
+    pub fn vars(&self) -> Vec<Var> {
+        let mut vars: Vec<Var> = self.params.iter().chain(&self.returns).copied().collect();
+        for block in &self.blocks {
+            vars.extend(block.insts.iter().flat_map(Instruction::vars));
+            match block.exit {
+                ExitInstruction::ConditionalBranch { cond, .. } => vars.push(cond),
+                ExitInstruction::Switch { scrutinee, .. } => vars.push(scrutinee),
+                ExitInstruction::Branch { .. } | ExitInstruction::Return => {}
+            }
+        }
+        vars.sort_unstable_by_key(|var| var.id);
+        vars.dedup_by_key(|var| var.id);
+        vars
+    }
+    /// Block indices in reverse postorder from the entry block, followed by
+    /// any blocks it cannot reach.
+    pub(crate) fn reverse_postorder(&self) -> Vec<usize> {
+        fn visit(function: &Function, id: usize, visited: &mut Vec<bool>, order: &mut Vec<usize>) {
+            visited[id] = true;
+            for successor in function.successors(id) {
+                if !visited[successor] {
+                    visit(function, successor, visited, order);
+                }
+            }
+            order.push(id);
+        }
+        let mut visited = vec![false; self.blocks.len()];
+        let mut order = Vec::new();
+        if !self.blocks.is_empty() {
+            visit(self, 0, &mut visited, &mut order);
+        }
+        order.reverse();
+        order.extend((0..self.blocks.len()).filter(|&id| !visited[id]));
+        order
+    }
+    /// Removes the blocks the entry block cannot reach, numbering the rest
+    /// in their order, and returns whether there were any.
+    pub(crate) fn remove_unreachable_blocks(&mut self) -> bool {
+        let mut reached = vec![false; self.blocks.len()];
+        let mut stack = vec![BlockId::entry().id];
+        while let Some(id) = stack.pop() {
+            if !std::mem::replace(&mut reached[id], true) {
+                stack.extend(self.successors(id));
+            }
+        }
+        if reached.iter().all(|&reached| reached) {
+            return false
+        }
+        let mut block_map = vec![0; self.blocks.len()];
+        let mut blocks = Vec::new();
+        for (id, block) in std::mem::take(&mut self.blocks).into_iter().enumerate() {
+            if reached[id] {
+                block_map[id] = blocks.len();
+                blocks.push(Block { id: blocks.len(), ..block });
+            }
+        }
+        let map = |block: &mut BlockId| block.id = block_map[block.id];
+        for block in &mut blocks {
+            for inst in &mut block.insts {
+                inst.map_phi_blocks(|block| reached[block.id].then(|| BlockId { id: block_map[block.id] }));
+            }
+            match &mut block.exit {
+                ExitInstruction::Branch { block } => map(block),
+                ExitInstruction::ConditionalBranch { block1, block2, .. } => {
+                    map(block1);
+                    map(block2);
+                }
+                ExitInstruction::Switch { targets, default, .. } => {
+                    targets.iter_mut().for_each(map);
+                    map(default);
+                }
+                ExitInstruction::Return => (),
+            }
+        }
+        self.blocks = blocks;
+        true
+    }
+    /// The indices of the blocks the block at `id` can branch to.
+    pub(crate) fn successors(&self, id: usize) -> Vec<usize> {
+        match &self.blocks[id].exit {
+            ExitInstruction::Branch { block } => vec![block.id],
+            ExitInstruction::ConditionalBranch { block1, block2, .. } => vec![block1.id, block2.id],
+            ExitInstruction::Switch { targets, default, .. } => {
+                targets.iter().chain([default]).map(|target| target.id).collect()
+            }
+            ExitInstruction::Return => vec![],
+        }
+    }
+    /// Measures the shape of the function's control flow. A branch to a
+    /// block that comes earlier in reverse postorder goes back round a loop.
+    pub fn cfg_metrics(&self) -> CfgMetrics {
+        let order = self.reverse_postorder();
+        let mut position = vec![0; self.blocks.len()];
+        for (index, &id) in order.iter().enumerate() {
+            position[id] = index;
+        }
+        // zero for blocks the entry block cannot reach
+        let mut depth = vec![0; self.blocks.len()];
+        let mut loop_header = vec![false; self.blocks.len()];
+        if let Some(entry) = depth.first_mut() {
+            *entry = 1;
+        }
+        for &id in &order {
+            if depth[id] == 0 {
+                continue
+            }
+            for successor in self.successors(id) {
+                if position[successor] <= position[id] {
+                    loop_header[successor] = true;
+                } else {
+                    depth[successor] = depth[successor].max(depth[id] + 1);
+                }
+            }
+        }
+        CfgMetrics {
+            blocks: self.blocks.len(),
+            instructions: self.blocks.iter().map(|block| block.insts.len()).sum(),
+            max_depth: depth.into_iter().max().unwrap_or(0),
+            loops: loop_header.into_iter().filter(|&header| header).count(),
+        }
+    }
+}
+
+/// The shape of a function's control flow graph.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct CfgMetrics {
+    pub blocks: usize,
+    pub instructions: usize,
+    /// The most blocks on a path from the entry block that never branches
+    /// back round a loop.
+    pub max_depth: usize,
+    /// The blocks that a loop branches back to.
+    pub loops: usize,
+}
+
+impl Instruction {
+    /// Every variable the instruction uses or defines.
+    pub fn vars(&self) -> Vec<Var> {
+        match self {
+            Instruction::AddInt { dest, a, b }
+            | Instruction::AddFloat { dest, a, b }
+            | Instruction::AndInt { dest, a, b }
+            | Instruction::OrInt { dest, a, b }
+            | Instruction::XorInt { dest, a, b }
+            | Instruction::ShiftLeft { dest, a, b }
+            | Instruction::ShiftRightArithmetic { dest, a, b }
+            | Instruction::ShiftRightLogical { dest, a, b }
+            | Instruction::SubInt { dest, a, b }
+            | Instruction::SubFloat { dest, a, b }
+            | Instruction::MulInt { dest, a, b }
+            | Instruction::MulFloat { dest, a, b }
+            | Instruction::DivInt { dest, a, b }
+            | Instruction::DivUInt { dest, a, b }
+            | Instruction::DivFloat { dest, a, b }
+            | Instruction::RemInt { dest, a, b }
+            | Instruction::RemUInt { dest, a, b }
+            | Instruction::RemFloat { dest, a, b }
+            | Instruction::EqInt { dest, a, b }
+            | Instruction::EqFloat { dest, a, b }
+            | Instruction::LessInt { dest, a, b }
+            | Instruction::LessUInt { dest, a, b }
+            | Instruction::LessFloat { dest, a, b }
+            | Instruction::LessEqInt { dest, a, b }
+            | Instruction::LessEqUInt { dest, a, b }
+            | Instruction::LessEqFloat { dest, a, b } => vec![*a, *b, *dest],
+            Instruction::ConstantInt { dest, .. } | Instruction::ConstantFloat { dest, .. } => vec![*dest],
+            Instruction::Convert { dest, src, .. } | Instruction::Copy { dest, src } => vec![*src, *dest],
+            Instruction::Select { cond, a, b, dest } => vec![*cond, *a, *b, *dest],
+            Instruction::Phi { dest, sources } => sources.iter().map(|&(_, var)| var).chain([*dest]).collect(),
+            Instruction::Call { args, returns, .. } | Instruction::CallBuiltin { args, returns, .. } => {
+                args.iter().chain(returns).copied().collect()
+            }
+            Instruction::Exit { code } => vec![*code],
+            Instruction::Abort | Instruction::Spawn { .. } => vec![],
+        }
+    }
+    /// The variables the instruction reads.
+    pub fn uses(&self) -> Vec<Var> {
+        match self {
+            Instruction::AddInt { a, b, .. }
+            | Instruction::AddFloat { a, b, .. }
+            | Instruction::AndInt { a, b, .. }
+            | Instruction::OrInt { a, b, .. }
+            | Instruction::XorInt { a, b, .. }
+            | Instruction::ShiftLeft { a, b, .. }
+            | Instruction::ShiftRightArithmetic { a, b, .. }
+            | Instruction::ShiftRightLogical { a, b, .. }
+            | Instruction::SubInt { a, b, .. }
+            | Instruction::SubFloat { a, b, .. }
+            | Instruction::MulInt { a, b, .. }
+            | Instruction::MulFloat { a, b, .. }
+            | Instruction::DivInt { a, b, .. }
+            | Instruction::DivUInt { a, b, .. }
+            | Instruction::DivFloat { a, b, .. }
+            | Instruction::RemInt { a, b, .. }
+            | Instruction::RemUInt { a, b, .. }
+            | Instruction::RemFloat { a, b, .. }
+            | Instruction::EqInt { a, b, .. }
+            | Instruction::EqFloat { a, b, .. }
+            | Instruction::LessInt { a, b, .. }
+            | Instruction::LessUInt { a, b, .. }
+            | Instruction::LessFloat { a, b, .. }
+            | Instruction::LessEqInt { a, b, .. }
+            | Instruction::LessEqUInt { a, b, .. }
+            | Instruction::LessEqFloat { a, b, .. } => vec![*a, *b],
+            Instruction::ConstantInt { .. } | Instruction::ConstantFloat { .. } => vec![],
+            Instruction::Convert { src, .. } | Instruction::Copy { src, .. } => vec![*src],
+            Instruction::Select { cond, a, b, .. } => vec![*cond, *a, *b],
+            Instruction::Phi { sources, .. } => sources.iter().map(|&(_, var)| var).collect(),
+            Instruction::Call { args, .. } | Instruction::CallBuiltin { args, .. } => args.clone(),
+            Instruction::Exit { code } => vec![*code],
+            Instruction::Abort | Instruction::Spawn { .. } => vec![],
+        }
+    }
+    /// The variables the instruction writes.
+    pub fn defs(&self) -> Vec<Var> {
+        match self {
+            Instruction::AddInt { dest, .. }
+            | Instruction::AddFloat { dest, .. }
+            | Instruction::AndInt { dest, .. }
+            | Instruction::OrInt { dest, .. }
+            | Instruction::XorInt { dest, .. }
+            | Instruction::ShiftLeft { dest, .. }
+            | Instruction::ShiftRightArithmetic { dest, .. }
+            | Instruction::ShiftRightLogical { dest, .. }
+            | Instruction::SubInt { dest, .. }
+            | Instruction::SubFloat { dest, .. }
+            | Instruction::MulInt { dest, .. }
+            | Instruction::MulFloat { dest, .. }
+            | Instruction::DivInt { dest, .. }
+            | Instruction::DivUInt { dest, .. }
+            | Instruction::DivFloat { dest, .. }
+            | Instruction::RemInt { dest, .. }
+            | Instruction::RemUInt { dest, .. }
+            | Instruction::RemFloat { dest, .. }
+            | Instruction::EqInt { dest, .. }
+            | Instruction::EqFloat { dest, .. }
+            | Instruction::LessInt { dest, .. }
+            | Instruction::LessUInt { dest, .. }
+            | Instruction::LessFloat { dest, .. }
+            | Instruction::LessEqInt { dest, .. }
+            | Instruction::LessEqUInt { dest, .. }
+            | Instruction::LessEqFloat { dest, .. }
+            | Instruction::ConstantInt { dest, .. }
+            | Instruction::ConstantFloat { dest, .. }
+            | Instruction::Convert { dest, .. }
+            | Instruction::Copy { dest, .. }
+            | Instruction::Select { dest, .. }
+            | Instruction::Phi { dest, .. } => vec![*dest],
+            Instruction::Call { returns, .. } | Instruction::CallBuiltin { returns, .. } => returns.clone(),
+            Instruction::Exit { .. } | Instruction::Abort | Instruction::Spawn { .. } => vec![],
+        }
+    }
+    /// Passes each variable the instruction writes through `rename`.
+    pub(crate) fn map_defs(&mut self, rename: &mut impl FnMut(Var) -> Var) {
+        match self {
+            Instruction::AddInt { dest, .. }
+            | Instruction::AddFloat { dest, .. }
+            | Instruction::AndInt { dest, .. }
+            | Instruction::OrInt { dest, .. }
+            | Instruction::XorInt { dest, .. }
+            | Instruction::ShiftLeft { dest, .. }
+            | Instruction::ShiftRightArithmetic { dest, .. }
+            | Instruction::ShiftRightLogical { dest, .. }
+            | Instruction::SubInt { dest, .. }
+            | Instruction::SubFloat { dest, .. }
+            | Instruction::MulInt { dest, .. }
+            | Instruction::MulFloat { dest, .. }
+            | Instruction::DivInt { dest, .. }
+            | Instruction::DivUInt { dest, .. }
+            | Instruction::DivFloat { dest, .. }
+            | Instruction::RemInt { dest, .. }
+            | Instruction::RemUInt { dest, .. }
+            | Instruction::RemFloat { dest, .. }
+            | Instruction::EqInt { dest, .. }
+            | Instruction::EqFloat { dest, .. }
+            | Instruction::LessInt { dest, .. }
+            | Instruction::LessUInt { dest, .. }
+            | Instruction::LessFloat { dest, .. }
+            | Instruction::LessEqInt { dest, .. }
+            | Instruction::LessEqUInt { dest, .. }
+            | Instruction::LessEqFloat { dest, .. }
+            | Instruction::ConstantInt { dest, .. }
+            | Instruction::ConstantFloat { dest, .. }
+            | Instruction::Convert { dest, .. }
+            | Instruction::Copy { dest, .. }
+            | Instruction::Select { dest, .. }
+            | Instruction::Phi { dest, .. } => *dest = rename(*dest),
+            Instruction::Call { returns, .. } | Instruction::CallBuiltin { returns, .. } => for var in returns {
+                *var = rename(*var);
+            }
+            Instruction::Exit { .. } | Instruction::Abort | Instruction::Spawn { .. } => (),
+        }
+    }
+    /// Passes each variable the instruction reads through `rename`.
+    pub(crate) fn map_uses(&mut self, rename: &mut impl FnMut(Var) -> Var) {
+        match self {
+            Instruction::AddInt { a, b, .. }
+            | Instruction::AddFloat { a, b, .. }
+            | Instruction::AndInt { a, b, .. }
+            | Instruction::OrInt { a, b, .. }
+            | Instruction::XorInt { a, b, .. }
+            | Instruction::ShiftLeft { a, b, .. }
+            | Instruction::ShiftRightArithmetic { a, b, .. }
+            | Instruction::ShiftRightLogical { a, b, .. }
+            | Instruction::SubInt { a, b, .. }
+            | Instruction::SubFloat { a, b, .. }
+            | Instruction::MulInt { a, b, .. }
+            | Instruction::MulFloat { a, b, .. }
+            | Instruction::DivInt { a, b, .. }
+            | Instruction::DivUInt { a, b, .. }
+            | Instruction::DivFloat { a, b, .. }
+            | Instruction::RemInt { a, b, .. }
+            | Instruction::RemUInt { a, b, .. }
+            | Instruction::RemFloat { a, b, .. }
+            | Instruction::EqInt { a, b, .. }
+            | Instruction::EqFloat { a, b, .. }
+            | Instruction::LessInt { a, b, .. }
+            | Instruction::LessUInt { a, b, .. }
+            | Instruction::LessFloat { a, b, .. }
+            | Instruction::LessEqInt { a, b, .. }
+            | Instruction::LessEqUInt { a, b, .. }
+            | Instruction::LessEqFloat { a, b, .. } => {
+                *a = rename(*a);
+                *b = rename(*b);
+            }
+            Instruction::ConstantInt { .. } | Instruction::ConstantFloat { .. } => (),
+            Instruction::Convert { src, .. } | Instruction::Copy { src, .. } => *src = rename(*src),
+            Instruction::Select { cond, a, b, .. } => {
+                *cond = rename(*cond);
+                *a = rename(*a);
+                *b = rename(*b);
+            }
+            Instruction::Phi { sources, .. } => for (_, var) in sources {
+                *var = rename(*var);
+            }
+            Instruction::Call { args, .. } | Instruction::CallBuiltin { args, .. } => for arg in args {
+                *arg = rename(*arg);
+            }
+            Instruction::Exit { code } => *code = rename(*code),
+            Instruction::Abort | Instruction::Spawn { .. } => (),
+        }
+    }
+    /// Passes the blocks a phi takes its sources from through `map`,
+    /// dropping the sources it gives `None` for.
+    pub(crate) fn map_phi_blocks(&mut self, mut map: impl FnMut(BlockId) -> Option<BlockId>) {
+        if let Instruction::Phi { sources, .. } = self {
+            sources.retain_mut(|(block, _)| match map(*block) {
+                Some(mapped) => {
+                    *block = mapped;
+                    true
+                }
+                None => false,
+            });
+        }
+    }
+    /// A copy with its variables passed through `rename`, uses before
+    /// definitions, and its callee through `function_map`.
+    fn renamed(&self, rename: &mut impl FnMut(Var) -> Var, function_map: &[usize]) -> Instruction {
+        let mut inst = self.clone();
+        match &mut inst {
+            Instruction::AddInt { dest, a, b }
+            | Instruction::AddFloat { dest, a, b }
+            | Instruction::AndInt { dest, a, b }
+            | Instruction::OrInt { dest, a, b }
+            | Instruction::XorInt { dest, a, b }
+            | Instruction::ShiftLeft { dest, a, b }
+            | Instruction::ShiftRightArithmetic { dest, a, b }
+            | Instruction::ShiftRightLogical { dest, a, b }
+            | Instruction::SubInt { dest, a, b }
+            | Instruction::SubFloat { dest, a, b }
+            | Instruction::MulInt { dest, a, b }
+            | Instruction::MulFloat { dest, a, b }
+            | Instruction::DivInt { dest, a, b }
+            | Instruction::DivUInt { dest, a, b }
+            | Instruction::DivFloat { dest, a, b }
+            | Instruction::RemInt { dest, a, b }
+            | Instruction::RemUInt { dest, a, b }
+            | Instruction::RemFloat { dest, a, b }
+            | Instruction::EqInt { dest, a, b }
+            | Instruction::EqFloat { dest, a, b }
+            | Instruction::LessInt { dest, a, b }
+            | Instruction::LessUInt { dest, a, b }
+            | Instruction::LessFloat { dest, a, b }
+            | Instruction::LessEqInt { dest, a, b }
+            | Instruction::LessEqUInt { dest, a, b }
+            | Instruction::LessEqFloat { dest, a, b } => {
+                *a = rename(*a);
+                *b = rename(*b);
+                *dest = rename(*dest);
+            }
+            Instruction::ConstantInt { dest, .. } | Instruction::ConstantFloat { dest, .. } => *dest = rename(*dest),
+            Instruction::Convert { dest, src, .. } | Instruction::Copy { dest, src } => {
+                *src = rename(*src);
+                *dest = rename(*dest);
+            }
+            Instruction::Select { cond, a, b, dest } => {
+                *cond = rename(*cond);
+                *a = rename(*a);
+                *b = rename(*b);
+                *dest = rename(*dest);
+            }
+            Instruction::Phi { dest, sources } => {
+                sources.iter_mut().for_each(|(_, var)| *var = rename(*var));
+                *dest = rename(*dest);
+            }
+            Instruction::Call { function, args, returns } => {
+                function.id = function_map[function.id];
+                args.iter_mut().chain(returns.iter_mut()).for_each(|var| *var = rename(*var));
+            }
+            Instruction::CallBuiltin { args, returns, .. } => {
+                args.iter_mut().chain(returns.iter_mut()).for_each(|var| *var = rename(*var));
+            }
+            Instruction::Exit { code } => *code = rename(*code),
+            Instruction::Abort => (),
+            Instruction::Spawn { function } => function.id = function_map[function.id],
+        }
+        inst
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Instruction::AddInt { dest, a, b } => {
+                write!(f, "r{} = r{} + r{}", dest.id, a.id, b.id)?
+            }
+            Instruction::AddFloat { dest, a, b } => {
+                write!(f, "r{} = r{} +. r{}", dest.id, a.id, b.id)?
+            }
+            Instruction::AndInt { dest, a, b } => {
+                write!(f, "r{} = r{} & r{}", dest.id, a.id, b.id)?
+            }
+            Instruction::OrInt { dest, a, b } => {
+                write!(f, "r{} = r{} | r{}", dest.id, a.id, b.id)?
+            }
+            Instruction::XorInt { dest, a, b } => {
+                write!(f, "r{} = r{} ^ r{}", dest.id, a.id, b.id)?
+            }
+            Instruction::ShiftLeft { dest, a, b } => {
+                write!(f, "r{} = r{} << r{}", dest.id, a.id, b.id)?
+            }
+            Instruction::ShiftRightArithmetic { dest, a, b } => {
+                write!(f, "r{} = r{} >> r{}", dest.id, a.id, b.id)?
+            }
+            Instruction::ShiftRightLogical { dest, a, b } => {
+                write!(f, "r{} = r{} >>> r{}", dest.id, a.id, b.id)?
+            }
+            Instruction::SubInt { dest, a, b } => {
+                write!(f, "r{} = r{} - r{}", dest.id, a.id, b.id)?
+            }
+            Instruction::SubFloat { dest, a, b } => {
+                write!(f, "r{} = r{} -. r{}", dest.id, a.id, b.id)?
+            }
+            Instruction::MulInt { dest, a, b } => {
+                write!(f, "r{} = r{} * r{}", dest.id, a.id, b.id)?
+            }
+            Instruction::MulFloat { dest, a, b } => {
+                write!(f, "r{} = r{} *. r{}", dest.id, a.id, b.id)?
+            }
+            Instruction::DivInt { dest, a, b } => {
+                write!(f, "r{} = r{} / r{}", dest.id, a.id, b.id)?
+            }
+            Instruction::DivUInt { dest, a, b } => {
+                write!(f, "r{} = r{} /u r{}", dest.id, a.id, b.id)?
+            }
+            Instruction::DivFloat { dest, a, b } => {
+                write!(f, "r{} = r{} /. r{}", dest.id, a.id, b.id)?
+            }
+            Instruction::RemInt { dest, a, b } => {
+                write!(f, "r{} = r{} % r{}", dest.id, a.id, b.id)?
+            }
+            Instruction::RemUInt { dest, a, b } => {
+                write!(f, "r{} = r{} %u r{}", dest.id, a.id, b.id)?
+            }
+            Instruction::RemFloat { dest, a, b } => {
+                write!(f, "r{} = r{} %. r{}", dest.id, a.id, b.id)?
+            }
+            Instruction::EqInt { dest, a, b } => {
+                write!(f, "r{} = r{} == r{}", dest.id, a.id, b.id)?
+            }
+            Instruction::EqFloat { dest, a, b } => {
+                write!(f, "r{} = r{} ==. r{}", dest.id, a.id, b.id)?
+            }
+            Instruction::LessInt { dest, a, b } => {
+                write!(f, "r{} = r{} < r{}", dest.id, a.id, b.id)?
+            }
+            Instruction::LessUInt { dest, a, b } => {
+                write!(f, "r{} = r{} <u r{}", dest.id, a.id, b.id)?
+            }
+            Instruction::LessFloat { dest, a, b } => {
+                write!(f, "r{} = r{} <. r{}", dest.id, a.id, b.id)?
+            }
+            Instruction::LessEqInt { dest, a, b } => {
+                write!(f, "r{} = r{} <= r{}", dest.id, a.id, b.id)?
+            }
+            Instruction::LessEqUInt { dest, a, b } => {
+                write!(f, "r{} = r{} <=u r{}", dest.id, a.id, b.id)?
+            }
+            Instruction::LessEqFloat { dest, a, b } => {
+                write!(f, "r{} = r{} <=. r{}", dest.id, a.id, b.id)?
+            }
+            Instruction::ConstantInt { dest, constant } => {
+                write!(f, "r{} = {}", dest.id, constant)?
+            }
+            Instruction::ConstantFloat { dest, constant } => {
+                write!(f, "r{} = {:?}", dest.id, constant)?
+            }
+            Instruction::Convert { dest, src, conversion } => {
+                write!(f, "r{} = {} r{}", dest.id, match conversion {
+                    Conversion::IntToFloat => "itof",
+                    Conversion::UIntToFloat => "utof",
+                    Conversion::FloatToInt => "ftoi",
+                    Conversion::FloatToUInt => "ftou",
+                }, src.id)?
+            }
+            Instruction::Copy { dest, src } => {
+                write!(f, "r{} = r{}", dest.id, src.id)?
+            }
+            Instruction::Select { dest, cond, a, b } => {
+                write!(f, "r{} = select r{}, r{}, r{}", dest.id, cond.id, a.id, b.id)?
+            }
+            Instruction::Phi { dest, sources } => {
+                let sources: Vec<String> = sources.iter().map(|(block, var)| format!("b{}: r{}", block.id, var.id)).collect();
+                write!(f, "r{} = phi [{}]", dest.id, sources.join(", "))?
+            }
+            Instruction::Call { function, args, returns } => {
+                let mut iter = returns.iter();
+                if let Some(var) = iter.next() {
+                    write!(f, "r{}", var.id)?;
+                    for var in iter {
+                        write!(f, ", r{}", var.id)?;
+                    }
+                    write!(f, " = ")?;
+                }
+                write!(f, "call f{} (", function.id)?;
+                let mut iter = args.iter();
+                if let Some(var) = iter.next() {
+                    write!(f, "r{}", var.id)?;
+                    for var in iter {
+                        write!(f, ", r{}", var.id)?;
+                    }
+                }
+                write!(f, ")")?;
+            }
+            Instruction::CallBuiltin { intrinsic, args, returns } => {
+                let mut iter = returns.iter();
+                if let Some(var) = iter.next() {
+                    write!(f, "r{}", var.id)?;
+                    for var in iter {
+                        write!(f, ", r{}", var.id)?;
+                    }
+                    write!(f, " = ")?;
+                }
+                write!(f, "call {} (", intrinsic.name())?;
+                let mut iter = args.iter();
+                if let Some(var) = iter.next() {
+                    write!(f, "r{}", var.id)?;
+                    for var in iter {
+                        write!(f, ", r{}", var.id)?;
+                    }
+                }
+                write!(f, ")")?;
+            }
+            Instruction::Exit { code } => {
+                write!(f, "exit r{}", code.id)?
+            }
+            Instruction::Abort => {
+                write!(f, "abort")?
+            }
+            Instruction::Spawn { function } => {
+                write!(f, "spawn f{}", function.id)?
+            }
+        }
+        Ok(())
+    }
+}
+
+impl ExitInstruction {
+    /// Passes each block the exit can go to through `map`.
+    pub(crate) fn map_targets(&mut self, mut map: impl FnMut(BlockId) -> BlockId) {
+        match self {
+            ExitInstruction::Branch { block } => *block = map(*block),
+            ExitInstruction::ConditionalBranch { block1, block2, .. } => {
+                *block1 = map(*block1);
+                *block2 = map(*block2);
+            }
+            ExitInstruction::Switch { targets, default, .. } => {
+                for target in targets {
+                    *target = map(*target);
+                }
+                *default = map(*default);
+            }
+            ExitInstruction::Return => (),
+        }
+    }
+}
+
+impl fmt::Display for ExitInstruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ExitInstruction::Return => {
+                write!(f, "return")?
+            }
+            ExitInstruction::ConditionalBranch { cond, block1, block2 } => {
+                write!(f, "if r{} goto b{} else goto b{}", cond.id, block1.id, block2.id)?;
+            }
+            ExitInstruction::Switch { scrutinee, targets, default } => {
+                write!(f, "switch r{} [", scrutinee.id)?;
+                for (i, target) in targets.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "b{}", target.id)?;
+                }
+                write!(f, "] else goto b{}", default.id)?;
+            }
+            ExitInstruction::Branch { block } => {
+                write!(f, "goto b{}", block.id)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// How `Program::display` lays out a program. The default prints what
+/// `Display` does.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PrintOptions<'a> {
+    /// Follow each destination with the kind of value it holds, `int` or
+    /// `float`, where the instructions say.
+    pub types: bool,
+    /// Pad destinations so that the `=` of a function's instructions line
+    /// up.
+    pub align: bool,
+    /// The source the program was compiled from, whose text is shown with
+    /// the number of each line.
+    pub source: Option<&'a str>,
+}
+
+/// A program printed with `PrintOptions`, from `Program::display`.
+pub struct Printed<'a> {
+    program: &'a Program,
+    options: PrintOptions<'a>,
+    /// The one function to print, rather than all of them.
+    function: Option<FunctionId>,
+}
+
+impl Printed<'_> {
+    /// Prints only the function `function_id`.
+    pub fn function(self, function_id: FunctionId) -> Self {
+        Printed { function: Some(function_id), ..self }
+    }
+}
+
+impl Program {
+    pub fn display<'a>(&'a self, options: PrintOptions<'a>) -> Printed<'a> {
+        Printed { program: self, options, function: None }
+    }
+    /// Whether each variable holds an `int` or a `float`, where that
+    /// follows from what writes it. Integers also stand for `uint` and
+    /// `bool` values, and what builtins return is not known.
+    fn kinds(&self) -> Vec<Option<&'static str>> {
+        let mut kinds = vec![None; self.variable_count];
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for function in &self.functions {
+                for inst in function.blocks.iter().flat_map(|block| &block.insts) {
+                    let found: Vec<(Var, Option<&'static str>)> = match inst {
+                        Instruction::AddFloat { dest, .. } | Instruction::SubFloat { dest, .. } | Instruction::MulFloat { dest, .. }
+                        | Instruction::DivFloat { dest, .. } | Instruction::RemFloat { dest, .. } | Instruction::ConstantFloat { dest, .. }
+                        | Instruction::Convert { dest, conversion: Conversion::IntToFloat | Conversion::UIntToFloat, .. } => vec![(*dest, Some("float"))],
+                        Instruction::Copy { dest, src } => vec![(*dest, kinds[src.id])],
+                        Instruction::Select { dest, a, b, .. } => vec![(*dest, kinds[a.id].or(kinds[b.id]))],
+                        Instruction::Phi { dest, sources } => vec![(*dest, sources.iter().find_map(|(_, var)| kinds[var.id]))],
                         Instruction::Call { function, args, returns } => {
-                            let mut iter = returns.iter();
-                            if let Some(var) = iter.next() {
-                                write!(f, "r{}", var.id)?;
-                                for var in iter {
-                                    write!(f, ", r{}", var.id)?;
-                                }
-                                write!(f, " = ")?;
-                            }
-                            write!(f, "call f{} (", function.id)?;
-                            let mut iter = args.iter();
-                            if let Some(var) = iter.next() {
-                                write!(f, "r{}", var.id)?;
-                                for var in iter {
-                                    write!(f, ", r{}", var.id)?;
-                                }
-                            }
-                            writeln!(f, ")")?;
+                            let callee = &self.functions[function.id];
+                            let params = callee.params.iter().zip(args).map(|(&param, arg)| (param, kinds[arg.id]));
+                            let returns = returns.iter().zip(&callee.returns).map(|(&var, ret)| (var, kinds[ret.id]));
+                            params.chain(returns).collect()
+                        }
+                        Instruction::CallBuiltin { .. } => Vec::new(),
+                        inst => inst.defs().into_iter().map(|dest| (dest, Some("int"))).collect(),
+                    };
+                    for (var, kind) in found {
+                        if kinds[var.id].is_none() && kind.is_some() {
+                            kinds[var.id] = kind;
+                            changed = true;
                         }
                     }
                 }
-                write!(f, "        ")?;
-                match block.exit {
-                    ExitInstruction::Return => {
-                        writeln!(f, "return")?
-                    }
-                    ExitInstruction::ConditionalBranch { cond, block1, block2 } => {
-                        writeln!(f, "if r{} goto b{} else goto b{}", cond.id, block1.id, block2.id)?;
+            }
+        }
+        kinds
+    }
+}
+
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.display(PrintOptions::default()).fmt(f)
+    }
+}
+
+impl fmt::Display for Printed<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let options = self.options;
+        let kinds = if options.types { self.program.kinds() } else { Vec::new() };
+        let var = |var: &Var| match kinds.get(var.id).copied().flatten() {
+            Some(kind) => format!("r{}: {}", var.id, kind),
+            None => format!("r{}", var.id),
+        };
+        let vars = |vars: &[Var]| vars.iter().map(var).collect::<Vec<_>>().join(", ");
+        let functions = self.program.functions.iter().enumerate()
+            .filter(|(function_id, _)| self.function.is_none_or(|only| only.id == *function_id));
+        for (function_id, function) in functions {
+            write!(f, "f{} ({})", function_id, vars(&function.params))?;
+            if !function.returns.is_empty() {
+                write!(f, " -> {}", vars(&function.returns))?;
+            }
+            if function.effects.is_pure() {
+                write!(f, " ; pure")?;
+            } else if function.effects.io {
+                write!(f, " ; io")?;
+            }
+            writeln!(f)?;
+            // the destinations of each instruction, and the rest of it
+            let insts: Vec<Vec<(String, String)>> = function.blocks.iter().map(|block| block.insts.iter().map(|inst| {
+                let text = inst.to_string();
+                match text.split_once(" = ") {
+                    Some((_, rest)) if !inst.defs().is_empty() => (vars(&inst.defs()), rest.to_string()),
+                    _ => (String::new(), text),
+                }
+            }).collect()).collect();
+            let width = if options.align {
+                insts.iter().flatten().map(|(dests, _)| dests.len()).max().unwrap_or(0)
+            } else {
+                0
+            };
+            for (block_id, (block, insts)) in function.blocks.iter().zip(insts).enumerate() {
+                if block_id == 0 {
+                    writeln!(f, "    main:")?;
+                } else {
+                    writeln!(f, "    b{}:", block_id)?;
+                }
+                let mut line = None;
+                for ((dests, rest), &inst_line) in insts.into_iter().zip(block.lines.iter()) {
+                    if line != Some(inst_line) {
+                        let text = options.source
+                            .and_then(|source| source.lines().nth((inst_line as usize).checked_sub(1)?))
+                            .map(str::trim)
+                            .filter(|text| !text.is_empty());
+                        match text {
+                            Some(text) => writeln!(f, "        ; line {}: {}", inst_line, text)?,
+                            None => writeln!(f, "        ; line {}", inst_line)?,
+                        }
+                        line = Some(inst_line);
                     }
-                    ExitInstruction::Branch { block } => {
-                        writeln!(f, "goto b{}", block.id)?;
+                    if dests.is_empty() {
+                        writeln!(f, "        {}", rest)?;
+                    } else {
+                        writeln!(f, "        {:width$} = {}", dests, rest, width = width)?;
                     }
                 }
-                writeln!(f, "")?;
+                writeln!(f, "        {}", block.exit)?;
+                writeln!(f)?;
             }
         }
         Ok(())
     }
 }
 
+impl FunctionId {
+    pub(crate) fn new(id: usize) -> FunctionId {
+        FunctionId { id }
+    }
+    pub fn get_id(&self) -> usize {
+        self.id
+    }
+}
+
 impl Var {
+    pub(crate) fn new(id: usize) -> Var {
+        Var { id }
+    }
     pub fn get_id(&self) -> usize {
         self.id
     }
@@ -0,0 +1,246 @@
+//! Reads instructions written as the IR dump prints them, with names in
+//! place of register numbers, for splicing hand-written IR into a function.
+//! Every definition of a name gets a slot of its own, so the instructions
+//! are in SSA form over the slots whatever the names are.
+
+use crate::hir::CastType;
+use crate::ir::{Conversion, Instruction, Var};
+
+/// Parsed instructions whose variables are indices into `slots`.
+#[derive(Debug)]
+pub struct InlineIr<'a> {
+    pub slots: Vec<Slot<'a>>,
+    pub instructions: Vec<Line<'a>>,
+}
+
+#[derive(Debug)]
+pub struct Slot<'a> {
+    pub name: &'a str,
+    /// Whether the slot is a name read before the instructions define it,
+    /// whose value comes from outside.
+    pub input: bool,
+}
+
+#[derive(Debug)]
+pub struct Line<'a> {
+    pub source: &'a str,
+    /// The number of lines before this one in the text.
+    pub offset: u32,
+    pub instruction: Instruction,
+    /// The slot the line defines and the type of its value.
+    pub defines: Option<(Var, SlotType)>,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub enum SlotType {
+    Is(CastType),
+    /// The same type as the value in another slot.
+    SameAs(Var),
+}
+
+#[derive(Debug)]
+pub struct IrParseError<'a> {
+    pub source: &'a str,
+    pub message: String,
+}
+
+impl<'a> InlineIr<'a> {
+    /// The slot each name is left in, in the order the names first appear.
+    pub fn bindings(&self) -> Vec<(&'a str, Var)> {
+        let mut bindings: Vec<(&'a str, Var)> = Vec::new();
+        for (index, slot) in self.slots.iter().enumerate() {
+            match bindings.iter_mut().find(|(name, _)| *name == slot.name) {
+                Some(binding) => binding.1 = Var::new(index),
+                None => bindings.push((slot.name, Var::new(index))),
+            }
+        }
+        bindings
+    }
+}
+
+pub fn parse(text: &str) -> Result<InlineIr<'_>, IrParseError<'_>> {
+    let mut parser = Parser { slots: Vec::new() };
+    let mut instructions = Vec::new();
+    for (offset, line) in text.lines().enumerate() {
+        let source = line.split(';').next().unwrap_or("").trim();
+        if source.is_empty() {
+            continue
+        }
+        let (instruction, defines) = parser.instruction(source).map_err(|message| IrParseError { source, message })?;
+        instructions.push(Line { source, offset: offset as u32, instruction, defines });
+    }
+    Ok(InlineIr { slots: parser.slots, instructions })
+}
+
+struct Parser<'a> {
+    slots: Vec<Slot<'a>>,
+}
+
+impl<'a> Parser<'a> {
+    fn instruction(&mut self, source: &'a str) -> Result<(Instruction, Option<(Var, SlotType)>), String> {
+        let (dest, value) = match source.split_once('=') {
+            Some((dest, value)) => (Some(dest.trim()), value.trim()),
+            None => (None, source),
+        };
+        let words: Vec<&str> = value.split_whitespace().collect();
+        if words.first() == Some(&"call") {
+            return Err("calls cannot be written in inline IR".to_string())
+        }
+        let dest = dest.map(name).transpose()?;
+        let (instruction, ty) = match (dest, words.as_slice()) {
+            (None, ["abort"]) => (Instruction::Abort, None),
+            (None, ["exit", code]) => (Instruction::Exit { code: self.read(code)? }, None),
+            (Some(_), [literal]) if literal.starts_with(|ch: char| ch.is_ascii_digit() || ch == '-') => {
+                let dest = Var::new(0);
+                if literal.contains('.') {
+                    let constant = literal.parse().map_err(|_| format!("`{}` is not a float", literal))?;
+                    (Instruction::ConstantFloat { dest, constant }, Some(SlotType::Is(CastType::Float)))
+                } else {
+                    let constant = literal.parse().map_err(|_| format!("`{}` is not an int", literal))?;
+                    (Instruction::ConstantInt { dest, constant }, Some(SlotType::Is(CastType::Int)))
+                }
+            }
+            (Some(_), [src]) => {
+                let src = self.read(src)?;
+                (Instruction::Copy { dest: Var::new(0), src }, Some(SlotType::SameAs(src)))
+            }
+            (Some(_), [conversion, src]) => {
+                let (conversion, ty) = match *conversion {
+                    "itof" => (Conversion::IntToFloat, CastType::Float),
+                    "utof" => (Conversion::UIntToFloat, CastType::Float),
+                    "ftoi" => (Conversion::FloatToInt, CastType::Int),
+                    "ftou" => (Conversion::FloatToUInt, CastType::UInt),
+                    _ => return Err(format!("unknown conversion `{}`", conversion)),
+                };
+                (Instruction::Convert { dest: Var::new(0), src: self.read(src)?, conversion }, Some(SlotType::Is(ty)))
+            }
+            (Some(_), ["select", ..]) => {
+                let operands: Vec<&str> = value["select".len()..].split(',').map(str::trim).collect();
+                match operands.as_slice() {
+                    [cond, a, b] => {
+                        let (cond, a, b) = (self.read(cond)?, self.read(a)?, self.read(b)?);
+                        (Instruction::Select { dest: Var::new(0), cond, a, b }, Some(SlotType::SameAs(a)))
+                    }
+                    _ => return Err("`select` takes a condition and two values".to_string()),
+                }
+            }
+            (Some(_), [a, op, b]) => {
+                let binary: fn(Var, Var, Var) -> Instruction = match *op {
+                    "+" => |dest, a, b| Instruction::AddInt { dest, a, b },
+                    "+." => |dest, a, b| Instruction::AddFloat { dest, a, b },
+                    "&" => |dest, a, b| Instruction::AndInt { dest, a, b },
+                    "|" => |dest, a, b| Instruction::OrInt { dest, a, b },
+                    "^" => |dest, a, b| Instruction::XorInt { dest, a, b },
+                    "<<" => |dest, a, b| Instruction::ShiftLeft { dest, a, b },
+                    ">>" => |dest, a, b| Instruction::ShiftRightArithmetic { dest, a, b },
+                    ">>>" => |dest, a, b| Instruction::ShiftRightLogical { dest, a, b },
+                    "-" => |dest, a, b| Instruction::SubInt { dest, a, b },
+                    "-." => |dest, a, b| Instruction::SubFloat { dest, a, b },
+                    "*" => |dest, a, b| Instruction::MulInt { dest, a, b },
+                    "*." => |dest, a, b| Instruction::MulFloat { dest, a, b },
+                    "/" => |dest, a, b| Instruction::DivInt { dest, a, b },
+                    "/u" => |dest, a, b| Instruction::DivUInt { dest, a, b },
+                    "/." => |dest, a, b| Instruction::DivFloat { dest, a, b },
+                    "%" => |dest, a, b| Instruction::RemInt { dest, a, b },
+                    "%u" => |dest, a, b| Instruction::RemUInt { dest, a, b },
+                    "%." => |dest, a, b| Instruction::RemFloat { dest, a, b },
+                    "==" => |dest, a, b| Instruction::EqInt { dest, a, b },
+                    "==." => |dest, a, b| Instruction::EqFloat { dest, a, b },
+                    "<" => |dest, a, b| Instruction::LessInt { dest, a, b },
+                    "<u" => |dest, a, b| Instruction::LessUInt { dest, a, b },
+                    "<." => |dest, a, b| Instruction::LessFloat { dest, a, b },
+                    "<=" => |dest, a, b| Instruction::LessEqInt { dest, a, b },
+                    "<=u" => |dest, a, b| Instruction::LessEqUInt { dest, a, b },
+                    "<=." => |dest, a, b| Instruction::LessEqFloat { dest, a, b },
+                    _ => return Err(format!("unknown operator `{}`", op)),
+                };
+                let a = self.read(a)?;
+                let instruction = binary(Var::new(0), a, self.read(b)?);
+                let ty = match instruction {
+                    Instruction::EqInt { .. } | Instruction::EqFloat { .. }
+                        | Instruction::LessInt { .. } | Instruction::LessUInt { .. } | Instruction::LessFloat { .. }
+                        | Instruction::LessEqInt { .. } | Instruction::LessEqUInt { .. }
+                        | Instruction::LessEqFloat { .. } => SlotType::Is(CastType::Bool),
+                    Instruction::AddFloat { .. } | Instruction::SubFloat { .. } | Instruction::MulFloat { .. }
+                        | Instruction::DivFloat { .. } | Instruction::RemFloat { .. } => SlotType::Is(CastType::Float),
+                    _ => SlotType::SameAs(a),
+                };
+                (instruction, Some(ty))
+            }
+            _ => return Err("expected an instruction".to_string()),
+        };
+        // the destination is defined after the operands are read, so that
+        // `x = x + y` reads the old `x`
+        Ok(match (dest, ty) {
+            (Some(dest), Some(ty)) => {
+                let dest = self.define(dest);
+                (with_dest(instruction, dest), Some((dest, ty)))
+            }
+            _ => (instruction, None),
+        })
+    }
+    /// The slot `name` is in now, or a new input slot if it has none yet.
+    fn read(&mut self, name: &'a str) -> Result<Var, String> {
+        let name = self::name(name)?;
+        match self.slots.iter().rposition(|slot| slot.name == name) {
+            Some(index) => Ok(Var::new(index)),
+            None => {
+                self.slots.push(Slot { name, input: true });
+                Ok(Var::new(self.slots.len() - 1))
+            }
+        }
+    }
+    fn define(&mut self, name: &'a str) -> Var {
+        self.slots.push(Slot { name, input: false });
+        Var::new(self.slots.len() - 1)
+    }
+}
+
+fn name(text: &str) -> Result<&str, String> {
+    let valid = text.starts_with(|ch: char| ch.is_alphabetic() || ch == '_')
+        && text.chars().all(|ch| ch.is_alphanumeric() || ch == '_');
+    if valid {
+        Ok(text)
+    } else {
+        Err(format!("expected a name, found `{}`", text))
+    }
+}
+
+fn with_dest(mut instruction: Instruction, var: Var) -> Instruction {
+    match &mut instruction {
+        Instruction::AddInt { dest, .. }
+        | Instruction::AddFloat { dest, .. }
+        | Instruction::AndInt { dest, .. }
+        | Instruction::OrInt { dest, .. }
+        | Instruction::XorInt { dest, .. }
+        | Instruction::ShiftLeft { dest, .. }
+        | Instruction::ShiftRightArithmetic { dest, .. }
+        | Instruction::ShiftRightLogical { dest, .. }
+        | Instruction::SubInt { dest, .. }
+        | Instruction::SubFloat { dest, .. }
+        | Instruction::MulInt { dest, .. }
+        | Instruction::MulFloat { dest, .. }
+        | Instruction::DivInt { dest, .. }
+        | Instruction::DivUInt { dest, .. }
+        | Instruction::DivFloat { dest, .. }
+        | Instruction::RemInt { dest, .. }
+        | Instruction::RemUInt { dest, .. }
+        | Instruction::RemFloat { dest, .. }
+        | Instruction::EqInt { dest, .. }
+        | Instruction::EqFloat { dest, .. }
+        | Instruction::LessInt { dest, .. }
+        | Instruction::LessUInt { dest, .. }
+        | Instruction::LessFloat { dest, .. }
+        | Instruction::LessEqInt { dest, .. }
+        | Instruction::LessEqUInt { dest, .. }
+        | Instruction::LessEqFloat { dest, .. }
+        | Instruction::ConstantInt { dest, .. }
+        | Instruction::ConstantFloat { dest, .. }
+        | Instruction::Convert { dest, .. }
+        | Instruction::Copy { dest, .. }
+        | Instruction::Select { dest, .. }
+        | Instruction::Phi { dest, .. } => *dest = var,
+        Instruction::Call { .. } | Instruction::CallBuiltin { .. } | Instruction::Exit { .. } | Instruction::Abort | Instruction::Spawn { .. } => (),
+    }
+    instruction
+}
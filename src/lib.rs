@@ -0,0 +1,155 @@
+#[macro_use]
+pub mod log;
+pub mod position;
+pub mod parser;
+pub mod ir;
+pub mod ir_parser;
+pub mod ast;
+pub mod hir;
+pub mod thir;
+pub mod report;
+pub mod compiler;
+pub mod callgraph;
+pub mod consteval;
+pub mod capture;
+pub mod scope;
+pub mod types;
+pub mod execute;
+pub mod heap;
+pub mod ops;
+pub mod builtins;
+pub mod diagnostics;
+pub mod error_codes;
+pub mod session;
+pub mod source_map;
+pub mod bytecode;
+pub mod codegen;
+pub mod optimize;
+pub mod diff;
+pub mod ssa;
+pub mod unroll;
+pub mod cache;
+pub mod cancel;
+pub mod clock;
+pub mod replay;
+pub mod playground;
+pub mod eval;
+#[cfg(feature = "capi")]
+pub mod capi;
+
+use cancel::CancellationToken;
+use diagnostics::{Diagnostic, Severity};
+use execute::{Trap, VirtualMachine};
+use ir::{FunctionId, Program};
+use session::{CompileOptions, Session};
+use std::fmt;
+use types::Type;
+
+pub use eval::Value;
+
+/// Parses and compiles `source`, returning the program, its entry function
+/// and any warnings. Every failure is reported as a diagnostic rather than a
+/// panic. Compiling still recurses once per level of nesting, up to
+/// `parser::MAX_DEPTH`, and once per function in a chain of calls being
+/// compiled, which is not limited. A debug build needs the 8 MiB stack of a
+/// main thread for the deepest nesting, and a long enough chain of calls
+/// overflows any stack, so this is not safe to run on untrusted input.
+pub fn compile_source(source: &str, script: bool) -> Result<(Program, FunctionId, Vec<Diagnostic>), Diagnostic> {
+    compile_source_with(source, script, CancellationToken::new())
+}
+
+/// `compile_source`, giving up with an error between stages once `token`
+/// is cancelled.
+pub fn compile_source_with(source: &str, script: bool, token: CancellationToken) -> Result<(Program, FunctionId, Vec<Diagnostic>), Diagnostic> {
+    let compiled = compile_program(source, script, token).map_err(|mut errors| errors.remove(0))?;
+    Ok((compiled.program, compiled.entry, compiled.warnings))
+}
+
+/// Compiles `source`, a list of declarations, so that its `main` can be
+/// run. Every error is returned, rather than only the first.
+pub fn compile(source: &str) -> Result<CompiledProgram, Error> {
+    compile_program(source, false, CancellationToken::new()).map_err(Error::Compile)
+}
+
+/// Compiles `source`, or returns every error found, of which there is at
+/// least one.
+fn compile_program(source: &str, script: bool, token: CancellationToken) -> Result<CompiledProgram, Vec<Diagnostic>> {
+    let session = Session::new(String::new(), source.to_string(), CompileOptions { script, ..CompileOptions::default() }).with_cancellation(token);
+    let compiled = session.parse().and_then(|ast| {
+        let hir = session.lower(&ast)?;
+        let (program, entry, ty) = session.compile(&hir)?;
+        let result = ty.detach().ok_or_else(|| ty.to_string());
+        Some((program, entry, result))
+    });
+    let (errors, warnings): (Vec<_>, Vec<_>) = session.take_diagnostics().into_iter()
+        .partition(|diagnostic| diagnostic.severity == Severity::Error);
+    if !errors.is_empty() {
+        return Err(errors)
+    }
+    let (program, entry, result) = compiled.ok_or_else(|| vec![Diagnostic::error("compilation failed".to_string(), None)])?;
+    Ok(CompiledProgram { program, entry, result, warnings })
+}
+
+/// A program compiled by `compile`, which can be run any number of times.
+pub struct CompiledProgram {
+    program: Program,
+    entry: FunctionId,
+    /// The type `main` returns, or its name if it cannot be read.
+    result: Result<Type<'static, 'static>, String>,
+    warnings: Vec<Diagnostic>,
+}
+
+impl CompiledProgram {
+    /// Runs `main` on a new virtual machine and returns its result.
+    pub fn run(&self) -> Result<Value, Error> {
+        let mut vm = VirtualMachine::new(&self.program);
+        vm.execute(self.program.get_function(self.entry)).map_err(Error::Run)?;
+        let ty = self.result.as_ref().map_err(|name| Error::NotAValue(name.clone()))?;
+        eval::read(ty, &vm).ok_or_else(|| Error::NotAValue(ty.to_string()))
+    }
+    pub fn get_program(&self) -> &Program {
+        &self.program
+    }
+    pub fn get_entry(&self) -> FunctionId {
+        self.entry
+    }
+    pub fn get_warnings(&self) -> &[Diagnostic] {
+        &self.warnings
+    }
+}
+
+/// Why `compile` or `CompiledProgram::run` failed.
+#[derive(Debug)]
+pub enum Error {
+    /// The source had errors, of which there is at least one.
+    Compile(Vec<Diagnostic>),
+    /// The program stopped before `main` returned, including by calling
+    /// `exit`.
+    Run(Trap),
+    /// `main` returned something other than a value, such as a function,
+    /// whose type is given.
+    NotAValue(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Compile(errors) => {
+                let errors: Vec<String> = errors.iter().map(Diagnostic::to_string).collect();
+                write!(f, "{}", errors.join("\n"))
+            }
+            Error::Run(trap) => write!(f, "{}", trap),
+            Error::NotAValue(ty) => write!(f, "`main` returned `{}`, which is not a value", ty),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Compile(errors) => errors.first().map(|error| error as _),
+            Error::Run(trap) => Some(trap),
+            Error::NotAValue(_) => None,
+        }
+    }
+}
@@ -1,37 +1,794 @@
-mod position;
-mod parser;
-mod ir;
-mod ast;
-mod compiler;
-mod scope;
-mod types;
-mod execute;
-
-use scope::Scope;
-use std::fs;
-use ir::{Function, Program};
-use execute::VirtualMachine;
+mod bundle;
+mod config;
+mod crash;
+mod examples;
+mod fuzz;
+mod project;
+mod repl;
+
+use std::{env, fs, iter, process};
+use std::path::{Path, PathBuf};
+use language::{ast, codegen, error_codes, log, optimize, report, thir, verbose, debug};
+use language::optimize::Pipeline;
+use language::unroll;
+use language::bytecode;
+use language::cache::{self, Cache};
+use language::ir::{FunctionId, PrintOptions, Program};
+use language::execute::{Trap, VirtualMachine};
+use language::types::Type;
+use language::callgraph::CallGraph;
+use language::clock::Clock;
+use language::replay::Recording;
+use language::session::{CompileOptions, Session};
+use language::source_map::SourceMap;
+use language::diagnostics::{ColorChoice, Diagnostic, ErrorFormat, Lint, LintLevel, Renderer, Severity, Span};
+use config::Config;
+use crash::{Reporter, Stage};
+
+enum Command {
+    Run,
+    Check,
+    /// Compiles the program without running it.
+    Build,
+    /// Prints the type of the expression at a line and column.
+    Type,
+    Repl,
+    Clean,
+    New(String),
+    Init,
+    Explain(String),
+    /// Prints the program in a bytecode file or a bundled executable.
+    Disasm(String),
+    /// Runs the example programs in a directory and checks what they print.
+    TestExamples,
+    /// Compiles and runs this many generated programs.
+    Fuzz(u64),
+}
+
+/// Intermediate output printed instead of running the program.
+#[derive(Copy, Clone)]
+enum Emit {
+    /// The syntax tree as parsed, printed back as source.
+    Ast,
+    AstJson,
+    Thir,
+    ThirJson,
+    /// The functions compiled, with their specializations.
+    Report,
+    /// The argument and return types of every specialization.
+    Signatures,
+    /// The call graph of the compiled program, in Graphviz's DOT language.
+    CallGraphDot,
+    /// The shape of each compiled function's control flow, as JSON.
+    CfgJson,
+    /// x86-64 assembly for the program, which `cc` can build.
+    Asm,
+    /// The IR of the program, with the kind of each register and the
+    /// source of each line.
+    Ir,
+    /// A unified diff of each function before and after each optimization
+    /// pass that changes it.
+    IrDiff,
+}
+
+impl Emit {
+    fn parse(value: &str) -> Option<Emit> {
+        match value {
+            "ast" => Some(Emit::Ast),
+            "ast-json" => Some(Emit::AstJson),
+            "thir" => Some(Emit::Thir),
+            "thir-json" => Some(Emit::ThirJson),
+            "report" => Some(Emit::Report),
+            "signatures" => Some(Emit::Signatures),
+            "callgraph-dot" => Some(Emit::CallGraphDot),
+            "cfg-json" => Some(Emit::CfgJson),
+            "asm" => Some(Emit::Asm),
+            "ir" => Some(Emit::Ir),
+            "ir-diff" => Some(Emit::IrDiff),
+            _ => None,
+        }
+    }
+}
+
+/// `none` turns off an emit kind set in the config file, and `result` says
+/// the same, for running the program and printing what it returns.
+fn parse_emit(value: &str) -> Result<Option<Emit>, String> {
+    match value {
+        "none" | "result" => Ok(None),
+        _ => Emit::parse(value).map(Some).ok_or_else(|| format!("invalid emit kind '{}'", value)),
+    }
+}
+
+struct Options {
+    command: Command,
+    script: bool,
+    verbosity: log::Level,
+    color: ColorChoice,
+    error_format: ErrorFormat,
+    emit: Option<Emit>,
+    seed: Option<u64>,
+    /// Run against a clock that starts at zero and only moves when the
+    /// program sleeps, so that timings are reproducible.
+    mock_clock: bool,
+    /// Trap when the program reads a register before writing it, to find
+    /// bugs in the compiler.
+    strict_registers: bool,
+    /// The names given with `--cfg`, which turn on `cfg(name)` expressions.
+    cfg: Vec<String>,
+    /// The warnings given with `-A`, `-W` and `-D`, in order.
+    lints: Vec<(String, LintLevel)>,
+    /// The function given with `--entry` to start the program at.
+    entry: Option<String>,
+    /// Run the IR optimization passes before running the program.
+    optimize: bool,
+    /// Check the program after compiling and after every optimization
+    /// pass, to find which one breaks it.
+    check_passes: bool,
+    /// The most times a loop can go round for the optimizer to unroll it.
+    unroll_threshold: usize,
+    /// Print what running the program cost to stderr.
+    stats: bool,
+    /// Where to write the inputs the program read, given with `--record`.
+    record: Option<String>,
+    /// A recording to run the program against, given with `--replay`.
+    replay: Option<String>,
+    /// Have `build` write an executable that runs the program.
+    bundle: bool,
+    /// The line and column given to `type` with `--at`.
+    at: Option<(usize, usize)>,
+    /// The file to compile instead of the entry file of the project, or
+    /// for `test-examples` the directory of examples. `build` and `check`
+    /// take any number of files, each compiled as its own program.
+    files: Vec<String>,
+    program_args: Vec<String>,
+}
+
+impl Options {
+    fn compile_options(&self) -> CompileOptions {
+        // ir-diff runs the passes itself, to see what each one does
+        let optimize = self.optimize && !matches!(self.emit, Some(Emit::IrDiff));
+        CompileOptions { script: self.script, cfg: self.cfg.clone(), lints: self.lints.clone(), entry: self.entry.clone(), optimize, passes: Pipeline::new().with_unroll_threshold(self.unroll_threshold), check_passes: self.check_passes }
+    }
+    /// -vv prints the IR, and --emit the tree, which a cached program has
+    /// neither of, so only a plain run or build uses the cache.
+    fn cache(&self, project_root: &Path) -> Option<Cache> {
+        match (&self.command, self.emit) {
+            (Command::Run | Command::Build, None) if !log::enabled(log::Level::Debug) => Some(Cache::new(project_root)),
+            _ => None,
+        }
+    }
+}
+
+/// Parses the command line, starting from the defaults in `config`.
+fn parse_args(args: impl Iterator<Item = String>, config: &Config) -> Result<Options, String> {
+    let emit = match &config.emit {
+        Some(value) => Some(Emit::parse(value).ok_or_else(|| format!("invalid emit kind '{}' in {}", value, config::CONFIG_FILE))?),
+        None => None,
+    };
+    let mut options = Options {
+        command: Command::Run,
+        script: config.script.unwrap_or(false),
+        verbosity: config.verbosity.unwrap_or(log::Level::Normal),
+        color: config.color.unwrap_or(ColorChoice::Auto),
+        error_format: config.error_format.unwrap_or(ErrorFormat::Human),
+        emit,
+        seed: config.seed,
+        mock_clock: false,
+        strict_registers: false,
+        cfg: Vec::new(),
+        lints: Vec::new(),
+        entry: None,
+        optimize: false,
+        check_passes: false,
+        unroll_threshold: unroll::DEFAULT_THRESHOLD,
+        stats: false,
+        record: None,
+        replay: None,
+        bundle: false,
+        at: None,
+        files: Vec::new(),
+        program_args: Vec::new(),
+    };
+    let mut args = args.peekable();
+    match args.peek().map(String::as_str) {
+        Some("run") => { args.next(); }
+        Some("check") => {
+            args.next();
+            options.command = Command::Check;
+        }
+        Some("build") => {
+            args.next();
+            options.command = Command::Build;
+        }
+        Some("type") => {
+            args.next();
+            options.command = Command::Type;
+        }
+        Some("repl") => {
+            args.next();
+            options.command = Command::Repl;
+        }
+        Some("clean") => {
+            args.next();
+            options.command = Command::Clean;
+        }
+        Some("new") => {
+            args.next();
+            let name = args.next().ok_or("new expects the name of the project")?;
+            options.command = Command::New(name);
+        }
+        Some("init") => {
+            args.next();
+            options.command = Command::Init;
+        }
+        Some("explain") => {
+            args.next();
+            let code = args.next().ok_or("explain expects an error code, such as E0002")?;
+            options.command = Command::Explain(code);
+        }
+        Some("disasm") => {
+            args.next();
+            let path = args.next().ok_or("disasm expects a bytecode file or an executable made by build --bundle")?;
+            options.command = Command::Disasm(path);
+        }
+        Some("test-examples") => {
+            args.next();
+            options.command = Command::TestExamples;
+        }
+        Some("fuzz") => {
+            args.next();
+            let runs = match args.next_if(|arg| !arg.starts_with('-')) {
+                Some(runs) => runs.parse().map_err(|_| format!("invalid number of runs '{}'", runs))?,
+                None => 1000,
+            };
+            options.command = Command::Fuzz(runs);
+        }
+        _ => (),
+    }
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--script" => options.script = true,
+            "--no-script" => options.script = false,
+            "--color" => {
+                let value = args.next().ok_or("--color expects auto, always or never")?;
+                options.color = ColorChoice::parse(&value).ok_or_else(|| format!("invalid color choice '{}'", value))?;
+            }
+            _ if arg.starts_with("--color=") => {
+                let value = &arg["--color=".len()..];
+                options.color = ColorChoice::parse(value).ok_or_else(|| format!("invalid color choice '{}'", value))?;
+            }
+            "--error-format" => {
+                let value = args.next().ok_or("--error-format expects human or json")?;
+                options.error_format = ErrorFormat::parse(&value).ok_or_else(|| format!("invalid error format '{}'", value))?;
+            }
+            _ if arg.starts_with("--error-format=") => {
+                let value = &arg["--error-format=".len()..];
+                options.error_format = ErrorFormat::parse(value).ok_or_else(|| format!("invalid error format '{}'", value))?;
+            }
+            "--emit" => {
+                let value = args.next().ok_or("--emit expects ast, ast-json, thir, thir-json, report, signatures, callgraph-dot, cfg-json, asm, ir, ir-diff, result or none")?;
+                options.emit = parse_emit(&value)?;
+            }
+            _ if arg.starts_with("--emit=") => options.emit = parse_emit(&arg["--emit=".len()..])?,
+            "-q" | "--quiet" => options.verbosity = log::Level::Quiet,
+            "-v" | "--verbose" => options.verbosity = log::Level::Verbose,
+            "-vv" => options.verbosity = log::Level::Debug,
+            "--stats" => options.stats = true,
+            "--bundle" => options.bundle = true,
+            "--at" => {
+                let value = args.next().ok_or("--at expects a position, such as 12:7")?;
+                options.at = Some(parse_position(&value).ok_or_else(|| format!("invalid position '{}', expected line:column", value))?);
+            }
+            "--" => options.program_args.extend(&mut args),
+            "--seed" => {
+                let value = args.next().ok_or("--seed expects a value")?;
+                let seed = value.parse::<u64>().map_err(|_| format!("invalid seed '{}'", value))?;
+                options.seed = Some(seed);
+            }
+            "--mock-clock" => options.mock_clock = true,
+            "--strict-registers" => options.strict_registers = true,
+            "-O" | "--optimize" => options.optimize = true,
+            // compiling without running is what `check` does
+            "--no-run" => if let Command::Run = options.command {
+                options.command = Command::Check
+            }
+            "--check-passes" => options.check_passes = true,
+            "--unroll-threshold" => {
+                let value = args.next().ok_or("--unroll-threshold expects the most times a loop can go round to be unrolled")?;
+                options.unroll_threshold = value.parse().map_err(|_| format!("invalid unroll threshold '{}'", value))?;
+            }
+            "--record" => options.record = Some(args.next().ok_or("--record expects the file to write the recording to")?),
+            "--replay" => options.replay = Some(args.next().ok_or("--replay expects a file written by --record")?),
+            "--cfg" => options.cfg.push(args.next().ok_or("--cfg expects a name")?),
+            "--entry" => options.entry = Some(args.next().ok_or("--entry expects the name of a function")?),
+            "-A" | "-W" | "-D" => {
+                let name = args.next().ok_or_else(|| format!("{} expects the name of a warning", arg))?;
+                if !Lint::is_name(&name) {
+                    return Err(format!("unknown warning '{}', expected one of {}", name, Lint::names()))
+                }
+                let level = match arg.as_str() {
+                    "-A" => LintLevel::Allow,
+                    "-W" => LintLevel::Warn,
+                    _ => LintLevel::Deny,
+                };
+                options.lints.push((name, level));
+            }
+            _ if arg.starts_with("--cfg=") => options.cfg.push(arg["--cfg=".len()..].to_string()),
+            _ if arg.starts_with("--entry=") => options.entry = Some(arg["--entry=".len()..].to_string()),
+            _ if !arg.starts_with('-') && options.files.is_empty() && matches!(options.command, Command::Run | Command::Check | Command::Build | Command::Type | Command::TestExamples) => {
+                options.files.push(arg)
+            }
+            _ if !arg.starts_with('-') && matches!(options.command, Command::Check | Command::Build) => options.files.push(arg),
+            _ => return Err(format!("unknown argument '{}'", arg)),
+        }
+    }
+    if let (Command::Type, None) = (&options.command, options.at) {
+        return Err("type expects --at and a position, such as --at 12:7".to_string())
+    }
+    if options.files.len() > 1 && options.emit.is_some() {
+        return Err("--emit expects a single file".to_string())
+    }
+    Ok(options)
+}
+
+/// Parses `line:column`, both counted from one.
+fn parse_position(value: &str) -> Option<(usize, usize)> {
+    let (line, column) = value.split_once(':')?;
+    Some((line.parse().ok()?, column.parse().ok()?))
+}
 
 fn main() {
-    let source = fs::read_to_string("example.txt").unwrap();
-    let ast = parser::parse_source(&source).unwrap();
-    println!("{}", ast.node);
-
-    let mut program = Program::new();
-    let mut function = Function::new();
-    let mut block = function.new_block();
-    let ty = compiler::compile(&ast, &mut Scope::new(), &mut program, &mut function, &mut block).unwrap();
-    block.ret(&mut function);
-    ty.return_ty(&mut function);
-    let main_id = program.add_function(function);
-    println!("{}", program);
+    if let Some(bundled) = bundle::load() {
+        run_bundled(bundled);
+        return
+    }
+    let root = project::find_root();
+    let config = Config::load(root.as_deref().unwrap_or_else(|| Path::new("")));
+    let (config, options) = match config.and_then(|config| parse_args(env::args().skip(1), &config).map(|options| (config, options))) {
+        Ok(loaded) => loaded,
+        Err(message) => {
+            eprintln!("error: {}", message);
+            process::exit(2)
+        }
+    };
+
+    log::set_level(options.verbosity);
 
+    if let Command::Explain(code) = &options.command {
+        match error_codes::explain(code) {
+            Some(explanation) => print!("{}", explanation),
+            None => {
+                eprintln!("error: no explanation for error code '{}'", code);
+                process::exit(1)
+            }
+        }
+        return
+    }
+
+    if let Command::Disasm(path) = &options.command {
+        if let Err(message) = disassemble(Path::new(path)) {
+            eprintln!("error: {}", message);
+            process::exit(1)
+        }
+        return
+    }
+
+    if let Command::Fuzz(runs) = options.command {
+        if !fuzz::run(options.seed.unwrap_or(0), runs) {
+            process::exit(1)
+        }
+        return
+    }
+
+    if let Command::Repl = options.command {
+        if let Err(error) = repl::Repl::new(Renderer::new(options.color, options.error_format)).run() {
+            eprintln!("error: {}", error);
+            process::exit(1)
+        }
+        return
+    }
+
+    if let Command::New(name) = &options.command {
+        scaffold(Path::new(name), false);
+        return
+    }
+    if let Command::Init = options.command {
+        scaffold(Path::new(""), true);
+        return
+    }
+
+    // outside of a project everything is relative to the working directory,
+    // and the entry file is example.txt as it always was
+    let project_root = root.clone().unwrap_or_default();
+    if let Command::Clean = options.command {
+        match cache::clean(&project_root) {
+            Ok(removed) => verbose!("{}", if removed { "removed the build directory" } else { "nothing to clean" }),
+            Err(error) => {
+                eprintln!("error: could not remove {}: {}", cache::BUILD_DIR, error);
+                process::exit(1)
+            }
+        }
+        return
+    }
+
+    let renderer = Renderer::new(options.color, options.error_format);
+    if let Command::TestExamples = options.command {
+        let dir = options.files.first().map_or_else(|| project_root.join(examples::DEFAULT_DIR), PathBuf::from);
+        match examples::run_all(&dir, &renderer) {
+            Ok(true) => (),
+            Ok(false) => process::exit(1),
+            Err(error) => {
+                eprintln!("error: could not read the examples in {}: {}", dir.display(), error);
+                process::exit(1)
+            }
+        }
+        return
+    }
+    // each file is its own program, so one failing does not stop the rest
+    if options.files.len() > 1 {
+        let failed = options.files.iter()
+            .filter(|file| !build_file(&options, Path::new(file), &project_root, &renderer))
+            .count();
+        if failed > 0 {
+            eprintln!("error: {} of {} files failed to compile", failed, options.files.len());
+            process::exit(1)
+        }
+        return
+    }
+    let path = match (options.files.first(), &root) {
+        (Some(file), _) => PathBuf::from(file),
+        (None, Some(root)) => root.join(config.entry.as_deref().unwrap_or_else(|| Path::new(project::DEFAULT_ENTRY))),
+        (None, None) => PathBuf::from("example.txt"),
+    };
+    let file_name = path.display().to_string();
+    let file_name = file_name.as_str();
+    let source = match fs::read_to_string(&path) {
+        Ok(source) => source,
+        Err(error) => {
+            renderer.emit(&Diagnostic::error(format!("could not read {}: {}", file_name, error), None), &SourceMap::single(file_name, ""));
+            process::exit(1)
+        }
+    };
+    let compile_options = options.compile_options();
+    let cache = options.cache(&project_root);
+    let key = Cache::key(&source, &compile_options);
+    if let Some((program, main_id, ty)) = cache.as_ref().and_then(|cache| cache.load(key)) {
+        verbose!("using the cached build of {}", file_name);
+        finish(&options, &program, main_id, &ty, &project_root, &path);
+        return
+    }
+
+    let reporter = Reporter { file_name, source: &source, options: &compile_options, project_root: &project_root };
+    let session = Session::new(file_name.to_string(), source.clone(), compile_options.clone());
+    // returns how many diagnostics were shown
+    let flush = |session: &Session| {
+        let files = session.source_map();
+        let diagnostics = session.take_diagnostics();
+        for diagnostic in &diagnostics {
+            renderer.emit(diagnostic, &files);
+        }
+        if diagnostics.iter().any(|diagnostic| diagnostic.severity == Severity::Error) {
+            process::exit(1)
+        }
+        diagnostics.len()
+    };
+
+    // errors the parser went past are shown with those found by compiling
+    // the rest of the program
+    let ast = match reporter.guard(Stage::Parse, || session.parse()) {
+        Some(ast) => ast,
+        None => {
+            flush(&session);
+            process::exit(1)
+        }
+    };
+    verbose!("parsed {}", file_name);
+    debug!("{}", ast);
+    // the tree as parsed does not need compiling
+    match options.emit {
+        Some(Emit::Ast) => {
+            flush(&session);
+            print!("{}", ast);
+            return
+        }
+        Some(Emit::AstJson) => {
+            flush(&session);
+            print!("{}", ast::to_json(&ast, &session.source));
+            return
+        }
+        _ => (),
+    }
+
+    let hir = match reporter.guard(Stage::Lower, || session.lower(&ast)) {
+        Some(hir) => hir,
+        None => {
+            flush(&session);
+            process::exit(1)
+        }
+    };
+    let compiled = reporter.guard(Stage::Compile, || session.compile(&hir));
+    // the tree is printed even when compiling fails, since the types found
+    // up to the error are what explain it
+    match options.emit {
+        Some(Emit::Thir) => print!("{}", thir::to_text(&hir, &session.source)),
+        Some(Emit::ThirJson) => print!("{}", thir::to_json(&hir, &session.source)),
+        Some(Emit::Report) => if let Some((program, _, _)) = &compiled {
+            print!("{}", report::to_text(&hir, program, &session.source))
+        }
+        Some(Emit::Signatures) => if compiled.is_some() {
+            print!("{}", report::signatures(&hir, &session.source))
+        }
+        Some(Emit::CallGraphDot) => if let Some((program, entry, _)) = &compiled {
+            print!("{}", report::call_graph_dot(&hir, program, *entry, &session.source))
+        }
+        Some(Emit::CfgJson) => if let Some((program, entry, _)) = &compiled {
+            print!("{}", report::cfg_json(&hir, program, *entry, &session.source))
+        }
+        Some(Emit::Asm) => if let Some((program, entry, ty)) = &compiled {
+            match codegen::emit(program, *entry, ty) {
+                Ok(asm) => print!("{}", asm),
+                Err(reason) => {
+                    eprintln!("error: {}", reason);
+                    process::exit(1)
+                }
+            }
+        }
+        Some(Emit::Ir) => if let Some((program, entry, _)) = &compiled {
+            let (canonical, _) = program.canonical(*entry);
+            print!("{}", canonical.display(PrintOptions { types: true, align: true, source: Some(&session.source) }))
+        }
+        Some(Emit::IrDiff) => if let Some((program, entry, _)) = &compiled {
+            let (mut program, _) = program.canonical(*entry);
+            let mut before = program.clone();
+            compile_options.passes.run_observed(&mut program, options.check_passes, |pass, after| {
+                print!("{}", optimize::diff(pass, &before, after, Some(&session.source)));
+                before = after.clone();
+            });
+        }
+        Some(Emit::Ast) | Some(Emit::AstJson) | None => (),
+    }
+    // the types found before an error are still worth showing
+    if let (Command::Type, Some((line, column))) = (&options.command, options.at) {
+        let described = Span::offset_at(&session.source, line, column).and_then(|offset| thir::describe_at(&hir, &session.source, offset));
+        if let Some(described) = &described {
+            print!("{}", described);
+        }
+        flush(&session);
+        if described.is_none() {
+            eprintln!("error: no expression at {}:{} in {}", line, column, file_name);
+            process::exit(1)
+        }
+        return
+    }
+    let reported = flush(&session);
+    let (program, main_id, ty) = match compiled {
+        Some(compiled) => compiled,
+        None => process::exit(1),
+    };
+    // type checking happens while lowering, so checking compiles the program
+    // and throws it away
+    if let Command::Check = options.command {
+        verbose!("no errors found");
+        return
+    }
+    if options.emit.is_some() {
+        return
+    }
+    verbose!("compiled {} functions using {} variables", program.get_function_count(), program.get_variable_count());
+    if log::enabled(log::Level::Debug) {
+        let (canonical, _) = program.canonical(main_id);
+        eprintln!("{}", canonical);
+        eprintln!("call graph, callees first:\n{}", CallGraph::new(&canonical));
+    }
+    // a program with warnings is compiled every time, so they are shown again
+    if let (Some(cache), 0) = (&cache, reported) {
+        store(cache, key, &program, main_id, &ty, file_name);
+    }
+    finish(&options, &program, main_id, &ty, &project_root, &path);
+}
+
+/// Stores the build of `file_name` in the cache.
+fn store(cache: &Cache, key: u64, program: &Program, main_id: FunctionId, ty: &Type, file_name: &str) {
+    match cache.store(key, program, main_id, ty) {
+        Ok(true) => verbose!("cached the build of {}", file_name),
+        Ok(false) => verbose!("not caching {}, since main returns a function", file_name),
+        Err(error) => verbose!("could not cache the build of {}: {}", file_name, error),
+    }
+}
+
+/// Runs a compiled program, or for `build` writes the executable asked for.
+fn finish(options: &Options, program: &Program, main_id: FunctionId, ty: &Type, project_root: &Path, path: &Path) {
+    if let Command::Build = options.command {
+        if options.bundle {
+            if let Err(message) = write_bundle(options, program, main_id, ty, project_root, path) {
+                eprintln!("error: {}", message);
+                process::exit(1)
+            }
+        }
+        return
+    }
+    run(options, program, main_id, ty)
+}
+
+/// Compiles one of the files given to `build` or `check`, showing its
+/// diagnostics, and for `build` caches it and writes the executable asked
+/// for. Returns whether it compiled.
+fn build_file(options: &Options, path: &Path, project_root: &Path, renderer: &Renderer) -> bool {
+    let file_name = path.display().to_string();
+    let source = match fs::read_to_string(path) {
+        Ok(source) => source,
+        Err(error) => {
+            renderer.emit(&Diagnostic::error(format!("could not read {}: {}", file_name, error), None), &SourceMap::single(&file_name, ""));
+            return false
+        }
+    };
+    let compile_options = options.compile_options();
+    let cache = options.cache(project_root);
+    let key = Cache::key(&source, &compile_options);
+    let (program, main_id, ty) = match cache.as_ref().and_then(|cache| cache.load(key)) {
+        Some((program, main_id, ty)) => {
+            verbose!("using the cached build of {}", file_name);
+            (program, main_id, Some(ty))
+        }
+        None => {
+            let reporter = Reporter { file_name: &file_name, source: &source, options: &compile_options, project_root };
+            let session = Session::new(file_name.clone(), source.clone(), compile_options.clone());
+            // main's type borrows the tree, so only a type that is a value
+            // outlives it, and only a value can be cached or bundled
+            let compiled = reporter.guard(Stage::Parse, || session.parse()).and_then(|ast| {
+                let hir = reporter.guard(Stage::Lower, || session.lower(&ast))?;
+                let (program, main_id, ty) = reporter.guard(Stage::Compile, || session.compile(&hir))?;
+                Some((program, main_id, ty.detach()))
+            });
+            let diagnostics = session.take_diagnostics();
+            for diagnostic in &diagnostics {
+                renderer.emit(diagnostic, &session.source_map());
+            }
+            let compiled = match compiled {
+                Some(compiled) if !diagnostics.iter().any(|diagnostic| diagnostic.severity == Severity::Error) => compiled,
+                _ => return false,
+            };
+            if let (Some(cache), Some(ty), true) = (&cache, &compiled.2, diagnostics.is_empty()) {
+                store(cache, key, &compiled.0, compiled.1, ty, &file_name);
+            }
+            compiled
+        }
+    };
+    verbose!("compiled {}", file_name);
+    if let (Command::Build, true) = (&options.command, options.bundle) {
+        let written = match &ty {
+            Some(ty) => write_bundle(options, &program, main_id, ty, project_root, path),
+            None => Err(BUNDLE_FUNCTION.to_string()),
+        };
+        if let Err(message) = written {
+            eprintln!("error: {}", message);
+            return false
+        }
+    }
+    true
+}
+
+/// Prints the program in `path`, which is either bytecode, as the cache
+/// keeps it, or an executable with bytecode bundled into it.
+fn disassemble(path: &Path) -> Result<(), String> {
+    let bytes = match bundle::read(path) {
+        Ok(Some(bundled)) => bundled.bytecode,
+        Ok(None) => fs::read(path).map_err(|error| format!("could not read {}: {}", path.display(), error))?,
+        Err(error) => return Err(format!("could not read {}: {}", path.display(), error)),
+    };
+    let text = bytecode::disassemble(&bytes)
+        .ok_or_else(|| format!("{} is not bytecode written by this version of the compiler", path.display()))?;
+    print!("{}", text);
+    Ok(())
+}
+
+const BUNDLE_FUNCTION: &str = "cannot bundle a program whose main returns a function";
+
+/// Writes an executable for the program in `path` to the build directory,
+/// named after the file.
+fn write_bundle(options: &Options, program: &Program, main_id: FunctionId, ty: &Type, project_root: &Path, path: &Path) -> Result<(), String> {
+    let bytes = bytecode::encode(program, main_id, ty).ok_or(BUNDLE_FUNCTION)?;
+    let name = path.file_stem().unwrap_or_else(|| "main".as_ref()).to_string_lossy();
+    let dir = project_root.join(cache::BUILD_DIR);
+    let exe = dir.join(format!("{}{}", name, env::consts::EXE_SUFFIX));
+    fs::create_dir_all(&dir).and_then(|()| bundle::write(&exe, &bytes, options.script))
+        .map_err(|error| format!("could not write {}: {}", exe.display(), error))?;
+    verbose!("wrote {}, content hash {:016x}", exe.display(), bytecode::content_hash(&bytes).expect("encoded programs match their hash"));
+    Ok(())
+}
+
+/// Runs the program bundled into this executable, passing it every
+/// argument.
+fn run_bundled(bundled: bundle::Bundled) {
+    let (program, main_id, ty) = match bytecode::decode(&bundled.bytecode) {
+        Some(decoded) => decoded,
+        None => {
+            eprintln!("error: the bundled program is corrupt");
+            process::exit(1)
+        }
+    };
+    let mut options = parse_args(iter::empty(), &Config::default()).expect("the default options are valid");
+    options.script = bundled.script;
+    options.program_args = env::args().skip(1).collect();
+    run(&options, &program, main_id, &ty)
+}
+
+/// Creates a project in `dir`, which is the working directory for `init`.
+fn scaffold(dir: &Path, init: bool) {
+    if let Err(error) = project::scaffold(dir) {
+        eprintln!("error: could not create the project: {}", error);
+        process::exit(1)
+    }
+    if init {
+        verbose!("created a project in the working directory");
+    } else {
+        verbose!("created a project in {}", dir.display());
+    }
+}
+
+fn run(options: &Options, program: &Program, main_id: FunctionId, ty: &Type) {
     let function = program.get_function(main_id);
 
-    let mut vm = VirtualMachine::new(&program);
-    vm.execute(function);
+    let mut vm = VirtualMachine::new(program);
+    if let Some(seed) = options.seed {
+        vm.set_seed(seed);
+    }
+    if options.mock_clock {
+        vm.set_clock(Clock::mock(0));
+    }
+    vm.set_strict(options.strict_registers);
+    vm.set_args(options.program_args.clone());
+    // the recording names the program it was made of, so that replaying it
+    // against another program is caught
+    let hash = || bytecode::encode(program, main_id, ty).and_then(|bytes| bytecode::content_hash(&bytes));
+    if let Some(path) = &options.replay {
+        let recording = fs::read_to_string(path).map_err(|error| error.to_string()).and_then(|text| Recording::parse(&text));
+        match recording {
+            Ok(recording) if recording.program.is_some() && recording.program != hash() => {
+                eprintln!("error: {} is a recording of a different program", path);
+                process::exit(1)
+            }
+            Ok(recording) => vm.replay(recording),
+            Err(error) => {
+                eprintln!("error: could not read {}: {}", path, error);
+                process::exit(1)
+            }
+        }
+    }
+    if options.record.is_some() {
+        vm.record(hash());
+    }
+    verbose!("running");
+    let result = vm.execute(function);
+    if let (Some(path), Some(recording)) = (&options.record, vm.take_recording()) {
+        match fs::write(path, recording.to_text()) {
+            Ok(()) => verbose!("recorded the run to {}", path),
+            Err(error) => eprintln!("error: could not write {}: {}", path, error),
+        }
+    }
+    if options.stats {
+        let stats = vm.get_stats();
+        eprintln!("instructions executed: {}", stats.instructions_executed);
+        eprintln!("calls: {}", stats.calls);
+        eprintln!("registers touched: {} of {}", stats.max_registers_touched, program.get_variable_count());
+        eprintln!("heap bytes allocated: {}", stats.heap_bytes_allocated);
+    }
+    match result {
+        Ok(()) => (),
+        Err(Trap::Exit { code, line }) => {
+            verbose!("program exited with code {} at line {}", code, line);
+            process::exit(code)
+        }
+        Err(trap) => {
+            eprintln!("error: {}", trap);
+            process::exit(1)
+        }
+    }
 
-    for var in function.get_returns() {
-        println!("{}", vm.get_register(*var))
+    if log::enabled(log::Level::Normal) {
+        println!("{}", vm.format_ty(ty));
+    }
+    if let (false, Type::Int(code)) = (options.script, ty) {
+        process::exit(vm.get_register(*code))
     }
 }
\ No newline at end of file
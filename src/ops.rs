@@ -0,0 +1,88 @@
+//! Arithmetic shared by the VM and compile-time evaluation, so that folding
+//! an expression can never give a different answer to running it.
+
+pub fn add_int(a: i32, b: i32) -> i32 {
+    a.wrapping_add(b)
+}
+
+pub fn add_float(a: f32, b: f32) -> f32 {
+    a + b
+}
+
+pub fn sub_int(a: i32, b: i32) -> i32 {
+    a.wrapping_sub(b)
+}
+
+pub fn sub_float(a: f32, b: f32) -> f32 {
+    a - b
+}
+
+pub fn mul_int(a: i32, b: i32) -> i32 {
+    a.wrapping_mul(b)
+}
+
+pub fn mul_float(a: f32, b: f32) -> f32 {
+    a * b
+}
+
+/// `None` when dividing by zero. Dividing the smallest `int` by -1 wraps.
+pub fn div_int(a: i32, b: i32) -> Option<i32> {
+    (b != 0).then(|| a.wrapping_div(b))
+}
+
+pub fn div_uint(a: i32, b: i32) -> Option<i32> {
+    (a as u32).checked_div(b as u32).map(|value| value as i32)
+}
+
+pub fn div_float(a: f32, b: f32) -> f32 {
+    a / b
+}
+
+/// `None` when dividing by zero. The result has the sign of `a`.
+pub fn rem_int(a: i32, b: i32) -> Option<i32> {
+    (b != 0).then(|| a.wrapping_rem(b))
+}
+
+pub fn rem_uint(a: i32, b: i32) -> Option<i32> {
+    (a as u32).checked_rem(b as u32).map(|value| value as i32)
+}
+
+pub fn rem_float(a: f32, b: f32) -> f32 {
+    a % b
+}
+
+pub fn less_uint(a: i32, b: i32) -> bool {
+    (a as u32) < (b as u32)
+}
+
+pub fn less_eq_uint(a: i32, b: i32) -> bool {
+    (a as u32) <= (b as u32)
+}
+
+pub fn shift_left(a: i32, amount: i32) -> i32 {
+    a.wrapping_shl(amount as u32)
+}
+
+pub fn shift_right_arithmetic(a: i32, amount: i32) -> i32 {
+    a.wrapping_shr(amount as u32)
+}
+
+pub fn shift_right_logical(a: i32, amount: i32) -> i32 {
+    (a as u32).wrapping_shr(amount as u32) as i32
+}
+
+pub fn int_to_float(value: i32) -> f32 {
+    value as f32
+}
+
+pub fn uint_to_float(value: i32) -> f32 {
+    value as u32 as f32
+}
+
+pub fn float_to_int(value: f32) -> i32 {
+    value as i32
+}
+
+pub fn float_to_uint(value: f32) -> i32 {
+    value as u32 as i32
+}
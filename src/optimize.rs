@@ -0,0 +1,650 @@
+//! Optimizations of the IR, run on a program before it executes when it is
+//! compiled with `--optimize`. Each pass rewrites the program in place and
+//! says whether it changed anything, and the passes run in turn until none
+//! does, since each can make work for the others.
+//!
+//! Registers are shared by every function, so a pass only trusts what holds
+//! across the whole program: a variable is a constant when its one
+//! definition anywhere is a constant, and an instruction is dead when no
+//! function reads what it writes.
+
+use crate::diff;
+use crate::ir::{BlockId, Conversion, ExitInstruction, Function, FunctionId, Instruction, PrintOptions, Program, Var};
+use crate::ops;
+use crate::{ssa, unroll};
+use std::collections::HashMap;
+use std::mem;
+
+/// A pass over the whole program, returning whether it changed anything.
+pub type Pass = fn(&mut Program) -> bool;
+
+/// Every pass, by name, in the order they run.
+pub const PASSES: &[(&str, Pass)] = &[
+    ("construct-ssa", ssa::construct),
+    ("fold-constants", fold_constants),
+    ("number-values", number_values),
+    ("propagate-copies", propagate_copies),
+    ("remove-dead-code", remove_dead_code),
+    ("simplify-branches", simplify_branches),
+];
+
+/// A place in the order of `PASSES` where a `Pipeline` can add passes.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Point {
+    /// Before every other pass, while each variable may still be written
+    /// in more than one place.
+    Start,
+    /// After constants are folded, so that a pass sees what they became.
+    AfterConstprop,
+    /// After every other pass, last before the program runs or becomes
+    /// native code. Each variable has a register of its own, so there is
+    /// no register allocation to come before.
+    End,
+}
+
+impl Point {
+    pub fn parse(name: &str) -> Option<Point> {
+        match name {
+            "start" => Some(Point::Start),
+            "after-constprop" => Some(Point::AfterConstprop),
+            "end" => Some(Point::End),
+            _ => None,
+        }
+    }
+    pub fn name(self) -> &'static str {
+        match self {
+            Point::Start => "start",
+            Point::AfterConstprop => "after-constprop",
+            Point::End => "end",
+        }
+    }
+    /// The pass the point comes after, or `None` for the start.
+    fn follows(self) -> Option<&'static str> {
+        match self {
+            Point::Start => None,
+            Point::AfterConstprop => Some("fold-constants"),
+            Point::End => PASSES.last().map(|&(name, _)| name),
+        }
+    }
+}
+
+/// The passes `PASSES` has, with passes of the library's user added at
+/// the points they name, and how far loops are unrolled.
+#[derive(Debug, Clone)]
+pub struct Pipeline {
+    added: Vec<(Point, &'static str, Pass)>,
+    unroll_threshold: usize,
+}
+
+impl Pipeline {
+    pub fn new() -> Pipeline {
+        Pipeline { added: Vec::new(), unroll_threshold: unroll::DEFAULT_THRESHOLD }
+    }
+    /// Unrolls loops that go round at most `threshold` times, or none for
+    /// zero.
+    pub fn with_unroll_threshold(mut self, threshold: usize) -> Pipeline {
+        self.unroll_threshold = threshold;
+        self
+    }
+    /// Adds `pass`, called `name`, at `point`, after any added there
+    /// before it.
+    pub fn insert(&mut self, point: Point, name: &'static str, pass: Pass) {
+        self.added.push((point, name, pass));
+    }
+    /// Every pass, by name, in the order they run.
+    pub fn passes(&self) -> Vec<(&'static str, Pass)> {
+        let added = |follows: Option<&'static str>| self.added.iter()
+            .filter(move |(point, _, _)| point.follows() == follows)
+            .map(|&(_, name, pass)| (name, pass));
+        let mut passes: Vec<_> = added(None).collect();
+        for &(name, pass) in PASSES {
+            passes.push((name, pass));
+            passes.extend(added(Some(name)));
+        }
+        passes
+    }
+    /// Runs the passes until the program stops changing, then unrolls what
+    /// loops it can and runs them again, until no more loops are unrolled.
+    /// `verify` checks the program after each step, as `run` does.
+    pub fn run(&self, program: &mut Program, verify: bool) {
+        self.run_observed(program, verify, |_, _| ())
+    }
+    /// Runs the pipeline as `run` does, giving `observe` the name of each
+    /// step that changes the program and the program it leaves.
+    pub fn run_observed(&self, program: &mut Program, verify: bool, mut observe: impl FnMut(&str, &Program)) {
+        let passes = self.passes();
+        run_observed(program, &passes, verify, &mut observe);
+        for _ in 0..MAX_ROUNDS {
+            if !unroll::unroll_loops(program, self.unroll_threshold) {
+                return
+            }
+            check(UNROLL, program, verify);
+            observe(UNROLL, program);
+            run_observed(program, &passes, verify, &mut observe);
+        }
+    }
+}
+
+impl Default for Pipeline {
+    fn default() -> Pipeline {
+        Pipeline::new()
+    }
+}
+
+/// How many times the passes run before giving up on reaching a point
+/// where none of them changes anything.
+const MAX_ROUNDS: usize = 16;
+
+/// What `--emit=ir-diff` calls the unrolling of loops, which runs after
+/// the passes.
+const UNROLL: &str = "unroll-loops";
+
+/// Runs every pass until the program stops changing, and unrolls loops
+/// as a default `Pipeline` does. With `verify`, the program is checked
+/// after each pass, as `run` does.
+pub fn optimize(program: &mut Program, verify: bool) {
+    Pipeline::new().run(program, verify)
+}
+
+/// Runs `passes` in order until the program stops changing. With `verify`,
+/// a pass that leaves the program invalid panics with its name and what it
+/// broke, rather than the program going wrong later on.
+pub fn run(program: &mut Program, passes: &[(&str, Pass)], verify: bool) {
+    run_observed(program, passes, verify, |_, _| ())
+}
+
+/// Runs `passes` as `run` does, giving `observe` the name of each pass
+/// that changes the program and the program it leaves.
+pub fn run_observed(program: &mut Program, passes: &[(&str, Pass)], verify: bool, mut observe: impl FnMut(&str, &Program)) {
+    for round in 0..MAX_ROUNDS {
+        let mut changed = false;
+        for (name, pass) in passes {
+            if pass(program) {
+                debug!("optimize: round {} of {} changed the program", round + 1, name);
+                check(name, program, verify);
+                observe(name, program);
+                changed = true;
+            }
+        }
+        if !changed {
+            return
+        }
+    }
+}
+
+/// With `verify`, panics if the pass `name` left the program invalid.
+fn check(name: &str, program: &Program, verify: bool) {
+    if verify {
+        if let Err(problem) = program.verify() {
+            panic!("the {} pass left an invalid program: {}", name, problem)
+        }
+    }
+}
+
+/// A unified diff of each function that the pass `name` changed, from
+/// `before` to `after`, printed as `--emit ir` prints it without aligning
+/// destinations, which would change every line of a function when its
+/// widest one goes. `source` gives the text of each line.
+pub fn diff(name: &str, before: &Program, after: &Program, source: Option<&str>) -> String {
+    let options = PrintOptions { types: true, align: false, source };
+    let text = |program: &Program, function_id: FunctionId| program.display(options).function(function_id).to_string();
+    after.get_function_ids().into_iter().map(|function_id| {
+        let id = function_id.get_id();
+        diff::unified(&format!("f{} before {}", id, name), &format!("f{} after {}", id, name), &text(before, function_id), &text(after, function_id))
+    }).collect()
+}
+
+#[derive(Debug, Copy, Clone)]
+pub(crate) enum Constant {
+    Int(i32),
+    Float(f32),
+}
+
+impl Constant {
+    fn instruction(self, dest: Var) -> Instruction {
+        match self {
+            Constant::Int(constant) => Instruction::ConstantInt { dest, constant },
+            Constant::Float(constant) => Instruction::ConstantFloat { dest, constant },
+        }
+    }
+}
+
+/// How many places can write each variable: the instructions that define
+/// it, and every call of the function it is a parameter of.
+fn count_definitions(program: &Program) -> Vec<usize> {
+    let mut counts = vec![0; program.get_variable_count()];
+    for function_id in program.get_function_ids() {
+        let function = program.get_function(function_id);
+        for param in function.get_params() {
+            counts[param.get_id()] += 1;
+        }
+        for block in function.get_blocks() {
+            for var in block.get_instructions().iter().flat_map(Instruction::defs) {
+                counts[var.get_id()] += 1;
+            }
+        }
+    }
+    counts
+}
+
+/// How many places read each variable, counting a function's results as
+/// read by its return.
+fn count_uses(program: &Program) -> Vec<usize> {
+    let mut counts = vec![0; program.get_variable_count()];
+    for function_id in program.get_function_ids() {
+        let function = program.get_function(function_id);
+        for var in function.get_returns() {
+            counts[var.get_id()] += 1;
+        }
+        for block in function.get_blocks() {
+            for var in block.get_instructions().iter().flat_map(Instruction::uses) {
+                counts[var.get_id()] += 1;
+            }
+            match block.get_exit_instruction() {
+                ExitInstruction::ConditionalBranch { cond: var, .. } | ExitInstruction::Switch { scrutinee: var, .. } => {
+                    counts[var.get_id()] += 1
+                }
+                ExitInstruction::Branch { .. } | ExitInstruction::Return => (),
+            }
+        }
+    }
+    counts
+}
+
+/// The value of each variable whose only definition is a constant.
+pub(crate) fn find_constants(program: &Program) -> Vec<Option<Constant>> {
+    let definitions = count_definitions(program);
+    let mut constants = vec![None; program.get_variable_count()];
+    for function_id in program.get_function_ids() {
+        for block in program.get_function(function_id).get_blocks() {
+            for inst in block.get_instructions() {
+                let (dest, constant) = match *inst {
+                    Instruction::ConstantInt { dest, constant } => (dest, Constant::Int(constant)),
+                    Instruction::ConstantFloat { dest, constant } => (dest, Constant::Float(constant)),
+                    _ => continue,
+                };
+                if definitions[dest.get_id()] == 1 {
+                    constants[dest.get_id()] = Some(constant);
+                }
+            }
+        }
+    }
+    constants
+}
+
+/// Computes instructions and branches whose operands are all constants,
+/// leaving the ones that would trap to trap when they run.
+fn fold_constants(program: &mut Program) -> bool {
+    let constants = find_constants(program);
+    let int = |var: Var| match constants[var.get_id()] {
+        Some(Constant::Int(value)) => Some(value),
+        _ => None,
+    };
+    let float = |var: Var| match constants[var.get_id()] {
+        Some(Constant::Float(value)) => Some(value),
+        _ => None,
+    };
+    let ints = |a: Var, b: Var| int(a).zip(int(b));
+    let floats = |a: Var, b: Var| float(a).zip(float(b));
+    let mut changed = false;
+    for function_id in program.get_function_ids() {
+        for block in program.get_function_mut(function_id).get_blocks_mut() {
+            for inst in block.get_instructions_mut() {
+                let folded = match *inst {
+                    Instruction::AddInt { dest, a, b } => ints(a, b).map(|(a, b)| (dest, Constant::Int(ops::add_int(a, b)))),
+                    Instruction::SubInt { dest, a, b } => ints(a, b).map(|(a, b)| (dest, Constant::Int(ops::sub_int(a, b)))),
+                    Instruction::MulInt { dest, a, b } => ints(a, b).map(|(a, b)| (dest, Constant::Int(ops::mul_int(a, b)))),
+                    Instruction::AndInt { dest, a, b } => ints(a, b).map(|(a, b)| (dest, Constant::Int(a & b))),
+                    Instruction::OrInt { dest, a, b } => ints(a, b).map(|(a, b)| (dest, Constant::Int(a | b))),
+                    Instruction::XorInt { dest, a, b } => ints(a, b).map(|(a, b)| (dest, Constant::Int(a ^ b))),
+                    Instruction::ShiftLeft { dest, a, b } => ints(a, b).map(|(a, b)| (dest, Constant::Int(ops::shift_left(a, b)))),
+                    Instruction::ShiftRightArithmetic { dest, a, b } => ints(a, b).map(|(a, b)| (dest, Constant::Int(ops::shift_right_arithmetic(a, b)))),
+                    Instruction::ShiftRightLogical { dest, a, b } => ints(a, b).map(|(a, b)| (dest, Constant::Int(ops::shift_right_logical(a, b)))),
+                    Instruction::DivInt { dest, a, b } => ints(a, b).and_then(|(a, b)| ops::div_int(a, b)).map(|value| (dest, Constant::Int(value))),
+                    Instruction::DivUInt { dest, a, b } => ints(a, b).and_then(|(a, b)| ops::div_uint(a, b)).map(|value| (dest, Constant::Int(value))),
+                    Instruction::RemInt { dest, a, b } => ints(a, b).and_then(|(a, b)| ops::rem_int(a, b)).map(|value| (dest, Constant::Int(value))),
+                    Instruction::RemUInt { dest, a, b } => ints(a, b).and_then(|(a, b)| ops::rem_uint(a, b)).map(|value| (dest, Constant::Int(value))),
+                    Instruction::EqInt { dest, a, b } => ints(a, b).map(|(a, b)| (dest, Constant::Int((a == b) as i32))),
+                    Instruction::LessInt { dest, a, b } => ints(a, b).map(|(a, b)| (dest, Constant::Int((a < b) as i32))),
+                    Instruction::LessUInt { dest, a, b } => ints(a, b).map(|(a, b)| (dest, Constant::Int(ops::less_uint(a, b) as i32))),
+                    Instruction::LessEqInt { dest, a, b } => ints(a, b).map(|(a, b)| (dest, Constant::Int((a <= b) as i32))),
+                    Instruction::LessEqUInt { dest, a, b } => ints(a, b).map(|(a, b)| (dest, Constant::Int(ops::less_eq_uint(a, b) as i32))),
+                    Instruction::AddFloat { dest, a, b } => floats(a, b).map(|(a, b)| (dest, Constant::Float(ops::add_float(a, b)))),
+                    Instruction::SubFloat { dest, a, b } => floats(a, b).map(|(a, b)| (dest, Constant::Float(ops::sub_float(a, b)))),
+                    Instruction::MulFloat { dest, a, b } => floats(a, b).map(|(a, b)| (dest, Constant::Float(ops::mul_float(a, b)))),
+                    Instruction::DivFloat { dest, a, b } => floats(a, b).map(|(a, b)| (dest, Constant::Float(ops::div_float(a, b)))),
+                    Instruction::RemFloat { dest, a, b } => floats(a, b).map(|(a, b)| (dest, Constant::Float(ops::rem_float(a, b)))),
+                    Instruction::EqFloat { dest, a, b } => floats(a, b).map(|(a, b)| (dest, Constant::Int((a == b) as i32))),
+                    Instruction::LessFloat { dest, a, b } => floats(a, b).map(|(a, b)| (dest, Constant::Int((a < b) as i32))),
+                    Instruction::LessEqFloat { dest, a, b } => floats(a, b).map(|(a, b)| (dest, Constant::Int((a <= b) as i32))),
+                    Instruction::Convert { dest, src, conversion } => match conversion {
+                        Conversion::IntToFloat => int(src).map(|value| (dest, Constant::Float(ops::int_to_float(value)))),
+                        Conversion::UIntToFloat => int(src).map(|value| (dest, Constant::Float(ops::uint_to_float(value)))),
+                        Conversion::FloatToInt => float(src).map(|value| (dest, Constant::Int(ops::float_to_int(value)))),
+                        Conversion::FloatToUInt => float(src).map(|value| (dest, Constant::Int(ops::float_to_uint(value)))),
+                    }
+                    Instruction::Copy { dest, src } => constants[src.get_id()].map(|constant| (dest, constant)),
+                    Instruction::Select { cond, a, b, dest } => {
+                        if let Some(cond) = int(cond) {
+                            *inst = Instruction::Copy { dest, src: if cond != 0 { a } else { b } };
+                            changed = true;
+                        }
+                        None
+                    }
+                    _ => None,
+                };
+                if let Some((dest, constant)) = folded {
+                    *inst = constant.instruction(dest);
+                    changed = true;
+                }
+            }
+            let target = match block.get_exit_instruction() {
+                &ExitInstruction::ConditionalBranch { cond, block1, block2 } => int(cond).map(|cond| if cond != 0 { block1 } else { block2 }),
+                ExitInstruction::Switch { scrutinee, targets, default } => {
+                    int(*scrutinee).map(|scrutinee| *targets.get(scrutinee as u32 as usize).unwrap_or(default))
+                }
+                ExitInstruction::Branch { .. } | ExitInstruction::Return => None,
+            };
+            if let Some(block_id) = target {
+                block.set_exit_instruction(ExitInstruction::Branch { block: block_id });
+                changed = true;
+            }
+        }
+    }
+    changed
+}
+
+/// What an instruction computes: its operation, its operands, in order of
+/// variable for an operation that does not care, and its conversion. Two
+/// instructions with the same value give the same result.
+#[derive(Clone, Hash, Eq, PartialEq)]
+struct Value {
+    operation: mem::Discriminant<Instruction>,
+    operands: Vec<Var>,
+    conversion: Option<Conversion>,
+}
+
+/// The variable `inst` writes and the value it computes, if it computes
+/// one from its operands alone. Constants are left to `fold_constants`.
+fn value(inst: &Instruction) -> Option<(Var, Value)> {
+    let (dest, conversion) = match *inst {
+        Instruction::AddInt { dest, .. }
+        | Instruction::AddFloat { dest, .. }
+        | Instruction::AndInt { dest, .. }
+        | Instruction::OrInt { dest, .. }
+        | Instruction::XorInt { dest, .. }
+        | Instruction::ShiftLeft { dest, .. }
+        | Instruction::ShiftRightArithmetic { dest, .. }
+        | Instruction::ShiftRightLogical { dest, .. }
+        | Instruction::SubInt { dest, .. }
+        | Instruction::SubFloat { dest, .. }
+        | Instruction::MulInt { dest, .. }
+        | Instruction::MulFloat { dest, .. }
+        | Instruction::DivInt { dest, .. }
+        | Instruction::DivUInt { dest, .. }
+        | Instruction::DivFloat { dest, .. }
+        | Instruction::RemInt { dest, .. }
+        | Instruction::RemUInt { dest, .. }
+        | Instruction::RemFloat { dest, .. }
+        | Instruction::EqInt { dest, .. }
+        | Instruction::EqFloat { dest, .. }
+        | Instruction::LessInt { dest, .. }
+        | Instruction::LessUInt { dest, .. }
+        | Instruction::LessFloat { dest, .. }
+        | Instruction::LessEqInt { dest, .. }
+        | Instruction::LessEqUInt { dest, .. }
+        | Instruction::LessEqFloat { dest, .. }
+        | Instruction::Select { dest, .. } => (dest, None),
+        Instruction::Convert { dest, conversion, .. } => (dest, Some(conversion)),
+        _ => return None,
+    };
+    let mut operands = inst.uses();
+    if let Instruction::AddInt { .. } | Instruction::AndInt { .. } | Instruction::OrInt { .. } | Instruction::XorInt { .. }
+        | Instruction::MulInt { .. } | Instruction::EqInt { .. } = inst {
+        operands.sort_by_key(|var| var.get_id());
+    }
+    Some((dest, Value { operation: mem::discriminant(inst), operands, conversion }))
+}
+
+/// Turns an instruction into a copy when an earlier one in its block, or
+/// one in a block that dominates it, computed the same value. Only
+/// variables that hold one value for the whole of a call of the function
+/// take part: those it writes in the one place anything does, and its
+/// parameters when nothing writes them. A call back into the function
+/// saves and restores them.
+fn number_values(program: &mut Program) -> bool {
+    let mut writes = vec![0; program.get_variable_count()];
+    for function_id in program.get_function_ids() {
+        for block in program.get_function(function_id).get_blocks() {
+            for var in block.get_instructions().iter().flat_map(Instruction::defs) {
+                writes[var.get_id()] += 1;
+            }
+        }
+    }
+    let mut changed = false;
+    for function_id in program.get_function_ids() {
+        let mut function = program.get_function(function_id).clone();
+        function.remove_unreachable_blocks();
+        let mut stable = vec![false; program.get_variable_count()];
+        for param in function.get_params() {
+            stable[param.get_id()] = writes[param.get_id()] == 0;
+        }
+        for block in function.get_blocks() {
+            for var in block.get_instructions().iter().flat_map(Instruction::defs) {
+                stable[var.get_id()] = writes[var.get_id()] == 1;
+            }
+        }
+        let idom = ssa::dominators(&function, &ssa::predecessors(&function));
+        let mut children = vec![Vec::new(); function.get_blocks().len()];
+        for (index, &parent) in idom.iter().enumerate().skip(1) {
+            children[parent].push(index);
+        }
+        if number_block(&mut function, BlockId::entry().get_id(), &children, &stable, &mut HashMap::new()) {
+            *program.get_function_mut(function_id) = function;
+            changed = true;
+        }
+    }
+    changed
+}
+
+/// Numbers the values of the block at `index` and of the blocks it
+/// dominates, with `available` holding the variable each value computed so
+/// far is in.
+fn number_block(function: &mut Function, index: usize, children: &[Vec<usize>], stable: &[bool], available: &mut HashMap<Value, Var>) -> bool {
+    let mut changed = false;
+    let mut added = Vec::new();
+    for inst in function.get_blocks_mut()[index].get_instructions_mut() {
+        let Some((dest, value)) = value(inst) else { continue };
+        if !stable[dest.get_id()] || !value.operands.iter().all(|var| stable[var.get_id()]) {
+            continue
+        }
+        match available.get(&value) {
+            Some(&src) => {
+                *inst = Instruction::Copy { dest, src };
+                changed = true;
+            }
+            None => {
+                available.insert(value.clone(), dest);
+                added.push(value);
+            }
+        }
+    }
+    for &child in &children[index] {
+        changed |= number_block(function, child, children, stable, available);
+    }
+    for value in added {
+        available.remove(&value);
+    }
+    changed
+}
+
+/// Reads the source of a copy in place of its destination for the rest of
+/// the block, until either is written again, and in the phis the block
+/// branches to. A call can write any register, so it forgets every copy.
+fn propagate_copies(program: &mut Program) -> bool {
+    let mut changed = false;
+    for function_id in program.get_function_ids() {
+        let function = program.get_function_mut(function_id);
+        let mut at_end = Vec::new();
+        for block in function.get_blocks_mut() {
+            let mut copies: HashMap<Var, Var> = HashMap::new();
+            let mut rename = |var: Var, copies: &HashMap<Var, Var>| match copies.get(&var) {
+                Some(&src) => {
+                    changed = true;
+                    src
+                }
+                None => var,
+            };
+            for inst in block.get_instructions_mut() {
+                // a phi reads its sources at the end of the blocks they name
+                if !matches!(inst, Instruction::Phi { .. }) {
+                    inst.map_uses(&mut |var| rename(var, &copies));
+                }
+                if let Instruction::Call { .. } | Instruction::CallBuiltin { .. } | Instruction::Spawn { .. } = inst {
+                    copies.clear();
+                }
+                for def in inst.defs() {
+                    copies.retain(|&dest, &mut src| dest != def && src != def);
+                }
+                if let Instruction::Copy { dest, src } = *inst {
+                    if dest != src {
+                        copies.insert(dest, src);
+                    }
+                }
+            }
+            let mut exit = block.get_exit_instruction().clone();
+            match &mut exit {
+                ExitInstruction::ConditionalBranch { cond: var, .. } | ExitInstruction::Switch { scrutinee: var, .. } => {
+                    *var = rename(*var, &copies)
+                }
+                ExitInstruction::Branch { .. } | ExitInstruction::Return => (),
+            }
+            block.set_exit_instruction(exit);
+            at_end.push(copies);
+        }
+        for block in function.get_blocks_mut() {
+            for inst in block.get_instructions_mut() {
+                let Instruction::Phi { sources, .. } = inst else { break };
+                for (block_id, var) in sources {
+                    if let Some(&src) = at_end[block_id.get_id()].get(var) {
+                        *var = src;
+                        changed = true;
+                    }
+                }
+            }
+        }
+    }
+    changed
+}
+
+/// Whether `inst` can be left out when nothing reads what it writes: it
+/// cannot trap or change anything else. Calls of functions are kept, since
+/// the closures a function returns read its registers directly.
+fn removable(inst: &Instruction, constants: &[Option<Constant>]) -> bool {
+    match inst {
+        Instruction::DivInt { b, .. } | Instruction::DivUInt { b, .. } | Instruction::RemInt { b, .. } | Instruction::RemUInt { b, .. } => {
+            matches!(constants[b.get_id()], Some(Constant::Int(divisor)) if divisor != 0)
+        }
+        Instruction::CallBuiltin { intrinsic, returns, .. } => !returns.is_empty() && intrinsic.effects().is_pure(),
+        Instruction::Call { .. } | Instruction::Exit { .. } | Instruction::Abort | Instruction::Spawn { .. } => false,
+        _ => true,
+    }
+}
+
+/// Removes the instructions whose results nothing reads.
+fn remove_dead_code(program: &mut Program) -> bool {
+    let uses = count_uses(program);
+    let constants = find_constants(program);
+    let mut changed = false;
+    for function_id in program.get_function_ids() {
+        for block in program.get_function_mut(function_id).get_blocks_mut() {
+            let count = block.get_instructions().len();
+            block.retain_instructions(|inst| {
+                !removable(inst, &constants) || inst.defs().iter().any(|var| uses[var.get_id()] > 0)
+            });
+            changed |= block.get_instructions().len() != count;
+        }
+    }
+    changed
+}
+
+/// Whether the block at `index` starts with phis, which name the blocks
+/// that branch to it, so that branching to it from elsewhere would need
+/// them changing too.
+fn has_phis(function: &Function, index: usize) -> bool {
+    matches!(function.get_blocks()[index].get_instructions().first(), Some(Instruction::Phi { .. }))
+}
+
+/// Where a branch to `block_id` ends up, going through empty blocks that
+/// only branch on to a block without phis.
+fn branch_target(function: &Function, mut block_id: BlockId) -> BlockId {
+    for _ in 0..function.get_blocks().len() {
+        let block = function.get_block(block_id);
+        match block.get_exit_instruction() {
+            &ExitInstruction::Branch { block: next } if block.get_instructions().is_empty()
+                && next.get_id() != block_id.get_id() && !has_phis(function, next.get_id()) => block_id = next,
+            _ => break,
+        }
+    }
+    block_id
+}
+
+/// Branches straight past empty blocks, turns conditional branches whose
+/// targets are the same into plain ones, joins a block to the one it always
+/// goes on to when nothing else goes there, and removes the blocks nothing
+/// branches to any more. None of these changes which blocks branch to a
+/// block with phis.
+fn simplify_branches(program: &mut Program) -> bool {
+    let mut changed = false;
+    for function_id in program.get_function_ids() {
+        let function = program.get_function_mut(function_id);
+        for index in 0..function.get_blocks().len() {
+            let mut exit = function.get_blocks()[index].get_exit_instruction().clone();
+            let mut thread = |block_id: &mut BlockId| {
+                let target = branch_target(function, *block_id);
+                if target.get_id() != block_id.get_id() {
+                    *block_id = target;
+                    changed = true;
+                }
+            };
+            match &mut exit {
+                ExitInstruction::Branch { block } => thread(block),
+                ExitInstruction::ConditionalBranch { block1, block2, .. } => {
+                    thread(block1);
+                    thread(block2);
+                }
+                ExitInstruction::Switch { targets, default, .. } => {
+                    targets.iter_mut().for_each(&mut thread);
+                    thread(default);
+                }
+                ExitInstruction::Return => (),
+            }
+            if let ExitInstruction::ConditionalBranch { block1, block2, .. } = exit {
+                if block1.get_id() == block2.get_id() {
+                    exit = ExitInstruction::Branch { block: block1 };
+                    changed = true;
+                }
+            }
+            function.get_blocks_mut()[index].set_exit_instruction(exit);
+        }
+        let mut predecessors = vec![0; function.get_blocks().len()];
+        for index in 0..function.get_blocks().len() {
+            for successor in function.successors(index) {
+                predecessors[successor] += 1;
+            }
+        }
+        for index in 0..function.get_blocks().len() {
+            if let &ExitInstruction::Branch { block: next } = function.get_blocks()[index].get_exit_instruction() {
+                let next = next.get_id();
+                let phis = has_phis(function, next) || function.successors(next).into_iter().any(|successor| has_phis(function, successor));
+                if next != index && next != BlockId::entry().get_id() && predecessors[next] == 1 && !phis {
+                    let next = function.get_blocks()[next].clone();
+                    function.get_blocks_mut()[index].append(&next);
+                    changed = true;
+                }
+            }
+        }
+        changed |= function.remove_unreachable_blocks();
+    }
+    changed
+}
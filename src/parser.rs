@@ -1,137 +1,864 @@
 use crate::position::Position;
-use crate::ast::{Expr, Parsed, BinaryOp};
+use crate::ast::{Expr, Parsed, BinaryOp, Prec};
+use crate::diagnostics::Diagnostic;
+use crate::source_map::SourceMap;
+use std::collections::BTreeMap;
+use std::fmt;
 
-#[derive(Debug)]
-pub struct ParseError<'a> {
-    pos: Position<'a>,
-    ty: ParseErrorType,
+/// Words that cannot be names. `return` is kept for syntax to come, and
+/// `ir` is only a keyword before a brace.
+pub const KEYWORDS: &[&str] = &[
+    "allow", "as", "break", "cfg", "defer", "else", "false", "fn", "for", "if", "in", "match", "return", "struct", "true", "while",
+];
+
+/// How deeply expressions can be nested. The stages after parsing recurse
+/// into each expression they hold, so deeper input would overflow the stack.
+pub const MAX_DEPTH: usize = 128;
+
+pub fn is_keyword(word: &str) -> bool {
+    KEYWORDS.contains(&word)
 }
 
+/// A parse error lists everything that would have been accepted where
+/// parsing stopped, names the keyword that was written as a name, or marks
+/// an expression nested more than `MAX_DEPTH` deep.
 #[derive(Debug)]
-pub enum ParseErrorType {
-    ExpectedValue,
-    ExpectedString(&'static str)
+pub struct ParseError<'a> {
+    pos: Position<'a>,
+    expected: Vec<Expected>,
+    keyword: Option<&'a str>,
+    too_deep: bool,
 }
 
-#[derive(PartialEq, PartialOrd, Copy, Clone)]
-enum Prec {
-    Block,
-    Tuple,
-    Expr,
-    Sum,
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Expected {
+    Value,
+    Token(&'static str),
+    Operator,
+    Name,
+    Type,
+    EndOfInput,
 }
 
 impl<'a> ParseError<'a> {
+    /// Whether the error was found at the very end of the input, meaning
+    /// more input could still make it parse.
+    pub fn is_incomplete(&self) -> bool {
+        self.pos.is_empty()
+    }
+    pub fn expected(&self) -> &[Expected] {
+        &self.expected
+    }
+    pub fn code(&self) -> &'static str {
+        match self.expected.as_slice() {
+            _ if self.keyword.is_some() => "E0102",
+            _ if self.too_deep => "E0103",
+            [Expected::Value] => "E0100",
+            _ => "E0101",
+        }
+    }
+    pub fn to_diagnostic(&self, files: &SourceMap) -> Diagnostic {
+        let span = match self.keyword {
+            Some(keyword) => files.span_of(keyword),
+            None => files.span_at(self.pos),
+        };
+        let diagnostic = Diagnostic::error(self.to_string(), span).with_code(self.code());
+        match self.too_deep {
+            true => diagnostic.with_help("give part of the expression a name of its own".to_string()),
+            false => diagnostic,
+        }
+    }
+    fn new(pos: Position<'a>, expected: Vec<Expected>) -> ParseError<'a> {
+        ParseError { pos, expected, keyword: None, too_deep: false }
+    }
     fn expected_value(pos: Position<'a>) -> ParseError<'a> {
-        ParseError { pos, ty: ParseErrorType::ExpectedValue }
+        ParseError::new(pos, vec![Expected::Value])
     }
     fn expected_string(pos: Position<'a>, string: &'static str) -> ParseError<'a> {
-        ParseError { pos, ty: ParseErrorType::ExpectedString(string) }
+        ParseError::new(pos, vec![Expected::Token(string)])
+    }
+    /// `keyword`, at `pos`, was written where a name was expected.
+    fn reserved(pos: Position<'a>, keyword: &'a str) -> ParseError<'a> {
+        ParseError { pos, expected: vec![Expected::Name], keyword: Some(keyword), too_deep: false }
+    }
+    /// The expression at `pos` is nested more than `MAX_DEPTH` deep.
+    fn too_deep(pos: Position<'a>) -> ParseError<'a> {
+        ParseError { pos, expected: Vec::new(), keyword: None, too_deep: true }
+    }
+    /// A closing token was missing after an expression parsed at `prec`, so
+    /// anything that could have continued the expression was valid too.
+    fn expected_after(pos: Position<'a>, expected: Expected, prec: Prec) -> ParseError<'a> {
+        let mut error = ParseError::new(pos, vec![expected]);
+        if prec <= Prec::Tuple {
+            error.expected.push(Expected::Token(","));
+        }
+        if prec < Prec::Call {
+            error.expected.push(Expected::Operator);
+        }
+        error
+    }
+}
+
+impl<'a> fmt::Display for ParseError<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(keyword) = self.keyword {
+            return write!(f, "`{}` is a keyword, so it cannot be used as a name", keyword)
+        }
+        if self.too_deep {
+            return write!(f, "expressions cannot be nested more than {} deep", MAX_DEPTH)
+        }
+        write!(f, "expected ")?;
+        for (index, expected) in self.expected.iter().enumerate() {
+            if index > 0 {
+                match self.expected.len() {
+                    2 => write!(f, " or ")?,
+                    len if index == len - 1 => write!(f, ", or ")?,
+                    _ => write!(f, ", ")?,
+                }
+            }
+            write!(f, "{}", expected)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Expected {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Expected::Value => write!(f, "a value"),
+            Expected::Token(token) => write!(f, "`{}`", token),
+            Expected::Operator => write!(f, "an operator"),
+            Expected::Name => write!(f, "a name"),
+            Expected::Type => write!(f, "a type"),
+            Expected::EndOfInput => write!(f, "the end of the input"),
+        }
+    }
+}
+
+fn is_ident_start(ch: char) -> bool {
+    ch.is_alphabetic() || ch == '_'
+}
+
+fn is_ident_char(ch: char) -> bool {
+    ch.is_alphanumeric() || ch == '_'
+}
+
+/// Whether `ch` can begin a value, and so a new expression in a block.
+fn is_value_start(ch: char) -> bool {
+    ch.is_numeric() || is_ident_start(ch) || ch == '(' || ch == '{' || ch == '|' || ch == '!'
+}
+
+/// The length of the `/* */` comment at the start of `source`, with the
+/// comments nested in it, or `None` if it is never closed.
+fn block_comment_len(source: &str) -> Option<usize> {
+    let bytes = source.as_bytes();
+    let mut depth = 0;
+    let mut index = 0;
+    while index < bytes.len() {
+        if bytes[index..].starts_with(b"/*") {
+            depth += 1;
+            index += 2;
+        } else if bytes[index..].starts_with(b"*/") {
+            depth -= 1;
+            index += 2;
+            if depth == 0 {
+                return Some(index)
+            }
+        } else {
+            index += 1;
+        }
     }
+    None
 }
 
+/// Skips spaces and comments, up to the end of the line. A `/* */` comment
+/// is a space even when it spans lines.
 fn skip_spaces(pos: Position) -> Position {
-    pos.next_while(|ch| ch.is_whitespace() && ch != '\n')
+    let mut pos = pos.next_while(|ch| ch.is_whitespace() && ch != '\n');
+    while pos.rest().starts_with("/*") {
+        let len = block_comment_len(pos.rest()).unwrap_or(pos.len());
+        pos = pos.skip(len).next_while(|ch| ch.is_whitespace() && ch != '\n');
+    }
+    if pos.rest().starts_with("//") {
+        pos.next_while(|ch| ch != '\n')
+    } else {
+        pos
+    }
 }
 
 fn skip_lines(pos: Position) -> Position {
-    pos.next_while(|ch| ch.is_whitespace())
+    let mut pos = skip_spaces(pos);
+    while let Some((next, '\n')) = pos.next() {
+        pos = skip_spaces(next);
+    }
+    pos
+}
+
+/// The comments in the source that no expression has kept yet, by offset.
+/// The items of a block are parsed before the block they are in, so items
+/// take the comments inside them before any enclosing item can.
+struct Comments<'a> {
+    source: &'a str,
+    unclaimed: BTreeMap<usize, &'a str>,
+    /// Whether the last `/* */` comment is never closed.
+    unclosed: bool,
+}
+
+impl<'a> Comments<'a> {
+    fn new(source: &'a str) -> Comments<'a> {
+        let mut unclaimed = BTreeMap::new();
+        let mut unclosed = false;
+        let mut from = 0;
+        while let Some(start) = source[from..].find('/').map(|index| from + index) {
+            let rest = &source[start..];
+            let end = if rest.starts_with("//") {
+                rest.find('\n').map_or(source.len(), |index| start + index)
+            } else if rest.starts_with("/*") {
+                let len = block_comment_len(rest);
+                unclosed = len.is_none();
+                len.map_or(source.len(), |len| start + len)
+            } else {
+                from = start + 1;
+                continue
+            };
+            unclaimed.insert(start, source[start..end].trim_end());
+            from = end;
+        }
+        Comments { source, unclaimed, unclosed }
+    }
+    fn offset(&self, pos: Position) -> usize {
+        self.source.len() - pos.len()
+    }
+    /// Takes the comments that start between `from` and `to`.
+    fn take(&mut self, from: usize, to: usize) -> Vec<&'a str> {
+        if self.unclaimed.is_empty() {
+            return Vec::new()
+        }
+        let offsets: Vec<usize> = self.unclaimed.range(from..to).map(|(&offset, _)| offset).collect();
+        offsets.iter().filter_map(|offset| self.unclaimed.remove(offset)).collect()
+    }
+    /// Gives `item` the comments before it and inside it, keeping the doc
+    /// comments directly before a declaration apart.
+    fn lead(&mut self, item: &mut Parsed<'a, Expr<'a>>, from: usize) {
+        let start = self.offset(item.start());
+        let mut leading = self.take(from, start);
+        if item.node.is_declaration() {
+            let doc = leading.iter().rev().take_while(|comment| comment.starts_with("///")).count();
+            item.trivia.doc = leading.split_off(leading.len() - doc);
+        }
+        leading.extend(self.take(start, self.offset(item.end())));
+        item.trivia.leading = leading;
+    }
+    /// Takes the comment after `pos` on the same line, if there is one.
+    fn take_same_line(&mut self, pos: Position) -> Option<&'a str> {
+        let from = self.offset(pos);
+        let (&offset, _) = self.unclaimed.range(from..).next()?;
+        if self.source[from..offset].contains('\n') {
+            return None
+        }
+        self.unclaimed.remove(&offset)
+    }
+    /// Gives the last item of `block` the comments after it, up to `to`.
+    fn take_after_block(&mut self, block: &mut Parsed<'a, Expr<'a>>, to: usize) {
+        if let Expr::Block { last, .. } = &mut block.node {
+            let from = self.offset(last.end());
+            last.trivia.trailing.extend(self.take(from, to));
+        }
+    }
+}
+
+/// The last item of a block, or the expression itself if it is not one.
+fn last_item<'b, 'a>(expr: &'b mut Parsed<'a, Expr<'a>>) -> &'b mut Parsed<'a, Expr<'a>> {
+    if !matches!(expr.node, Expr::Block { .. }) {
+        return expr
+    }
+    match &mut expr.node {
+        Expr::Block { last, .. } => last,
+        _ => unreachable!(),
+    }
+}
+
+/// An expression waiting on one inside it. The parser keeps these on a stack
+/// rather than recursing, so deeply nested input cannot overflow the stack.
+enum Frame<'a> {
+    /// `(`, waiting for `)`.
+    Bracket { start: Position<'a> },
+    /// `{`, waiting for `}`.
+    Brace { start: Position<'a> },
+    FuncPattern { start: Position<'a>, name: Parsed<'a, Option<&'a str>> },
+    FuncBody { start: Position<'a>, name: Parsed<'a, Option<&'a str>>, pattern: Parsed<'a, Expr<'a>> },
+    /// `|`, waiting for each parameter of a function written `|x, y| body`.
+    LambdaParams { start: Position<'a>, params: Vec<Parsed<'a, Expr<'a>>> },
+    Struct { start: Position<'a> },
+    IfCond { start: Position<'a> },
+    IfConc { start: Position<'a>, cond: Parsed<'a, Expr<'a>> },
+    /// `match`, waiting for the value before the arms.
+    MatchValue { start: Position<'a> },
+    /// The pattern of an arm, after the arms before it.
+    MatchPattern { start: Position<'a>, value: Box<Parsed<'a, Expr<'a>>>, arms: Vec<(Parsed<'a, Expr<'a>>, Parsed<'a, Expr<'a>>)> },
+    MatchArm { start: Position<'a>, value: Box<Parsed<'a, Expr<'a>>>, arms: Vec<(Parsed<'a, Expr<'a>>, Parsed<'a, Expr<'a>>)>, pattern: Parsed<'a, Expr<'a>> },
+    /// `for`, waiting for the pattern before `in`.
+    ForPattern { start: Position<'a>, label: Option<&'a str> },
+    ForIterable { start: Position<'a>, label: Option<&'a str>, pattern: Box<Parsed<'a, Expr<'a>>> },
+    ForBody { start: Position<'a>, label: Option<&'a str>, pattern: Box<Parsed<'a, Expr<'a>>>, iterable: Box<Parsed<'a, Expr<'a>>> },
+    /// `while`, waiting for its bracketed condition.
+    WhileCond { start: Position<'a>, label: Option<&'a str> },
+    WhileBody { start: Position<'a>, label: Option<&'a str>, cond: Box<Parsed<'a, Expr<'a>>> },
+    Defer { start: Position<'a> },
+    /// `!`, waiting for its operand.
+    Not { start: Position<'a> },
+    /// `cfg(name)`, waiting for the expression it controls.
+    Cfg { start: Position<'a>, name: Parsed<'a, &'a str> },
+    /// `allow(name)`, waiting for the expression it applies to.
+    Allow { start: Position<'a>, name: Parsed<'a, &'a str> },
+    Binary { left: Parsed<'a, Expr<'a>>, op: BinaryOp },
+    Tuple { left: Parsed<'a, Expr<'a>> },
+    Block { left: Parsed<'a, Expr<'a>> },
+}
+
+enum Step<'a> {
+    /// A value was parsed, and operators may follow it.
+    Operator(Parsed<'a, Expr<'a>>),
+    /// Parse an expression at `pos` at `prec`, then go back to `frame`.
+    Nested { frame: Frame<'a>, pos: Position<'a>, prec: Prec },
+    /// Nothing more belongs to the expression.
+    Done(Parsed<'a, Expr<'a>>),
+}
+
+/// The whitespace after the end of a token or an expression. The token, each
+/// expression that ends at the same place and each frame resumed there all
+/// look past it again, so it is only scanned once.
+struct Whitespace<'a> {
+    from: usize,
+    spaces: Position<'a>,
+    lines: Position<'a>,
+}
+
+impl<'a> Whitespace<'a> {
+    /// The end of the spaces and of the lines after `pos`.
+    fn after(cache: &mut Option<Whitespace<'a>>, pos: Position<'a>) -> (Position<'a>, Position<'a>) {
+        match cache {
+            Some(whitespace) if whitespace.from == pos.len() => (whitespace.spaces, whitespace.lines),
+            _ => {
+                let spaces = skip_spaces(pos);
+                let lines = skip_lines(spaces);
+                *cache = Some(Whitespace { from: pos.len(), spaces, lines });
+                (spaces, lines)
+            }
+        }
+    }
+}
+
+/// Parses `source`, going on past a missing `)` or `}` as if it were there.
+/// The errors gone past are added to `recovered`, and any other error ends
+/// parsing.
+fn parse<'a>(source: &'a str, recovered: &mut Vec<ParseError<'a>>) -> Result<Parsed<'a, Expr<'a>>, ParseError<'a>> {
+    let mut stack: Vec<(Frame<'a>, Prec)> = Vec::new();
+    let mut whitespace = None;
+    let mut comments = Comments::new(source);
+    // the comment hides the rest of the input, so nothing else can be wrong
+    if comments.unclosed {
+        let end = Position::from_source(source).skip(source.len());
+        return Err(ParseError::expected_string(end, "*/"))
+    }
+    let mut prec = Prec::Block;
+    let mut step = parse_value(skip_lines(Position::from_source(source)), false, &mut whitespace)?;
+    loop {
+        step = match step {
+            Step::Operator(left) => parse_operator(left, prec, &mut whitespace)?,
+            Step::Nested { frame, pos, prec: inner } => {
+                stack.push((frame, prec));
+                prec = inner;
+                parse_value(pos, binds_names(&stack), &mut whitespace)?
+            }
+            Step::Done(mut expr) => match stack.pop() {
+                Some((frame, outer)) => {
+                    prec = outer;
+                    // the items of a block keep the comments back to the
+                    // brace the block started at
+                    let block_start = match stack.last() {
+                        Some((Frame::Brace { start }, _)) => comments.offset(*start),
+                        _ => 0,
+                    };
+                    resume(frame, expr, &mut comments, &mut whitespace, block_start, recovered)?
+                }
+                None => {
+                    comments.take_after_block(&mut expr, usize::MAX);
+                    comments.lead(&mut expr, 0);
+                    let end = comments.offset(expr.end());
+                    expr.trivia.trailing.extend(comments.take(end, usize::MAX));
+                    return Ok(expr)
+                }
+            }
+        }
+    }
 }
 
-fn parse<'a>(start: Position<'a>, prec: Prec) -> Result<Parsed<'a, Expr<'a>>, ParseError> {
-    let mut left = match start.next() {
+/// Whether the value parsed next is part of a pattern that binds names:
+/// the parameters of a function or the pattern of a `for` or an arm.
+fn binds_names(stack: &[(Frame, Prec)]) -> bool {
+    let pattern = stack.iter().rev().find(|(frame, _)| !matches!(frame, Frame::Bracket { .. } | Frame::Tuple { .. }));
+    matches!(pattern, Some((Frame::FuncPattern { .. } | Frame::LambdaParams { .. } | Frame::ForPattern { .. } | Frame::MatchPattern { .. }, _)))
+}
+
+/// Parses the value at `start`, which is part of a pattern that binds names
+/// if `binding` is set.
+fn parse_value<'a>(start: Position<'a>, binding: bool, whitespace: &mut Option<Whitespace<'a>>) -> Result<Step<'a>, ParseError<'a>> {
+    match start.next() {
         Some((pos, ch)) if ch.is_numeric() => {
             let end = pos.next_while(|ch| ch.is_numeric());
-            Ok(Parsed::new(start, end, Expr::IntLiteral(Position::slice(start, end))))
+            Ok(Step::Operator(match end.next() {
+                Some((pos, '.')) if matches!(pos.next(), Some((_, ch)) if ch.is_numeric()) => {
+                    let end = pos.next_while(|ch| ch.is_numeric());
+                    Parsed::new(start, end, Expr::FloatLiteral(Position::slice(start, end)))
+                }
+                // a suffix is `u`, or `i` or `u` and a size, such as `i32`
+                Some((pos, 'u')) => {
+                    let end = pos.next_while(|ch| ch.is_numeric());
+                    Parsed::new(start, end, Expr::IntLiteral(Position::slice(start, end)))
+                }
+                Some((pos, 'i')) if matches!(pos.next(), Some((_, ch)) if ch.is_numeric()) => {
+                    let end = pos.next_while(|ch| ch.is_numeric());
+                    Parsed::new(start, end, Expr::IntLiteral(Position::slice(start, end)))
+                }
+                _ => Parsed::new(start, end, Expr::IntLiteral(Position::slice(start, end))),
+            }))
         }
         Some((pos, '(')) => {
-            let expr = parse(skip_lines(pos), Prec::Tuple)?;
-            match expr.end().next() {
-                Some((end, ')')) => Ok(Parsed::new(start, end, expr.node)),
-                _ => Err(ParseError::expected_string(skip_lines(expr.end()), ")"))
+            if let Some((end, ')')) = skip_lines(pos).next() {
+                return Ok(Step::Operator(Parsed::new(start, end, Expr::Tuple { exprs: vec![] })))
             }
+            Ok(Step::Nested { frame: Frame::Bracket { start }, pos: skip_lines(pos), prec: Prec::Tuple })
         }
-        Some((pos, '{')) => {
-            let expr = parse(skip_lines(pos), Prec::Block)?;
-            match skip_lines(expr.end()).next() {
-                Some((end, '}')) => Ok(Parsed::new(start, end, expr.node)),
-                _ => Err(ParseError::expected_string(skip_lines(expr.end()), "}"))
+        Some((pos, '{')) => Ok(Step::Nested { frame: Frame::Brace { start }, pos: skip_lines(pos), prec: Prec::Block }),
+        Some((pos, '!')) => Ok(Step::Nested { frame: Frame::Not { start }, pos: skip_lines(pos), prec: Prec::Cast }),
+        Some((pos, '|')) => match skip_lines(pos).next() {
+            Some((end, '|')) => {
+                let pattern = Parsed::new(start, end, Expr::Tuple { exprs: vec![] });
+                Ok(lambda_body(start, pattern, end))
             }
+            // a parameter stops before `|`, so it cannot be an operator
+            _ => Ok(Step::Nested { frame: Frame::LambdaParams { start, params: Vec::new() }, pos: skip_lines(pos), prec: Prec::BitOr }),
         }
-        Some((pos, ch)) if ch.is_alphabetic() => {
-            let end = pos.next_while(|ch| ch.is_alphanumeric());
-            match Position::slice(start, end) {
+        Some((pos, ch)) if is_ident_start(ch) => {
+            let end = pos.next_while(is_ident_char);
+            let (spaces, after) = Whitespace::after(whitespace, end);
+            let word = Position::slice(start, end);
+            // a keyword that starts a construct was meant as a name where a
+            // name is bound or assigned
+            let assigned = matches!(spaces.next(), Some((next, '=')) if !matches!(next.next(), Some((_, '=' | '>'))));
+            if is_keyword(word) && !matches!(word, "true" | "false") && (binding || assigned) {
+                return Err(ParseError::reserved(start, word))
+            }
+            match word {
                 "fn" => {
-                    let name = match skip_lines(end).next() {
-                        Some((pos, ch)) if ch.is_alphabetic() => {
-                            let end_name = pos.next_while(|ch| ch.is_alphanumeric());
-                            Parsed::new(skip_lines(end), end_name, Some(Position::slice(skip_lines(end), end_name)))
+                    let name = match after.next() {
+                        Some((pos, ch)) if is_ident_start(ch) => {
+                            let end_name = pos.next_while(is_ident_char);
+                            let name = Position::slice(after, end_name);
+                            if is_keyword(name) {
+                                return Err(ParseError::reserved(after, name))
+                            }
+                            Parsed::new(after, end_name, Some(name))
                         }
-                        _ => Parsed::new(skip_lines(end), skip_lines(end), None),
+                        _ => Parsed::new(after, after, None),
+                    };
+                    let pattern_start = skip_lines(name.end());
+                    match pattern_start.next() {
+                        Some((_, '(')) => Ok(Step::Nested { frame: Frame::FuncPattern { start, name }, pos: pattern_start, prec: Prec::Call }),
+                        _ if name.node.is_some() => Err(ParseError::expected_string(pattern_start, "(")),
+                        _ => Err(ParseError::new(after, vec![Expected::Name, Expected::Token("(")])),
+                    }
+                }
+                "struct" => match after.next() {
+                    Some((_, '{')) => Ok(Step::Nested { frame: Frame::Struct { start }, pos: after, prec: Prec::Expr }),
+                    _ => Err(ParseError::expected_string(after, "{")),
+                }
+                "if" => match after.next() {
+                    Some((_, '(')) => Ok(Step::Nested { frame: Frame::IfCond { start }, pos: after, prec: Prec::Call }),
+                    _ => Err(ParseError::expected_string(after, "(")),
+                }
+                "match" => Ok(Step::Nested { frame: Frame::MatchValue { start }, pos: after, prec: Prec::Expr }),
+                "defer" => Ok(Step::Nested { frame: Frame::Defer { start }, pos: after, prec: Prec::Expr }),
+                "for" => Ok(Step::Nested { frame: Frame::ForPattern { start, label: None }, pos: after, prec: Prec::Call }),
+                "while" => while_cond(start, None, after),
+                // a label must be on the same line, so that `break` can end
+                // a line before an item that starts with a name
+                "break" => {
+                    let label_start = spaces;
+                    let label_end = label_start.next_while(is_ident_char);
+                    let label = match Position::slice(label_start, label_end) {
+                        label if is_keyword(label) => None,
+                        label if label.starts_with(is_ident_start) => Some(label),
+                        _ => None,
+                    };
+                    let end = if label.is_some() { label_end } else { end };
+                    Ok(Step::Operator(Parsed::new(start, end, Expr::Break { label })))
+                }
+                // `ir` is only a keyword when a brace follows on the same
+                // line, so it can still be used as a name
+                "ir" if matches!(spaces.next(), Some((_, '{'))) => {
+                    let text_start = spaces.next().map_or(end, |(pos, _)| pos);
+                    let text_end = text_start.next_while(|ch| ch != '}');
+                    match text_end.next() {
+                        Some((close, _)) => Ok(Step::Operator(Parsed::new(start, close, Expr::InlineIr(Position::slice(text_start, text_end))))),
+                        None => Err(ParseError::expected_string(text_end, "}")),
+                    }
+                }
+                keyword @ ("cfg" | "allow") => {
+                    let name_start = match after.next() {
+                        Some((pos, '(')) => skip_lines(pos),
+                        _ => return Err(ParseError::expected_string(after, "(")),
                     };
-                    let pattern = match skip_lines(name.end()).next() {
-                        Some((_, '(')) => parse(skip_lines(name.end()), Prec::Expr),
-                        _ => Err(ParseError::expected_string(skip_lines(end), "(")),
-                    }?;
-                    let expr = parse(skip_lines(pattern.end()), Prec::Expr)?;
-                    Ok(Parsed::new(start, expr.end(), Expr::Func { name: name.node, pattern: Box::new(pattern), expr: Box::new(expr) }))
-                }
-                "struct" => {
-                    let body = match skip_lines(end).next() {
-                        Some((_, '{')) => parse(skip_lines(end), Prec::Expr),
-                        _ => Err(ParseError::expected_string(skip_lines(end), "{")),
-                    }?;
-                    Ok(Parsed::new(start, body.end(), Expr::Struct { body: Box::new(body) }))
-                }
-                "if" => {
-                    let cond = match skip_lines(end).next() {
-                        Some((_, '(')) => parse(skip_lines(end), Prec::Expr),
-                        _ => Err(ParseError::expected_string(skip_lines(end), "(")),
-                    }?;
-                    let conc = parse(skip_lines(cond.end()), Prec::Expr)?;
-                    Ok(Parsed::new(start, conc.end(), Expr::If { cond: Box::new(cond), conc: Box::new(conc) }))
-                }
-                "true" | "false" => Ok(Parsed::new(start, end, Expr::BoolLiteral(Position::slice(start, end)))),
-                _ => Ok(Parsed::new(start, end, Expr::Ident(Position::slice(start, end)))),
+                    let name_end = name_start.next_while(is_ident_char);
+                    if !matches!(name_start.next(), Some((_, ch)) if is_ident_start(ch)) {
+                        return Err(ParseError::new(name_start, vec![Expected::Name]))
+                    }
+                    let name = Parsed::new(name_start, name_end, Position::slice(name_start, name_end));
+                    match skip_lines(name_end).next() {
+                        Some((close, ')')) => {
+                            let frame = match keyword {
+                                "cfg" => Frame::Cfg { start, name },
+                                _ => Frame::Allow { start, name },
+                            };
+                            Ok(Step::Nested { frame, pos: skip_lines(close), prec: Prec::Expr })
+                        }
+                        _ => Err(ParseError::expected_string(skip_lines(name_end), ")")),
+                    }
+                }
+                "true" | "false" => Ok(Step::Operator(Parsed::new(start, end, Expr::BoolLiteral(Position::slice(start, end))))),
+                // the keywords left are those that only follow a value, or
+                // that nothing uses yet
+                keyword if is_keyword(keyword) => Err(ParseError::reserved(start, keyword)),
+                // `name:` labels the loop that follows it
+                label if matches!(spaces.next(), Some((_, ':'))) => {
+                    let keyword_start = spaces.next().map_or(end, |(colon, _)| skip_lines(colon));
+                    let keyword_end = keyword_start.next_while(is_ident_char);
+                    match Position::slice(keyword_start, keyword_end) {
+                        "for" => Ok(Step::Nested { frame: Frame::ForPattern { start, label: Some(label) }, pos: skip_lines(keyword_end), prec: Prec::Call }),
+                        "while" => while_cond(start, Some(label), skip_lines(keyword_end)),
+                        _ => Err(ParseError::new(keyword_start, vec![Expected::Token("for"), Expected::Token("while")])),
+                    }
+                }
+                _ => Ok(Step::Operator(Parsed::new(start, end, Expr::Ident(Position::slice(start, end))))),
             }
         }
         _ => Err(ParseError::expected_value(start))
-    }?;
+    }
+}
 
-    loop {
-        let start = skip_spaces(left.end());
-        left = match start.next() {
-            Some((pos, '+')) if prec < Prec::Sum => {
-                Expr::new_binary(left, parse(skip_lines(pos), prec)?, BinaryOp::Plus)
+/// `while` is followed by its condition in brackets, as `if` is.
+fn while_cond<'a>(start: Position<'a>, label: Option<&'a str>, after: Position<'a>) -> Result<Step<'a>, ParseError<'a>> {
+    match after.next() {
+        Some((_, '(')) => Ok(Step::Nested { frame: Frame::WhileCond { start, label }, pos: after, prec: Prec::Call }),
+        _ => Err(ParseError::expected_string(after, "(")),
+    }
+}
+
+/// `|params| body` is a function with no name, so the body follows as it
+/// does after `fn(params)`.
+fn lambda_body<'a>(start: Position<'a>, pattern: Parsed<'a, Expr<'a>>, end: Position<'a>) -> Step<'a> {
+    let name = Parsed::new(start, start, None);
+    Step::Nested { frame: Frame::FuncBody { start, name, pattern }, pos: skip_lines(end), prec: Prec::Expr }
+}
+
+fn parse_operator<'a>(left: Parsed<'a, Expr<'a>>, prec: Prec, whitespace: &mut Option<Whitespace<'a>>) -> Result<Step<'a>, ParseError<'a>> {
+    let (start, next_line) = Whitespace::after(whitespace, left.end());
+    let nested = |frame, pos, prec| Ok(Step::Nested { frame, pos, prec });
+    // comparisons and the logical operators have no `op=` form, so `<=`
+    // is checked before `<<=` and `&&` before `&=`
+    let comparison = match start.next() {
+        Some((pos, '=')) => match pos.next() {
+            Some((pos, '=')) => Some((pos, Prec::Compare, BinaryOp::DoubleEquals)),
+            _ => None,
+        }
+        Some((pos, '!')) => match pos.next() {
+            Some((pos, '=')) => Some((pos, Prec::Compare, BinaryOp::NotEqual)),
+            _ => None,
+        }
+        Some((pos, '<')) => match pos.next() {
+            Some((pos, '=')) => Some((pos, Prec::Compare, BinaryOp::LessOrEqual)),
+            Some((_, '<')) => None,
+            _ => Some((pos, Prec::Compare, BinaryOp::Less)),
+        }
+        Some((pos, '>')) => match pos.next() {
+            Some((pos, '=')) => Some((pos, Prec::Compare, BinaryOp::GreaterOrEqual)),
+            Some((_, '>')) => None,
+            _ => Some((pos, Prec::Compare, BinaryOp::Greater)),
+        }
+        Some((pos, '&')) => match pos.next() {
+            Some((pos, '&')) => Some((pos, Prec::And, BinaryOp::And)),
+            _ => None,
+        }
+        Some((pos, '|')) => match pos.next() {
+            Some((pos, '|')) => Some((pos, Prec::Or, BinaryOp::Or)),
+            _ => None,
+        }
+        _ => None,
+    };
+    if let Some((pos, op_prec, op)) = comparison {
+        return if prec < op_prec {
+            nested(Frame::Binary { left, op }, skip_lines(pos), op_prec)
+        } else {
+            Ok(Step::Done(left))
+        }
+    }
+    let operator = match start.next() {
+        Some((pos, '+')) => Some((pos, Prec::Sum, BinaryOp::Plus, BinaryOp::PlusEquals)),
+        Some((pos, '-')) => Some((pos, Prec::Sum, BinaryOp::Minus, BinaryOp::MinusEquals)),
+        Some((pos, '*')) => Some((pos, Prec::Product, BinaryOp::Times, BinaryOp::TimesEquals)),
+        Some((pos, '/')) => Some((pos, Prec::Product, BinaryOp::Divide, BinaryOp::DivideEquals)),
+        Some((pos, '%')) => Some((pos, Prec::Product, BinaryOp::Remainder, BinaryOp::RemainderEquals)),
+        Some((pos, '&')) => Some((pos, Prec::BitAnd, BinaryOp::BitAnd, BinaryOp::BitAndEquals)),
+        Some((pos, '^')) => Some((pos, Prec::BitXor, BinaryOp::BitXor, BinaryOp::BitXorEquals)),
+        Some((pos, '|')) => Some((pos, Prec::BitOr, BinaryOp::BitOr, BinaryOp::BitOrEquals)),
+        Some((pos, '<')) => match pos.next() {
+            Some((pos, '<')) => Some((pos, Prec::Shift, BinaryOp::ShiftLeft, BinaryOp::ShiftLeftEquals)),
+            _ => None,
+        }
+        Some((pos, '>')) => match pos.next() {
+            Some((pos, '>')) => Some((pos, Prec::Shift, BinaryOp::ShiftRight, BinaryOp::ShiftRightEquals)),
+            _ => None,
+        }
+        _ => None,
+    };
+    // `op=` is an assignment, so it binds as loosely as `=` does
+    if let Some((pos, op_prec, op, assign_op)) = operator {
+        return match pos.next() {
+            Some((pos, '=')) if prec <= Prec::Expr => nested(Frame::Binary { left, op: assign_op }, skip_lines(pos), Prec::Expr),
+            Some((_, '=')) => Ok(Step::Done(left)),
+            _ if prec < op_prec => nested(Frame::Binary { left, op }, skip_lines(pos), op_prec),
+            _ => Ok(Step::Done(left)),
+        }
+    }
+    match start.next() {
+        Some((pos, '=')) if prec <= Prec::Expr => {
+            nested(Frame::Binary { left, op: BinaryOp::SingleEquals }, skip_lines(pos), Prec::Expr)
+        }
+        Some((_, '(')) if prec < Prec::Call => {
+            nested(Frame::Binary { left, op: BinaryOp::Bracket }, start, Prec::Call)
+        }
+        Some((pos, '.')) if prec < Prec::Call => {
+            if !matches!(pos.next(), Some((_, ch)) if is_ident_start(ch)) {
+                return Err(ParseError::new(pos, vec![Expected::Name]))
             }
-            Some((pos, '=')) if prec <= Prec::Expr => {
-                Expr::new_binary(left, parse(skip_lines(pos), Prec::Expr)?, BinaryOp::SingleEquals)
+            let name_end = pos.next_while(is_ident_char);
+            let name = Parsed::new(pos, name_end, Position::slice(pos, name_end));
+            Ok(Step::Operator(Parsed::new(left.start(), name_end, Expr::Field { expr: Box::new(left), name })))
+        }
+        Some((pos, ',')) if prec <= Prec::Tuple => {
+            nested(Frame::Tuple { left }, skip_lines(pos), Prec::Tuple)
+        }
+        Some((_, ch)) if ch.is_alphabetic() => {
+            let end = start.next_while(|ch| ch.is_alphabetic());
+            let keyword = Position::slice(start, end);
+            match keyword {
+                // only as loose as an assignment, so that in `f(x) else y`
+                // the call's result is unwrapped rather than its argument
+                "else" if prec <= Prec::Expr => nested(Frame::Binary { left, op: BinaryOp::Else }, skip_lines(end), Prec::Expr),
+                "as" if prec < Prec::Cast => {
+                    let ty_start = skip_lines(end);
+                    let ty_end = ty_start.next_while(|ch| ch.is_alphanumeric());
+                    if ty_end.len() == ty_start.len() {
+                        return Err(ParseError::new(ty_start, vec![Expected::Type]))
+                    }
+                    let ty = Parsed::new(ty_start, ty_end, Position::slice(ty_start, ty_end));
+                    Ok(Step::Operator(Parsed::new(left.start(), ty_end, Expr::Cast { expr: Box::new(left), ty })))
+                }
+                _ => Ok(Step::Done(left))
+            }
+        }
+        _ => match next_line.next() {
+            Some((_, ch)) if is_value_start(ch) && prec <= Prec::Block => nested(Frame::Block { left }, next_line, Prec::Expr),
+            _ => Ok(Step::Done(left)),
+        }
+    }
+}
+
+/// Finishes `frame` now that the expression it was waiting on is parsed.
+fn resume<'a>(frame: Frame<'a>, mut expr: Parsed<'a, Expr<'a>>, comments: &mut Comments<'a>, whitespace: &mut Option<Whitespace<'a>>, block_start: usize, recovered: &mut Vec<ParseError<'a>>) -> Result<Step<'a>, ParseError<'a>> {
+    // the expression's own operators have already looked past its end
+    let (spaces, lines) = Whitespace::after(whitespace, expr.end());
+    Ok(match frame {
+        Frame::Bracket { start } => match expr.end().next() {
+            Some((end, ')')) => Step::Operator(Parsed::new(start, end, expr.node)),
+            // a `)` is taken to be left out when nothing else follows on
+            // the line, or a `}` closes instead
+            _ => {
+                let error = ParseError::expected_after(lines, Expected::Token(")"), Prec::Tuple);
+                if !matches!(spaces.next(), None | Some((_, '\n')) | Some((_, '}'))) {
+                    return Err(error)
+                }
+                recovered.push(error);
+                Step::Operator(Parsed::new(start, spaces, expr.node))
             }
-            Some((_, '(')) => {
-                Expr::new_binary(left, parse(start, Prec::Tuple)?, BinaryOp::Bracket)
+        }
+        Frame::Brace { start } => match lines.next() {
+            Some((end, '}')) => {
+                comments.take_after_block(&mut expr, comments.offset(end));
+                Step::Operator(Parsed::new(start, end, expr.node))
             }
-            Some((pos, ',')) if prec <= Prec::Tuple => {
-                Expr::new_tuple(left, parse(skip_lines(pos), Prec::Tuple)?)
+            _ => {
+                let error = ParseError::expected_after(lines, Expected::Token("}"), Prec::Block);
+                // lines after a missing `}` are parsed as part of the block,
+                // so it can only be missing at the end or before a `)`
+                if !matches!(lines.next(), None | Some((_, ')'))) {
+                    return Err(error)
+                }
+                recovered.push(error);
+                comments.take_after_block(&mut expr, comments.offset(lines));
+                Step::Operator(Parsed::new(start, lines, expr.node))
             }
-            Some((_, ch)) if ch.is_alphabetic() => {
-                let end = start.next_while(|ch| ch.is_alphabetic());
-                let keyword = Position::slice(start, end);
-                match keyword {
-                    "else" => Expr::new_binary(left, parse(skip_lines(end), Prec::Expr)?, BinaryOp::Else),
-                    _ => return Ok(left)
+        }
+        Frame::FuncPattern { start, name } => {
+            Step::Nested { frame: Frame::FuncBody { start, name, pattern: expr }, pos: lines, prec: Prec::Expr }
+        }
+        Frame::FuncBody { start, name, pattern } => {
+            Step::Operator(Parsed::new(start, expr.end(), Expr::Func { name: name.node, pattern: Box::new(pattern), expr: Box::new(expr) }))
+        }
+        Frame::LambdaParams { start, mut params } => {
+            let pos = lines;
+            params.push(expr);
+            match pos.next() {
+                Some((next, ',')) => Step::Nested { frame: Frame::LambdaParams { start, params }, pos: skip_lines(next), prec: Prec::BitOr },
+                Some((end, '|')) => {
+                    let pattern = match params.len() {
+                        1 => params.pop().unwrap(),
+                        _ => Parsed::new(params[0].start(), params[params.len() - 1].end(), Expr::Tuple { exprs: params }),
+                    };
+                    lambda_body(start, pattern, end)
                 }
+                _ => return Err(ParseError::new(pos, vec![Expected::Token(","), Expected::Token("|")])),
             }
-            _ => {
-                let pos = skip_lines(start);
-                match pos.next() {
-                    Some((_, ch)) if ch != '}' && prec <= Prec::Block => Expr::new_block(left, parse(pos, Prec::Expr)?),
-                    _ => return Ok(left),
+        }
+        Frame::Struct { start } => Step::Operator(Parsed::new(start, expr.end(), Expr::Struct { body: Box::new(expr) })),
+        Frame::IfCond { start } => {
+            Step::Nested { frame: Frame::IfConc { start, cond: expr }, pos: lines, prec: Prec::Expr }
+        }
+        Frame::IfConc { start, cond } => {
+            Step::Operator(Parsed::new(start, expr.end(), Expr::If { cond: Box::new(cond), conc: Box::new(expr) }))
+        }
+        Frame::MatchValue { start } => {
+            match lines.next() {
+                Some((pos, '{')) => Step::Nested { frame: Frame::MatchPattern { start, value: Box::new(expr), arms: Vec::new() }, pos: skip_lines(pos), prec: Prec::Call },
+                _ => return Err(ParseError::expected_after(lines, Expected::Token("{"), Prec::Expr)),
+            }
+        }
+        Frame::MatchPattern { start, value, arms } => {
+            let pos = match lines.next() {
+                Some((pos, '=')) => match pos.next() {
+                    Some((pos, '>')) => pos,
+                    _ => return Err(ParseError::expected_string(lines, "=>")),
                 }
+                _ => return Err(ParseError::expected_string(lines, "=>")),
+            };
+            Step::Nested { frame: Frame::MatchArm { start, value, arms, pattern: expr }, pos: skip_lines(pos), prec: Prec::Expr }
+        }
+        // arms are separated by a `,` or a new line, and a `,` can also
+        // follow the last
+        Frame::MatchArm { start, value, mut arms, pattern } => {
+            arms.push((pattern, expr));
+            let next = match lines.next() {
+                Some((pos, ',')) => skip_lines(pos),
+                Some((_, '}')) => lines,
+                _ if matches!(spaces.next(), Some((_, '\n'))) => lines,
+                _ => return Err(ParseError::new(lines, vec![Expected::Token(","), Expected::Token("}"), Expected::Operator])),
+            };
+            match next.next() {
+                Some((end, '}')) => Step::Operator(Parsed::new(start, end, Expr::Match { value, arms })),
+                _ => Step::Nested { frame: Frame::MatchPattern { start, value, arms }, pos: next, prec: Prec::Call },
+            }
+        }
+        Frame::ForPattern { start, label } => {
+            let keyword_end = lines.next_while(is_ident_char);
+            if Position::slice(lines, keyword_end) != "in" {
+                return Err(ParseError::expected_string(lines, "in"))
             }
+            Step::Nested { frame: Frame::ForIterable { start, label, pattern: Box::new(expr) }, pos: skip_lines(keyword_end), prec: Prec::Expr }
         }
+        Frame::ForIterable { start, label, pattern } => {
+            Step::Nested { frame: Frame::ForBody { start, label, pattern, iterable: Box::new(expr) }, pos: lines, prec: Prec::Expr }
+        }
+        Frame::ForBody { start, label, pattern, iterable } => {
+            Step::Operator(Parsed::new(start, expr.end(), Expr::For { label, pattern, iterable, body: Box::new(expr) }))
+        }
+        Frame::WhileCond { start, label } => {
+            Step::Nested { frame: Frame::WhileBody { start, label, cond: Box::new(expr) }, pos: lines, prec: Prec::Expr }
+        }
+        Frame::WhileBody { start, label, cond } => {
+            Step::Operator(Parsed::new(start, expr.end(), Expr::While { label, cond, body: Box::new(expr) }))
+        }
+        Frame::Defer { start } => Step::Operator(Parsed::new(start, expr.end(), Expr::Defer { body: Box::new(expr) })),
+        Frame::Not { start } => Step::Operator(Parsed::new(start, expr.end(), Expr::Not { expr: Box::new(expr) })),
+        Frame::Cfg { start, name } => {
+            Step::Operator(Parsed::new(start, expr.end(), Expr::Cfg { name, body: Box::new(expr) }))
+        }
+        Frame::Allow { start, name } => {
+            Step::Operator(Parsed::new(start, expr.end(), Expr::Allow { name, body: Box::new(expr) }))
+        }
+        Frame::Binary { left, op } => Step::Operator(Expr::new_binary(left, expr, op)),
+        Frame::Tuple { left } => Step::Operator(Expr::new_tuple(left, expr)),
+        Frame::Block { mut left } => {
+            if !matches!(left.node, Expr::Block { .. }) {
+                comments.lead(&mut left, block_start);
+            }
+            let previous = last_item(&mut left);
+            previous.trivia.trailing.extend(comments.take_same_line(previous.end()));
+            comments.lead(&mut expr, block_start);
+            Step::Operator(Expr::new_block(left, expr))
+        }
+    })
+}
+
+/// Parses `source`, failing at the first error.
+pub fn parse_source(source: &str) -> Result<Parsed<'_, Expr<'_>>, ParseError<'_>> {
+    let mut recovered = Vec::new();
+    let expr = parse_recovering(source, &mut recovered)?;
+    match recovered.into_iter().next() {
+        Some(error) => Err(error),
+        None => Ok(expr),
     }
 }
 
-pub fn parse_source(source: &str) -> Result<Parsed<Expr>, ParseError> {
-    parse(Position::from_source(source), Prec::Block)
+/// Parses `source` as if any `)` or `}` that looks left out were there,
+/// adding an error to `recovered` for each, so that later stages can still
+/// report errors in the rest of the program.
+pub fn parse_recovering<'a>(source: &'a str, recovered: &mut Vec<ParseError<'a>>) -> Result<Parsed<'a, Expr<'a>>, ParseError<'a>> {
+    let expr = parse(source, recovered)?;
+    let end = skip_lines(expr.end());
+    if !end.is_empty() {
+        return Err(ParseError::new(end, vec![Expected::Token(","), Expected::Operator, Expected::EndOfInput]))
+    }
+    if let Some(nested) = too_deep(&expr) {
+        return Err(ParseError::too_deep(nested.start()))
+    }
+    Ok(expr)
+}
+
+/// The first expression in `expr` nested more than `MAX_DEPTH` deep. The
+/// items of a block after a `defer` are lowered inside it, so each `defer`
+/// nests the items after it one deeper.
+fn too_deep<'p, 'a>(expr: &'p Parsed<'a, Expr<'a>>) -> Option<&'p Parsed<'a, Expr<'a>>> {
+    let mut stack = vec![(expr, 1)];
+    while let Some((expr, depth)) = stack.pop() {
+        if depth > MAX_DEPTH {
+            return Some(expr)
+        }
+        let mut inner = depth + 1;
+        for child in expr.get_node().children() {
+            stack.push((child, inner));
+            if let Expr::Block { .. } = expr.get_node() {
+                inner += is_defer(child) as usize;
+            }
+        }
+    }
+    None
+}
+
+fn is_defer(item: &Parsed<Expr>) -> bool {
+    match item.get_node() {
+        Expr::Defer { .. } => true,
+        Expr::Cfg { body, .. } => is_defer(body),
+        _ => false,
+    }
 }
\ No newline at end of file
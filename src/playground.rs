@@ -0,0 +1,105 @@
+//! One call from source to output, for running the compiler where there is
+//! no filesystem or terminal, such as a web playground built for
+//! wasm32-unknown-unknown. Everything is returned as strings.
+
+use crate::clock::Clock;
+use crate::diagnostics::{ColorChoice, Diagnostic, ErrorFormat, Renderer};
+use crate::execute::{Resource, SandboxConfig, Trap, VirtualMachine};
+use crate::session::{CompileOptions, Session};
+use crate::types::Type;
+
+const FILE_NAME: &str = "playground";
+
+/// The limits a program run by the playground runs under. There is no
+/// timeout, as the system's clock panics on wasm32, so fuel bounds how long
+/// a program runs instead.
+pub const LIMITS: SandboxConfig = SandboxConfig { fuel: Some(10_000_000), max_memory: Some(64 << 20), timeout: None };
+
+#[derive(Debug, Clone, Default)]
+pub struct PlaygroundOutput {
+    /// Whether the program compiled without errors.
+    pub compiled: bool,
+    /// Every error and warning, rendered as the command line shows them but
+    /// without color, then the trap that stopped the program, if any.
+    pub diagnostics: String,
+    /// The compiled program in canonical order, empty if compiling failed.
+    pub ir: String,
+    /// What the program wrote with `print`, a line for each call.
+    pub printed: String,
+    /// The value `main` returned, empty if the program exited or did not
+    /// compile.
+    pub output: String,
+    /// The code the program exited with: the `int` returned by `main`, the
+    /// argument to `exit`, or none for other results.
+    pub exit_code: Option<i32>,
+    /// The limit that stopped the program, if it ran into one.
+    pub exhausted: Option<Resource>,
+}
+
+/// Compiles `source` as a list of declarations and runs its `main` within
+/// `LIMITS`.
+pub fn compile_and_run(source: &str) -> PlaygroundOutput {
+    compile_and_run_with(source, LIMITS)
+}
+
+/// Compiles `source` and runs its `main` within `limits`.
+pub fn compile_and_run_with(source: &str, limits: SandboxConfig) -> PlaygroundOutput {
+    build(source, Some(limits))
+}
+
+/// Compiles `source` without running it, leaving the output empty.
+pub fn compile(source: &str) -> PlaygroundOutput {
+    build(source, None)
+}
+
+fn build(source: &str, limits: Option<SandboxConfig>) -> PlaygroundOutput {
+    let mut out = PlaygroundOutput::default();
+    let session = Session::new(FILE_NAME.to_string(), source.to_string(), CompileOptions::default());
+    let ast = session.parse();
+    let hir = ast.as_ref().and_then(|ast| session.lower(ast));
+    let compiled = hir.as_ref().and_then(|hir| session.compile(hir));
+    // a program parsed past errors can still compile, but is never run
+    let failed = session.has_errors();
+    let renderer = Renderer::new(ColorChoice::Never, ErrorFormat::Human);
+    let files = session.source_map();
+    for diagnostic in session.take_diagnostics() {
+        out.diagnostics.push_str(&renderer.render(&diagnostic, &files));
+    }
+    let (program, entry, ty) = match compiled {
+        Some(compiled) if !failed => compiled,
+        _ => return out,
+    };
+    let (program, entry) = program.canonical(entry);
+    // the canonical program numbers its variables afresh, so the result is
+    // read from the entry function's returns, which keep their order
+    let ty = ty.map_to(program.get_function(entry).get_returns());
+    out.compiled = true;
+    out.ir = program.to_string();
+    let limits = match limits {
+        Some(limits) => limits,
+        None => return out,
+    };
+    let mut vm = VirtualMachine::new(&program);
+    // the system's clock panics on wasm32, so time only passes by sleeping
+    vm.set_clock(Clock::mock(0));
+    vm.set_sandbox(limits);
+    vm.capture_output();
+    let result = vm.execute(program.get_function(entry));
+    out.printed = vm.take_output();
+    match result {
+        Ok(()) => {
+            out.output = vm.format_ty(&ty);
+            if let Type::Int(code) = ty {
+                out.exit_code = Some(vm.get_register(code));
+            }
+        }
+        Err(Trap::Exit { code, .. }) => out.exit_code = Some(code),
+        Err(trap) => {
+            if let Trap::Exhausted(resource) = trap {
+                out.exhausted = Some(resource);
+            }
+            out.diagnostics.push_str(&renderer.render(&Diagnostic::error(trap.to_string(), None), &files))
+        }
+    }
+    out
+}
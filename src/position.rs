@@ -21,16 +21,39 @@ impl<'a> Position<'a> {
             (Position::new(self.line, self.column + 1, chars.as_str()), ch)
         })
     }
+    pub fn line(&self) -> i32 {
+        self.line
+    }
     pub fn len(&self) -> usize {
         self.source.len()
     }
+    pub fn is_empty(&self) -> bool {
+        self.source.is_empty()
+    }
+    /// The source from this position to the end of the file.
+    pub fn rest(&self) -> &'a str {
+        self.source
+    }
     pub fn slice(start: Position<'a>, end: Position<'a>) -> &'a str {
         &start.source[0..start.len()-end.len()]
     }
+    /// The position `len` bytes on, which must be the end of a character.
+    pub fn skip(&self, len: usize) -> Position<'a> {
+        let end = self.len() - len;
+        let mut pos = *self;
+        while pos.len() > end {
+            pos = pos.next().expect("the end is within the source").0;
+        }
+        pos
+    }
     pub fn next_while<F: Fn(char) -> bool>(&self, condition: F) -> Position<'a> {
-        match self.next() {
-            Some((pos, ch)) if condition(ch) => pos.next_while(condition),
-            _ => *self
+        let mut pos = *self;
+        while let Some((next, ch)) = pos.next() {
+            if !condition(ch) {
+                break
+            }
+            pos = next;
         }
+        pos
     }
 }
\ No newline at end of file
@@ -0,0 +1,34 @@
+use std::path::{Path, PathBuf};
+use std::{env, fs, io};
+use crate::config::CONFIG_FILE;
+
+/// The entry file of a new project, and of a directory without a config file.
+pub const DEFAULT_ENTRY: &str = "main.txt";
+
+const MAIN_TEMPLATE: &str = "fn main() 0\n";
+
+/// The closest directory, from the working directory up, that holds a config
+/// file, as a path relative to the working directory so that file names in
+/// diagnostics stay short. Paths in the config file are relative to it.
+pub fn find_root() -> Option<PathBuf> {
+    let cwd = env::current_dir().ok()?;
+    let depth = cwd.ancestors().position(|dir| dir.join(CONFIG_FILE).is_file())?;
+    Some((0..depth).map(|_| "..").collect())
+}
+
+/// Creates a config file and an entry file in `dir`, creating `dir` if it
+/// does not exist. Nothing is overwritten: an existing config file is an
+/// error, and an existing entry file is kept.
+pub fn scaffold(dir: &Path) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+    let config = dir.join(CONFIG_FILE);
+    if config.exists() {
+        return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{} already exists", config.display())))
+    }
+    fs::write(&config, format!("entry = \"{}\"\n", DEFAULT_ENTRY))?;
+    let entry = dir.join(DEFAULT_ENTRY);
+    if !entry.exists() {
+        fs::write(entry, MAIN_TEMPLATE)?;
+    }
+    Ok(())
+}
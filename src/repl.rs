@@ -0,0 +1,194 @@
+use std::{env, fs, mem, process};
+use std::fs::OpenOptions;
+use std::io::{self, BufRead, Write};
+use std::path::PathBuf;
+use language::ast::{Expr, Parsed};
+use language::hir::{self, Hir};
+use language::diagnostics::{Lint, Renderer};
+use language::execute::{Trap, VirtualMachine};
+use language::heap::Heap;
+use language::ir::{Function, Program};
+use language::scope::Scope;
+use language::source_map::SourceMap;
+use language::{builtins, compiler, parser};
+
+const HISTORY_FILE: &str = ".language_history";
+const REPL_FILE: &str = "<repl>";
+
+/// An interactive session. Every entry is compiled as a new function of the
+/// same program against a shared scope, so definitions from earlier entries
+/// stay visible. Entries are leaked to give them the lifetime of the session,
+/// and each is a file of its own in the source map, so that a diagnostic can
+/// point back at an earlier entry.
+pub struct Repl {
+    program: Program,
+    scope: Scope<'static, 'static>,
+    registers: Vec<i32>,
+    heap: Heap,
+    last_ast: Option<&'static Parsed<'static, Expr<'static>>>,
+    files: SourceMap<'static>,
+    history: Vec<String>,
+    history_path: Option<PathBuf>,
+    renderer: Renderer,
+}
+
+impl Repl {
+    pub fn new(renderer: Renderer) -> Repl {
+        let history_path = env::var_os("HOME").map(|home| PathBuf::from(home).join(HISTORY_FILE));
+        let history = history_path.as_ref()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .map(|contents| contents.lines().map(|line| line.replace("\\n", "\n")).collect())
+            .unwrap_or_default();
+        Repl {
+            program: Program::new(),
+            scope: builtins::prelude(),
+            registers: Vec::new(),
+            heap: Heap::default(),
+            last_ast: None,
+            files: SourceMap::new(),
+            history,
+            history_path,
+            renderer,
+        }
+    }
+    pub fn run(&mut self) -> io::Result<()> {
+        let stdin = io::stdin();
+        let mut lines = stdin.lock().lines();
+        loop {
+            prompt("> ")?;
+            let mut input = match lines.next() {
+                Some(line) => line?,
+                None => return Ok(()),
+            };
+            if input.trim().is_empty() {
+                continue
+            }
+            if let Some(command) = input.trim().strip_prefix(':') {
+                if !self.command(command) {
+                    return Ok(())
+                }
+                continue
+            }
+            while parser::parse_source(&input).err().is_some_and(|error| error.is_incomplete()) {
+                prompt("... ")?;
+                match lines.next() {
+                    Some(line) => {
+                        input.push('\n');
+                        input.push_str(&line?);
+                    }
+                    None => return Ok(()),
+                }
+            }
+            self.record(&input)?;
+            self.evaluate(input);
+        }
+    }
+    fn command(&mut self, command: &str) -> bool {
+        let (name, argument) = match command.find(char::is_whitespace) {
+            Some(index) => (&command[..index], command[index..].trim()),
+            None => (command, ""),
+        };
+        match name {
+            "q" | "quit" => return false,
+            "ir" => print!("{}", self.program),
+            "ast" => match self.last_ast {
+                Some(ast) => println!("{}", ast),
+                None => println!("no input yet"),
+            },
+            "type" => self.print_type(argument.to_string()),
+            "history" => for (index, entry) in self.history.iter().enumerate() {
+                println!("{:>4}  {}", index + 1, entry.replace('\n', "\n      "));
+            },
+            _ => println!("unknown command ':{}' (try :ir, :ast, :type <expr>, :history or :quit)", name),
+        }
+        true
+    }
+    fn record(&mut self, input: &str) -> io::Result<()> {
+        self.history.push(input.to_string());
+        if let Some(path) = &self.history_path {
+            let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+            writeln!(file, "{}", input.replace('\n', "\\n"))?;
+        }
+        Ok(())
+    }
+    fn parse(&mut self, input: String) -> Option<&'static Parsed<'static, Expr<'static>>> {
+        let source: &'static str = Box::leak(input.into_boxed_str());
+        self.files.add_file(REPL_FILE.to_string(), source);
+        match parser::parse_source(source) {
+            Ok(ast) => Some(Box::leak(Box::new(ast))),
+            Err(error) => {
+                self.renderer.emit(&error.to_diagnostic(&self.files), &self.files);
+                None
+            }
+        }
+    }
+    fn lower(&self, ast: &'static Parsed<'static, Expr<'static>>) -> Option<&'static Hir<'static>> {
+        match hir::lower(ast, &[]) {
+            Ok(hir) => Some(Box::leak(Box::new(hir))),
+            Err(error) => {
+                self.renderer.emit(&error.to_diagnostic(&self.files), &self.files);
+                None
+            }
+        }
+    }
+    fn print_type(&mut self, input: String) {
+        if let Some(hir) = self.parse(input).and_then(|ast| self.lower(ast)) {
+            let mut function = Function::new();
+            let mut block = function.new_block();
+            match compiler::compile(hir, &mut self.scope.closure(), &mut self.program, &mut function, &mut block) {
+                Ok(ty) => println!("{}", ty),
+                Err(error) => self.renderer.emit(&error.to_diagnostic(&self.files), &self.files),
+            }
+        }
+    }
+    fn evaluate(&mut self, input: String) {
+        let ast = match self.parse(input) {
+            Some(ast) => ast,
+            None => return,
+        };
+        self.last_ast = Some(ast);
+        let hir = match self.lower(ast) {
+            Some(hir) => hir,
+            None => return,
+        };
+        let mut function = Function::new();
+        let mut block = function.new_block();
+        // names bound before the error have registers that are never
+        // written, since the entry does not run
+        let snapshot = self.scope.snapshot();
+        let ty = match compiler::compile(hir, &mut self.scope, &mut self.program, &mut function, &mut block) {
+            Ok(ty) => ty,
+            Err(error) => {
+                self.scope.rollback(snapshot);
+                self.renderer.emit(&error.to_diagnostic(&self.files), &self.files);
+                return
+            }
+        };
+        // a function defined in one entry can be called by a later one
+        let warnings = compiler::warnings(hir, &self.files).into_iter()
+            .filter(|warning| warning.lint != Some(Lint::DeadCode));
+        for warning in warnings {
+            self.renderer.emit(&warning, &self.files);
+        }
+        block.ret(&mut function);
+        ty.return_ty(&mut function);
+        let function_id = self.program.add_function(function);
+
+        let mut vm = VirtualMachine::with_registers(&self.program, mem::take(&mut self.registers))
+            .with_heap(mem::take(&mut self.heap));
+        let result = vm.execute(self.program.get_function(function_id));
+        let output = vm.format_ty(&ty);
+        self.heap = vm.take_heap();
+        self.registers = vm.into_registers();
+        match result {
+            Ok(()) => println!("{}", output),
+            Err(Trap::Exit { code, .. }) => process::exit(code),
+            Err(trap) => eprintln!("error: {}", trap),
+        }
+    }
+}
+
+fn prompt(text: &str) -> io::Result<()> {
+    print!("{}", text);
+    io::stdout().flush()
+}
@@ -0,0 +1,71 @@
+//! Recordings of the inputs of a run, so that a bug in a run can be
+//! reproduced exactly by replaying it.
+
+/// Everything a run of a program read from outside of it, so that the run
+/// can be repeated exactly. Written as one `name value` pair per line.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Recording {
+    /// The content hash of the program's bytecode, if it could be encoded.
+    pub program: Option<u64>,
+    pub seed: u64,
+    pub args: Vec<String>,
+    /// What each call of `now_millis` returned, in order.
+    pub clock: Vec<u64>,
+}
+
+impl Recording {
+    pub fn to_text(&self) -> String {
+        let mut out = String::new();
+        if let Some(program) = self.program {
+            out.push_str(&format!("program {:016x}\n", program));
+        }
+        out.push_str(&format!("seed {}\n", self.seed));
+        for arg in &self.args {
+            out.push_str(&format!("arg {}\n", arg.escape_default()));
+        }
+        for millis in &self.clock {
+            out.push_str(&format!("clock {}\n", millis));
+        }
+        out
+    }
+    /// Reads a recording written by `to_text`, describing the first line
+    /// that is not part of one.
+    pub fn parse(text: &str) -> Result<Recording, String> {
+        let mut recording = Recording::default();
+        for (index, line) in text.lines().enumerate() {
+            let invalid = || format!("invalid line {} in the recording: {}", index + 1, line);
+            let (name, value) = line.split_once(' ').ok_or_else(invalid)?;
+            match name {
+                "program" => recording.program = Some(u64::from_str_radix(value, 16).map_err(|_| invalid())?),
+                "seed" => recording.seed = value.parse().map_err(|_| invalid())?,
+                "arg" => recording.args.push(unescape(value).ok_or_else(invalid)?),
+                "clock" => recording.clock.push(value.parse().map_err(|_| invalid())?),
+                _ => return Err(invalid()),
+            }
+        }
+        Ok(recording)
+    }
+}
+
+/// Undoes `str::escape_default`.
+fn unescape(value: &str) -> Option<String> {
+    let mut out = String::new();
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue
+        }
+        match chars.next()? {
+            'n' => out.push('\n'),
+            'r' => out.push('\r'),
+            't' => out.push('\t'),
+            'u' => {
+                let digits: String = chars.by_ref().skip(1).take_while(|&c| c != '}').collect();
+                out.push(char::from_u32(u32::from_str_radix(&digits, 16).ok()?)?);
+            }
+            c => out.push(c),
+        }
+    }
+    Some(out)
+}
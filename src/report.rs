@@ -0,0 +1,158 @@
+//! Summaries of what compiling a program produced for each of its
+//! functions, for seeing why a program compiled to more code than expected
+//! and for tools that analyse the compiled program.
+
+use crate::callgraph::CallGraph;
+use crate::diagnostics::{json_string, Span};
+use crate::hir::{Hir, HirKind, PatternKind, Specialization};
+use crate::ir::{FunctionId, Program};
+use std::fmt::Write;
+
+/// Totals over every specialization in a report.
+#[derive(Default)]
+struct Totals {
+    functions: usize,
+    specializations: usize,
+    blocks: usize,
+    instructions: usize,
+}
+
+/// Lists every function in `hir`, with the argument and return types of
+/// each specialization compiled for it and the size of its code in
+/// `program`. A function bound by an assignment is named after the name it
+/// is bound to.
+pub fn to_text(hir: &Hir, program: &Program, source: &str) -> String {
+    let mut out = String::new();
+    let mut totals = Totals::default();
+    visit_functions(hir, None, source, &mut |name, specializations| {
+        let _ = match specializations.len() {
+            0 => writeln!(out, "fn {}: never called", name),
+            len => writeln!(out, "fn {}: {}", name, count(len, "specialization")),
+        };
+        for specialization in specializations {
+            let function = program.get_function(specialization.function);
+            let metrics = function.cfg_metrics();
+            let _ = writeln!(
+                out, "  {} -> {}: {}, {}, {}",
+                specialization.param_ty, specialization.return_ty,
+                count(metrics.blocks, "block"), count(metrics.instructions, "instruction"), count(function.vars().len(), "register"),
+            );
+            totals.blocks += metrics.blocks;
+            totals.instructions += metrics.instructions;
+        }
+        totals.functions += 1;
+        totals.specializations += specializations.len();
+    });
+    let _ = writeln!(
+        out, "{}, {}, {}, {}",
+        count(totals.functions, "function"), count(totals.specializations, "specialization"),
+        count(totals.blocks, "block"), count(totals.instructions, "instruction"),
+    );
+    out
+}
+
+/// Lists the signature of every specialization of every function in `hir`,
+/// one to a line in the order the functions are written, as in
+/// `square(int) -> int`. Unlike `to_text`, nothing depends on the code
+/// compiled, so the listing only changes when the types do.
+pub fn signatures(hir: &Hir, source: &str) -> String {
+    let mut out = String::new();
+    visit_functions(hir, None, source, &mut |name, specializations| {
+        if specializations.is_empty() {
+            let _ = writeln!(out, "{}: never called", name);
+        }
+        for specialization in specializations {
+            let _ = writeln!(out, "{} -> {}", call_name(name, &specialization.param_ty), specialization.return_ty);
+        }
+    });
+    out
+}
+
+/// Renders the call graph of `program` in Graphviz's DOT language, with a
+/// node for each specialization.
+pub fn call_graph_dot(hir: &Hir, program: &Program, entry: FunctionId, source: &str) -> String {
+    let names = function_names(hir, program, entry, source);
+    let graph = CallGraph::new(program);
+    let mut out = String::from("digraph calls {\n");
+    for function in program.get_function_ids() {
+        let _ = writeln!(out, "    f{} [label={}];", function.get_id(), json_string(&names[function.get_id()]));
+    }
+    for function in program.get_function_ids() {
+        for callee in graph.callees(function) {
+            let _ = writeln!(out, "    f{} -> f{};", function.get_id(), callee.get_id());
+        }
+    }
+    out.push_str("}\n");
+    out
+}
+
+/// Renders the shape of every function's control flow graph, and which
+/// functions it calls, as a single JSON object.
+pub fn cfg_json(hir: &Hir, program: &Program, entry: FunctionId, source: &str) -> String {
+    let names = function_names(hir, program, entry, source);
+    let graph = CallGraph::new(program);
+    let functions: Vec<String> = program.get_function_ids().into_iter().map(|function| {
+        let metrics = program.get_function(function).cfg_metrics();
+        let calls: Vec<String> = graph.callees(function).iter().map(|callee| callee.get_id().to_string()).collect();
+        format!(
+            "{{\"id\":{},\"name\":{},\"blocks\":{},\"instructions\":{},\"max_depth\":{},\"loops\":{},\"calls\":[{}]}}",
+            function.get_id(), json_string(&names[function.get_id()]), metrics.blocks, metrics.instructions,
+            metrics.max_depth, metrics.loops, calls.join(","),
+        )
+    }).collect();
+    format!("{{\"entry\":{},\"functions\":[{}]}}\n", entry.get_id(), functions.join(","))
+}
+
+/// A name for every function in `program`: the function a specialization
+/// was compiled from and the type of its argument, as in `f(int)`.
+fn function_names(hir: &Hir, program: &Program, entry: FunctionId, source: &str) -> Vec<String> {
+    let mut names: Vec<String> = program.get_function_ids().iter().map(|function| format!("f{}", function.get_id())).collect();
+    names[entry.get_id()] = "entry".to_string();
+    visit_functions(hir, None, source, &mut |name, specializations| {
+        for specialization in specializations {
+            names[specialization.function.get_id()] = call_name(name, &specialization.param_ty);
+        }
+    });
+    names
+}
+
+/// `name` applied to an argument of type `param_ty`, as in `f(int)`.
+fn call_name(name: &str, param_ty: &str) -> String {
+    match param_ty.starts_with('(') {
+        true => format!("{}{}", name, param_ty),
+        false => format!("{}({})", name, param_ty),
+    }
+}
+
+/// Calls `visit` with the name and the specializations of every function
+/// in `hir`. A function with no name of its own is named after the name it
+/// is bound to, or else where it is defined.
+fn visit_functions(hir: &Hir, binding: Option<&str>, source: &str, visit: &mut dyn FnMut(&str, &[Specialization])) {
+    if let HirKind::Func { name, body, .. } = &hir.kind {
+        let name = match name.or(binding) {
+            Some(name) => name.to_string(),
+            None => {
+                let (line, column) = Span::of(source, hir.source).line_column(source);
+                format!("fn@{}:{}", line, column)
+            }
+        };
+        visit(&name, &body.specializations.borrow());
+    }
+    let binding = match &hir.kind {
+        HirKind::Assign { pattern, .. } => match pattern.kind {
+            PatternKind::Bind(name) => Some(name),
+            _ => None,
+        },
+        _ => None,
+    };
+    for child in hir.children() {
+        visit_functions(child, binding, source, visit);
+    }
+}
+
+fn count(count: usize, noun: &str) -> String {
+    match count {
+        1 => format!("1 {}", noun),
+        _ => format!("{} {}s", count, noun),
+    }
+}
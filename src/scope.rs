@@ -1,11 +1,26 @@
 use std::{cell::RefCell, rc::Rc};
+use crate::hir::Hir;
+use crate::ir::BlockId;
 use crate::types::Type;
 
+/// The names a loop carries from one pass to the next, with the types they
+/// are carried in.
+pub type Carried<'a, 'b> = Rc<[(&'a str, Type<'a, 'b>)]>;
+
 #[derive(Debug, Clone)]
 pub struct Scope<'a, 'b> {
     node: Rc<RefCell<ScopeNode<'a, 'b>>>,
 }
 
+/// A scope as it was, which it can be rolled back to after compiling
+/// something that failed part way through binding names.
+#[derive(Debug, Clone)]
+pub struct Snapshot<'a, 'b> {
+    node: Rc<RefCell<ScopeNode<'a, 'b>>>,
+    /// The definitions `assign` can change in place, with their types.
+    types: Vec<(Rc<RefCell<ScopeNode<'a, 'b>>>, Type<'a, 'b>)>,
+}
+
 #[derive(Debug)]
 pub enum ScopeNode<'a, 'b> {
     Empty,
@@ -13,6 +28,40 @@ pub enum ScopeNode<'a, 'b> {
         name: &'a str,
         ty: Type<'a, 'b>,
         previous: Rc<RefCell<ScopeNode<'a, 'b>>>,
+    },
+    Closure {
+        previous: Rc<RefCell<ScopeNode<'a, 'b>>>,
+    },
+    /// Code that may not run: an arm of a `match`, or the side of an `if`,
+    /// `else`, `&&` or `||` that is branched to. The names it binds,
+    /// including by assigning a name from outside, are only bound inside
+    /// it, until the compiler joins the names from outside with their
+    /// values from where it did not run.
+    Arm {
+        previous: Rc<RefCell<ScopeNode<'a, 'b>>>,
+    },
+    /// The body of a loop, which is a closure boundary that `break` can
+    /// also leave by branching to `exit`. The names in `carried` keep their
+    /// values after the loop, so `break` copies them into their registers.
+    Loop {
+        label: Option<&'a str>,
+        exit: BlockId,
+        carried: Carried<'a, 'b>,
+        previous: Rc<RefCell<ScopeNode<'a, 'b>>>,
+    },
+    /// The items of a block after a `defer`. A `break` out of them runs
+    /// `deferred` while it is `pending`, which it stops being once the
+    /// block's own exit has run it.
+    Deferred {
+        deferred: &'b [Hir<'a>],
+        pending: bool,
+        previous: Rc<RefCell<ScopeNode<'a, 'b>>>,
+    },
+}
+
+impl<'a, 'b> Default for Scope<'a, 'b> {
+    fn default() -> Scope<'a, 'b> {
+        Scope::new()
     }
 }
 
@@ -29,8 +78,93 @@ impl<'a, 'b> Scope<'a, 'b> {
     pub fn get(&self, search: &'a str) -> Option<Type<'a, 'b>> {
         self.node.borrow().get(search)
     }
+    pub fn snapshot(&self) -> Snapshot<'a, 'b> {
+        let mut types = Vec::new();
+        let mut node = Rc::clone(&self.node);
+        loop {
+            let previous = match &*node.borrow() {
+                ScopeNode::Definition { ty, previous, .. } => {
+                    types.push((Rc::clone(&node), ty.clone()));
+                    Rc::clone(previous)
+                }
+                ScopeNode::Deferred { previous, .. } => Rc::clone(previous),
+                _ => break,
+            };
+            node = previous;
+        }
+        Snapshot { node: Rc::clone(&self.node), types }
+    }
+    /// Forgets every name bound since `snapshot`, and gives names assigned
+    /// since then their types from before.
+    pub fn rollback(&mut self, snapshot: Snapshot<'a, 'b>) {
+        for (node, old_ty) in snapshot.types {
+            if let ScopeNode::Definition { ty, .. } = &mut *node.borrow_mut() {
+                *ty = old_ty;
+            }
+        }
+        self.node = snapshot.node;
+    }
+    /// The name as it was written where `search` was first bound.
+    pub fn definition(&self, search: &'a str) -> Option<&'a str> {
+        self.node.borrow().definition(search)
+    }
     pub fn closure(&self) -> Scope<'a, 'b> {
-        Scope { node: Rc::clone(&self.node) }
+        Scope { node: Rc::new(RefCell::new(ScopeNode::Closure { previous: Rc::clone(&self.node) })) }
+    }
+    pub fn arm(&self) -> Scope<'a, 'b> {
+        Scope { node: Rc::new(RefCell::new(ScopeNode::Arm { previous: Rc::clone(&self.node) })) }
+    }
+    pub fn loop_body(&self, label: Option<&'a str>, exit: BlockId, carried: Carried<'a, 'b>) -> Scope<'a, 'b> {
+        Scope { node: Rc::new(RefCell::new(ScopeNode::Loop { label, exit, carried, previous: Rc::clone(&self.node) })) }
+    }
+    pub fn deferring(&self, deferred: &'b [Hir<'a>]) -> Scope<'a, 'b> {
+        Scope { node: Rc::new(RefCell::new(ScopeNode::Deferred { deferred, pending: true, previous: Rc::clone(&self.node) })) }
+    }
+    /// Whether a `break` out of a scope made by `deferring` runs what it
+    /// defers.
+    pub fn set_pending(&self, is_pending: bool) {
+        if let ScopeNode::Deferred { pending, .. } = &mut *self.node.borrow_mut() {
+            *pending = is_pending;
+        }
+    }
+    /// The pending scopes made by `deferring` that a `break` to the loop
+    /// `loop_exit` finds leaves, innermost first, with what they defer.
+    pub fn pending_defers(&self, label: Option<&str>) -> Vec<(Scope<'a, 'b>, &'b [Hir<'a>])> {
+        let mut defers = Vec::new();
+        let mut node = Rc::clone(&self.node);
+        loop {
+            let previous = match &*node.borrow() {
+                ScopeNode::Empty | ScopeNode::Closure { .. } => return Vec::new(),
+                ScopeNode::Loop { label: found, .. } if label.is_none() || *found == label => return defers,
+                ScopeNode::Deferred { deferred, pending, previous } => {
+                    if *pending {
+                        defers.push((Scope { node: Rc::clone(&node) }, *deferred));
+                    }
+                    Rc::clone(previous)
+                }
+                ScopeNode::Definition { previous, .. } | ScopeNode::Arm { previous } | ScopeNode::Loop { previous, .. } => Rc::clone(previous),
+            };
+            node = previous;
+        }
+    }
+    /// Whether `search` is bound in this scope since `boundary`, a scope it
+    /// was made from.
+    pub fn binds_since(&self, search: &'a str, boundary: &Scope<'a, 'b>) -> bool {
+        let mut node = Rc::clone(&self.node);
+        while !Rc::ptr_eq(&node, &boundary.node) {
+            let previous = match &*node.borrow() {
+                ScopeNode::Empty => return false,
+                ScopeNode::Definition { name, .. } if *name == search => return true,
+                ScopeNode::Definition { previous, .. } | ScopeNode::Closure { previous } | ScopeNode::Arm { previous } | ScopeNode::Loop { previous, .. } | ScopeNode::Deferred { previous, .. } => Rc::clone(previous),
+            };
+            node = previous;
+        }
+        false
+    }
+    /// The exit of the innermost loop, or of the one with `label`, in the
+    /// function being compiled, and the names that loop carries.
+    pub fn loop_exit(&self, label: Option<&str>) -> Option<(BlockId, Carried<'a, 'b>)> {
+        self.node.borrow().loop_exit(label)
     }
 }
 
@@ -45,6 +179,33 @@ impl<'a, 'b> ScopeNode<'a, 'b> {
                     previous.borrow().get(search)
                 }
             }
+            ScopeNode::Closure { previous } | ScopeNode::Arm { previous } | ScopeNode::Loop { previous, .. } | ScopeNode::Deferred { previous, .. } => previous.borrow().get(search),
+        }
+    }
+    pub fn definition(&self, search: &'a str) -> Option<&'a str> {
+        match self {
+            ScopeNode::Empty => None,
+            ScopeNode::Definition { name, previous, .. } => {
+                if name == &search {
+                    Some(name)
+                } else {
+                    previous.borrow().definition(search)
+                }
+            }
+            ScopeNode::Closure { previous } | ScopeNode::Arm { previous } | ScopeNode::Loop { previous, .. } | ScopeNode::Deferred { previous, .. } => previous.borrow().definition(search),
+        }
+    }
+    /// Loops outside the function being compiled cannot be left from it, so
+    /// the search stops at the closure the function's body starts with.
+    pub fn loop_exit(&self, search: Option<&str>) -> Option<(BlockId, Carried<'a, 'b>)> {
+        match self {
+            ScopeNode::Empty | ScopeNode::Closure { .. } => None,
+            ScopeNode::Definition { previous, .. } | ScopeNode::Arm { previous } | ScopeNode::Deferred { previous, .. } => previous.borrow().loop_exit(search),
+            ScopeNode::Loop { label, exit, carried, previous } => match search {
+                None => Some((*exit, Rc::clone(carried))),
+                Some(search) if *label == Some(search) => Some((*exit, Rc::clone(carried))),
+                Some(_) => previous.borrow().loop_exit(search),
+            },
         }
     }
     pub fn assign(&mut self, search: &'a str, new_ty: &Type<'a, 'b>) -> bool {
@@ -58,6 +219,10 @@ impl<'a, 'b> ScopeNode<'a, 'b> {
                     previous.borrow_mut().assign(search, new_ty)
                 }
             }
+            // the items after a `defer` always run, so names from before it
+            // can be changed in place
+            ScopeNode::Deferred { previous, .. } => previous.borrow_mut().assign(search, new_ty),
+            ScopeNode::Closure { .. } | ScopeNode::Arm { .. } | ScopeNode::Loop { .. } => false,
         }
     }
 }
\ No newline at end of file
@@ -0,0 +1,148 @@
+use crate::ast::{Expr, Parsed};
+use crate::cancel::CancellationToken;
+use crate::diagnostics::{Diagnostic, Lint, LintLevel, Severity, ALL_LINTS};
+use crate::hir::{self, Hir};
+use crate::ir::{FunctionId, Program};
+use crate::optimize::Pipeline;
+use crate::source_map::SourceMap;
+use crate::types::Type;
+use crate::{compiler, parser};
+use std::cell::RefCell;
+
+/// The function a program of declarations starts by calling.
+pub const DEFAULT_ENTRY: &str = "main";
+
+/// The options that change what a compilation produces.
+#[derive(Debug, Clone, Default)]
+pub struct CompileOptions {
+    /// Compile the file as a single expression rather than a list of
+    /// declarations with a `main` function.
+    pub script: bool,
+    /// The names that `cfg(name)` expressions are compiled for.
+    pub cfg: Vec<String>,
+    /// The levels set for warnings, by name, in the order they were given.
+    /// A program is only cached when it has no warnings, so these are part
+    /// of what decides whether a cached build can be used.
+    pub lints: Vec<(String, LintLevel)>,
+    /// The function a program of declarations starts by calling, if not
+    /// `main`.
+    pub entry: Option<String>,
+    /// Run the IR optimization passes on the compiled program.
+    pub optimize: bool,
+    /// The passes `optimize` runs, which can include passes of the
+    /// library's user.
+    pub passes: Pipeline,
+    /// Check the program is valid after compiling it and after each
+    /// optimization pass, panicking at the first step that breaks it.
+    pub check_passes: bool,
+}
+
+/// One compilation of one source file. The session owns the source and its
+/// options, and collects the diagnostics each stage reports, so that the
+/// driver decides when and how they are shown.
+pub struct Session {
+    pub file_name: String,
+    pub source: String,
+    pub options: CompileOptions,
+    diagnostics: RefCell<Vec<Diagnostic>>,
+    cancellation: CancellationToken,
+}
+
+impl Session {
+    pub fn new(file_name: String, source: String, options: CompileOptions) -> Session {
+        Session { file_name, source, options, diagnostics: RefCell::new(Vec::new()), cancellation: CancellationToken::new() }
+    }
+    /// Checks `token` before each stage, which then reports an error and
+    /// returns nothing once it is cancelled.
+    pub fn with_cancellation(mut self, token: CancellationToken) -> Session {
+        self.cancellation = token;
+        self
+    }
+    fn cancelled(&self) -> bool {
+        let cancelled = self.cancellation.is_cancelled();
+        if cancelled && !self.has_errors() {
+            self.report(Diagnostic::error("compilation was cancelled".to_string(), None));
+        }
+        cancelled
+    }
+    /// Adds `diagnostic`, dropping it or making it an error if it is a
+    /// warning whose level was set.
+    pub fn report(&self, mut diagnostic: Diagnostic) {
+        if let (Severity::Warning, Some(lint)) = (diagnostic.severity, diagnostic.lint) {
+            match self.lint_level(lint) {
+                LintLevel::Allow => return,
+                LintLevel::Warn => (),
+                LintLevel::Deny => {
+                    diagnostic.severity = Severity::Error;
+                    diagnostic = diagnostic.with_note(format!("`{}` warnings are denied", lint.name()));
+                }
+            }
+        }
+        self.diagnostics.borrow_mut().push(diagnostic)
+    }
+    /// The last level set for `lint`, which is shown by default.
+    fn lint_level(&self, lint: Lint) -> LintLevel {
+        self.options.lints.iter().rev()
+            .find(|(name, _)| name == lint.name() || name == ALL_LINTS)
+            .map_or(LintLevel::Warn, |(_, level)| *level)
+    }
+    /// Removes and returns everything reported so far.
+    pub fn take_diagnostics(&self) -> Vec<Diagnostic> {
+        self.diagnostics.replace(Vec::new())
+    }
+    /// The files of the compilation, for resolving the spans of its
+    /// diagnostics. There is only the one file until files can import others.
+    pub fn source_map(&self) -> SourceMap<'_> {
+        SourceMap::single(&self.file_name, &self.source)
+    }
+    pub fn has_errors(&self) -> bool {
+        self.diagnostics.borrow().iter().any(|diagnostic| diagnostic.severity == Severity::Error)
+    }
+    pub fn parse(&self) -> Option<Parsed<'_, Expr<'_>>> {
+        if self.cancelled() {
+            return None
+        }
+        // a tree parsed past errors is still lowered and compiled, for the
+        // errors in the rest of the program
+        let mut recovered = Vec::new();
+        let ast = parser::parse_recovering(&self.source, &mut recovered);
+        for error in recovered.iter().chain(ast.as_ref().err()) {
+            self.report(error.to_diagnostic(&self.source_map()));
+        }
+        ast.ok()
+    }
+    pub fn lower<'a>(&self, ast: &Parsed<'a, Expr<'a>>) -> Option<Hir<'a>> {
+        if self.cancelled() {
+            return None
+        }
+        hir::lower(ast, &self.options.cfg).map_err(|error| self.report(error.to_diagnostic(&self.source_map()))).ok()
+    }
+    /// Compiles the program, reporting any warnings along with it.
+    pub fn compile<'a, 'b>(&self, hir: &'b Hir<'a>) -> Option<(Program, FunctionId, Type<'a, 'b>)> {
+        if self.cancelled() {
+            return None
+        }
+        match compiler::compile_program(hir, self.options.script, self.options.entry.as_deref().unwrap_or(DEFAULT_ENTRY)) {
+            Ok(mut compiled) => {
+                if self.options.check_passes {
+                    if let Err(problem) = compiled.0.verify() {
+                        panic!("the compiler produced an invalid program: {}", problem)
+                    }
+                }
+                if self.options.optimize {
+                    self.options.passes.run(&mut compiled.0, self.options.check_passes);
+                }
+                for warning in compiler::warnings(hir, &self.source_map()) {
+                    self.report(warning);
+                }
+                Some(compiled)
+            }
+            Err(errors) => {
+                for error in errors {
+                    self.report(error.to_diagnostic(&self.source_map()));
+                }
+                None
+            }
+        }
+    }
+}
@@ -0,0 +1,81 @@
+//! Every file in a compilation, laid out one after another in a single
+//! range of byte offsets, so that a `Span` alone says which file it is in.
+
+use crate::diagnostics::Span;
+use crate::position::Position;
+
+pub struct SourceMap<'a> {
+    files: Vec<SourceFile<'a>>,
+}
+
+pub struct SourceFile<'a> {
+    pub name: String,
+    pub source: &'a str,
+    /// The offset of the first byte of the file in the map.
+    pub start: usize,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct FileId {
+    id: usize,
+}
+
+impl<'a> SourceMap<'a> {
+    pub fn new() -> SourceMap<'a> {
+        SourceMap { files: Vec::new() }
+    }
+    /// A map holding only `source`, which starts at offset zero so that its
+    /// spans are the same as offsets into the file.
+    pub fn single(name: &str, source: &'a str) -> SourceMap<'a> {
+        let mut map = SourceMap::new();
+        map.add_file(name.to_string(), source);
+        map
+    }
+    /// Files are separated by one unused offset, so that the span at the end
+    /// of a file is never also the start of the next one.
+    pub fn add_file(&mut self, name: String, source: &'a str) -> FileId {
+        let start = self.files.last().map_or(0, |file| file.start + file.source.len() + 1);
+        self.files.push(SourceFile { name, source, start });
+        FileId { id: self.files.len() - 1 }
+    }
+    pub fn get_file(&self, id: FileId) -> &SourceFile<'a> {
+        &self.files[id.id]
+    }
+    pub fn files(&self) -> impl Iterator<Item = (FileId, &SourceFile<'a>)> {
+        self.files.iter().enumerate().map(|(id, file)| (FileId { id }, file))
+    }
+    /// The file containing `offset`.
+    pub fn lookup(&self, offset: usize) -> Option<FileId> {
+        let id = self.files.partition_point(|file| file.start <= offset).checked_sub(1)?;
+        if offset <= self.files[id].start + self.files[id].source.len() {
+            Some(FileId { id })
+        } else {
+            None
+        }
+    }
+    /// The span of `slice`, if it is a subslice of one of the files. Names
+    /// defined by the compiler itself are not.
+    pub fn span_of(&self, slice: &str) -> Option<Span> {
+        self.files.iter().find_map(|file| {
+            let local = Span::within(file.source, slice)?;
+            Some(Span { start: file.start + local.start, end: file.start + local.end })
+        })
+    }
+    /// The empty span at `pos`.
+    pub fn span_at(&self, pos: Position) -> Option<Span> {
+        self.span_of(pos.rest())
+    }
+}
+
+impl<'a> Default for SourceMap<'a> {
+    fn default() -> SourceMap<'a> {
+        SourceMap::new()
+    }
+}
+
+impl<'a> SourceFile<'a> {
+    /// `span`, which must be in this file, as offsets into its source.
+    pub fn local(&self, span: Span) -> Span {
+        Span { start: span.start - self.start, end: span.end - self.start }
+    }
+}
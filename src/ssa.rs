@@ -0,0 +1,359 @@
+//! Construction of static single assignment form, where each variable is
+//! written by one instruction and values from different paths meet in
+//! phis. The compiler carries the state of a loop in registers that each
+//! pass overwrites; this gives every write its own variable, so that later
+//! passes can trust what a variable's one definition says about it.
+//!
+//! Phis go at the iterated dominance frontier of a variable's definitions,
+//! wherever it is live, and variables are renamed along the dominator tree,
+//! following Cytron et al. Native code, which cannot tell which block it
+//! came from, takes a program back out of SSA form first.
+
+use crate::ir::{BlockId, ExitInstruction, Function, Instruction, Program, Var};
+use std::collections::HashMap;
+
+/// Gives each write of a variable that is written more than once a
+/// variable of its own, in every function where that can be done, and
+/// returns whether there were any.
+pub fn construct(program: &mut Program) -> bool {
+    let mut changed = false;
+    for (function_id, vars) in program.get_function_ids().into_iter().zip(candidates(program)) {
+        if vars.is_empty() {
+            continue
+        }
+        let mut function = program.get_function(function_id).clone();
+        function.remove_unreachable_blocks();
+        let predecessors = predecessors(&function);
+        // a phi in the entry block would have no source for the call
+        if !predecessors[BlockId::entry().get_id()].is_empty() {
+            continue
+        }
+        construct_function(&mut function, &vars, &predecessors, program);
+        *program.get_function_mut(function_id) = function;
+        changed = true;
+    }
+    changed
+}
+
+/// Turns the phis of every function into copies at the end of the blocks
+/// they name, for code that cannot pick a phi's source by the block it came
+/// from, and returns whether there were any. A block that branches to a
+/// block with phis and somewhere else too first branches through a new
+/// block of its own, where the copies go, so that they only run on the way
+/// to the phis.
+pub fn destruct(program: &mut Program) -> bool {
+    let mut changed = false;
+    for function_id in program.get_function_ids() {
+        let function = program.get_function(function_id);
+        if !function.get_blocks().iter().any(|block| starts_with_phi(block.get_instructions())) {
+            continue
+        }
+        let mut function = function.clone();
+        split_edges(&mut function);
+        let predecessors = predecessors(&function);
+        for (index, predecessors) in predecessors.iter().enumerate() {
+            let phis: Vec<Instruction> = function.get_blocks()[index].get_instructions().iter()
+                .take_while(|inst| matches!(inst, Instruction::Phi { .. }))
+                .cloned()
+                .collect();
+            for &predecessor in predecessors {
+                let copies = phis.iter().filter_map(|phi| match phi {
+                    Instruction::Phi { dest, sources } => {
+                        let &(_, src) = sources.iter().find(|(block, _)| block.get_id() == predecessor)
+                            .expect("a phi has a source for each block that branches to it");
+                        Some((*dest, src))
+                    }
+                    _ => None,
+                }).filter(|(dest, src)| dest != src).collect();
+                parallel_copy(&mut function, predecessor, copies, program);
+            }
+            function.get_blocks_mut()[index].retain_instructions(|inst| !matches!(inst, Instruction::Phi { .. }));
+        }
+        *program.get_function_mut(function_id) = function;
+        changed = true;
+    }
+    changed
+}
+
+fn starts_with_phi(insts: &[Instruction]) -> bool {
+    matches!(insts.first(), Some(Instruction::Phi { .. }))
+}
+
+/// Gives each branch to a block with phis from a block that can also go
+/// elsewhere a block of its own in between, which only branches on.
+fn split_edges(function: &mut Function) {
+    for index in 0..function.get_blocks().len() {
+        let mut successors = function.successors(index);
+        successors.sort_unstable();
+        successors.dedup();
+        if successors.len() < 2 {
+            continue
+        }
+        successors.retain(|&successor| starts_with_phi(function.get_blocks()[successor].get_instructions()));
+        for successor in successors {
+            let between = function.new_block();
+            let between_id = between.get_id();
+            between.branch(BlockId::new(successor), function);
+            let mut exit = function.get_blocks()[index].get_exit_instruction().clone();
+            exit.map_targets(|block| if block.get_id() == successor { between_id } else { block });
+            function.get_blocks_mut()[index].set_exit_instruction(exit);
+            for inst in function.get_blocks_mut()[successor].get_instructions_mut() {
+                inst.map_phi_blocks(|block| Some(if block.get_id() == index { between_id } else { block }));
+            }
+        }
+    }
+}
+
+/// Adds `copies`, of sources to destinations, to the end of the block at
+/// `index`, as if every source were read before any destination is
+/// written. Sources that another copy overwrites go through a variable of
+/// their own first.
+fn parallel_copy(function: &mut Function, index: usize, copies: Vec<(Var, Var)>, program: &mut Program) {
+    let block = &mut function.get_blocks_mut()[index];
+    let previous = program.set_line(block.get_exit_line());
+    let clobbered = |src: Var| copies.iter().any(|&(dest, _)| dest == src);
+    let staged: Vec<(Var, Var)> = copies.iter().map(|&(dest, src)| match clobbered(src) {
+        true => {
+            let temp = program.new_variable();
+            block.copy(temp, src, program);
+            (dest, temp)
+        }
+        false => (dest, src),
+    }).collect();
+    for (dest, src) in staged {
+        block.copy(dest, src, program);
+    }
+    program.set_line(previous);
+}
+
+/// The variables each function can rename: those it writes more than once
+/// that no other function uses, since registers are shared, and that are
+/// not parameters or results, which calls write and read by name.
+fn candidates(program: &Program) -> Vec<Vec<Var>> {
+    let count = program.get_variable_count();
+    let mut owners: Vec<Option<Option<usize>>> = vec![None; count];
+    let mut definitions = vec![0; count];
+    let mut fixed = vec![false; count];
+    for function_id in program.get_function_ids() {
+        let function = program.get_function(function_id);
+        for var in function.get_params().iter().chain(function.get_returns()) {
+            fixed[var.get_id()] = true;
+        }
+        for var in function.vars() {
+            let owner = &mut owners[var.get_id()];
+            *owner = match *owner {
+                None => Some(Some(function_id.get_id())),
+                Some(Some(id)) if id == function_id.get_id() => Some(Some(id)),
+                Some(_) => Some(None),
+            };
+        }
+        for block in function.get_blocks() {
+            for var in block.get_instructions().iter().flat_map(Instruction::defs) {
+                definitions[var.get_id()] += 1;
+            }
+        }
+    }
+    let mut candidates = vec![Vec::new(); program.get_function_count()];
+    for (id, owner) in owners.into_iter().enumerate() {
+        if let Some(Some(owner)) = owner.filter(|_| definitions[id] > 1 && !fixed[id]) {
+            candidates[owner].push(Var::new(id));
+        }
+    }
+    candidates
+}
+
+fn construct_function(function: &mut Function, vars: &[Var], predecessors: &[Vec<usize>], program: &mut Program) {
+    let idom = dominators(function, predecessors);
+    let frontiers = frontiers(predecessors, &idom);
+    let mut phis: Vec<Vec<Instruction>> = vec![Vec::new(); function.get_blocks().len()];
+    for &var in vars {
+        let live = live_in(function, var, predecessors);
+        let mut work: Vec<usize> = (0..function.get_blocks().len())
+            .filter(|&index| function.get_blocks()[index].get_instructions().iter().any(|inst| inst.defs().contains(&var)))
+            .collect();
+        let mut placed = vec![false; function.get_blocks().len()];
+        while let Some(index) = work.pop() {
+            for &frontier in &frontiers[index] {
+                if live[frontier] && !placed[frontier] {
+                    placed[frontier] = true;
+                    // every source starts as `var`, and is renamed from the
+                    // end of the block it names
+                    let sources = predecessors[frontier].iter().map(|&predecessor| (BlockId::new(predecessor), var)).collect();
+                    phis[frontier].push(Instruction::Phi { dest: var, sources });
+                    work.push(frontier);
+                }
+            }
+        }
+    }
+    for (block, phis) in function.get_blocks_mut().iter_mut().zip(phis) {
+        if !phis.is_empty() {
+            block.insert_phis(phis);
+        }
+    }
+    let mut children = vec![Vec::new(); function.get_blocks().len()];
+    for (index, &parent) in idom.iter().enumerate().skip(1) {
+        children[parent].push(index);
+    }
+    let mut stacks: HashMap<Var, Vec<Var>> = vars.iter().map(|&var| (var, Vec::new())).collect();
+    rename(function, BlockId::entry().get_id(), &children, &mut stacks, program);
+}
+
+/// Renames the variables of the block at `index` and of the blocks it
+/// dominates, with `stacks` holding the latest name of each variable. A
+/// variable read before any write keeps its name, which nothing writes any
+/// more, as before it held no value there.
+fn rename(function: &mut Function, index: usize, children: &[Vec<usize>], stacks: &mut HashMap<Var, Vec<Var>>, program: &mut Program) {
+    let current = |stacks: &HashMap<Var, Vec<Var>>, var: Var| stacks.get(&var).and_then(|stack| stack.last()).copied().unwrap_or(var);
+    let mut pushed = Vec::new();
+    let block = &mut function.get_blocks_mut()[index];
+    for inst in block.get_instructions_mut() {
+        // a phi reads its sources at the end of the blocks they name
+        if !matches!(inst, Instruction::Phi { .. }) {
+            inst.map_uses(&mut |var| current(stacks, var));
+        }
+        inst.map_defs(&mut |var| match stacks.get_mut(&var) {
+            Some(stack) => {
+                let renamed = program.new_variable();
+                stack.push(renamed);
+                pushed.push(var);
+                renamed
+            }
+            None => var,
+        });
+    }
+    let mut exit = block.get_exit_instruction().clone();
+    match &mut exit {
+        ExitInstruction::ConditionalBranch { cond: var, .. } | ExitInstruction::Switch { scrutinee: var, .. } => {
+            *var = current(stacks, *var)
+        }
+        ExitInstruction::Branch { .. } | ExitInstruction::Return => (),
+    }
+    block.set_exit_instruction(exit);
+    let mut successors = function.successors(index);
+    successors.sort_unstable();
+    successors.dedup();
+    for successor in successors {
+        for inst in function.get_blocks_mut()[successor].get_instructions_mut() {
+            let Instruction::Phi { sources, .. } = inst else { break };
+            for (_, var) in sources.iter_mut().filter(|(block, _)| block.get_id() == index) {
+                *var = current(stacks, *var);
+            }
+        }
+    }
+    for &child in &children[index] {
+        rename(function, child, children, stacks, program);
+    }
+    for var in pushed {
+        stacks.get_mut(&var).expect("only renamed variables are pushed").pop();
+    }
+}
+
+/// The indices of the blocks that branch to each block, each once.
+pub(crate) fn predecessors(function: &Function) -> Vec<Vec<usize>> {
+    let mut predecessors = vec![Vec::new(); function.get_blocks().len()];
+    for index in 0..function.get_blocks().len() {
+        for successor in function.successors(index) {
+            if !predecessors[successor].contains(&index) {
+                predecessors[successor].push(index);
+            }
+        }
+    }
+    predecessors
+}
+
+/// The immediate dominator of each block, with the entry block its own,
+/// by the iterative algorithm of Cooper, Harvey and Kennedy. Every block
+/// must be reachable.
+pub(crate) fn dominators(function: &Function, predecessors: &[Vec<usize>]) -> Vec<usize> {
+    let order = function.reverse_postorder();
+    let mut position = vec![0; order.len()];
+    for (index, &id) in order.iter().enumerate() {
+        position[id] = index;
+    }
+    let mut idom: Vec<Option<usize>> = vec![None; order.len()];
+    idom[BlockId::entry().get_id()] = Some(BlockId::entry().get_id());
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for &id in order.iter().skip(1) {
+            let mut new = None;
+            for &predecessor in predecessors[id].iter().filter(|&&predecessor| idom[predecessor].is_some()) {
+                new = Some(match new {
+                    None => predecessor,
+                    Some(mut other) => {
+                        let mut finger = predecessor;
+                        while finger != other {
+                            while position[finger] > position[other] {
+                                finger = idom[finger].expect("processed blocks have a dominator");
+                            }
+                            while position[other] > position[finger] {
+                                other = idom[other].expect("processed blocks have a dominator");
+                            }
+                        }
+                        finger
+                    }
+                });
+            }
+            if idom[id] != new {
+                idom[id] = new;
+                changed = true;
+            }
+        }
+    }
+    idom.into_iter().map(|idom| idom.expect("every block is reachable")).collect()
+}
+
+/// The blocks in the dominance frontier of each block: those where a path
+/// from it meets a path that does not go through it.
+fn frontiers(predecessors: &[Vec<usize>], idom: &[usize]) -> Vec<Vec<usize>> {
+    let mut frontiers = vec![Vec::new(); idom.len()];
+    for (id, predecessors) in predecessors.iter().enumerate().filter(|(_, predecessors)| predecessors.len() > 1) {
+        for &predecessor in predecessors {
+            let mut runner = predecessor;
+            while runner != idom[id] {
+                if !frontiers[runner].contains(&id) {
+                    frontiers[runner].push(id);
+                }
+                runner = idom[runner];
+            }
+        }
+    }
+    frontiers
+}
+
+/// Whether `var` holds a value that may still be read at the start of each
+/// block. A phi reads its sources at the end of the blocks they come from.
+fn live_in(function: &Function, var: Var, predecessors: &[Vec<usize>]) -> Vec<bool> {
+    let blocks = function.get_blocks();
+    let mut exposed = vec![false; blocks.len()];
+    let mut defines = vec![false; blocks.len()];
+    let mut read_at_end = vec![false; blocks.len()];
+    for (index, block) in blocks.iter().enumerate() {
+        for inst in block.get_instructions() {
+            match inst {
+                Instruction::Phi { sources, .. } => for (block, _) in sources.iter().filter(|(_, source)| *source == var) {
+                    read_at_end[block.get_id()] = true;
+                }
+                inst => exposed[index] |= !defines[index] && inst.uses().contains(&var),
+            }
+            defines[index] |= inst.defs().contains(&var);
+        }
+        if let ExitInstruction::ConditionalBranch { cond: read, .. } | ExitInstruction::Switch { scrutinee: read, .. } = block.get_exit_instruction() {
+            exposed[index] |= !defines[index] && *read == var;
+        }
+    }
+    let mut live = exposed.clone();
+    let mut work: Vec<usize> = (0..blocks.len()).filter(|&index| live[index] || read_at_end[index]).collect();
+    while let Some(index) = work.pop() {
+        if !live[index] && defines[index] {
+            continue
+        }
+        live[index] = live[index] || !defines[index];
+        for &predecessor in &predecessors[index] {
+            if !live[predecessor] && !defines[predecessor] {
+                work.push(predecessor);
+            }
+        }
+    }
+    live
+}
@@ -0,0 +1,222 @@
+use crate::diagnostics::{json_string, Span};
+use crate::capture;
+use crate::hir::{Hir, HirKind};
+use std::fmt::Write;
+
+/// Renders the HIR together with the annotations left on it by compiling,
+/// one node per line. Nodes that were never compiled, such as the body of a
+/// function that is never called, show no type.
+pub fn to_text(hir: &Hir, source: &str) -> String {
+    let mut out = String::new();
+    write_text(&mut out, hir, source, 0);
+    out
+}
+
+/// Renders the annotated HIR as a single JSON object, for tools.
+pub fn to_json(hir: &Hir, source: &str) -> String {
+    let mut out = String::new();
+    write_json(&mut out, hir, source);
+    out.push('\n');
+    out
+}
+
+/// Renders the innermost node whose source contains `offset`, as its line
+/// of `to_text`, or returns `None` if no node does.
+pub fn describe_at(hir: &Hir, source: &str, offset: usize) -> Option<String> {
+    let node = node_at(hir, source, offset)?;
+    let mut out = String::new();
+    write_node(&mut out, node, source, 0);
+    Some(out)
+}
+
+/// A use of a name, and the name it refers to where it was bound.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Reference {
+    pub use_span: Span,
+    pub definition: Span,
+}
+
+/// Every use of a name that compiling `hir` resolved, in source order, so
+/// that tools can go from uses to definitions and back without compiling
+/// again. Uses of builtins, which are not defined in the source, and of
+/// code that was never compiled, are left out.
+pub fn references(hir: &Hir, source: &str) -> Vec<Reference> {
+    let mut references = Vec::new();
+    collect_references(hir, source, &mut references);
+    references
+}
+
+fn collect_references(hir: &Hir, source: &str, references: &mut Vec<Reference>) {
+    if let HirKind::Var(name) = hir.kind {
+        let definition = hir.annotations.borrow().iter().find_map(|annotation| annotation.definition);
+        if let Some(definition) = definition.and_then(|definition| Span::within(source, definition)) {
+            // the node's own source can take in brackets around the name
+            let use_span = Span::within(source, name).unwrap_or_else(|| Span::of(source, hir.source));
+            references.push(Reference { use_span, definition });
+        }
+    }
+    for child in hir.children() {
+        collect_references(child, source, references);
+    }
+}
+
+/// The definition of the name used or defined at `offset`.
+pub fn definition_at(references: &[Reference], offset: usize) -> Option<Span> {
+    references.iter()
+        .find(|reference| contains(reference.use_span, offset) || contains(reference.definition, offset))
+        .map(|reference| reference.definition)
+}
+
+/// Every use of the name used or defined at `offset`.
+pub fn uses_at(references: &[Reference], offset: usize) -> Vec<Span> {
+    match definition_at(references, offset) {
+        Some(definition) => references.iter()
+            .filter(|reference| reference.definition == definition)
+            .map(|reference| reference.use_span)
+            .collect(),
+        None => Vec::new(),
+    }
+}
+
+/// Whether `offset` is in `span`, or just past its end, as for `node_at`.
+fn contains(span: Span, offset: usize) -> bool {
+    span.start <= offset && offset <= span.end
+}
+
+/// The innermost node whose source contains `offset`. An offset just past
+/// the end of a node counts as in it, so that a position after a name
+/// still finds the name.
+fn node_at<'h, 'a>(hir: &'h Hir<'a>, source: &str, offset: usize) -> Option<&'h Hir<'a>> {
+    if !contains(Span::of(source, hir.source), offset) {
+        return None
+    }
+    hir.children().into_iter().find_map(|child| node_at(child, source, offset)).or(Some(hir))
+}
+
+fn write_text(out: &mut String, hir: &Hir, source: &str, depth: usize) {
+    write_node(out, hir, source, depth);
+    for child in hir.children() {
+        write_text(out, child, source, depth + 1);
+    }
+}
+
+/// Writes the line for `hir` alone, without its children.
+fn write_node(out: &mut String, hir: &Hir, source: &str, depth: usize) {
+    let (line, column) = Span::of(source, hir.source).line_column(source);
+    let _ = write!(out, "{}{}", "  ".repeat(depth), kind_name(hir));
+    if let Some(detail) = detail(hir) {
+        let _ = write!(out, " {}", detail);
+    }
+    let _ = write!(out, " @ {}:{}", line, column);
+    let annotations = hir.annotations.borrow();
+    if !annotations.is_empty() {
+        let types: Vec<&str> = annotations.iter().map(|annotation| annotation.ty.as_str()).collect();
+        let _ = write!(out, " : {}", types.join(" | "));
+    }
+    if let Some(definition) = annotations.iter().find_map(|annotation| annotation.definition) {
+        match Span::within(source, definition) {
+            Some(span) => {
+                let (line, column) = span.line_column(source);
+                let _ = write!(out, " -> defined at {}:{}", line, column);
+            }
+            None => out.push_str(" -> builtin"),
+        }
+    }
+    out.push('\n');
+}
+
+fn write_json(out: &mut String, hir: &Hir, source: &str) {
+    let span = Span::of(source, hir.source);
+    let (line, column) = span.line_column(source);
+    let _ = write!(out, "{{\"kind\":{},", json_string(kind_name(hir)));
+    match detail(hir) {
+        Some(detail) => { let _ = write!(out, "\"detail\":{},", json_string(&detail)); }
+        None => out.push_str("\"detail\":null,"),
+    }
+    let _ = write!(out, "\"start\":{},\"end\":{},\"line\":{},\"column\":{},\"types\":[", span.start, span.end, line, column);
+    let annotations = hir.annotations.borrow();
+    let types: Vec<String> = annotations.iter().map(|annotation| json_string(&annotation.ty)).collect();
+    out.push_str(&types.join(","));
+    out.push_str("],\"definition\":");
+    match annotations.iter().find_map(|annotation| annotation.definition) {
+        Some(definition) => match Span::within(source, definition) {
+            Some(span) => {
+                let (line, column) = span.line_column(source);
+                let _ = write!(out, "{{\"builtin\":false,\"start\":{},\"end\":{},\"line\":{},\"column\":{}}}", span.start, span.end, line, column);
+            }
+            None => out.push_str("{\"builtin\":true}"),
+        },
+        None => out.push_str("null"),
+    }
+    out.push_str(",\"children\":[");
+    for (index, child) in hir.children().into_iter().enumerate() {
+        if index > 0 {
+            out.push(',');
+        }
+        write_json(out, child, source);
+    }
+    out.push_str("]}");
+}
+
+fn kind_name(hir: &Hir) -> &'static str {
+    match hir.kind {
+        HirKind::Int(_) => "int",
+        HirKind::UInt(_) => "uint",
+        HirKind::Float(_) => "float",
+        HirKind::Bool(_) => "bool",
+        HirKind::Var(_) => "var",
+        HirKind::Tuple(_) => "tuple",
+        HirKind::Block(_) => "block",
+        HirKind::Func { .. } => "fn",
+        HirKind::Binary { .. } => "binary",
+        HirKind::Call { .. } => "call",
+        HirKind::Assign { .. } => "assign",
+        HirKind::If { .. } => "if",
+        HirKind::Match { .. } => "match",
+        HirKind::For { .. } => "for",
+        HirKind::While { .. } => "while",
+        HirKind::Else { .. } => "else",
+        HirKind::Break { .. } => "break",
+        HirKind::Deferred { .. } => "deferred",
+        HirKind::Unwrap { .. } => "unwrap",
+        HirKind::Cast { .. } => "cast",
+        HirKind::Not { .. } => "not",
+        HirKind::Struct(_) => "struct",
+        HirKind::Field { .. } => "field",
+        HirKind::InlineIr { .. } => "ir",
+    }
+}
+
+fn detail(hir: &Hir) -> Option<String> {
+    match &hir.kind {
+        HirKind::Int(value) => Some(value.to_string()),
+        HirKind::UInt(value) => Some(format!("{}u", value)),
+        HirKind::Float(value) => Some(format!("{:?}", value)),
+        HirKind::Bool(value) => Some(value.to_string()),
+        HirKind::Var(name) => Some(name.to_string()),
+        HirKind::Func { name, param, body } => {
+            let mut detail = match name {
+                Some(name) => format!("{} {}", name, param.source),
+                None => param.source.to_string(),
+            };
+            let captures: Vec<&str> = capture::captures(hir).iter().map(|var| var.source).collect();
+            if !captures.is_empty() {
+                detail.push_str(&format!(" captures {}", captures.join(", ")));
+            }
+            if body.performs_io.get() {
+                detail.push_str(" performs io");
+            }
+            Some(detail)
+        }
+        HirKind::For { label, .. } | HirKind::While { label, .. } | HirKind::Break { label } => label.map(str::to_string),
+        HirKind::Binary { op, .. } => Some(op.to_string()),
+        HirKind::Assign { pattern, .. } => Some(pattern.source.to_string()),
+        HirKind::Match { arms, .. } => {
+            let patterns: Vec<&str> = arms.iter().map(|(pattern, _)| pattern.source).collect();
+            Some(patterns.join(", "))
+        }
+        HirKind::Cast { ty, .. } => Some(ty.to_string()),
+        HirKind::Field { name, .. } => Some(name.to_string()),
+        _ => None,
+    }
+}
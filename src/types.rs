@@ -1,18 +1,35 @@
-use std::{rc::Rc, cell::RefCell};
-use crate::ir::{Var, FunctionId, Block, Function, Program};
-use crate::ast::{Parsed, Expr};
+use std::{rc::Rc, cell::RefCell, fmt};
+use crate::ir::{Var, BlockId, FunctionId, Block, Effects, Function, Program};
+use crate::hir::{CastType, Hir, Pattern};
+use crate::builtins::Builtin;
+use crate::scope::Scope;
 
 #[derive(Debug, Clone)]
 pub enum Type<'a, 'b> {
     Int(Var),
+    UInt(Var),
+    Float(Var),
     Bool(Var),
     Maybe(Var, Box<Type<'a, 'b>>),
     Tuple(Vec<Type<'a, 'b>>),
+    /// Named fields, held like a tuple of their values in the order they
+    /// were written.
+    Struct(Vec<(Rc<str>, Type<'a, 'b>)>),
+    /// The handle of a map on the heap, with the types of its keys and
+    /// values once something has been inserted.
+    Map(Var, Option<(CastType, CastType)>),
+    /// The handle of a channel on the heap, with the type of its values
+    /// once something has been sent on it.
+    Chan(Var, Option<CastType>),
     Func {
-        pattern: &'b Parsed<'a, Expr<'a>>,
-        expr: &'b Parsed<'a, Expr<'a>>,
+        param: &'b Pattern<'a>,
+        body: &'b Hir<'a>,
         impls: Rc<RefCell<Vec<Implementation<'a, 'b>>>>,
-    }
+        /// The specializations currently being compiled.
+        pending: Rc<RefCell<Vec<Pending<'a, 'b>>>>,
+        scope: Scope<'a, 'b>,
+    },
+    Builtin(Builtin),
 }
 
 #[derive(Debug)]
@@ -20,21 +37,39 @@ pub struct Implementation<'a, 'b> {
     pub param_ty: Type<'a, 'b>,
     pub return_ty: Type<'a, 'b>,
     pub function: FunctionId,
+    pub effects: Effects,
+}
+
+/// A specialization whose body is still being compiled, which recursive
+/// calls are made to before its return type is known.
+#[derive(Debug)]
+pub struct Pending<'a, 'b> {
+    pub param_ty: Type<'a, 'b>,
+    pub function: FunctionId,
+    /// The return type recursive calls assumed, checked against the body
+    /// once it is compiled.
+    pub return_ty: Option<Type<'a, 'b>>,
 }
 
 impl<'a, 'b> PartialEq for Type<'a, 'b> {
     fn eq(&self, other: &Type<'a, 'b>) -> bool {
         match (self, other) {
             (Type::Int(_), Type::Int(_)) => true,
+            (Type::UInt(_), Type::UInt(_)) => true,
+            (Type::Float(_), Type::Float(_)) => true,
+            (Type::Builtin(a), Type::Builtin(b)) => a == b,
             (Type::Bool(_), Type::Bool(_)) => true,
+            (Type::Map(_, a), Type::Map(_, b)) => a == b,
+            (Type::Chan(_, a), Type::Chan(_, b)) => a == b,
             (Type::Tuple(atypes), Type::Tuple(btypes)) if atypes.len() == btypes.len() => {
                 for (a, b) in atypes.iter().zip(btypes) {
                     if a != b {
                         return false
                     }
                 }
-                return true
+                true
             }
+            (Type::Struct(afields), Type::Struct(bfields)) => afields == bfields,
             _ => false,
         }
     }
@@ -43,29 +78,66 @@ impl<'a, 'b> PartialEq for Type<'a, 'b> {
 impl<'a, 'b> Eq for Type<'a, 'b> {}
 
 impl<'a, 'b> Type<'a, 'b> {
-    pub fn merge(cond: Var, a: &Type<'a, 'b>, b: &Type<'a, 'b>, program: &mut Program, block: &mut Block) -> Type<'a, 'b> {
+    /// Selects between `a` and `b` depending on `cond`, or returns `None` if
+    /// the two types cannot be held in the same variables.
+    pub fn merge(cond: Var, a: &Type<'a, 'b>, b: &Type<'a, 'b>, program: &mut Program, block: &mut Block) -> Option<Type<'a, 'b>> {
+        Type::merge_with(a, b, &mut |a, b| block.select(cond, a, b, program))
+    }
+    /// Takes `a` when `block` was branched to from `from_a`, and `b` when
+    /// from `from_b`, with phis at its start, or returns `None` as `merge`
+    /// does.
+    pub fn join(a: &Type<'a, 'b>, from_a: BlockId, b: &Type<'a, 'b>, from_b: BlockId, program: &mut Program, block: &mut Block) -> Option<Type<'a, 'b>> {
+        Type::merge_with(a, b, &mut |a, b| block.phi(vec![(from_a, a), (from_b, b)], program))
+    }
+    /// Pairs up the variables of `a` and `b`, giving each pair to `merge`
+    /// for the variable that holds the result.
+    fn merge_with(a: &Type<'a, 'b>, b: &Type<'a, 'b>, merge: &mut impl FnMut(Var, Var) -> Var) -> Option<Type<'a, 'b>> {
         match (a, b) {
-            (Type::Int(a), Type::Int(b)) => Type::Int(block.phi(cond, *a, *b, program)),
-            (Type::Bool(a), Type::Bool(b)) => Type::Bool(block.phi(cond, *a, *b, program)),
+            (Type::Int(a), Type::Int(b)) => Some(Type::Int(merge(*a, *b))),
+            (Type::UInt(a), Type::UInt(b)) => Some(Type::UInt(merge(*a, *b))),
+            (Type::Float(a), Type::Float(b)) => Some(Type::Float(merge(*a, *b))),
+            (Type::Bool(a), Type::Bool(b)) => Some(Type::Bool(merge(*a, *b))),
+            (Type::Maybe(atag, a), Type::Maybe(btag, b)) => {
+                let tag = merge(*atag, *btag);
+                Some(Type::Maybe(tag, Box::new(Type::merge_with(a, b, merge)?)))
+            }
             (Type::Tuple(atypes), Type::Tuple(btypes)) if atypes.len() == btypes.len() => {
                 let mut types = vec![];
                 for (a, b) in atypes.iter().zip(btypes) {
-                    types.push(Type::merge(cond, a, b, program, block))
+                    types.push(Type::merge_with(a, b, merge)?)
+                }
+                Some(Type::Tuple(types))
+            }
+            (Type::Struct(afields), Type::Struct(bfields)) if afields.len() == bfields.len() => {
+                let mut fields = vec![];
+                for ((name, a), (other, b)) in afields.iter().zip(bfields) {
+                    if name != other {
+                        return None
+                    }
+                    fields.push((name.clone(), Type::merge_with(a, b, merge)?))
                 }
-                Type::Tuple(types)
+                Some(Type::Struct(fields))
             }
-            _ => unimplemented!(),
+            (Type::Map(a, atypes), Type::Map(b, btypes)) if atypes == btypes => Some(Type::Map(merge(*a, *b), *atypes)),
+            (Type::Chan(a, aty), Type::Chan(b, bty)) if aty == bty => Some(Type::Chan(merge(*a, *b), *aty)),
+            (func @ Type::Func { impls, .. }, Type::Func { impls: other, .. }) if Rc::ptr_eq(impls, other) => Some(func.clone()),
+            (Type::Builtin(a), Type::Builtin(b)) if a == b => Some(Type::Builtin(*a)),
+            _ => None,
         }
     }
     pub fn get_used_vars(&self) -> Vec<Var> {
         let mut vars = Vec::new();
         self.add_vars_to_vec(&mut vars);
-        vars.iter().cloned().collect::<Vec<Var>>()
+        vars.to_vec()
     }
     pub fn add_vars_to_vec(&self, map: &mut Vec<Var>) {
         match self {
             Type::Int(var) => { map.push(*var); },
+            Type::UInt(var) => { map.push(*var); },
+            Type::Float(var) => { map.push(*var); },
             Type::Bool(var) => { map.push(*var); },
+            Type::Map(var, _) => { map.push(*var); },
+            Type::Chan(var, _) => { map.push(*var); },
             Type::Maybe(var, ty) => {
                 map.push(*var);
                 ty.add_vars_to_vec(map);
@@ -73,13 +145,21 @@ impl<'a, 'b> Type<'a, 'b> {
             Type::Tuple(types) => for ty in types {
                 ty.add_vars_to_vec(map)
             }
+            Type::Struct(fields) => for (_, ty) in fields {
+                ty.add_vars_to_vec(map)
+            }
             Type::Func { .. } => (),
+            Type::Builtin(_) => (),
         }
     }
     pub fn map_to(&self, mut vars: &[Var]) -> Type<'a, 'b> {
         match self {
             Type::Int(_) => Type::Int(vars[0]),
+            Type::UInt(_) => Type::UInt(vars[0]),
+            Type::Float(_) => Type::Float(vars[0]),
             Type::Bool(_) => Type::Bool(vars[0]),
+            Type::Map(_, types) => Type::Map(vars[0], *types),
+            Type::Chan(_, ty) => Type::Chan(vars[0], *ty),
             Type::Maybe(_, ty) => Type::Maybe(vars[0], Box::new(ty.map_to(&vars[1..]))),
             Type::Tuple(types) => {
                 let mut vec = vec![];
@@ -89,16 +169,48 @@ impl<'a, 'b> Type<'a, 'b> {
                 }
                 Type::Tuple(vec)
             },
+            Type::Struct(fields) => {
+                let mut vec = vec![];
+                for (name, ty) in fields {
+                    vec.push((name.clone(), ty.map_to(&vars[..ty.size()])));
+                    vars = &vars[ty.size()..];
+                }
+                Type::Struct(vec)
+            },
             Type::Func { .. } => self.clone(),
+            Type::Builtin(_) => self.clone(),
         }
     }
+    /// The type without the functions it could hold, which borrow the
+    /// program they were compiled from, or `None` if it holds a function.
+    pub fn detach<'c, 'd>(&self) -> Option<Type<'c, 'd>> {
+        Some(match self {
+            Type::Int(var) => Type::Int(*var),
+            Type::UInt(var) => Type::UInt(*var),
+            Type::Float(var) => Type::Float(*var),
+            Type::Bool(var) => Type::Bool(*var),
+            Type::Map(var, types) => Type::Map(*var, *types),
+            Type::Chan(var, ty) => Type::Chan(*var, *ty),
+            Type::Maybe(var, ty) => Type::Maybe(*var, Box::new(ty.detach()?)),
+            Type::Tuple(types) => Type::Tuple(types.iter().map(Type::detach).collect::<Option<_>>()?),
+            Type::Struct(fields) => Type::Struct(fields.iter().map(|(name, ty)| Some((name.clone(), ty.detach()?))).collect::<Option<_>>()?),
+            Type::Builtin(builtin) => Type::Builtin(*builtin),
+            Type::Func { .. } => return None,
+        })
+    }
     pub fn size(&self) -> usize {
         match self {
             Type::Int(_) => 1,
+            Type::UInt(_) => 1,
+            Type::Float(_) => 1,
             Type::Bool(_) => 1,
+            Type::Map(..) => 1,
+            Type::Chan(..) => 1,
             Type::Maybe(_, ty) => 1 + ty.size(),
             Type::Tuple(types) => types.iter().map(|ty| ty.size()).sum(),
+            Type::Struct(fields) => fields.iter().map(|(_, ty)| ty.size()).sum(),
             Type::Func { .. } => 0,
+            Type::Builtin(_) => 0,
         }
     }
     pub fn as_parameter_ty(&self, function: &mut Function, program: &mut Program) -> Type<'a, 'b> {
@@ -113,4 +225,36 @@ impl<'a, 'b> Type<'a, 'b> {
             function.return_var(var)
         }
     }
-}
\ No newline at end of file
+}
+impl<'a, 'b> fmt::Display for Type<'a, 'b> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Type::Int(_) => write!(f, "int"),
+            Type::UInt(_) => write!(f, "uint"),
+            Type::Float(_) => write!(f, "float"),
+            Type::Bool(_) => write!(f, "bool"),
+            Type::Maybe(_, ty) => write!(f, "maybe {}", ty),
+            Type::Tuple(types) => {
+                write!(f, "(")?;
+                let mut iter = types.iter();
+                if let Some(ty) = iter.next() {
+                    write!(f, "{}", ty)?;
+                    for ty in iter {
+                        write!(f, ", {}", ty)?;
+                    }
+                }
+                write!(f, ")")
+            }
+            Type::Struct(fields) => {
+                let fields: Vec<String> = fields.iter().map(|(name, ty)| format!("{}: {}", name, ty)).collect();
+                write!(f, "struct {{ {} }}", fields.join(", "))
+            }
+            Type::Map(_, Some((key, value))) => write!(f, "map({}, {})", key, value),
+            Type::Map(_, None) => write!(f, "map"),
+            Type::Chan(_, Some(ty)) => write!(f, "chan({})", ty),
+            Type::Chan(_, None) => write!(f, "chan"),
+            Type::Func { .. } => write!(f, "fn"),
+            Type::Builtin(builtin) => write!(f, "builtin {}", builtin.name()),
+        }
+    }
+}
@@ -0,0 +1,298 @@
+//! Unrolling of loops that go round a small number of times known when
+//! compiling. Such a loop is unrolled in full: its blocks are copied once
+//! for each time round, with variables of their own, so that the program
+//! goes straight through and the branches back to the header go.
+//!
+//! The loops unrolled are those the optimizer leaves in SSA form whose
+//! header is the only way in and out. The header's branch compares a
+//! counter with a constant, and the counter is a phi of a constant from
+//! before the loop and of itself plus a constant from the end of the body.
+
+use crate::builtins::Intrinsic;
+use crate::ir::{Block, BlockId, ExitInstruction, Function, Instruction, Program, Var};
+use crate::optimize::{find_constants, Constant};
+use crate::{ops, ssa};
+use std::collections::HashMap;
+use std::mem;
+
+/// The most times a loop can go round to be unrolled, unless
+/// `--unroll-threshold` says otherwise.
+pub const DEFAULT_THRESHOLD: usize = 8;
+
+/// The most instructions the copies of one loop can have, however few
+/// times it goes round.
+const MAX_INSTRUCTIONS: usize = 1024;
+
+/// A loop of a function, by block index.
+struct Loop {
+    header: usize,
+    /// The one block that branches back to the header.
+    latch: usize,
+    /// Every block in the loop, the header among them, in order.
+    blocks: Vec<usize>,
+    /// The one block outside the loop that branches to the header.
+    preheader: usize,
+    /// Where the header goes to go round again, and to leave.
+    body: usize,
+    exit: usize,
+}
+
+/// What unrolling needs to know about every variable of the program.
+struct Facts {
+    constants: Vec<Option<Constant>>,
+    /// How many instructions write each variable.
+    writes: Vec<usize>,
+    /// Whether more than one function uses or defines each variable.
+    shared: Vec<bool>,
+}
+
+/// Unrolls a loop of each function that goes round at most `threshold`
+/// times, and returns whether there were any. A threshold of zero unrolls
+/// nothing.
+pub fn unroll_loops(program: &mut Program, threshold: usize) -> bool {
+    if threshold == 0 {
+        return false
+    }
+    let facts = facts(program);
+    let mut changed = false;
+    for function_id in program.get_function_ids() {
+        let mut function = program.get_function(function_id).clone();
+        function.remove_unreachable_blocks();
+        let unrollable = loops(&function).into_iter().find_map(|found| {
+            let trips = trip_count(&function, &found, &facts, threshold)?;
+            let size: usize = found.blocks.iter().map(|&index| function.get_blocks()[index].get_instructions().len()).sum();
+            (trips * size <= MAX_INSTRUCTIONS && can_copy(&function, &found, &facts)).then_some((found, trips))
+        });
+        if let Some((found, trips)) = unrollable {
+            debug!("unroll: f{} goes round the loop at b{} {} times", function_id.get_id(), found.header, trips);
+            unroll(&mut function, &found, trips, program);
+            function.remove_unreachable_blocks();
+            *program.get_function_mut(function_id) = function;
+            changed = true;
+        }
+    }
+    changed
+}
+
+fn facts(program: &Program) -> Facts {
+    let mut writes = vec![0; program.get_variable_count()];
+    let mut user = vec![None; program.get_variable_count()];
+    let mut shared = vec![false; program.get_variable_count()];
+    for function_id in program.get_function_ids() {
+        let function = program.get_function(function_id);
+        for block in function.get_blocks() {
+            for var in block.get_instructions().iter().flat_map(Instruction::defs) {
+                writes[var.get_id()] += 1;
+            }
+        }
+        for var in function.vars() {
+            match user[var.get_id()] {
+                None => user[var.get_id()] = Some(function_id.get_id()),
+                Some(other) => shared[var.get_id()] |= other != function_id.get_id(),
+            }
+        }
+    }
+    Facts { constants: find_constants(program), writes, shared }
+}
+
+/// The loops of `function` with one block that branches back to the
+/// header, one that branches to it from outside, and only the header
+/// leaving the loop or coming into it.
+fn loops(function: &Function) -> Vec<Loop> {
+    let count = function.get_blocks().len();
+    let predecessors = ssa::predecessors(function);
+    let idom = ssa::dominators(function, &predecessors);
+    let dominates = |dominator: usize, mut index: usize| loop {
+        if index == dominator {
+            return true
+        }
+        if index == idom[index] {
+            return false
+        }
+        index = idom[index];
+    };
+    let mut found = Vec::new();
+    for header in 0..count {
+        let latches: Vec<usize> = predecessors[header].iter().copied().filter(|&predecessor| dominates(header, predecessor)).collect();
+        let &[latch] = &latches[..] else { continue };
+        if latch == header {
+            continue
+        }
+        // the blocks that reach the latch without going through the header
+        let mut in_loop = vec![false; count];
+        in_loop[header] = true;
+        let mut stack = vec![latch];
+        while let Some(index) = stack.pop() {
+            if !mem::replace(&mut in_loop[index], true) {
+                stack.extend(&predecessors[index]);
+            }
+        }
+        let outside: Vec<usize> = predecessors[header].iter().copied().filter(|&predecessor| !in_loop[predecessor]).collect();
+        let &[preheader] = &outside[..] else { continue };
+        let &ExitInstruction::ConditionalBranch { block1, block2, .. } = function.get_blocks()[header].get_exit_instruction() else { continue };
+        let (body, exit) = match (in_loop[block1.get_id()], in_loop[block2.get_id()]) {
+            (true, false) => (block1.get_id(), block2.get_id()),
+            (false, true) => (block2.get_id(), block1.get_id()),
+            _ => continue,
+        };
+        let blocks: Vec<usize> = (0..count).filter(|&index| in_loop[index]).collect();
+        let closed = blocks.iter().filter(|&&index| index != header).all(|&index| {
+            function.successors(index).into_iter().all(|successor| in_loop[successor])
+                && predecessors[index].iter().all(|&predecessor| in_loop[predecessor])
+        });
+        if closed {
+            found.push(Loop { header, latch, blocks, preheader, body, exit });
+        }
+    }
+    found
+}
+
+/// Compares two numbers the way a comparison instruction does.
+type Compare = fn(i32, i32) -> bool;
+
+/// The comparison `inst` makes if it writes `cond`, and what it compares.
+fn comparison(inst: &Instruction, cond: Var) -> Option<(Compare, Var, Var)> {
+    match *inst {
+        Instruction::LessInt { dest, a, b } if dest == cond => Some((|a, b| a < b, a, b)),
+        Instruction::LessEqInt { dest, a, b } if dest == cond => Some((|a, b| a <= b, a, b)),
+        Instruction::LessUInt { dest, a, b } if dest == cond => Some((ops::less_uint, a, b)),
+        Instruction::LessEqUInt { dest, a, b } if dest == cond => Some((ops::less_eq_uint, a, b)),
+        Instruction::CallBuiltin { intrinsic, ref args, ref returns } if returns[..] == [cond] => match intrinsic {
+            Intrinsic::RangeNextInt => Some((|a, b| a < b, args[0], args[1])),
+            Intrinsic::RangeNextUInt => Some((ops::less_uint, args[0], args[1])),
+            _ => None,
+        }
+        _ => None,
+    }
+}
+
+/// How many times `found` goes round, if its header compares a counter
+/// with a constant and that is at most `threshold`.
+fn trip_count(function: &Function, found: &Loop, facts: &Facts, threshold: usize) -> Option<usize> {
+    let int = |var: Var| match facts.constants[var.get_id()] {
+        Some(Constant::Int(value)) => Some(value),
+        _ => None,
+    };
+    let header = &function.get_blocks()[found.header];
+    let &ExitInstruction::ConditionalBranch { cond, block1, .. } = header.get_exit_instruction() else { return None };
+    let (compare, a, b) = header.get_instructions().iter().find_map(|inst| comparison(inst, cond))?;
+    let (counter, limit, counter_first) = match (int(a), int(b)) {
+        (None, Some(limit)) => (a, limit, true),
+        (Some(limit), None) => (b, limit, false),
+        _ => return None,
+    };
+    let sources = header.get_instructions().iter().find_map(|inst| match inst {
+        Instruction::Phi { dest, sources } if *dest == counter => Some(sources),
+        _ => None,
+    })?;
+    let source = |from: usize| sources.iter().find(|(block, _)| block.get_id() == from).map(|&(_, var)| var);
+    let start = int(source(found.preheader)?)?;
+    let next = source(found.latch)?;
+    if facts.writes[counter.get_id()] != 1 || facts.writes[next.get_id()] != 1 {
+        return None
+    }
+    let step = found.blocks.iter()
+        .flat_map(|&index| function.get_blocks()[index].get_instructions())
+        .find_map(|inst| match *inst {
+            Instruction::AddInt { dest, a, b } if dest == next && a == counter => int(b),
+            Instruction::AddInt { dest, a, b } if dest == next && b == counter => int(a),
+            Instruction::SubInt { dest, a, b } if dest == next && a == counter => int(b).map(|step| ops::sub_int(0, step)),
+            _ => None,
+        })?;
+    let goes_round = block1.get_id() == found.body;
+    let mut value = start;
+    for trips in 0..=threshold {
+        let compared = if counter_first { compare(value, limit) } else { compare(limit, value) };
+        if compared != goes_round {
+            return Some(trips)
+        }
+        value = ops::add_int(value, step);
+    }
+    None
+}
+
+/// Whether the variables `found` writes can be given new names in each
+/// copy: only this function uses them, each is written once, and those
+/// written after the header are not read outside the loop, which only
+/// sees the header's.
+fn can_copy(function: &Function, found: &Loop, facts: &Facts) -> bool {
+    let in_loop = |index: usize| found.blocks.binary_search(&index).is_ok();
+    let defs = |index: usize| function.get_blocks()[index].get_instructions().iter().flat_map(Instruction::defs);
+    if !found.blocks.iter().flat_map(|&index| defs(index)).all(|var| facts.writes[var.get_id()] == 1 && !facts.shared[var.get_id()]) {
+        return false
+    }
+    let mut read_outside: Vec<Var> = function.get_returns().clone();
+    for block in function.get_blocks().iter().filter(|block| !in_loop(block.get_id().get_id())) {
+        read_outside.extend(block.get_instructions().iter().flat_map(Instruction::uses));
+        if let ExitInstruction::ConditionalBranch { cond: var, .. } | ExitInstruction::Switch { scrutinee: var, .. } = block.get_exit_instruction() {
+            read_outside.push(*var);
+        }
+    }
+    found.blocks.iter().filter(|&&index| index != found.header).flat_map(|&index| defs(index)).all(|var| !read_outside.contains(&var))
+}
+
+/// Copies the blocks of `found` once for each of the `trips` times it goes
+/// round, each copy going on to the next, and leaves the header to go
+/// round no more, going straight to the exit. The header's phis become
+/// copies of the value from before the loop or from the copy before.
+fn unroll(function: &mut Function, found: &Loop, trips: usize, program: &mut Program) {
+    let defs: Vec<Var> = found.blocks.iter()
+        .flat_map(|&index| function.get_blocks()[index].get_instructions())
+        .flat_map(Instruction::defs)
+        .collect();
+    let renames: Vec<HashMap<Var, Var>> = (0..trips).map(|_| defs.iter().map(|&var| (var, program.new_variable())).collect()).collect();
+    let first = function.get_blocks().len();
+    // the last time round, the header is the one the loop had
+    let copy_of = |trip: usize, index: usize| match trip == trips {
+        true => index,
+        false => first + trip * found.blocks.len() + found.blocks.binary_search(&index).expect("the block is in the loop"),
+    };
+    let mut copies = Vec::new();
+    for trip in 0..=trips {
+        let rename = |var: Var| renames.get(trip).and_then(|rename| rename.get(&var)).copied().unwrap_or(var);
+        let previous = |var: Var| renames[trip - 1].get(&var).copied().unwrap_or(var);
+        for &index in &found.blocks {
+            if trip == trips && index != found.header {
+                continue
+            }
+            let block = &function.get_blocks()[index];
+            let insts = block.get_instructions().iter().map(|inst| match inst {
+                Instruction::Phi { dest, sources } if index == found.header => {
+                    let from = if trip == 0 { found.preheader } else { found.latch };
+                    let &(_, src) = sources.iter().find(|(block, _)| block.get_id() == from).expect("the header's phis name the preheader and the latch");
+                    Instruction::Copy { dest: rename(*dest), src: if trip == 0 { src } else { previous(src) } }
+                }
+                inst => {
+                    let mut inst = inst.clone();
+                    inst.map_uses(&mut |var| rename(var));
+                    inst.map_defs(&mut |var| rename(var));
+                    inst.map_phi_blocks(|block| Some(BlockId::new(copy_of(trip, block.get_id()))));
+                    inst
+                }
+            }).collect();
+            let mut exit = block.get_exit_instruction().clone();
+            if index == found.header {
+                exit = ExitInstruction::Branch { block: BlockId::new(if trip == trips { found.exit } else { copy_of(trip, found.body) }) };
+            } else {
+                exit.map_targets(|block| BlockId::new(match block.get_id() == found.header {
+                    true => copy_of(trip + 1, block.get_id()),
+                    false => copy_of(trip, block.get_id()),
+                }));
+                if let ExitInstruction::ConditionalBranch { cond: var, .. } | ExitInstruction::Switch { scrutinee: var, .. } = &mut exit {
+                    *var = rename(*var);
+                }
+            }
+            let lines = (0..block.get_instructions().len()).map(|line| block.get_line(line)).collect();
+            copies.push(Block::from_parts(copy_of(trip, index), insts, lines, exit, block.get_effects()));
+        }
+    }
+    let header = copies.pop().expect("the header is copied last");
+    function.get_blocks_mut()[found.header] = header;
+    function.get_blocks_mut().extend(copies);
+    let mut exit = function.get_blocks()[found.preheader].get_exit_instruction().clone();
+    exit.map_targets(|block| match block.get_id() == found.header {
+        true => BlockId::new(copy_of(0, found.header)),
+        false => block,
+    });
+    function.get_blocks_mut()[found.preheader].set_exit_instruction(exit);
+}
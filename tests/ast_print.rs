@@ -0,0 +1,103 @@
+//! Printing a parsed program and parsing the text again gives back the
+//! same tree.
+
+use language::ast::{Expr, Parsed};
+use language::parser::parse_source;
+
+/// The tree without positions, with every expression bracketed.
+fn shape(expr: &Parsed<Expr>) -> String {
+    let kind = match expr.get_node() {
+        Expr::IntLiteral(text) | Expr::FloatLiteral(text) | Expr::BoolLiteral(text) | Expr::Ident(text) => text.to_string(),
+        Expr::Binary { op, .. } => op.to_string(),
+        Expr::Func { name, .. } => format!("fn {}", name.unwrap_or("")),
+        Expr::Cast { ty, .. } => format!("as {}", ty.node),
+        Expr::Field { name, .. } => format!(".{}", name.node),
+        Expr::Tuple { .. } => "tuple".to_string(),
+        Expr::Block { .. } => "block".to_string(),
+        Expr::If { .. } => "if".to_string(),
+        Expr::Not { .. } => "!".to_string(),
+        other => format!("{:?}", std::mem::discriminant(other)),
+    };
+    let children: Vec<String> = expr.get_node().children().into_iter().map(shape).collect();
+    format!("({} {})", kind, children.join(" "))
+}
+
+/// Parses `source`, prints it, and checks the printed text parses into the
+/// same tree, returning the printed text.
+fn round_trip(source: &str) -> String {
+    let parsed = parse_source(source).unwrap_or_else(|error| panic!("{}: {}", source, error));
+    let printed = parsed.to_string();
+    let reparsed = match parse_source(&printed) {
+        Ok(reparsed) => shape(&reparsed),
+        Err(error) => panic!("{} printed as {}: {}", source, printed, error),
+    };
+    assert_eq!(shape(&parsed), reparsed, "{} printed as {}", source, printed);
+    printed
+}
+
+const OPERATORS: &[&str] = &[
+    "+", "-", "*", "/", "%", "==", "!=", "<", "<=", ">", ">=", "&&", "||", "&", "|", "^", "<<", ">>",
+    "=", "+=", "-=", "*=", "/=", "%=", "&=", "|=", "^=", "<<=", ">>=", "else",
+];
+
+/// A small xorshift generator, so that every run tests the same programs.
+struct Rng(u64);
+
+impl Rng {
+    fn below(&mut self, bound: usize) -> usize {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 % bound as u64) as usize
+    }
+}
+
+/// A fully bracketed expression at most `depth` deep.
+fn expression(rng: &mut Rng, depth: usize) -> String {
+    if depth == 0 {
+        return ["a", "b", "1", "2.5", "true"][rng.below(5)].to_string()
+    }
+    let kind = rng.below(9);
+    let op = OPERATORS[rng.below(OPERATORS.len())];
+    let mut sub = || format!("({})", expression(rng, depth - 1));
+    match kind {
+        0 => format!("!{}", sub()),
+        1 => format!("{} as int", sub()),
+        2 => format!("{}.x", sub()),
+        3 => format!("f{}", sub()),
+        4 => format!("if {} {}", sub(), sub()),
+        5 => format!("fn(x) {}", sub()),
+        6 => format!("({}, {})", sub(), sub()),
+        _ => {
+            let left = sub();
+            format!("{} {} {}", left, op, sub())
+        }
+    }
+}
+
+#[test]
+fn generated_expressions_print_as_they_parse() {
+    let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+    for _ in 0..2000 {
+        let depth = 1 + rng.below(5);
+        round_trip(&format!("fn main() {}", expression(&mut rng, depth)));
+    }
+}
+
+#[test]
+fn brackets_are_kept_only_where_needed() {
+    assert_eq!(round_trip("fn main() (a = 1) + 2"), "fn main() (a = 1) + 2");
+    assert_eq!(round_trip("fn main() a - (b - c)"), "fn main() a - (b - c)");
+    assert_eq!(round_trip("fn main() (a - b) - c"), "fn main() a - b - c");
+    assert_eq!(round_trip("fn main() a = (b = c)"), "fn main() a = b = c");
+    assert_eq!(round_trip("fn main() (a * b) + (c * d)"), "fn main() a * b + c * d");
+    assert_eq!(round_trip("fn main() !(a + b)"), "fn main() !(a + b)");
+}
+
+#[test]
+fn else_takes_a_whole_call_on_its_left() {
+    let parsed = parse_source("fn main() f(x) else y").unwrap();
+    assert!(shape(&parsed).contains("(else (() (f ) (x )) (y ))"), "{}", shape(&parsed));
+    let parsed = parse_source("fn main() a = b else c + 1").unwrap();
+    assert!(shape(&parsed).contains("(= (a ) (else (b ) (+ (c ) (1 ))))"), "{}", shape(&parsed));
+}
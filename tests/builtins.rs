@@ -0,0 +1,29 @@
+//! Builtins given arguments they can't use.
+
+use language::playground::compile_and_run;
+
+#[test]
+fn pow_of_ints_traps_on_a_negative_exponent() {
+    let out = compile_and_run("fn main() {\n    e = 0 - 1\n    pow(2, e)\n}");
+    assert!(out.compiled);
+    assert_eq!(out.output, "");
+    assert_eq!(out.diagnostics, "error: `pow` was given a negative exponent at line 3\n");
+}
+
+#[test]
+fn pow_of_ints_takes_a_zero_exponent() {
+    assert_eq!(compile_and_run("fn main() pow(7, 0)").output, "1");
+}
+
+#[test]
+fn rand_range_traps_on_an_empty_range() {
+    let out = compile_and_run("fn main() {\n    low = 5\n    rand_range(low, 1)\n}");
+    assert_eq!(out.diagnostics, "error: `rand_range` was given an empty range at line 3\n");
+    let out = compile_and_run("fn main() rand_range(3, 3)");
+    assert_eq!(out.diagnostics, "error: `rand_range` was given an empty range at line 1\n");
+}
+
+#[test]
+fn rand_range_of_one_value_gives_it() {
+    assert_eq!(compile_and_run("fn main() rand_range(5, 6)").output, "5");
+}
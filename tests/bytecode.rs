@@ -0,0 +1,93 @@
+//! Encoding compiled programs and decoding them again.
+
+use language::bytecode::{content_hash, decode, disassemble, encode};
+use language::compiler::compile_program;
+use language::execute::{SandboxConfig, VirtualMachine};
+use language::ir::{FunctionId, PrintOptions, Program};
+use language::types::Type;
+use language::{hir, parser};
+use std::fs;
+
+/// What running `main` prints and returns, or the trap that stopped it.
+fn run(program: &Program, entry: FunctionId, ty: &Type) -> String {
+    let mut vm = VirtualMachine::new(program);
+    vm.set_sandbox(SandboxConfig { fuel: Some(10_000_000), ..SandboxConfig::default() });
+    vm.capture_output();
+    let result = vm.execute(program.get_function(entry));
+    let printed = vm.take_output();
+    match result {
+        Ok(()) => format!("{}{}", printed, vm.format_ty(ty)),
+        Err(trap) => format!("{}{}", printed, trap),
+    }
+}
+
+/// Compiles `source`, encodes and decodes it, and checks that the decoded
+/// program is the one encoded and runs the same. Returns the encoding.
+fn round_trip(source: &str) -> Vec<u8> {
+    let ast = parser::parse_source(source).unwrap();
+    let hir = hir::lower(&ast, &[]).unwrap();
+    let (program, entry, ty) = compile_program(&hir, false, "main").map_err(|_| ()).unwrap();
+    let bytes = encode(&program, entry, &ty).unwrap();
+    let (decoded, decoded_entry, decoded_ty) = decode(&bytes).unwrap();
+    let (reachable, reachable_entry) = program.reachable(entry);
+    assert_eq!(decoded.to_string(), reachable.to_string());
+    assert_eq!(decoded_entry.get_id(), reachable_entry.get_id());
+    assert_eq!(decoded_ty.to_string(), ty.to_string());
+    assert_eq!(encode(&decoded, decoded_entry, &decoded_ty).as_deref(), Some(&bytes[..]));
+    assert_eq!(run(&decoded, decoded_entry, &decoded_ty), run(&program, entry, &ty));
+    bytes
+}
+
+#[test]
+fn examples_round_trip() {
+    for entry in fs::read_dir("examples").unwrap() {
+        let source = fs::read_to_string(entry.unwrap().path()).unwrap();
+        round_trip(&source);
+    }
+}
+
+#[test]
+fn every_result_shape_round_trips() {
+    round_trip("fn main() 1.5");
+    round_trip("fn main() (1, (true, 2u))");
+    round_trip("fn main() if (false) 1");
+    round_trip("fn main() {\n    p = struct { x = 1\n y = 2.0 }\n    p\n}");
+    round_trip("fn main() {\n    m = map()\n    m = insert(m, 1, 2)\n    m\n}");
+}
+
+#[test]
+fn a_function_cannot_be_encoded() {
+    let ast = parser::parse_source("fn main() fn(x) x").unwrap();
+    let hir = hir::lower(&ast, &[]).unwrap();
+    let (program, entry, ty) = compile_program(&hir, false, "main").map_err(|_| ()).unwrap();
+    assert!(encode(&program, entry, &ty).is_none());
+}
+
+#[test]
+fn changed_bytes_are_rejected() {
+    let bytes = round_trip("fn main() {\n    print(1)\n    2\n}");
+    assert!(content_hash(&bytes).is_some());
+    for index in 0..bytes.len() {
+        let mut changed = bytes.clone();
+        changed[index] ^= 1;
+        assert!(decode(&changed).is_none(), "flipping byte {} was not noticed", index);
+    }
+    for len in 0..bytes.len() {
+        assert!(decode(&bytes[..len]).is_none(), "{} bytes were read as a program", len);
+    }
+}
+
+#[test]
+fn disassembly_lists_the_functions_then_prints_them() {
+    let bytes = round_trip("fn square(x) x * x\nfn main() square(3) + 1");
+    let text = disassemble(&bytes).unwrap();
+    let (program, _, _) = decode(&bytes).unwrap();
+    let hash = content_hash(&bytes).unwrap();
+    let header = format!("; version 10, hash {:016x}\n; entry f2, returning int\n\
+        ; f0: 0 parameters, 1 result, 1 block, 4 instructions, pure\n\
+        ; f1: 1 parameter, 1 result, 1 block, 1 instruction, pure\n\
+        ; f2: 0 parameters, 1 result, 1 block, 1 instruction, pure\n\n", hash);
+    let listing = program.display(PrintOptions { types: true, align: true, source: None }).to_string();
+    assert_eq!(text, header + &listing);
+    assert!(disassemble(&bytes[1..]).is_none());
+}
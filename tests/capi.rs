@@ -0,0 +1,32 @@
+//! The C interface, called as C would call it.
+#![cfg(feature = "capi")]
+
+use language::capi::*;
+use std::ffi::{CStr, CString};
+
+const FOREVER: &str = "fn main() {\n    n = 0\n    while (true) n += 1\n    n\n}";
+
+#[test]
+fn run_limited_reports_the_limit() {
+    let source = CString::new(FOREVER).unwrap();
+    unsafe {
+        let result = lang_run_limited(source.as_ptr(), 1000, 0);
+        assert_eq!(lang_result_exhausted(result), 1);
+        let diagnostics = CStr::from_ptr(lang_result_diagnostics(result)).to_str().unwrap();
+        assert!(diagnostics.contains("ran out of fuel"), "{}", diagnostics);
+        lang_result_free(result);
+        let result = lang_run_limited(source.as_ptr(), 0, 10);
+        assert_eq!(lang_result_exhausted(result), 3);
+        lang_result_free(result);
+    }
+}
+
+#[test]
+fn a_program_that_stops_exhausts_nothing() {
+    let source = CString::new("fn main() 7").unwrap();
+    unsafe {
+        let result = lang_run(source.as_ptr());
+        assert_eq!((lang_result_exhausted(result), lang_result_exit_code(result)), (0, 7));
+        lang_result_free(result);
+    }
+}
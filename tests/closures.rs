@@ -0,0 +1,46 @@
+//! Functions that capture names from the scope they are created in.
+
+use language::Error;
+
+fn run(source: &str) -> String {
+    language::compile(source).and_then(|compiled| compiled.run()).map_or_else(|error| error.to_string(), |value| value.to_string())
+}
+
+fn error_code(source: &str) -> Option<&'static str> {
+    match language::compile(source) {
+        Err(Error::Compile(errors)) => errors[0].code,
+        _ => None,
+    }
+}
+
+#[test]
+fn returned_function_capturing_a_parameter() {
+    let source = "fn make(n) |x| x + n\nfn main() (make(1)(10), make(2)(10))";
+    assert_eq!(error_code(source), Some("E0015"));
+}
+
+#[test]
+fn returned_function_calling_one_that_captures_a_local() {
+    let source = "fn make(n) {\n    fn add(x) x + n\n    |x| add(x)\n}\nfn main() make(1)(2)";
+    assert_eq!(error_code(source), Some("E0015"));
+}
+
+#[test]
+fn returned_function_capturing_a_global_and_a_function() {
+    let source = "k = 5\nfn make() {\n    fn double(x) x * 2\n    |x| double(x) + k\n}\nfn main() make()(3)";
+    assert_eq!(run(source), "11");
+}
+
+#[test]
+fn passed_function_capturing_a_local() {
+    let source = "fn apply(f, x) f(x)\nfn main() {\n    n = 3\n    apply(|x| x + n, 4)\n}";
+    assert_eq!(run(source), "7");
+}
+
+#[test]
+fn captured_name_assigned_in_a_match_arm() {
+    let source = "fn main() {\n    n = 1\n    f = |x| x + n\n    match 2 {\n        2 => n = 5,\n        _ => 0\n    }\n    f(1)\n}";
+    assert_eq!(error_code(source), Some("E0011"));
+    let source = "fn main() {\n    n = 1\n    f = |x| x + n\n    match 2 {\n        n => n + 1\n    }\n    f(1)\n}";
+    assert_eq!(run(source), "2");
+}
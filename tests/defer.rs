@@ -0,0 +1,79 @@
+//! `defer`, which runs an expression when its block exits, whether at its
+//! end or by a `break` out of it.
+
+use language::execute::VirtualMachine;
+
+/// What running `main` in `source` prints.
+fn output(source: &str) -> String {
+    let compiled = language::compile(source).expect("the program compiles");
+    let mut vm = VirtualMachine::new(compiled.get_program());
+    vm.capture_output();
+    vm.execute(compiled.get_program().get_function(compiled.get_entry())).expect("the program runs");
+    vm.take_output()
+}
+
+#[test]
+fn break_runs_pending_defers() {
+    let source = "fn main() while (true) {\n    defer print(100)\n    break\n}";
+    assert_eq!(output(source), "100\n");
+}
+
+#[test]
+fn break_runs_the_defers_of_every_block_it_leaves() {
+    let source = "fn main() outer: for i in range(0, 3) {\n    defer print(i)\n    for j in range(0, 3) {\n        defer print(10 + j)\n        if (j == 1) break outer\n    }\n}";
+    assert_eq!(output(source), "10\n11\n0\n");
+}
+
+#[test]
+fn break_before_a_defer_does_not_run_it() {
+    let source = "fn main() while (true) {\n    defer print(1)\n    if (true) break\n    defer print(2)\n}";
+    assert_eq!(output(source), "1\n");
+}
+
+#[test]
+fn break_keeps_what_defers_assign() {
+    let source = "fn main() {\n    n = 0\n    while (n < 10) {\n        defer n = n + 5\n        break\n    }\n    n\n}";
+    assert_eq!(language::compile(source).and_then(|compiled| compiled.run()).unwrap().to_string(), "5");
+}
+
+#[test]
+fn defers_run_in_reverse_order_after_the_value() {
+    let source = "fn main() {\n    defer print(1)\n    defer print(2)\n    print(3)\n}";
+    assert_eq!(output(source), "3\n2\n1\n");
+}
+
+#[test]
+fn nested_blocks_run_their_own_defers_first() {
+    let source = "fn main() {\n    defer print(1)\n    {\n        defer print(2)\n        {\n            defer print(3)\n            print(4)\n        }\n        print(5)\n    }\n}";
+    assert_eq!(output(source), "4\n3\n5\n2\n1\n");
+}
+
+#[test]
+fn defers_run_on_every_pass_of_a_loop() {
+    let source = "fn main() for i in range(0, 3) {\n    defer print(i)\n    print(10 + i)\n}";
+    assert_eq!(output(source), "10\n0\n11\n1\n12\n2\n");
+}
+
+#[test]
+fn defers_in_a_function_run_when_it_returns() {
+    let source = "fn f(x) {\n    defer print(x)\n    x * 2\n}\nfn main() print(f(3) + f(4))";
+    assert_eq!(output(source), "3\n4\n14\n");
+}
+
+#[test]
+fn a_defer_inside_a_defer_runs_when_the_deferred_block_exits() {
+    let source = "fn main() {\n    defer {\n        defer print(1)\n        print(2)\n    }\n    print(3)\n}";
+    assert_eq!(output(source), "3\n2\n1\n");
+}
+
+#[test]
+fn a_block_ending_in_a_defer_gives_unit() {
+    let source = "fn main() {\n    x = 1\n    defer print(x)\n}";
+    assert_eq!(language::compile(source).and_then(|compiled| compiled.run()).unwrap().to_string(), "()");
+}
+
+#[test]
+fn early_exit_from_nested_loops_runs_defers_once() {
+    let source = "fn main() {\n    defer print(0)\n    while (true) {\n        defer print(1)\n        while (true) {\n            defer print(2)\n            break\n        }\n        break\n    }\n}";
+    assert_eq!(output(source), "2\n1\n0\n");
+}
@@ -0,0 +1,53 @@
+//! Unified diffs of texts, and of the IR each optimization pass changes.
+
+use language::compiler::compile_program;
+use language::diff::unified;
+use language::optimize::{self, PASSES};
+use language::{hir, parser};
+
+fn lines(text: &str) -> String {
+    text.split(' ').map(|line| format!("{}\n", line)).collect()
+}
+
+#[test]
+fn same_texts_have_no_diff() {
+    assert_eq!(unified("old", "new", "a\nb\n", "a\nb\n"), "");
+}
+
+#[test]
+fn changes_far_apart_have_hunks_of_their_own() {
+    let diff = unified("old", "new", &lines("a b c d e f g h i j k l m n"), &lines("a B c d e f g h i j k l n x"));
+    assert_eq!(diff, "--- old\n+++ new\n@@ -1,5 +1,5 @@\n a\n-b\n+B\n c\n d\n e\n@@ -10,5 +10,5 @@\n j\n k\n l\n-m\n n\n+x\n");
+}
+
+#[test]
+fn changes_close_together_share_a_hunk() {
+    let diff = unified("old", "new", &lines("a b c d e f g h i"), &lines("a X c d e f g Y i"));
+    assert_eq!(diff, "--- old\n+++ new\n@@ -1,9 +1,9 @@\n a\n-b\n+X\n c\n d\n e\n f\n g\n-h\n+Y\n i\n");
+}
+
+#[test]
+fn an_empty_text_starts_before_the_first_line() {
+    assert_eq!(unified("old", "new", "", "a\nb\n"), "--- old\n+++ new\n@@ -0,0 +1,2 @@\n+a\n+b\n");
+    assert_eq!(unified("old", "new", "a\nb\n", ""), "--- old\n+++ new\n@@ -1,2 +0,0 @@\n-a\n-b\n");
+}
+
+#[test]
+fn each_pass_shows_what_it_changed() {
+    let source = "fn f(a, b) {\n    x = a * b\n    (if (a < b) a * b + 1) else b * a - 1\n}\n\nfn main() f(3, 4)";
+    let ast = parser::parse_source(source).unwrap();
+    let hir = hir::lower(&ast, &[]).unwrap();
+    let (program, entry, _) = compile_program(&hir, false, "main").map_err(|_| ()).unwrap();
+    let (mut program, _) = program.canonical(entry);
+    let mut before = program.clone();
+    let mut diffs = Vec::new();
+    optimize::run_observed(&mut program, PASSES, true, |pass, after| {
+        diffs.push((pass.to_string(), optimize::diff(pass, &before, after, Some(source))));
+        before = after.clone();
+    });
+    let (_, numbered) = diffs.iter().find(|(pass, _)| pass == "number-values").expect("number-values changes the program");
+    assert!(numbered.starts_with("--- f0 before number-values\n+++ f0 after number-values\n@@ "));
+    assert!(numbered.contains("-        r4: int = r0 * r1\n+        r4: int = r2\n"));
+    assert!(numbered.contains("-        r7: int = r1 * r0\n+        r7: int = r2\n"));
+    assert!(diffs.iter().all(|(_, diff)| !diff.is_empty()));
+}
@@ -0,0 +1,15 @@
+//! What compile errors are called and the codes they are reported with.
+
+/// The code and message of the error compiling `source` gives.
+fn error(source: &str) -> (Option<&'static str>, String) {
+    let diagnostic = language::compile_source(source, false).err().unwrap();
+    (diagnostic.code, diagnostic.message)
+}
+
+#[test]
+fn struct_bodies_that_are_not_fields_are_invalid() {
+    let expected = (Some("E0008"), "invalid struct body".to_string());
+    assert_eq!(error("fn main() struct { 1 + 2 }"), expected);
+    assert_eq!(error("fn main() {\n    x = 1\n    struct { x, x = 2 }\n}"), expected);
+}
+
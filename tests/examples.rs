@@ -0,0 +1,14 @@
+//! Runs the example programs, which give what they print in `// output:`
+//! comments, on every backend.
+
+use std::process::Command;
+
+#[test]
+fn examples() {
+    let output = Command::new(env!("CARGO_BIN_EXE_language"))
+        .arg("test-examples")
+        .arg(concat!(env!("CARGO_MANIFEST_DIR"), "/examples"))
+        .output()
+        .expect("the compiler runs");
+    assert!(output.status.success(), "{}{}", String::from_utf8_lossy(&output.stdout), String::from_utf8_lossy(&output.stderr));
+}
@@ -0,0 +1,45 @@
+//! Maps and channels used through a handle that was never allocated, which
+//! the compiler should never emit, stop the program rather than the VM.
+
+use language::builtins::Intrinsic;
+use language::execute::{Trap, VirtualMachine};
+use language::ir::{Function, Program};
+
+/// Runs `intrinsic` with every argument set to a handle nothing allocated.
+fn run_with_bad_handle(intrinsic: Intrinsic, args: usize, returns: usize) -> Result<(), Trap> {
+    let mut program = Program::new();
+    let mut function = Function::new();
+    let mut block = function.new_block();
+    let handle = block.constant_int(3, &mut program);
+    block.call_builtin(intrinsic, vec![handle; args], returns, &mut program);
+    block.ret(&mut function);
+    let entry = program.add_function(function);
+    VirtualMachine::new(&program).execute(program.get_function(entry))
+}
+
+#[test]
+fn maps_trap_on_a_bad_handle() {
+    for (intrinsic, args, returns) in [(Intrinsic::MapInsert, 3, 0), (Intrinsic::MapGet, 2, 2), (Intrinsic::MapRemove, 2, 2), (Intrinsic::MapLen, 1, 1), (Intrinsic::MapNext, 3, 3)] {
+        assert!(matches!(run_with_bad_handle(intrinsic, args, returns), Err(Trap::InvalidHandle { .. })), "{}", intrinsic.name());
+    }
+}
+
+#[test]
+fn channels_trap_on_a_bad_handle() {
+    for (intrinsic, args, returns) in [(Intrinsic::ChanSend, 2, 0), (Intrinsic::ChanRecv, 1, 2)] {
+        assert!(matches!(run_with_bad_handle(intrinsic, args, returns), Err(Trap::InvalidHandle { .. })), "{}", intrinsic.name());
+    }
+}
+
+#[test]
+fn a_negative_handle_traps() {
+    let mut program = Program::new();
+    let mut function = Function::new();
+    let mut block = function.new_block();
+    let handle = block.constant_int(-1, &mut program);
+    block.call_builtin(Intrinsic::MapLen, vec![handle], 1, &mut program);
+    block.ret(&mut function);
+    let entry = program.add_function(function);
+    let result = VirtualMachine::new(&program).execute(program.get_function(entry));
+    assert!(matches!(result, Err(Trap::InvalidHandle { .. })));
+}
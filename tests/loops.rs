@@ -0,0 +1,30 @@
+//! Names from outside a loop that its body assigns, which keep their
+//! values after it.
+
+fn run(source: &str) -> String {
+    language::compile(source).and_then(|compiled| compiled.run()).map_or_else(|error| error.to_string(), |value| value.to_string())
+}
+
+#[test]
+fn for_body_carries_assigned_names() {
+    let source = "fn main() {\n    total = 0\n    for i in range(0, 5) {\n        total += i\n    }\n    total\n}";
+    assert_eq!(run(source), "10");
+}
+
+#[test]
+fn break_from_a_for_keeps_assigned_names() {
+    let source = "fn main() {\n    total = 0\n    for i in range(0, 10) {\n        total += i\n        if (total > 8) break\n    }\n    total\n}";
+    assert_eq!(run(source), "10");
+}
+
+#[test]
+fn for_pattern_does_not_assign_an_outer_name() {
+    let source = "fn main() {\n    i = 100\n    for i in range(0, 3) {\n        i = i + 1\n    }\n    i\n}";
+    assert_eq!(run(source), "100");
+}
+
+#[test]
+fn while_body_carries_a_for_inside_it() {
+    let source = "fn main() {\n    total = 0\n    n = 0\n    while (n < 3) {\n        for i in range(0, n) {\n            total += i\n        }\n        n += 1\n    }\n    total\n}";
+    assert_eq!(run(source), "1");
+}
@@ -0,0 +1,42 @@
+//! Names from outside a `match` that its arms assign, which keep the value
+//! of the arm that ran.
+
+fn run(source: &str) -> String {
+    language::compile(source).and_then(|compiled| compiled.run()).map_or_else(|error| error.to_string(), |value| value.to_string())
+}
+
+#[test]
+fn arm_assigns_an_outer_name() {
+    let source = "fn main() {\n    x = 1\n    match 2 {\n        2 => x = 5,\n        _ => 0\n    }\n    x\n}";
+    assert_eq!(run(source), "5");
+}
+
+#[test]
+fn arms_that_do_not_run_leave_the_name_as_it_was() {
+    let source = "fn f(n) {\n    x = 1\n    match n {\n        0 => x = 2,\n        1 => 0,\n        _ => x = 3\n    }\n    x\n}\n\nfn main() (f(0), f(1), f(2))";
+    assert_eq!(run(source), "(2, (1, 3))");
+}
+
+#[test]
+fn arm_pattern_does_not_assign_an_outer_name() {
+    let source = "fn main() {\n    x = 1\n    match 4 {\n        x => x = x + 1\n    }\n    x\n}";
+    assert_eq!(run(source), "1");
+}
+
+#[test]
+fn while_body_carries_names_its_arms_assign() {
+    let source = "fn main() {\n    x = 0\n    i = 0\n    while (i < 4) {\n        match i {\n            1 => x = x + 10,\n            3 => x = x + 100,\n            _ => 0\n        }\n        i += 1\n    }\n    x\n}";
+    assert_eq!(run(source), "110");
+}
+
+#[test]
+fn for_body_carries_names_its_arms_assign() {
+    let source = "fn main() {\n    x = 0\n    for i in range(0, 4) {\n        match i % 2 {\n            0 => x += i,\n            _ => 0\n        }\n    }\n    x\n}";
+    assert_eq!(run(source), "2");
+}
+
+#[test]
+fn arms_must_leave_a_name_with_one_type() {
+    let source = "fn main() {\n    x = 1\n    match 2 {\n        2 => x = true,\n        _ => 0\n    }\n    x\n}";
+    assert!(run(source).contains("mismatched types"), "{}", run(source));
+}
@@ -0,0 +1,83 @@
+//! Deeply nested expressions, which are rejected past `MAX_DEPTH` rather
+//! than overflowing the stack.
+
+use language::parser::MAX_DEPTH;
+
+/// Runs `test` on a thread with the stack of a main thread, which the
+/// later stages of a debug build need at the deepest nesting allowed.
+fn with_stack(test: impl FnOnce() + Send + 'static) {
+    std::thread::Builder::new().stack_size(16 << 20).spawn(test).unwrap().join().unwrap();
+}
+
+fn run(source: &str) -> Result<String, Option<&'static str>> {
+    language::compile_source(source, false).map_err(|error| error.code)?;
+    language::compile(source).and_then(|compiled| compiled.run()).map(|value| value.to_string()).map_err(|_| None)
+}
+
+fn sum(terms: usize) -> String {
+    format!("fn main() {}", vec!["1"; terms].join(" + "))
+}
+
+fn nots(count: usize) -> String {
+    format!("fn main() {}true", "!".repeat(count))
+}
+
+fn functions(count: usize) -> String {
+    format!("fn main() {}0", "fn f(x) ".repeat(count))
+}
+
+fn lambdas(count: usize) -> String {
+    format!("fn main() {{\n    f = {}0\n    f(0)\n}}", "fn(x) ".repeat(count))
+}
+
+fn ifs(count: usize) -> String {
+    format!("fn main() {}1", "if (true) ".repeat(count))
+}
+
+fn blocks(count: usize) -> String {
+    format!("fn main() {}x{}", "{\n    x = 1\n".repeat(count), "\n}".repeat(count))
+}
+
+fn defers(count: usize) -> String {
+    format!("fn main() {{\n    x = 1\n{}    x\n}}", "    defer x\n".repeat(count))
+}
+
+/// Every generator at a depth a few levels under the limit, leaving room
+/// for the declarations around the nested expression.
+#[test]
+fn nesting_under_the_limit_compiles() {
+    with_stack(|| {
+        let count = MAX_DEPTH - 4;
+        assert_eq!(run(&sum(count)), Ok(count.to_string()));
+        assert_eq!(run(&nots(count)), Ok("true".to_string()));
+        assert!(language::compile_source(&functions(count), false).is_ok());
+        assert!(language::compile_source(&lambdas(count), false).is_ok());
+        assert_eq!(run(&ifs(count)), Ok("1".to_string()));
+        assert_eq!(run(&blocks(count)), Ok("1".to_string()));
+        assert_eq!(run(&defers(count)), Ok("1".to_string()));
+    });
+}
+
+#[test]
+fn nesting_past_the_limit_is_an_error() {
+    with_stack(|| {
+        assert_eq!(run(&sum(MAX_DEPTH)), Err(Some("E0103")));
+        assert_eq!(run(&nots(MAX_DEPTH)), Err(Some("E0103")));
+        assert_eq!(run(&functions(MAX_DEPTH)), Err(Some("E0103")));
+        assert_eq!(run(&lambdas(MAX_DEPTH)), Err(Some("E0103")));
+        assert_eq!(run(&ifs(MAX_DEPTH)), Err(Some("E0103")));
+        assert_eq!(run(&blocks(MAX_DEPTH)), Err(Some("E0103")));
+        assert_eq!(run(&defers(MAX_DEPTH)), Err(Some("E0103")));
+    });
+}
+
+#[test]
+fn very_deep_nesting_is_an_error() {
+    with_stack(|| {
+        assert_eq!(run(&sum(5000)), Err(Some("E0103")));
+        assert_eq!(run(&nots(10000)), Err(Some("E0103")));
+        assert_eq!(run(&functions(3000)), Err(Some("E0103")));
+        assert_eq!(run(&blocks(10000)), Err(Some("E0103")));
+        assert_eq!(run(&defers(10000)), Err(Some("E0103")));
+    });
+}
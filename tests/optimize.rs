@@ -0,0 +1,145 @@
+//! Passes of the optimizer, run on their own, and passes added to the
+//! pipeline.
+
+use language::compiler::compile_program;
+use language::execute::{SandboxConfig, VirtualMachine};
+use language::ir::{Function, FunctionId, Instruction, Program};
+use language::optimize::{self, Pass, Pipeline, Point, PASSES};
+use language::session::{CompileOptions, Session};
+use language::types::Type;
+use language::{hir, parser};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// What running `main` prints and returns, or the trap that stopped it.
+fn run(program: &Program, entry: FunctionId, ty: &Type) -> String {
+    let mut vm = VirtualMachine::new(program);
+    vm.set_sandbox(SandboxConfig { fuel: Some(10_000_000), ..SandboxConfig::default() });
+    vm.capture_output();
+    let result = vm.execute(program.get_function(entry));
+    let printed = vm.take_output();
+    match result {
+        Ok(()) => format!("{}{}", printed, vm.format_ty(ty)),
+        Err(trap) => format!("{}{}", printed, trap),
+    }
+}
+
+/// Runs the passes called `names` on `source`, checking that it runs as it
+/// did, and returns the program.
+fn optimize(source: &str, names: &[&str]) -> Program {
+    let ast = parser::parse_source(source).unwrap();
+    let hir = hir::lower(&ast, &[]).unwrap();
+    let (mut program, entry, ty) = compile_program(&hir, false, "main").map_err(|_| ()).unwrap();
+    let expected = run(&program, entry, &ty);
+    let passes: Vec<_> = names.iter().map(|&name| pass(name)).collect();
+    optimize::run(&mut program, &passes, true);
+    assert_eq!(run(&program, entry, &ty), expected);
+    program
+}
+
+fn pass(name: &str) -> (&'static str, Pass) {
+    *PASSES.iter().find(|(pass, _)| *pass == name).expect("the pass exists")
+}
+
+fn count(program: &Program, matches: impl Fn(&Instruction) -> bool) -> usize {
+    program.get_function_ids().into_iter()
+        .flat_map(|function_id| program.get_function(function_id).get_blocks())
+        .flat_map(|block| block.get_instructions())
+        .filter(|inst| matches(inst))
+        .count()
+}
+
+fn multiplies(program: &Program) -> usize {
+    count(program, |inst| matches!(inst, Instruction::MulInt { .. }))
+}
+
+#[test]
+fn values_from_a_dominating_block_are_reused() {
+    let source = "fn f(a, b) {\n    x = a * b\n    (if (a < b) a * b + 1) else b * a - 1\n}\n\nfn main() f(3, 4)";
+    assert_eq!(multiplies(&optimize(source, &[])), 3);
+    assert_eq!(multiplies(&optimize(source, &["number-values"])), 1);
+}
+
+#[test]
+fn values_from_another_branch_are_not_reused() {
+    let source = "fn f(a, b) (if (a < b) a * b + 1) else b * a - 1\n\nfn main() (f(3, 4), f(4, 3))";
+    assert_eq!(multiplies(&optimize(source, &["number-values"])), 2);
+}
+
+#[test]
+fn operands_written_twice_are_not_trusted() {
+    let mut program = Program::new();
+    let mut function = Function::new();
+    let mut block = function.new_block();
+    let c = block.constant_int(3, &mut program);
+    let b = block.constant_int(4, &mut program);
+    let p = block.mul_int(c, b, &mut program);
+    block.copy(c, b, &program);
+    let q = block.mul_int(c, b, &mut program);
+    let result = block.sub_int(p, q, &mut program);
+    function.return_var(result);
+    block.ret(&mut function);
+    let entry = program.add_function(function);
+    let ty = Type::Int(result);
+    assert_eq!(run(&program, entry, &ty), "-4");
+    optimize::run(&mut program, &[pass("number-values")], true);
+    assert_eq!(multiplies(&program), 2);
+    assert_eq!(run(&program, entry, &ty), "-4");
+}
+
+#[test]
+fn recursion_keeps_reused_values() {
+    let source = "fn f(n) {\n    x = n * n\n    y = (if (n == 0) 0) else f(n - 1)\n    y + n * n - x + 1\n}\n\nfn main() f(5)";
+    assert_eq!(multiplies(&optimize(source, &["number-values"])), 1);
+}
+
+#[test]
+fn an_unused_pow_that_traps_is_kept() {
+    let source = "fn main() {\n    e = 0 - 2\n    pow(3, e)\n    5\n}";
+    optimize(source, &["construct-ssa", "fold-constants", "propagate-copies", "remove-dead-code"]);
+}
+
+fn nothing(_: &mut Program) -> bool {
+    false
+}
+
+#[test]
+fn added_passes_run_at_their_points() {
+    let mut pipeline = Pipeline::new();
+    pipeline.insert(Point::End, "last", nothing);
+    pipeline.insert(Point::AfterConstprop, "folded", nothing);
+    pipeline.insert(Point::Start, "first", nothing);
+    pipeline.insert(Point::AfterConstprop, "folded-again", nothing);
+    let names: Vec<_> = pipeline.passes().into_iter().map(|(name, _)| name).collect();
+    assert_eq!(names, [
+        "first", "construct-ssa", "fold-constants", "folded", "folded-again", "number-values",
+        "propagate-copies", "remove-dead-code", "simplify-branches", "last",
+    ]);
+    assert_eq!(Pipeline::new().passes().len(), PASSES.len());
+}
+
+#[test]
+fn points_have_names() {
+    for point in [Point::Start, Point::AfterConstprop, Point::End] {
+        assert_eq!(Point::parse(point.name()), Some(point));
+    }
+    assert_eq!(Point::parse("before-regalloc"), None);
+}
+
+/// How many additions `count_adds` last saw, if it has run.
+static ADDS: AtomicUsize = AtomicUsize::new(usize::MAX);
+
+fn count_adds(program: &mut Program) -> bool {
+    ADDS.store(count(program, |inst| matches!(inst, Instruction::AddInt { .. })), Ordering::SeqCst);
+    false
+}
+
+#[test]
+fn sessions_run_added_passes() {
+    let mut options = CompileOptions { optimize: true, ..CompileOptions::default() };
+    options.passes.insert(Point::AfterConstprop, "count-adds", count_adds);
+    let session = Session::new(String::new(), "fn main() 1 + 2".to_string(), options);
+    let ast = session.parse().unwrap();
+    let hir = session.lower(&ast).unwrap();
+    session.compile(&hir).unwrap();
+    assert_eq!(ADDS.load(Ordering::SeqCst), 0);
+}
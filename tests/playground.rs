@@ -0,0 +1,39 @@
+//! Running programs through the playground, as a web page would.
+
+use language::execute::{Resource, SandboxConfig};
+use language::playground::{compile_and_run, compile_and_run_with};
+
+#[test]
+fn sleep_moves_a_mock_clock() {
+    let out = compile_and_run("fn main() {\n    start = now_millis()\n    sleep(60000)\n    now_millis() - start\n}");
+    assert_eq!(out.output, "60000");
+}
+
+#[test]
+fn a_trap_is_rendered_as_an_error() {
+    let out = compile_and_run("fn main() {\n    zero = 0\n    1 / zero\n}");
+    assert!(out.compiled);
+    assert_eq!(out.output, "");
+    assert_eq!(out.diagnostics, "error: division by zero at line 3\n");
+}
+
+#[test]
+fn a_program_that_never_stops_runs_out_of_fuel() {
+    let out = compile_and_run("fn main() {\n    n = 0\n    while (true) n += 1\n    n\n}");
+    assert!(out.diagnostics.contains("error: the program ran out of fuel"), "{}", out.diagnostics);
+    assert_eq!(out.exhausted, Some(Resource::Fuel));
+}
+
+#[test]
+fn limits_can_be_given() {
+    let source = "fn main() {\n    n = 0\n    while (n < 1000) n += 1\n    n\n}";
+    assert_eq!(compile_and_run(source).output, "1000");
+    let out = compile_and_run_with(source, SandboxConfig { fuel: Some(100), ..SandboxConfig::default() });
+    assert_eq!((out.output.as_str(), out.exhausted), ("", Some(Resource::Fuel)));
+}
+
+#[test]
+fn exit_is_not_an_error() {
+    let out = compile_and_run("fn main() {\n    exit(3)\n    0\n}");
+    assert_eq!((out.exit_code, out.diagnostics.as_str()), (Some(3), ""));
+}
@@ -0,0 +1,33 @@
+//! The REPL, given its entries on stdin.
+
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+/// What the REPL prints to stdout and to stderr for `input`.
+fn repl(input: &str) -> (String, String) {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_language"))
+        .arg("repl")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("the compiler runs");
+    child.stdin.take().unwrap().write_all(input.as_bytes()).unwrap();
+    let output = child.wait_with_output().unwrap();
+    (String::from_utf8_lossy(&output.stdout).into_owned(), String::from_utf8_lossy(&output.stderr).into_owned())
+}
+
+#[test]
+fn traps_are_errors_as_when_running() {
+    let (stdout, stderr) = repl("1 / 0\n2 + 3\n");
+    assert_eq!(stderr, "error: division by zero at line 1\n");
+    assert!(stdout.contains("5\n"), "{}", stdout);
+    assert!(!stdout.contains("division"), "{}", stdout);
+}
+
+#[test]
+fn running_out_is_reported_as_when_running() {
+    let (_, stderr) = repl("{\n    fn f(n) (if (n == 0) 0) else f(n - 1) + 1\n    f(100000000)\n}\n");
+    assert!(stderr.starts_with("error: calls nested more than "), "{}", stderr);
+    assert!(!stderr.contains("Stack"), "{}", stderr);
+}
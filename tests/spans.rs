@@ -0,0 +1,69 @@
+//! What lowering makes from sugar is found at the code that was written.
+
+use language::compiler::compile_program;
+use language::diagnostics::Span;
+use language::source_map::SourceMap;
+use language::{hir, parser, thir};
+
+/// The typed tree of `source`, as `--emit thir` shows it.
+fn thir(source: &str) -> String {
+    let ast = parser::parse_source(source).unwrap();
+    let hir = hir::lower(&ast, &[]).unwrap();
+    compile_program(&hir, false, "main").map_err(|_| ()).unwrap();
+    thir::to_text(&hir, source)
+}
+
+fn slice(source: &str, span: Span) -> &str {
+    &source[span.start..span.end]
+}
+
+#[test]
+fn the_unit_of_a_trailing_defer_is_at_the_defer() {
+    let thir = thir("fn main() {\n    x = 1\n    defer x\n}");
+    assert!(thir.contains("\n      tuple @ 3:5 : ()\n      var x @ 3:11 : int"), "{}", thir);
+}
+
+#[test]
+fn the_unit_of_a_lone_defer_is_at_the_defer() {
+    let thir = thir("fn main() {\n    x = 1\n    if (true) defer x\n}");
+    assert!(thir.contains("\n        var x @ 3:21 : int -> defined at 2:5\n        tuple @ 3:15 : ()"), "{}", thir);
+}
+
+#[test]
+fn compound_assignment_errors_point_at_what_was_written() {
+    let source = "fn main() {\n    x = 1\n    x += true\n    x\n}";
+    let error = language::compile_source(source, false).err().unwrap();
+    let labels: Vec<_> = error.labels.iter().map(|label| (slice(source, label.span), label.primary)).collect();
+    assert_eq!(labels, [("x += true", true), ("x", false), ("true", false)]);
+}
+
+#[test]
+fn keywords_written_as_names_are_pointed_at_alone() {
+    let cases = [
+        ("fn match(x) x", "match"),
+        ("fn main() {\n    else = 1\n}", "else"),
+        ("fn while(x) x", "while"),
+        ("fn main() {\n    for = 1\n}", "for"),
+        ("fn main() {\n    while = 1\n}", "while"),
+        ("fn main() {\n    match = 1\n}", "match"),
+        ("fn main() {\n    struct = 3\n}", "struct"),
+        ("fn f(while) 1", "while"),
+        ("fn f(a, (b, if)) 1", "if"),
+        ("fn main() |x, defer| x", "defer"),
+        ("fn main() for fn in range(0, 1) 0", "fn"),
+        ("fn main() match 1 { (a, struct) => 1 }", "struct"),
+    ];
+    for (source, keyword) in cases {
+        let error = parser::parse_source(source).err().unwrap();
+        let diagnostic = error.to_diagnostic(&SourceMap::single("main.txt", source));
+        assert_eq!(diagnostic.code, Some("E0102"));
+        let labels: Vec<_> = diagnostic.labels.iter().map(|label| slice(source, label.span)).collect();
+        assert_eq!(labels, [keyword]);
+    }
+}
+
+#[test]
+fn keywords_that_start_a_construct_still_do() {
+    let source = "fn main() {\n    x = 1\n    match x == 1 {\n        true => while (false) 0,\n        _ => ()\n    }\n    f = |n| for i in range(0, n) i\n    if (x == 1) f(2)\n}";
+    assert!(parser::parse_source(source).is_ok());
+}
@@ -0,0 +1,72 @@
+//! Taking optimized programs out of SSA form, as native code needs.
+
+use language::compiler::compile_program;
+use language::execute::{SandboxConfig, VirtualMachine};
+use language::ir::{FunctionId, Instruction, Program};
+use language::types::Type;
+use language::{hir, optimize, parser, ssa};
+use std::fs;
+
+/// What running `main` prints and returns, or the trap that stopped it.
+fn run(program: &Program, entry: FunctionId, ty: &Type) -> String {
+    let mut vm = VirtualMachine::new(program);
+    vm.set_sandbox(SandboxConfig { fuel: Some(10_000_000), ..SandboxConfig::default() });
+    vm.capture_output();
+    let result = vm.execute(program.get_function(entry));
+    let printed = vm.take_output();
+    match result {
+        Ok(()) => format!("{}{}", printed, vm.format_ty(ty)),
+        Err(trap) => format!("{}{}", printed, trap),
+    }
+}
+
+fn blocks(program: &Program) -> usize {
+    program.get_function_ids().into_iter().map(|function_id| program.get_function(function_id).get_blocks().len()).sum()
+}
+
+fn phis(program: &Program) -> usize {
+    program.get_function_ids().into_iter()
+        .flat_map(|function_id| program.get_function(function_id).get_blocks())
+        .flat_map(|block| block.get_instructions())
+        .filter(|inst| matches!(inst, Instruction::Phi { .. }))
+        .count()
+}
+
+/// Optimizes `source` and takes it out of SSA form, checking that no phis
+/// are left, that the program is still valid and that it runs as it did.
+/// Returns how many phis there were and how many blocks were added.
+fn destruct(source: &str) -> (usize, usize) {
+    let ast = parser::parse_source(source).unwrap();
+    let hir = hir::lower(&ast, &[]).unwrap();
+    let (mut program, entry, ty) = compile_program(&hir, false, "main").map_err(|_| ()).unwrap();
+    let expected = run(&program, entry, &ty);
+    optimize::optimize(&mut program, true);
+    let (before, block_count) = (phis(&program), blocks(&program));
+    assert_eq!(ssa::destruct(&mut program), before > 0);
+    assert_eq!(phis(&program), 0);
+    program.verify().unwrap();
+    assert_eq!(run(&program, entry, &ty), expected);
+    (before, blocks(&program) - block_count)
+}
+
+#[test]
+fn examples_run_the_same_without_phis() {
+    let mut paths: Vec<_> = fs::read_dir(concat!(env!("CARGO_MANIFEST_DIR"), "/examples")).unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    paths.sort();
+    let total: usize = paths.iter().map(|path| destruct(&fs::read_to_string(path).unwrap()).0).sum();
+    assert!(total > 0);
+}
+
+#[test]
+fn swapped_names_are_copied_as_one() {
+    let source = "fn main() {\n    a = 1\n    b = 2\n    for i in range(0, 11) {\n        t = a\n        a = b\n        b = t\n    }\n    a * 10 + b\n}";
+    assert_eq!(destruct(source), (3, 0));
+}
+
+#[test]
+fn a_branch_to_phis_and_elsewhere_is_split() {
+    let source = "fn main() {\n    total = 0\n    n = 0\n    while (n < 10) {\n        if (n % 3 == 0) total += n\n        n += 1\n    }\n    total\n}";
+    assert_eq!(destruct(source), (2, 1));
+}
@@ -0,0 +1,157 @@
+//! Switches, built by hand or from a `match` on ints, run by the VM,
+//! optimized, and encoded as bytecode.
+
+use language::bytecode::{decode, encode};
+use language::compiler::compile_program;
+use language::execute::{SandboxConfig, VirtualMachine};
+use language::ir::{Block, ExitInstruction, Function, FunctionId, Program, Var};
+use language::types::Type;
+use language::{hir, optimize, parser};
+
+/// What running `main` returns, or the trap that stopped it.
+fn run(program: &Program, entry: FunctionId, ty: &Type) -> String {
+    let mut vm = VirtualMachine::new(program);
+    vm.set_sandbox(SandboxConfig { fuel: Some(10_000_000), ..SandboxConfig::default() });
+    match vm.execute(program.get_function(entry)) {
+        Ok(()) => vm.format_ty(ty),
+        Err(trap) => trap.to_string(),
+    }
+}
+
+fn switches(program: &Program) -> usize {
+    program.get_function_ids().into_iter()
+        .flat_map(|function_id| program.get_function(function_id).get_blocks())
+        .filter(|block| matches!(block.get_exit_instruction(), ExitInstruction::Switch { .. }))
+        .count()
+}
+
+/// Ends `block` with a switch on `scrutinee` to blocks that return 1, 2 or
+/// 3 for 0, 1 or 2, and 9 for anything else.
+fn switch(scrutinee: Var, mut block: Block, program: &mut Program, function: &mut Function) {
+    let result = block.constant_int(0, program);
+    function.return_var(result);
+    let cases: Vec<_> = (0..4).map(|_| function.new_block()).collect();
+    let targets = cases[..3].iter().map(|case| case.get_id()).collect();
+    let default = cases[3].get_id();
+    block.switch(scrutinee, targets, default, function);
+    for (mut case, value) in cases.into_iter().zip([1, 2, 3, 9]) {
+        let constant = case.constant_int(value, program);
+        case.copy(result, constant, program);
+        case.ret(function);
+    }
+}
+
+/// A function that switches on its parameter.
+fn pick(program: &mut Program) -> FunctionId {
+    let mut function = Function::new();
+    let scrutinee = function.new_parameter(program);
+    let block = function.new_block();
+    switch(scrutinee, block, program, &mut function);
+    program.add_function(function)
+}
+
+/// A program whose `main` calls `pick` with each of `scrutinees`, and gives
+/// what it gave as the digits of one number.
+fn picks(scrutinees: &[i32]) -> (Program, FunctionId, Type<'static, 'static>) {
+    let mut program = Program::new();
+    let pick = pick(&mut program);
+    let mut function = Function::new();
+    let mut block = function.new_block();
+    let ten = block.constant_int(10, &mut program);
+    let mut total = block.constant_int(0, &mut program);
+    for &scrutinee in scrutinees {
+        let scrutinee = block.constant_int(scrutinee, &mut program);
+        let picked = block.call(pick, vec![scrutinee], 1, &mut program)[0];
+        let shifted = block.mul_int(total, ten, &mut program);
+        total = block.add_int(shifted, picked, &mut program);
+    }
+    function.return_var(total);
+    block.ret(&mut function);
+    let entry = program.add_function(function);
+    program.verify().unwrap();
+    (program, entry, Type::Int(total))
+}
+
+#[test]
+fn scrutinees_past_the_end_go_to_the_default() {
+    let (program, entry, ty) = picks(&[0, 1, 2, 3, 100, -1, i32::MIN]);
+    assert_eq!(run(&program, entry, &ty), "1239999");
+    assert!(program.to_string().contains("switch r0 [b1, b2, b3] else goto b4"), "{}", program);
+}
+
+#[test]
+fn optimized_switches_run_the_same() {
+    let (mut program, entry, ty) = picks(&[2, -1, 0, 3, 1]);
+    optimize::optimize(&mut program, true);
+    assert_eq!(switches(&program), 1);
+    assert_eq!(run(&program, entry, &ty), "39192");
+}
+
+#[test]
+fn switches_on_constants_become_branches() {
+    for (constant, expected) in [(1, "2"), (3, "9"), (-1, "9")] {
+        let mut program = Program::new();
+        let mut function = Function::new();
+        let mut block = function.new_block();
+        let scrutinee = block.constant_int(constant, &mut program);
+        switch(scrutinee, block, &mut program, &mut function);
+        let ty = Type::Int(function.get_returns()[0]);
+        let entry = program.add_function(function);
+        assert_eq!(run(&program, entry, &ty), expected);
+        optimize::optimize(&mut program, true);
+        assert_eq!(switches(&program), 0);
+        assert_eq!(run(&program, entry, &ty), expected);
+    }
+}
+
+#[test]
+fn switches_round_trip_through_bytecode() {
+    let (program, entry, ty) = picks(&[0, 3, -1, 2]);
+    let bytes = encode(&program, entry, &ty).unwrap();
+    let (decoded, decoded_entry, decoded_ty) = decode(&bytes).unwrap();
+    let (reachable, _) = program.reachable(entry);
+    assert_eq!(decoded.to_string(), reachable.to_string());
+    assert_eq!(switches(&decoded), 1);
+    assert_eq!(run(&decoded, decoded_entry, &decoded_ty), "1993");
+}
+
+/// Compiles `source`, checking that it runs the same optimized, and gives
+/// what it returns and how many switches it compiled to.
+fn compile(source: &str) -> (String, usize) {
+    let ast = parser::parse_source(source).unwrap();
+    let hir = hir::lower(&ast, &[]).unwrap();
+    let (mut program, entry, ty) = compile_program(&hir, false, "main").map_err(|_| ()).unwrap();
+    let result = run(&program, entry, &ty);
+    let count = switches(&program);
+    optimize::optimize(&mut program, true);
+    assert_eq!(run(&program, entry, &ty), result);
+    (result, count)
+}
+
+#[test]
+fn matches_on_close_literals_switch() {
+    let source = "fn f(n) match n {\n    3 => 30,\n    5 => 50,\n    4 => 40,\n    _ => 0\n}\n\nfn main() (f(3), f(4), f(5), f(6), f(2), f(0 - 1), f(0 - 2147483647 - 1))";
+    assert_eq!(compile(source), ("(30, (40, (50, (0, (0, (0, 0))))))".to_string(), 1));
+}
+
+#[test]
+fn matches_on_uints_switch() {
+    let source = "fn f(n) match n {\n    0u => 1,\n    1u => 2,\n    2u => 3,\n    n => 9\n}\n\nfn main() (f(0u), f(2u), f(3u), f(4294967295u))";
+    assert_eq!(compile(source), ("(1, (3, (9, 9)))".to_string(), 1));
+}
+
+#[test]
+fn matches_without_a_catch_all_trap_past_the_switch() {
+    let source = "fn f(n) match n {\n    1 => 10,\n    2 => 20,\n    3 => 30\n}\n\nfn main() f(2) + f(4)";
+    assert_eq!(compile(source), ("`else trap` or `match` found no value at line 1".to_string(), 1));
+}
+
+#[test]
+fn matches_on_few_far_apart_or_repeated_literals_test_each_arm() {
+    let few = "fn f(n) match n {\n    1 => 10,\n    2 => 20,\n    _ => 0\n}\n\nfn main() (f(1), f(2), f(3))";
+    assert_eq!(compile(few), ("(10, (20, 0))".to_string(), 0));
+    let far = "fn f(n) match n {\n    1 => 10,\n    2 => 20,\n    100 => 30,\n    _ => 0\n}\n\nfn main() (f(1), f(100), f(3))";
+    assert_eq!(compile(far), ("(10, (30, 0))".to_string(), 0));
+    let repeated = "fn f(n) match n {\n    1 => 10,\n    2 => 20,\n    1 => 30,\n    _ => 0\n}\n\nfn main() (f(1), f(2), f(3))";
+    assert_eq!(compile(repeated), ("(10, (20, 0))".to_string(), 0));
+}
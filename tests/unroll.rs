@@ -0,0 +1,114 @@
+//! Unrolling loops that go round a few times known when compiling.
+
+use language::compiler::compile_program;
+use language::execute::{SandboxConfig, VirtualMachine};
+use language::ir::{FunctionId, Instruction, Program};
+use language::optimize::Pipeline;
+use language::types::Type;
+use language::{hir, parser, unroll};
+
+/// What running `main` prints and returns, or the trap that stopped it.
+fn run(program: &Program, entry: FunctionId, ty: &Type) -> String {
+    let mut vm = VirtualMachine::new(program);
+    vm.set_sandbox(SandboxConfig { fuel: Some(10_000_000), ..SandboxConfig::default() });
+    vm.capture_output();
+    let result = vm.execute(program.get_function(entry));
+    let printed = vm.take_output();
+    match result {
+        Ok(()) => format!("{}{}", printed, vm.format_ty(ty)),
+        Err(trap) => format!("{}{}", printed, trap),
+    }
+}
+
+fn loops(program: &Program) -> usize {
+    program.get_function_ids().into_iter().map(|function_id| program.get_function(function_id).cfg_metrics().loops).sum()
+}
+
+fn multiplies(program: &Program) -> usize {
+    program.get_function_ids().into_iter()
+        .flat_map(|function_id| program.get_function(function_id).get_blocks())
+        .flat_map(|block| block.get_instructions())
+        .filter(|inst| matches!(inst, Instruction::MulInt { .. }))
+        .count()
+}
+
+/// Optimizes `source` without unrolling, then unrolls its loops that go
+/// round at most `threshold` times, checking that the program is still
+/// valid and runs as it did. Returns the program and whether any loop was
+/// unrolled.
+fn unroll(source: &str, threshold: usize) -> (Program, bool) {
+    let ast = parser::parse_source(source).unwrap();
+    let hir = hir::lower(&ast, &[]).unwrap();
+    let (mut program, entry, ty) = compile_program(&hir, false, "main").map_err(|_| ()).unwrap();
+    let expected = run(&program, entry, &ty);
+    Pipeline::new().with_unroll_threshold(0).run(&mut program, true);
+    assert_eq!(run(&program, entry, &ty), expected);
+    let unrolled = unroll::unroll_loops(&mut program, threshold);
+    program.verify().unwrap();
+    assert_eq!(run(&program, entry, &ty), expected);
+    (program, unrolled)
+}
+
+/// Squares the numbers below `n` in a `for` and adds them up.
+fn squares(n: &str) -> String {
+    format!("fn main() {{\n    total = 0\n    for i in range(0, {}) {{\n        total += i * i\n    }}\n    total + 0\n}}", n)
+}
+
+#[test]
+fn a_range_is_copied_once_for_each_number() {
+    let (program, unrolled) = unroll(&squares("5"), unroll::DEFAULT_THRESHOLD);
+    assert!(unrolled);
+    assert_eq!(loops(&program), 0);
+    assert_eq!(multiplies(&program), 5);
+}
+
+#[test]
+fn an_empty_range_goes_straight_past() {
+    let (program, unrolled) = unroll(&squares("0"), unroll::DEFAULT_THRESHOLD);
+    assert!(unrolled);
+    assert_eq!(loops(&program), 0);
+    assert_eq!(multiplies(&program), 0);
+}
+
+#[test]
+fn loops_over_the_threshold_are_kept() {
+    let (program, unrolled) = unroll(&squares("9"), 8);
+    assert!(!unrolled);
+    assert_eq!(loops(&program), 1);
+    let (program, unrolled) = unroll(&squares("9"), 9);
+    assert!(unrolled);
+    assert_eq!(multiplies(&program), 9);
+    assert!(!unroll(&squares("3"), 0).1);
+}
+
+#[test]
+fn a_while_counting_down_is_unrolled() {
+    let source = "fn main() {\n    n = 6\n    total = 1\n    while (0 < n) {\n        total *= 2\n        n -= 2\n    }\n    total + 0\n}";
+    let (program, unrolled) = unroll(source, unroll::DEFAULT_THRESHOLD);
+    assert!(unrolled);
+    assert_eq!(multiplies(&program), 3);
+}
+
+#[test]
+fn loops_without_a_constant_bound_are_kept() {
+    let source = "fn f(n) {\n    total = 0\n    for i in range(0, n) {\n        total += i * i\n    }\n    total + 0\n}\n\nfn main() f(4)";
+    assert!(!unroll(source, unroll::DEFAULT_THRESHOLD).1);
+}
+
+#[test]
+fn loops_with_another_way_out_are_kept() {
+    let source = "fn main() {\n    total = 0\n    for i in range(0, 5) {\n        total += i * i\n        if (total > 4) break\n    }\n    total + 0\n}";
+    assert!(!unroll(source, unroll::DEFAULT_THRESHOLD).1);
+}
+
+#[test]
+fn nested_loops_are_unrolled_inside_out() {
+    let source = "fn main() {\n    total = 0\n    for i in range(0, 3) {\n        for j in range(0, 2) {\n            total += i * j\n        }\n    }\n    total + 0\n}";
+    let ast = parser::parse_source(source).unwrap();
+    let hir = hir::lower(&ast, &[]).unwrap();
+    let (mut program, entry, ty) = compile_program(&hir, false, "main").map_err(|_| ()).unwrap();
+    let expected = run(&program, entry, &ty);
+    Pipeline::new().run(&mut program, true);
+    assert_eq!(loops(&program), 0);
+    assert_eq!(run(&program, entry, &ty), expected);
+}